@@ -0,0 +1,65 @@
+//! 虚拟机的性能测试: cargo bench
+//!
+//! *_decoded 为预先解码执行 (VM::execute), *_step 为逐条分派原来的指令 (VM::execute_step, 引入解码之前的执行方式),
+//! *_run_for 为分段执行 (调试器与 run_for 使用的方式), 用于对比
+
+#![feature(test)]
+
+extern crate test;
+
+use std::io;
+
+use pl0::{compiler::Compiler, parser::Paser, vm::Opcode, vm::VM};
+use test::Bencher;
+
+const FIB: &str = "fn fib(n) { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } } fib(30)";
+const LOOP: &str = "var i = 0, s = 0; while i < 1000000 { s += i; i += 1 } s";
+
+fn compile(source: &str) -> Vec<Opcode> {
+  Compiler::compile(&Paser::paser(source).unwrap()).unwrap()
+}
+
+fn run_for(codes: &[Opcode]) -> isize {
+  let mut vm = VM::new();
+  vm.load(codes.to_vec());
+  match vm.run_for(u64::MAX, &mut io::sink()) {
+    pl0::vm::Status::Finished(result) => result,
+    status => panic!("{:?}", status),
+  }
+}
+
+#[bench]
+fn fib_decoded(b: &mut Bencher) {
+  let codes = compile(FIB);
+  b.iter(|| assert_eq!(VM::execute_with_output(&codes, &mut io::sink()), Ok(832040)));
+}
+
+#[bench]
+fn fib_step(b: &mut Bencher) {
+  let codes = compile(FIB);
+  b.iter(|| assert_eq!(VM::execute_step(&codes, &mut io::sink()), Ok(832040)));
+}
+
+#[bench]
+fn fib_run_for(b: &mut Bencher) {
+  let codes = compile(FIB);
+  b.iter(|| assert_eq!(run_for(&codes), 832040));
+}
+
+#[bench]
+fn loop_decoded(b: &mut Bencher) {
+  let codes = compile(LOOP);
+  b.iter(|| assert_eq!(VM::execute_with_output(&codes, &mut io::sink()), Ok(499999500000)));
+}
+
+#[bench]
+fn loop_step(b: &mut Bencher) {
+  let codes = compile(LOOP);
+  b.iter(|| assert_eq!(VM::execute_step(&codes, &mut io::sink()), Ok(499999500000)));
+}
+
+#[bench]
+fn loop_run_for(b: &mut Bencher) {
+  let codes = compile(LOOP);
+  b.iter(|| assert_eq!(run_for(&codes), 499999500000));
+}
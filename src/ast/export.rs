@@ -0,0 +1,466 @@
+//! 抽象语法树的导出: 供其他工具使用的 JSON, 用于教学幻灯片的 Graphviz DOT 以及 S 表达式,
+//! 以及从 JSON 导入
+
+use std::fmt::Write;
+
+use serde_json::{json, Map, Value};
+
+use crate::SpanOffset;
+
+use super::{Expression, ExpressionKind, Identifier, Infix, Prefix, Program, Statement, StatementKind};
+
+const INFIXES: [Infix; 10] = [
+  Infix::Eq,
+  Infix::Ne,
+  Infix::Lt,
+  Infix::Gt,
+  Infix::LtEq,
+  Infix::GtEq,
+  Infix::Add,
+  Infix::Sub,
+  Infix::Mul,
+  Infix::Div,
+];
+
+/// 导出时的树: 节点的种类, 位置, 标量属性以及按顺序排列的子节点
+struct Node {
+  kind: &'static str,
+  pos: Option<SpanOffset>,
+  attributes: Vec<(&'static str, Value)>,
+  children: Vec<(&'static str, Child)>,
+}
+
+enum Child {
+  Node(Node),
+  List(Vec<Node>), // 语句块, 参数等
+  None,            // 没有返回值的 return, 没有 else 的 if
+}
+
+impl Node {
+  fn new(kind: &'static str, pos: Option<SpanOffset>) -> Self {
+    Node { kind, pos, attributes: vec![], children: vec![] }
+  }
+
+  fn attribute(mut self, name: &'static str, value: impl Into<Value>) -> Self {
+    self.attributes.push((name, value.into()));
+    self
+  }
+
+  fn child(mut self, name: &'static str, child: Node) -> Self {
+    self.children.push((name, Child::Node(child)));
+    self
+  }
+
+  fn list(mut self, name: &'static str, children: Vec<Node>) -> Self {
+    self.children.push((name, Child::List(children)));
+    self
+  }
+
+  fn optional(mut self, name: &'static str, child: Option<Node>) -> Self {
+    self.children.push((name, child.map_or(Child::None, Child::Node)));
+    self
+  }
+
+  fn from_program(program: &Program) -> Self {
+    Node::new("Program", None).list("statements", Node::block(&program.statements))
+  }
+
+  fn from_statement(statement: &Statement) -> Self {
+    let node = |kind| Node::new(kind, Some(statement.pos));
+    match &statement.kind {
+      StatementKind::Empty => node("Empty"),
+      StatementKind::Const(items) => node("Const").list("declarators", Node::declarators(items)),
+      StatementKind::Variable(items) => node("Variable").list("declarators", Node::declarators(items)),
+      StatementKind::Function(name, params, body) => node("Function")
+        .child("name", Node::from_identifier(name))
+        .list("params", params.iter().map(Node::from_identifier).collect())
+        .list("body", Node::block(body)),
+      StatementKind::Assign(name, value) => {
+        node("Assign").child("name", Node::from_identifier(name)).child("value", Node::from_expression(value))
+      }
+      StatementKind::Return(value) => node("Return").optional("value", value.as_ref().map(Node::from_expression)),
+      StatementKind::Expression(expression) => {
+        node("Expression").child("expression", Node::from_expression(expression))
+      }
+    }
+  }
+
+  fn from_expression(expression: &Expression) -> Self {
+    let node = |kind| Node::new(kind, Some(expression.pos));
+    match &expression.kind {
+      ExpressionKind::Identifier(name) => node("Identifier").attribute("name", name.as_str()),
+      ExpressionKind::Integer(value) => node("Integer").attribute("value", *value as i64),
+      ExpressionKind::Infix(infix, left, right) => node("Infix")
+        .attribute("operator", infix.to_string())
+        .child("left", Node::from_expression(left))
+        .child("right", Node::from_expression(right)),
+      ExpressionKind::Prefix(prefix, operand) => {
+        node("Prefix").attribute("operator", prefix.to_string()).child("operand", Node::from_expression(operand))
+      }
+      ExpressionKind::Call(callee, arguments) => node("Call")
+        .child("callee", Node::from_expression(callee))
+        .list("arguments", arguments.iter().map(Node::from_expression).collect()),
+      ExpressionKind::Function(name, params, body) => node("Function")
+        .child("name", Node::from_identifier(name))
+        .list("params", params.iter().map(Node::from_identifier).collect())
+        .list("body", Node::block(body)),
+      ExpressionKind::If(condition, then_s, else_s) => {
+        let node = node("If").child("condition", Node::from_expression(condition)).list("then", Node::block(then_s));
+        match else_s {
+          Some(else_s) => node.list("else", Node::block(else_s)),
+          None => node.optional("else", None),
+        }
+      }
+      ExpressionKind::While(condition, body) => {
+        node("While").child("condition", Node::from_expression(condition)).list("body", Node::block(body))
+      }
+    }
+  }
+
+  fn block(statements: &[Statement]) -> Vec<Node> {
+    statements.iter().map(Node::from_statement).collect()
+  }
+
+  fn declarators(items: &[(Identifier, Expression)]) -> Vec<Node> {
+    items
+      .iter()
+      .map(|(name, value)| {
+        Node::new("Declarator", None)
+          .child("name", Node::from_identifier(name))
+          .child("value", Node::from_expression(value))
+      })
+      .collect()
+  }
+
+  fn from_identifier(identifier: &Identifier) -> Self {
+    Node::new("Identifier", Some(identifier.pos)).attribute("name", identifier.name.as_str())
+  }
+
+  fn to_json(&self) -> Value {
+    let mut object = Map::new();
+    object.insert("kind".to_string(), json!(self.kind));
+    if let Some(pos) = self.pos {
+      object.insert("pos".to_string(), json!({ "begin": pos.begin, "end": pos.end }));
+    }
+    for (name, value) in &self.attributes {
+      object.insert(name.to_string(), value.clone());
+    }
+    for (name, child) in &self.children {
+      let value = match child {
+        Child::Node(node) => node.to_json(),
+        Child::List(nodes) => Value::Array(nodes.iter().map(Node::to_json).collect()),
+        Child::None => Value::Null,
+      };
+      object.insert(name.to_string(), value);
+    }
+    Value::Object(object)
+  }
+
+  /// 种类与标量属性, 例如 Infix +
+  fn label(&self) -> String {
+    let attributes = self.attributes.iter().map(|(_, value)| match value {
+      Value::String(string) => string.clone(),
+      value => value.to_string(),
+    });
+    [self.kind.to_string()].into_iter().chain(attributes).collect::<Vec<_>>().join(" ")
+  }
+
+  fn to_sexpr(&self, out: &mut String) {
+    write!(out, "({}", self.label()).unwrap();
+    for (_, child) in &self.children {
+      match child {
+        Child::Node(node) => {
+          out.push(' ');
+          node.to_sexpr(out);
+        }
+        Child::List(nodes) => {
+          out.push_str(" [");
+          for (index, node) in nodes.iter().enumerate() {
+            if index > 0 {
+              out.push(' ');
+            }
+            node.to_sexpr(out);
+          }
+          out.push(']');
+        }
+        Child::None => {}
+      }
+    }
+    out.push(')');
+  }
+
+  /// 写出节点与指向子节点的边, 返回节点的编号
+  fn to_dot(&self, out: &mut String, count: &mut usize) -> usize {
+    let id = *count;
+    *count += 1;
+    let label = self.label().replace('\\', "\\\\").replace('"', "\\\"");
+    writeln!(out, "  n{} [label=\"{}\"];", id, label).unwrap();
+    for (name, child) in &self.children {
+      let nodes = match child {
+        Child::Node(node) => vec![node],
+        Child::List(nodes) => nodes.iter().collect(),
+        Child::None => vec![],
+      };
+      for node in nodes {
+        let child = node.to_dot(out, count);
+        writeln!(out, "  n{} -> n{} [label=\"{}\"];", id, child, name).unwrap();
+      }
+    }
+    id
+  }
+}
+
+///
+/// 导出为 JSON, 每个节点都有 kind, 除了 Program 与 Declarator 都有 pos (字符偏移 begin, end)
+///
+/// 例如 a + 1 为 {"kind": "Infix", "pos": {"begin": 0, "end": 5}, "operator": "+", "left": ..., "right": ...}
+///
+pub fn to_json(program: &Program) -> Value {
+  Node::from_program(program).to_json()
+}
+
+/// 导出为 S 表达式, 语句块等列表写在方括号中, 例如 (Program [(Expression (Infix + (Identifier a) (Integer 1)))])
+pub fn to_sexpr(program: &Program) -> String {
+  let mut out = String::new();
+  Node::from_program(program).to_sexpr(&mut out);
+  out
+}
+
+/// 导出为 Graphviz DOT 格式的树, 边上标注子节点的属性名
+pub fn to_dot(program: &Program) -> String {
+  let mut out = String::from("digraph ast {\n  node [shape=box, fontname=\"monospace\"];\n  edge [fontsize=10];\n");
+  Node::from_program(program).to_dot(&mut out, &mut 0);
+  out.push_str("}\n");
+  out
+}
+
+///
+/// 从 to_json 的格式导入, 其他工具生成的程序可以由编译器编译
+///
+pub fn from_json(value: &Value) -> Result<Program, String> {
+  expect_kind(value, &["Program"])?;
+  Ok(Program { statements: statements(field(value, "statements")?)? })
+}
+
+fn kind(value: &Value) -> Result<&str, String> {
+  value["kind"].as_str().ok_or_else(|| format!("expect a node with kind, but get {}", value))
+}
+
+fn expect_kind<'a>(value: &'a Value, kinds: &[&str]) -> Result<&'a str, String> {
+  let kind = kind(value)?;
+  if kinds.contains(&kind) {
+    Ok(kind)
+  } else {
+    Err(format!("expect {}, but get {}", kinds.join(" or "), kind))
+  }
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> Result<&'a Value, String> {
+  value.get(name).ok_or_else(|| format!("{} need field {}", value["kind"].as_str().unwrap_or("node"), name))
+}
+
+fn list<'a>(value: &'a Value, name: &str) -> Result<&'a Vec<Value>, String> {
+  field(value, name)?.as_array().ok_or_else(|| format!("field {} should be an array", name))
+}
+
+fn pos(value: &Value) -> Result<SpanOffset, String> {
+  let pos = field(value, "pos")?;
+  match (pos["begin"].as_u64(), pos["end"].as_u64()) {
+    (Some(begin), Some(end)) if begin <= end => Ok((begin as usize, end as usize).into()),
+    _ => Err(format!("illegal pos: {}", pos)),
+  }
+}
+
+fn string(value: &Value, name: &str) -> Result<String, String> {
+  field(value, name)?.as_str().map(str::to_string).ok_or_else(|| format!("field {} should be a string", name))
+}
+
+fn statements(value: &Value) -> Result<Vec<Statement>, String> {
+  value.as_array().ok_or_else(|| format!("expect statements, but get {}", value))?.iter().map(statement).collect()
+}
+
+fn identifier(value: &Value) -> Result<Identifier, String> {
+  expect_kind(value, &["Identifier"])?;
+  Ok(Identifier { pos: pos(value)?, name: string(value, "name")? })
+}
+
+fn identifiers(value: &Value, name: &str) -> Result<Vec<Identifier>, String> {
+  list(value, name)?.iter().map(identifier).collect()
+}
+
+fn declarators(value: &Value) -> Result<Vec<(Identifier, Expression)>, String> {
+  list(value, "declarators")?
+    .iter()
+    .map(|declarator| {
+      expect_kind(declarator, &["Declarator"])?;
+      Ok((identifier(field(declarator, "name")?)?, expression(field(declarator, "value")?)?))
+    })
+    .collect()
+}
+
+fn statement(value: &Value) -> Result<Statement, String> {
+  let kind = match expect_kind(value, &["Empty", "Const", "Variable", "Function", "Assign", "Return", "Expression"])? {
+    "Empty" => StatementKind::Empty,
+    "Const" => StatementKind::Const(declarators(value)?),
+    "Variable" => StatementKind::Variable(declarators(value)?),
+    "Function" => StatementKind::Function(
+      identifier(field(value, "name")?)?,
+      identifiers(value, "params")?,
+      statements(field(value, "body")?)?,
+    ),
+    "Assign" => StatementKind::Assign(identifier(field(value, "name")?)?, expression(field(value, "value")?)?),
+    "Return" => StatementKind::Return(match value.get("value") {
+      None | Some(Value::Null) => None,
+      Some(value) => Some(expression(value)?),
+    }),
+    _ => StatementKind::Expression(expression(field(value, "expression")?)?),
+  };
+  Ok(Statement { pos: pos(value)?, kind })
+}
+
+fn expression(value: &Value) -> Result<Expression, String> {
+  let boxed = |name| expression(field(value, name)?).map(Box::new);
+  let kind = match expect_kind(value, &["Identifier", "Integer", "Infix", "Prefix", "Call", "Function", "If", "While"])?
+  {
+    "Identifier" => ExpressionKind::Identifier(string(value, "name")?),
+    "Integer" => ExpressionKind::Integer(
+      field(value, "value")?.as_i64().and_then(|value| isize::try_from(value).ok()).ok_or("illegal integer")?,
+    ),
+    "Infix" => {
+      let operator = string(value, "operator")?;
+      let infix = INFIXES
+        .into_iter()
+        .find(|infix| infix.to_string() == operator)
+        .ok_or_else(|| format!("unknown infix operator: {}", operator))?;
+      ExpressionKind::Infix(infix, boxed("left")?, boxed("right")?)
+    }
+    "Prefix" => {
+      let prefix = match string(value, "operator")?.as_str() {
+        "!" => Prefix::Not,
+        "-" => Prefix::Neg,
+        operator => return Err(format!("unknown prefix operator: {}", operator)),
+      };
+      ExpressionKind::Prefix(prefix, boxed("operand")?)
+    }
+    "Call" => ExpressionKind::Call(
+      boxed("callee")?,
+      list(value, "arguments")?.iter().map(expression).collect::<Result<_, _>>()?,
+    ),
+    "Function" => ExpressionKind::Function(
+      identifier(field(value, "name")?)?,
+      identifiers(value, "params")?,
+      statements(field(value, "body")?)?,
+    ),
+    "If" => ExpressionKind::If(
+      boxed("condition")?,
+      statements(field(value, "then")?)?,
+      match value.get("else") {
+        None | Some(Value::Null) => None,
+        Some(else_s) => Some(statements(else_s)?),
+      },
+    ),
+    _ => ExpressionKind::While(boxed("condition")?, statements(field(value, "body")?)?),
+  };
+  Ok(Expression { pos: pos(value)?, kind })
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use crate::{ast::AstNode, parser::Paser};
+
+  use super::{from_json, to_dot, to_json, to_sexpr};
+
+  const INPUT: &str = "var x = -1; fn f(a, b) { return a + b } if x < 0 { x = f(x, 2) } else { return }";
+
+  #[test]
+  fn test_json() {
+    let program = Paser::paser("a + 1;").unwrap();
+    assert_eq!(
+      to_json(&program),
+      json!({
+        "kind": "Program",
+        "statements": [{
+          "kind": "Expression",
+          "pos": { "begin": 0, "end": 5 },
+          "expression": {
+            "kind": "Infix",
+            "pos": { "begin": 0, "end": 5 },
+            "operator": "+",
+            "left": { "kind": "Identifier", "pos": { "begin": 0, "end": 1 }, "name": "a" },
+            "right": { "kind": "Integer", "pos": { "begin": 4, "end": 5 }, "value": 1 },
+          },
+        }],
+      })
+    );
+
+    // 导出再导入后结构与位置都不变
+    for input in [INPUT, include_str!("../../examples/fib.pl0"), include_str!("../../examples/a.pl0")] {
+      let program = Paser::paser(input).unwrap();
+      let imported = from_json(&to_json(&program)).unwrap();
+      assert_eq!(format!("{:?}", imported), format!("{:?}", program));
+      assert_eq!(imported.unparse(), program.unparse());
+    }
+  }
+
+  #[test]
+  fn test_import_errors() {
+    assert_eq!(from_json(&json!({ "kind": "Statement" })).unwrap_err(), "expect Program, but get Statement");
+    assert_eq!(from_json(&json!([])).unwrap_err(), "expect a node with kind, but get []");
+    let program = json!({ "kind": "Program", "statements": [{ "kind": "Return", "pos": { "begin": 0 } }] });
+    assert_eq!(from_json(&program).unwrap_err(), "illegal pos: {\"begin\":0}");
+    let program = json!({ "kind": "Program", "statements": [{ "kind": "Return", "pos": { "begin": 5, "end": 2 } }] });
+    assert_eq!(from_json(&program).unwrap_err(), "illegal pos: {\"begin\":5,\"end\":2}");
+    let program =
+      json!({ "kind": "Program", "statements": [{ "kind": "Expression", "pos": { "begin": 0, "end": 1 } }] });
+    assert_eq!(from_json(&program).unwrap_err(), "Expression need field expression");
+    let expression = json!({ "kind": "Prefix", "pos": { "begin": 0, "end": 1 }, "operator": "~", "operand": null });
+    let program = json!({ "kind": "Program", "statements": [{ "kind": "Return", "pos": { "begin": 0, "end": 1 }, "value": expression }] });
+    assert_eq!(from_json(&program).unwrap_err(), "unknown prefix operator: ~");
+  }
+
+  #[test]
+  fn test_sexpr() {
+    let program = Paser::paser(INPUT).unwrap();
+    assert_eq!(
+      to_sexpr(&program),
+      "(Program [\
+        (Variable [(Declarator (Identifier x) (Prefix - (Integer 1)))]) \
+        (Function (Identifier f) [(Identifier a) (Identifier b)] [(Return (Infix + (Identifier a) (Identifier b)))]) \
+        (Expression (If (Infix < (Identifier x) (Integer 0)) \
+          [(Assign (Identifier x) (Call (Identifier f) [(Identifier x) (Integer 2)]))] [(Return)]))])"
+        .replace("  ", "")
+    );
+  }
+
+  #[test]
+  fn test_dot() {
+    let dot = to_dot(&Paser::paser("while x { x -= 1 }").unwrap());
+    assert_eq!(
+      dot,
+      r#"digraph ast {
+  node [shape=box, fontname="monospace"];
+  edge [fontsize=10];
+  n0 [label="Program"];
+  n1 [label="Expression"];
+  n2 [label="While"];
+  n3 [label="Identifier x"];
+  n2 -> n3 [label="condition"];
+  n4 [label="Assign"];
+  n5 [label="Identifier x"];
+  n4 -> n5 [label="name"];
+  n6 [label="Infix -"];
+  n7 [label="Identifier x"];
+  n6 -> n7 [label="left"];
+  n8 [label="Integer 1"];
+  n6 -> n8 [label="right"];
+  n4 -> n6 [label="value"];
+  n2 -> n4 [label="body"];
+  n1 -> n2 [label="expression"];
+  n0 -> n1 [label="statements"];
+}
+"#
+    );
+  }
+}
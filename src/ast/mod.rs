@@ -0,0 +1,568 @@
+use std::fmt::Display;
+
+use crate::SpanOffset;
+
+pub mod export;
+
+pub trait AstNode {
+  fn unparse(&self) -> String;
+}
+
+#[derive(Debug, Clone)]
+pub struct Program {
+  pub statements: Vec<Statement>,
+}
+
+impl AstNode for Program {
+  fn unparse(&self) -> String {
+    self.statements.unparse()
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Statement {
+  pub pos: SpanOffset,
+  pub kind: StatementKind,
+}
+
+/// 标识符
+#[derive(Debug, Clone)]
+pub struct Identifier {
+  pub pos: SpanOffset,
+
+  pub name: String,
+}
+
+impl AstNode for Identifier {
+  fn unparse(&self) -> String {
+    self.name.clone()
+  }
+}
+
+/// 语句
+#[derive(Debug, Clone)]
+pub enum StatementKind {
+  Empty,                                                 // 空语句
+  Const(Vec<(Identifier, Expression)>),                  // 常量声明语句
+  Variable(Vec<(Identifier, Expression)>),               // 变量声明语句
+  Function(Identifier, Vec<Identifier>, Vec<Statement>), // 函数语句
+  Assign(Identifier, Expression),                        // 赋值语句
+  Return(Option<Expression>),                            // 返回语句
+  Expression(Expression),                                // 表达式语句
+}
+
+impl AstNode for [Statement] {
+  fn unparse(&self) -> String {
+    self.iter().map(AstNode::unparse).collect::<Vec<_>>().join(" ")
+  }
+}
+
+impl AstNode for Statement {
+  fn unparse(&self) -> String {
+    match &self.kind {
+      StatementKind::Empty => ";".to_string(),
+      StatementKind::Const(c) => format!(
+        "const {};",
+        c.iter().map(|(a, b)| format!("{} = {}", a.unparse(), b.unparse())).collect::<Vec<_>>().join(", ")
+      ),
+      StatementKind::Variable(vs) => format!(
+        "var {:};",
+        vs.iter().map(|(a, b)| format!("{:} = {:}", a.unparse(), b.unparse())).collect::<Vec<_>>().join(", ")
+      ),
+
+      StatementKind::Function(name, args, b) => {
+        format!(
+          "fn {}({}) {{ {} }}",
+          name.unparse(),
+          args.iter().map(AstNode::unparse).collect::<Vec<_>>().join(", "),
+          b.unparse()
+        )
+      }
+
+      StatementKind::Assign(ident, expression) => format!("{} = {};", ident.unparse(), expression.unparse()),
+
+      StatementKind::Return(Some(e)) => format!("return {:};", e.unparse()),
+      StatementKind::Return(None) => "return;".to_string(),
+      StatementKind::Expression(e) => format!("{};", e.unparse()),
+    }
+  }
+}
+
+/// 表达式
+#[derive(Debug, Clone)]
+pub struct Expression {
+  pub pos: SpanOffset,
+
+  pub kind: ExpressionKind,
+}
+
+impl AstNode for Expression {
+  fn unparse(&self) -> String {
+    match &self.kind {
+      ExpressionKind::Identifier(x) => x.to_string(),
+      ExpressionKind::Integer(x) => format!("{:}", x),
+      ExpressionKind::Infix(infix, left, right) => {
+        format!("({:} {:} {:})", left.unparse(), infix, right.unparse())
+      }
+      ExpressionKind::Prefix(prefix, e) => format!("({:}{:})", prefix, e.unparse()),
+      ExpressionKind::Call(callee, args) => {
+        format!("{:}({:})", callee.unparse(), args.iter().map(|kind| kind.unparse()).collect::<Vec<_>>().join(", "),)
+      }
+      ExpressionKind::Function(name, args, b) => format!(
+        "(fn {}({}) {{ {} }})",
+        name.unparse(),
+        args.iter().map(AstNode::unparse).collect::<Vec<_>>().join(", "),
+        b.unparse()
+      ),
+      ExpressionKind::If(condition, t, e) => {
+        let mut string = format!("if {:} {{ {:} }}", condition.unparse(), t.unparse());
+
+        if let Some(x) = e {
+          string.push_str(&format!(" else {{ {:} }}", x.unparse()))
+        }
+        string
+      }
+      ExpressionKind::While(c, b) => format!("while {:} {{ {:} }}", c.unparse(), b.unparse()),
+    }
+  }
+}
+
+/// 表达式
+#[derive(Debug, Clone)]
+pub enum ExpressionKind {
+  Identifier(String),
+  Integer(isize),
+  Infix(Infix, Box<Expression>, Box<Expression>),
+  Prefix(Prefix, Box<Expression>),
+  Call(Box<Expression>, Vec<Expression>), // 函数调用: 被调用的可以是任意求值为函数的表达式
+  Function(Identifier, Vec<Identifier>, Vec<Statement>), // 函数定义表达式, 值为闭包
+  If(Box<Expression>, Vec<Statement>, Option<Vec<Statement>>), // if 语句
+  While(Box<Expression>, Vec<Statement>), // while 语句
+}
+
+/// 前缀表达式
+#[derive(Debug, Clone)]
+pub enum Prefix {
+  Not, // 取返
+  Neg, // 取相反数
+}
+
+impl Display for Prefix {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Prefix::Not => write!(f, "!"),
+      Prefix::Neg => write!(f, "-"),
+    }
+  }
+}
+
+/// 中缀表达式
+#[derive(Debug, Clone)]
+pub enum Infix {
+  Eq,   // ==
+  Ne,   // !=
+  Lt,   // <
+  Gt,   // >
+  LtEq, // <=
+  GtEq, // >=
+
+  Add, // +
+  Sub, // -
+  Mul, // *
+  Div, // /
+}
+
+impl Display for Infix {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Infix::Eq => write!(f, "=="),
+      Infix::Ne => write!(f, "!="),
+      Infix::Lt => write!(f, "<"),
+      Infix::Gt => write!(f, ">"),
+      Infix::LtEq => write!(f, "<="),
+      Infix::GtEq => write!(f, ">="),
+      Infix::Add => write!(f, "+"),
+      Infix::Sub => write!(f, "-"),
+      Infix::Mul => write!(f, "*"),
+      Infix::Div => write!(f, "/"),
+    }
+  }
+}
+
+///
+/// 按引用遍历抽象语法树
+///
+/// 默认的方法调用对应的 walk 函数访问所有子节点, 实现时只需要重写关心的节点,
+/// 重写的方法中调用 walk 函数可以继续访问子节点
+///
+pub trait Visitor {
+  fn visit_program(&mut self, program: &Program) {
+    walk_program(self, program)
+  }
+
+  /// 函数体, if 与 while 的语句块
+  fn visit_block(&mut self, statements: &[Statement]) {
+    walk_block(self, statements)
+  }
+
+  fn visit_statement(&mut self, statement: &Statement) {
+    walk_statement(self, statement)
+  }
+
+  fn visit_expression(&mut self, expression: &Expression) {
+    walk_expression(self, expression)
+  }
+
+  /// 声明或者赋值的标识符: 常量, 变量, 函数名, 参数以及赋值语句的左部
+  fn visit_identifier(&mut self, _identifier: &Identifier) {}
+}
+
+pub fn walk_program<V: Visitor + ?Sized>(visitor: &mut V, program: &Program) {
+  program.statements.iter().for_each(|statement| visitor.visit_statement(statement));
+}
+
+pub fn walk_block<V: Visitor + ?Sized>(visitor: &mut V, statements: &[Statement]) {
+  statements.iter().for_each(|statement| visitor.visit_statement(statement));
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Statement) {
+  match &statement.kind {
+    StatementKind::Empty => {}
+    StatementKind::Const(items) | StatementKind::Variable(items) => {
+      for (ident, expression) in items {
+        visitor.visit_identifier(ident);
+        visitor.visit_expression(expression);
+      }
+    }
+    StatementKind::Function(name, args, statements) => {
+      visitor.visit_identifier(name);
+      args.iter().for_each(|arg| visitor.visit_identifier(arg));
+      visitor.visit_block(statements);
+    }
+    StatementKind::Assign(ident, expression) => {
+      visitor.visit_identifier(ident);
+      visitor.visit_expression(expression);
+    }
+    StatementKind::Return(expression) => expression.iter().for_each(|expression| visitor.visit_expression(expression)),
+    StatementKind::Expression(expression) => visitor.visit_expression(expression),
+  }
+}
+
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, expression: &Expression) {
+  match &expression.kind {
+    ExpressionKind::Identifier(_) | ExpressionKind::Integer(_) => {}
+    ExpressionKind::Infix(_, left, right) => {
+      visitor.visit_expression(left);
+      visitor.visit_expression(right);
+    }
+    ExpressionKind::Prefix(_, operand) => visitor.visit_expression(operand),
+    ExpressionKind::Call(callee, args) => {
+      visitor.visit_expression(callee);
+      args.iter().for_each(|arg| visitor.visit_expression(arg));
+    }
+    ExpressionKind::Function(name, args, statements) => {
+      visitor.visit_identifier(name);
+      args.iter().for_each(|arg| visitor.visit_identifier(arg));
+      visitor.visit_block(statements);
+    }
+    ExpressionKind::If(condition, then_s, else_s) => {
+      visitor.visit_expression(condition);
+      visitor.visit_block(then_s);
+      else_s.iter().for_each(|else_s| visitor.visit_block(else_s));
+    }
+    ExpressionKind::While(condition, statements) => {
+      visitor.visit_expression(condition);
+      visitor.visit_block(statements);
+    }
+  }
+}
+
+///
+/// 按可变引用遍历抽象语法树, 可以原地修改节点, 与 Visitor 的访问顺序相同
+///
+pub trait VisitorMut {
+  fn visit_program_mut(&mut self, program: &mut Program) {
+    walk_program_mut(self, program)
+  }
+
+  fn visit_block_mut(&mut self, statements: &mut Vec<Statement>) {
+    walk_block_mut(self, statements)
+  }
+
+  fn visit_statement_mut(&mut self, statement: &mut Statement) {
+    walk_statement_mut(self, statement)
+  }
+
+  fn visit_expression_mut(&mut self, expression: &mut Expression) {
+    walk_expression_mut(self, expression)
+  }
+
+  fn visit_identifier_mut(&mut self, _identifier: &mut Identifier) {}
+}
+
+pub fn walk_program_mut<V: VisitorMut + ?Sized>(visitor: &mut V, program: &mut Program) {
+  program.statements.iter_mut().for_each(|statement| visitor.visit_statement_mut(statement));
+}
+
+/// 参数为 Vec, 重写 visit_block_mut 时可以增删语句
+#[allow(clippy::ptr_arg)]
+pub fn walk_block_mut<V: VisitorMut + ?Sized>(visitor: &mut V, statements: &mut Vec<Statement>) {
+  statements.iter_mut().for_each(|statement| visitor.visit_statement_mut(statement));
+}
+
+pub fn walk_statement_mut<V: VisitorMut + ?Sized>(visitor: &mut V, statement: &mut Statement) {
+  match &mut statement.kind {
+    StatementKind::Empty => {}
+    StatementKind::Const(items) | StatementKind::Variable(items) => {
+      for (ident, expression) in items {
+        visitor.visit_identifier_mut(ident);
+        visitor.visit_expression_mut(expression);
+      }
+    }
+    StatementKind::Function(name, args, statements) => {
+      visitor.visit_identifier_mut(name);
+      args.iter_mut().for_each(|arg| visitor.visit_identifier_mut(arg));
+      visitor.visit_block_mut(statements);
+    }
+    StatementKind::Assign(ident, expression) => {
+      visitor.visit_identifier_mut(ident);
+      visitor.visit_expression_mut(expression);
+    }
+    StatementKind::Return(expression) => {
+      expression.iter_mut().for_each(|expression| visitor.visit_expression_mut(expression))
+    }
+    StatementKind::Expression(expression) => visitor.visit_expression_mut(expression),
+  }
+}
+
+pub fn walk_expression_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expression: &mut Expression) {
+  match &mut expression.kind {
+    ExpressionKind::Identifier(_) | ExpressionKind::Integer(_) => {}
+    ExpressionKind::Infix(_, left, right) => {
+      visitor.visit_expression_mut(left);
+      visitor.visit_expression_mut(right);
+    }
+    ExpressionKind::Prefix(_, operand) => visitor.visit_expression_mut(operand),
+    ExpressionKind::Call(callee, args) => {
+      visitor.visit_expression_mut(callee);
+      args.iter_mut().for_each(|arg| visitor.visit_expression_mut(arg));
+    }
+    ExpressionKind::Function(name, args, statements) => {
+      visitor.visit_identifier_mut(name);
+      args.iter_mut().for_each(|arg| visitor.visit_identifier_mut(arg));
+      visitor.visit_block_mut(statements);
+    }
+    ExpressionKind::If(condition, then_s, else_s) => {
+      visitor.visit_expression_mut(condition);
+      visitor.visit_block_mut(then_s);
+      else_s.iter_mut().for_each(|else_s| visitor.visit_block_mut(else_s));
+    }
+    ExpressionKind::While(condition, statements) => {
+      visitor.visit_expression_mut(condition);
+      visitor.visit_block_mut(statements);
+    }
+  }
+}
+
+///
+/// 按值变换抽象语法树, 每个方法取得节点的所有权并返回新的节点
+///
+/// 默认的方法先变换子节点 (自底向上), 语句块可以增删语句
+///
+pub trait Fold {
+  fn fold_program(&mut self, program: Program) -> Program {
+    walk_program_fold(self, program)
+  }
+
+  fn fold_block(&mut self, statements: Vec<Statement>) -> Vec<Statement> {
+    walk_block_fold(self, statements)
+  }
+
+  fn fold_statement(&mut self, statement: Statement) -> Statement {
+    walk_statement_fold(self, statement)
+  }
+
+  fn fold_expression(&mut self, expression: Expression) -> Expression {
+    walk_expression_fold(self, expression)
+  }
+
+  fn fold_identifier(&mut self, identifier: Identifier) -> Identifier {
+    identifier
+  }
+}
+
+pub fn walk_program_fold<F: Fold + ?Sized>(folder: &mut F, program: Program) -> Program {
+  Program { statements: program.statements.into_iter().map(|statement| folder.fold_statement(statement)).collect() }
+}
+
+pub fn walk_block_fold<F: Fold + ?Sized>(folder: &mut F, statements: Vec<Statement>) -> Vec<Statement> {
+  statements.into_iter().map(|statement| folder.fold_statement(statement)).collect()
+}
+
+pub fn walk_statement_fold<F: Fold + ?Sized>(folder: &mut F, statement: Statement) -> Statement {
+  let mut items = |items: Vec<(Identifier, Expression)>| {
+    items
+      .into_iter()
+      .map(|(ident, expression)| (folder.fold_identifier(ident), folder.fold_expression(expression)))
+      .collect()
+  };
+  let kind = match statement.kind {
+    StatementKind::Empty => StatementKind::Empty,
+    StatementKind::Const(constants) => StatementKind::Const(items(constants)),
+    StatementKind::Variable(variables) => StatementKind::Variable(items(variables)),
+    StatementKind::Function(name, args, statements) => StatementKind::Function(
+      folder.fold_identifier(name),
+      args.into_iter().map(|arg| folder.fold_identifier(arg)).collect(),
+      folder.fold_block(statements),
+    ),
+    StatementKind::Assign(ident, expression) => {
+      StatementKind::Assign(folder.fold_identifier(ident), folder.fold_expression(expression))
+    }
+    StatementKind::Return(expression) => {
+      StatementKind::Return(expression.map(|expression| folder.fold_expression(expression)))
+    }
+    StatementKind::Expression(expression) => StatementKind::Expression(folder.fold_expression(expression)),
+  };
+  Statement { pos: statement.pos, kind }
+}
+
+pub fn walk_expression_fold<F: Fold + ?Sized>(folder: &mut F, expression: Expression) -> Expression {
+  let kind = match expression.kind {
+    kind @ (ExpressionKind::Identifier(_) | ExpressionKind::Integer(_)) => kind,
+    ExpressionKind::Infix(infix, left, right) => {
+      ExpressionKind::Infix(infix, Box::new(folder.fold_expression(*left)), Box::new(folder.fold_expression(*right)))
+    }
+    ExpressionKind::Prefix(prefix, operand) => {
+      ExpressionKind::Prefix(prefix, Box::new(folder.fold_expression(*operand)))
+    }
+    ExpressionKind::Call(callee, args) => ExpressionKind::Call(
+      Box::new(folder.fold_expression(*callee)),
+      args.into_iter().map(|arg| folder.fold_expression(arg)).collect(),
+    ),
+    ExpressionKind::Function(name, args, statements) => ExpressionKind::Function(
+      folder.fold_identifier(name),
+      args.into_iter().map(|arg| folder.fold_identifier(arg)).collect(),
+      folder.fold_block(statements),
+    ),
+    ExpressionKind::If(condition, then_s, else_s) => ExpressionKind::If(
+      Box::new(folder.fold_expression(*condition)),
+      folder.fold_block(then_s),
+      else_s.map(|else_s| folder.fold_block(else_s)),
+    ),
+    ExpressionKind::While(condition, statements) => {
+      ExpressionKind::While(Box::new(folder.fold_expression(*condition)), folder.fold_block(statements))
+    }
+  };
+  Expression { pos: expression.pos, kind }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::parser::Paser;
+
+  use super::{
+    walk_block_fold, walk_expression, walk_expression_fold, walk_expression_mut, walk_statement, AstNode, Expression,
+    ExpressionKind, Fold, Identifier, Infix, Statement, StatementKind, Visitor, VisitorMut,
+  };
+
+  const INPUT: &str =
+    "const n = 2; var x = n * 3 + 4 * 5; fn f(a) { a + x; } while x < 10 { x = f(1 + 2); } if 0 { f(0) }";
+
+  #[test]
+  fn test_visitor() {
+    // 统计调用与声明的标识符, 不进入函数体
+    #[derive(Default)]
+    struct Counter {
+      calls: usize,
+      identifiers: Vec<String>,
+    }
+
+    impl Visitor for Counter {
+      fn visit_expression(&mut self, expression: &Expression) {
+        if let ExpressionKind::Call(..) = expression.kind {
+          self.calls += 1;
+        }
+        walk_expression(self, expression)
+      }
+
+      fn visit_identifier(&mut self, identifier: &Identifier) {
+        self.identifiers.push(identifier.name.clone());
+      }
+
+      fn visit_statement(&mut self, statement: &Statement) {
+        if let StatementKind::Function(name, ..) = &statement.kind {
+          self.visit_identifier(name);
+        } else {
+          walk_statement(self, statement)
+        }
+      }
+    }
+
+    let mut counter = Counter::default();
+    counter.visit_program(&Paser::paser(INPUT).unwrap());
+    assert_eq!(counter.calls, 2);
+    assert_eq!(counter.identifiers, ["n", "x", "f", "x"]);
+  }
+
+  #[test]
+  fn test_visitor_mut() {
+    struct Rename;
+
+    impl VisitorMut for Rename {
+      fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        if let ExpressionKind::Identifier(name) = &mut expression.kind {
+          name.make_ascii_uppercase();
+        }
+        walk_expression_mut(self, expression)
+      }
+
+      fn visit_identifier_mut(&mut self, identifier: &mut Identifier) {
+        identifier.name.make_ascii_uppercase();
+      }
+    }
+
+    let mut program = Paser::paser(INPUT).unwrap();
+    Rename.visit_program_mut(&mut program);
+    assert_eq!(
+      program.unparse(),
+      "const N = 2; var X = ((N * 3) + (4 * 5)); fn F(A) { (A + X); } while (X < 10) { X = F((1 + 2)); }; if 0 { F(0); };"
+    );
+  }
+
+  #[test]
+  fn test_fold() {
+    // 常量折叠, 并删除语句块中的空语句
+    struct ConstantFolding;
+
+    impl Fold for ConstantFolding {
+      fn fold_block(&mut self, statements: Vec<Statement>) -> Vec<Statement> {
+        let statements = walk_block_fold(self, statements);
+        statements.into_iter().filter(|statement| !matches!(statement.kind, StatementKind::Empty)).collect()
+      }
+
+      fn fold_expression(&mut self, expression: Expression) -> Expression {
+        let expression = walk_expression_fold(self, expression);
+        let value = match &expression.kind {
+          ExpressionKind::Infix(infix, left, right) => match (infix, &left.kind, &right.kind) {
+            (Infix::Add, ExpressionKind::Integer(a), ExpressionKind::Integer(b)) => a.checked_add(*b),
+            (Infix::Mul, ExpressionKind::Integer(a), ExpressionKind::Integer(b)) => a.checked_mul(*b),
+            _ => None,
+          },
+          _ => None,
+        };
+        match value {
+          Some(value) => Expression { pos: expression.pos, kind: ExpressionKind::Integer(value) },
+          None => expression,
+        }
+      }
+    }
+
+    let program = ConstantFolding.fold_program(Paser::paser(INPUT).unwrap());
+    assert_eq!(
+      program.unparse(),
+      "const n = 2; var x = ((n * 3) + 20); fn f(a) { (a + x); } while (x < 10) { x = f(3); }; if 0 { f(0); };"
+    );
+    let program = ConstantFolding.fold_program(Paser::paser("fn f() { ; 1 + 2 * 3; }").unwrap());
+    assert_eq!(program.unparse(), "fn f() { 7; }");
+  }
+}
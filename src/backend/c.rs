@@ -0,0 +1,551 @@
+use std::{
+  collections::HashSet,
+  env,
+  fmt::Write as _,
+  fs, io,
+  path::Path,
+  process::{self, Command},
+  sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+  ast::{Expression, ExpressionKind, Identifier, Infix, Prefix, Program, Statement, StatementKind},
+  vm::builtins::Builtins,
+};
+
+use super::resolve::{Access, Error, Resolver, Symbol};
+
+/// 运行时, 内联在生成的代码开头: 栈帧与闭包的表示, 内建函数与检查溢出的整数运算
+pub const RUNTIME: &str = include_str!("runtime.h");
+
+///
+/// 将抽象语法树翻译为一个独立的 C99 源文件
+///
+/// 每个 PL/0 函数翻译为一个 C 函数 (env, args, argc): env 为函数定义所在的作用域的栈帧.
+/// 被内层函数引用的作用域在堆上分配栈帧结构 struct sN (第一个成员为静态链), 其余变量为 C 的局部变量.
+/// 表达式按照虚拟机的求值顺序 (实参从右向左) 展开为临时变量, 闭包为堆上的 [标记, 函数, 栈帧].
+/// 函数对自身的尾调用翻译为跳转, 其余尾调用依赖 C 编译器的尾调用优化
+pub fn compile(program: &Program) -> Result<String, Vec<Error>> {
+  let mut generator = Generator::new(HashSet::new());
+  generator.program(program);
+
+  // 第二遍: 被内层函数引用的作用域使用栈帧结构
+  if let Some(memory) = generator.resolver.captured().filter(|_| generator.errors.is_empty()) {
+    generator = Generator::new(memory);
+    generator.program(program);
+  }
+
+  if generator.errors.is_empty() {
+    Ok(generator.module())
+  } else {
+    Err(generator.errors)
+  }
+}
+
+/// 使用系统中的 cc 将生成的 C 代码编译为可执行文件
+pub fn build(source: &str, output: &Path) -> io::Result<()> {
+  static COUNTER: AtomicUsize = AtomicUsize::new(0);
+  let dir = env::temp_dir().join(format!("pl0-c-{}-{}", process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+  fs::create_dir_all(&dir)?;
+
+  let path = dir.join("program.c");
+  fs::write(&path, source)?;
+  let result = Command::new("cc").arg("-std=c99").arg("-O2").arg("-o").arg(output).arg(&path).output();
+  fs::remove_dir_all(&dir)?;
+
+  let result = result?;
+  if !result.status.success() {
+    return Err(io::Error::other(String::from_utf8_lossy(&result.stderr).into_owned()));
+  }
+  Ok(())
+}
+
+/// 整数常量, C 中没有最小整数的字面量
+fn literal(value: isize) -> String {
+  match value {
+    isize::MIN => "INT64_MIN".to_string(),
+    _ => value.to_string(),
+  }
+}
+
+/// 正在生成的函数体
+#[derive(Default)]
+struct Body {
+  code: Vec<String>,
+  depth: usize,
+  temps: usize,
+  restart: bool, // 有对自身的尾调用, 函数开头需要标签
+}
+
+struct Generator {
+  resolver: Resolver,
+  builtins: Builtins,
+  errors: Vec<Error>,
+  functions: Vec<String>, // 生成完成的函数, 按照编号排列
+  stack: Vec<Body>,
+  arguments: usize, // 实参个数的最大值
+}
+
+impl Generator {
+  fn new(memory: HashSet<usize>) -> Self {
+    Generator {
+      resolver: Resolver::new(memory),
+      builtins: Builtins::new(),
+      errors: vec![],
+      functions: vec![],
+      stack: vec![],
+      arguments: 0,
+    }
+  }
+
+  fn module(&self) -> String {
+    let mut out = String::from("/* 由 pl0 生成 */\n");
+    out.push_str(RUNTIME);
+    out.push('\n');
+    for (id, scope) in self.resolver.scopes.iter().enumerate().filter(|(_, scope)| scope.memory) {
+      writeln!(out, "struct s{} {{\n  struct pl0_frame frame;", id).unwrap();
+      scope.variables.iter().for_each(|variable| writeln!(out, "  int64_t v{};", variable).unwrap());
+      out.push_str("};\n\n");
+    }
+    writeln!(out, "static int64_t pl0_args[{}];\n", self.arguments.max(1)).unwrap();
+    for id in 1..self.functions.len() {
+      writeln!(out, "static int64_t f{}(struct pl0_frame *env, const int64_t *args, int64_t argc);", id).unwrap();
+    }
+    self.functions[1..].iter().chain(&self.functions[..1]).for_each(|function| write!(out, "\n{}", function).unwrap());
+    out
+  }
+
+  fn program(&mut self, program: &Program) {
+    self.functions.push(String::new());
+    self.stack.push(Body::default());
+    self.enter_frame(0);
+    let value = self.statements(&program.statements, false);
+    if let Some(value) = value {
+      self.emit(format!("return {};", value));
+    }
+    self.finish_function(0, "static int64_t pl0_main(void) {".to_string());
+  }
+
+  /// 生成一行代码, 以 { 结尾的行之后缩进, 以 } 开头的行之前取消缩进
+  fn emit(&mut self, line: impl Into<String>) {
+    let line = line.into();
+    let body = self.stack.last_mut().unwrap();
+    if line.starts_with('}') {
+      body.depth -= 1;
+    }
+    body.code.push(format!("{}{}", "  ".repeat(body.depth + 1), line));
+    if line.ends_with('{') {
+      body.depth += 1;
+    }
+  }
+
+  /// 新的临时变量, 初始值为 value
+  fn temp(&mut self, value: impl Into<String>) -> String {
+    let body = self.stack.last_mut().unwrap();
+    let name = format!("t{}", body.temps);
+    body.temps += 1;
+    self.emit(format!("int64_t {} = {};", name, value.into()));
+    name
+  }
+
+  fn finish_function(&mut self, id: usize, header: String) {
+    let body = self.stack.pop().unwrap();
+    let mut out = header;
+    out.push('\n');
+    if body.restart {
+      out.push_str("start:;\n");
+    }
+    body.code.iter().for_each(|line| writeln!(out, "{}", line).unwrap());
+    out.push_str("}\n");
+    self.functions[id] = out;
+  }
+
+  /// 进入作用域时在堆上分配栈帧结构
+  fn enter_frame(&mut self, scope: usize) {
+    if !self.resolver.scopes[scope].memory {
+      return;
+    }
+    self.emit(format!("struct s{0} *s{0} = pl0_alloc(sizeof(struct s{0}));", scope));
+    let parent = self.resolver.parent_frame(scope);
+    let parent = self.environment(parent);
+    self.emit(format!("s{}->frame.link = {};", scope, parent));
+  }
+
+  /// 栈帧指针 (struct pl0_frame *)
+  fn frame(&self, access: Access) -> String {
+    match access {
+      Access::Local(scope) => format!("&s{}->frame", scope),
+      Access::Env(hops) => format!("env{}", "->link".repeat(hops)),
+    }
+  }
+
+  fn environment(&self, access: Option<Access>) -> String {
+    match access {
+      Some(access) => self.frame(access),
+      None => "NULL".to_string(),
+    }
+  }
+
+  /// 变量的左值
+  fn variable(&self, variable: usize) -> String {
+    if !self.resolver.in_memory(variable) {
+      return format!("v{}", variable);
+    }
+    let scope = self.resolver.variables[variable].scope;
+    match self.resolver.access(scope) {
+      Access::Local(scope) => format!("s{}->v{}", scope, variable),
+      access => format!("((struct s{} *){})->v{}", scope, self.frame(access), variable),
+    }
+  }
+
+  /// 定义变量并初始化
+  fn define(&mut self, variable: usize, value: &str) {
+    if self.resolver.in_memory(variable) {
+      self.emit(format!("{} = {};", self.variable(variable), value));
+    } else {
+      self.emit(format!("int64_t v{} = {};", variable, value));
+    }
+  }
+
+  ///
+  /// 语句序列, 值为最后一条有值的语句的值 (默认为 0), tail 为真时最后一条表达式语句处于尾部位置.
+  /// 所有路径都已经返回时没有值
+  ///
+  fn statements(&mut self, statements: &[Statement], tail: bool) -> Option<String> {
+    let mut value = Some("0".to_string());
+    for (index, statement) in statements.iter().enumerate() {
+      let produce =
+        !matches!(statement.kind, StatementKind::Empty | StatementKind::Const(_) | StatementKind::Function(..));
+      let result = self.statement(statement, tail && index + 1 == statements.len());
+      if produce {
+        value = result;
+      }
+    }
+    value
+  }
+
+  fn statement(&mut self, statement: &Statement, tail: bool) -> Option<String> {
+    match &statement.kind {
+      StatementKind::Empty => {}
+      StatementKind::Const(constants) => {
+        for (ident, e) in constants {
+          match e.kind {
+            ExpressionKind::Integer(value) => self.resolver.constant(&ident.name, value),
+            _ => self.errors.push((format!("only integer can assign to constant, but get {:?}", e.kind), e.pos)),
+          }
+        }
+      }
+
+      StatementKind::Variable(variables) => {
+        let mut value = String::new();
+        for (ident, e) in variables {
+          // 初始值中不能引用正在定义的变量
+          value = self.expression(e);
+          let variable = self.resolver.declare(&ident.name);
+          self.define(variable, &value);
+        }
+        return Some(value);
+      }
+
+      StatementKind::Function(ident, args, statements) => {
+        self.function(ident, args, statements);
+      }
+
+      StatementKind::Assign(ident, e) => match self.resolver.find_variable(&ident.name) {
+        Some(variable) => {
+          let value = self.expression(e);
+          self.emit(format!("{} = {};", self.variable(variable), value));
+          return Some(value);
+        }
+        None => {
+          self.errors.push((format!("variable is undefined: {:}", ident.name), statement.pos));
+          return Some("0".to_string());
+        }
+      },
+
+      StatementKind::Return(e) => {
+        let value = match e {
+          Some(e) if self.resolver.function() > 0 => self.tail_expression(e)?,
+          Some(e) => self.expression(e),
+          None => "0".to_string(),
+        };
+        self.emit(format!("return {};", value));
+        return None;
+      }
+
+      StatementKind::Expression(e) if tail => return self.tail_expression(e),
+      StatementKind::Expression(e) => return Some(self.expression(e)),
+    }
+    Some("0".to_string())
+  }
+
+  /// 函数定义, 函数名绑定在当前作用域中
+  fn function(&mut self, ident: &Identifier, args: &[Identifier], statements: &[Statement]) -> Option<usize> {
+    if self.builtins.lookup(&ident.name).is_some() {
+      self
+        .errors
+        .push((format!("unable define funcation name as same as builtins function: {}", ident.name), ident.pos));
+      return None;
+    }
+
+    let id = self.resolver.enter_function(&ident.name, args);
+    self.functions.push(String::new());
+    self.stack.push(Body::default());
+
+    // 实参个数不足时报错, 多余的实参被忽略
+    if !args.is_empty() {
+      self.emit(format!("if (argc < {}) {{", args.len()));
+      self.emit("pl0_fail(\"missing arguments\");");
+      self.emit("}");
+    }
+    let scope = self.resolver.functions[id].scope;
+    self.enter_frame(scope);
+    for (index, variable) in self.resolver.functions[id].params.clone().into_iter().enumerate() {
+      self.define(variable, &format!("args[{}]", index));
+    }
+
+    if let Some(value) = self.statements(statements, true) {
+      self.emit(format!("return {};", value));
+    }
+    self.resolver.leave_function();
+
+    let header = format!(
+      "/* {} */\nstatic int64_t f{}(struct pl0_frame *env, const int64_t *args, int64_t argc) {{",
+      self.resolver.functions[id].name, id
+    );
+    self.finish_function(id, header);
+    Some(id)
+  }
+
+  /// 闭包: 函数与函数定义所在的栈帧
+  fn closure(&mut self, function: usize) -> String {
+    let environment = self.environment(self.resolver.environment(function));
+    self.temp(format!("pl0_closure(f{}, {})", function, environment))
+  }
+
+  fn tail_expression(&mut self, expression: &Expression) -> Option<String> {
+    match &expression.kind {
+      ExpressionKind::Call(callee, args) => self.call(callee, args, true),
+      ExpressionKind::If(condition, then_s, else_s) => self.condition(condition, then_s, else_s, true),
+      _ => Some(self.expression(expression)),
+    }
+  }
+
+  /// 表达式的值: 临时变量或者常量
+  fn expression(&mut self, expression: &Expression) -> String {
+    match &expression.kind {
+      ExpressionKind::Identifier(name) => match self.resolver.find(name) {
+        Some(Symbol::Constant(value)) => literal(value),
+        Some(Symbol::Variable(variable)) => self.temp(self.variable(variable)),
+        Some(Symbol::Function(function)) => self.closure(function),
+        None => {
+          self.errors.push((format!("identifier is not define: {}", name), expression.pos));
+          "0".to_string()
+        }
+      },
+
+      ExpressionKind::Integer(value) => literal(*value),
+
+      ExpressionKind::Infix(infix, left, right) => {
+        let left = self.expression(left);
+        let right = self.expression(right);
+        let value = match infix {
+          Infix::Add => format!("pl0_add({}, {})", left, right),
+          Infix::Sub => format!("pl0_sub({}, {})", left, right),
+          Infix::Mul => format!("pl0_mul({}, {})", left, right),
+          Infix::Div => format!("pl0_div({}, {})", left, right),
+          Infix::Eq => format!("{} == {}", left, right),
+          Infix::Ne => format!("{} != {}", left, right),
+          Infix::Lt => format!("{} < {}", left, right),
+          Infix::Gt => format!("{} > {}", left, right),
+          Infix::LtEq => format!("{} <= {}", left, right),
+          Infix::GtEq => format!("{} >= {}", left, right),
+        };
+        self.temp(value)
+      }
+
+      ExpressionKind::Prefix(Prefix::Not, e) => {
+        let value = self.expression(e);
+        self.temp(format!("{} == 0", value))
+      }
+      ExpressionKind::Prefix(Prefix::Neg, e) => {
+        let value = self.expression(e);
+        self.temp(format!("pl0_sub(0, {})", value))
+      }
+
+      ExpressionKind::Call(callee, args) => self.call(callee, args, false).unwrap_or_else(|| "0".to_string()),
+
+      ExpressionKind::Function(ident, args, statements) => match self.function(ident, args, statements) {
+        Some(function) => self.closure(function),
+        None => "0".to_string(),
+      },
+
+      ExpressionKind::If(condition, then_s, else_s) => {
+        self.condition(condition, then_s, else_s, false).unwrap_or_else(|| "0".to_string())
+      }
+
+      ExpressionKind::While(condition, statements) => {
+        self.emit("for (;;) {");
+        let value = self.expression(condition);
+        self.emit(format!("if ({} == 0) {{", value));
+        self.emit("break;");
+        self.emit("}");
+        self.block(statements, false);
+        self.emit("}");
+        "0".to_string()
+      }
+    }
+  }
+
+  /// if 表达式, 两个分支的值保存在同一个临时变量中
+  fn condition(
+    &mut self,
+    condition: &Expression,
+    then_s: &[Statement],
+    else_s: &Option<Vec<Statement>>,
+    tail: bool,
+  ) -> Option<String> {
+    let condition = self.expression(condition);
+    let body = self.stack.last_mut().unwrap();
+    let result = format!("t{}", body.temps);
+    body.temps += 1;
+    self.emit(format!("int64_t {};", result));
+
+    self.emit(format!("if ({} != 0) {{", condition));
+    let then_value = self.block(then_s, tail);
+    if let Some(value) = &then_value {
+      self.emit(format!("{} = {};", result, value));
+    }
+    self.emit("} else {");
+    let else_value = match else_s {
+      Some(else_s) => self.block(else_s, tail),
+      None => Some("0".to_string()),
+    };
+    if let Some(value) = &else_value {
+      self.emit(format!("{} = {};", result, value));
+    }
+    self.emit("}");
+    (then_value.is_some() || else_value.is_some()).then_some(result)
+  }
+
+  /// 语句块, 语句块中定义的名字离开语句块后不可见
+  fn block(&mut self, statements: &[Statement], tail: bool) -> Option<String> {
+    let scope = self.resolver.enter_block();
+    self.enter_frame(scope);
+    let value = self.statements(statements, tail);
+    self.resolver.leave_block();
+    value
+  }
+
+  ///
+  /// 函数调用: 实参逆序求值, 间接调用时再对被调用的表达式求值. 全部求值之后才写入 pl0_args,
+  /// 被调函数在执行其他代码之前取出实参
+  ///
+  fn call(&mut self, callee: &Expression, args: &[Expression], tail: bool) -> Option<String> {
+    let tail = tail && self.resolver.function() > 0;
+    if let ExpressionKind::Identifier(name) = &callee.kind {
+      if let Some(id) = self.builtins.lookup(name) {
+        let values = self.arguments(args);
+        self.pass(&values);
+        let call = format!("pl0_{}(pl0_args, {})", self.builtins.symbol(id), args.len());
+        return Some(self.temp(call));
+      }
+
+      if let Some(Symbol::Function(function)) = self.resolver.find(name) {
+        let values = self.arguments(args);
+        let environment = self.environment(self.resolver.environment(function));
+        self.pass(&values);
+        // 对自身的尾调用: 重新开始执行函数
+        if tail && function == self.resolver.function() && args.len() >= self.resolver.functions[function].params.len()
+        {
+          self.emit("args = pl0_args;");
+          self.emit(format!("argc = {};", args.len()));
+          self.emit("goto start;");
+          self.stack.last_mut().unwrap().restart = true;
+          return None;
+        }
+        return self.invoke(format!("f{}({}, pl0_args, {})", function, environment, args.len()), tail);
+      }
+    }
+
+    // 间接调用: 检查是否为闭包
+    let values = self.arguments(args);
+    let value = self.expression(callee);
+    let body = self.stack.last_mut().unwrap();
+    let closure = format!("c{}", body.temps);
+    body.temps += 1;
+    self.emit(format!("struct pl0_closure *{} = pl0_callee({});", closure, value));
+    self.pass(&values);
+    self.invoke(format!("{0}->code({0}->env, pl0_args, {1})", closure, args.len()), tail)
+  }
+
+  fn invoke(&mut self, call: String, tail: bool) -> Option<String> {
+    if tail {
+      self.emit(format!("return {};", call));
+      None
+    } else {
+      Some(self.temp(call))
+    }
+  }
+
+  /// 逆序求值实参, 按照实参的顺序返回
+  fn arguments(&mut self, args: &[Expression]) -> Vec<String> {
+    let mut values = args.iter().rev().map(|e| self.expression(e)).collect::<Vec<_>>();
+    values.reverse();
+    values
+  }
+
+  fn pass(&mut self, values: &[String]) {
+    self.arguments = self.arguments.max(values.len());
+    for (index, value) in values.iter().enumerate() {
+      self.emit(format!("pl0_args[{}] = {};", index, value));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{env, fs, process::Command};
+
+  use crate::{
+    ast::Program,
+    backend::tests::{self, check, Backend, Report},
+  };
+
+  use super::{build, compile};
+
+  /// 编译为可执行文件并运行, 输出程序的返回值. 系统中没有 cc 时返回 None
+  fn run(program: &Program, name: &str) -> Result<Option<String>, String> {
+    let source = format!("#define PL0_PRINT_RESULT\n{}", compile(program).map_err(|err| format!("{:?}", err))?);
+    if Command::new("cc").arg("--version").output().is_err() {
+      return Ok(None);
+    }
+    let path = env::temp_dir().join(format!("pl0-c-{}-{}", std::process::id(), name));
+    build(&source, &path).unwrap_or_else(|err| panic!("{}: {}\n{}", name, err, source));
+    let output = Command::new(&path).output().unwrap();
+    fs::remove_file(&path).unwrap();
+    Ok(Some(String::from_utf8(output.stdout).unwrap()))
+  }
+
+  const C: Backend = Backend { report: Report::Result, run };
+
+  #[test]
+  fn test_examples() {
+    tests::examples(&C);
+  }
+
+  #[test]
+  fn test_programs() {
+    tests::programs(&C);
+    // 深度一百万的尾调用: 对自身的尾调用翻译为跳转, 间接的尾调用依赖 C 编译器的尾调用优化
+    let source = "fn sum(n, s) { if n == 0 { s } else { sum(n - 1, s + n) } }
+fn count(f, n) { if n == 0 { 0 } else { f(f, n - 1) } }
+println(sum(1000000, 0), count(count, 1000000))";
+    check(&C, source, "tail-call");
+  }
+
+  #[test]
+  fn test_differential() {
+    tests::differential(&C);
+  }
+}
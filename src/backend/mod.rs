@@ -0,0 +1,104 @@
+//! 本地代码后端: 将虚拟机指令或者抽象语法树翻译为其他目标的代码
+
+pub mod c;
+pub mod pcode;
+mod resolve;
+pub mod wat;
+pub mod x86_64;
+
+/// 各个后端共用的测试: 比较后端生成的程序与虚拟机的输出
+#[cfg(test)]
+pub(crate) mod tests {
+  use std::fs;
+
+  use crate::{ast::Program, compiler::Compiler, generator::Generator, parser::Paser, vm::VM};
+
+  /// 后端报告程序结果的方式
+  pub(crate) enum Report {
+    Address, // 只输出运行时错误, 带有指令地址 (与 pl0 run 相同)
+    Result,  // 最后输出返回值或者运行时错误, 运行时错误没有指令地址
+  }
+
+  pub(crate) struct Backend {
+    pub report: Report,
+    /// 编译并运行程序, 返回程序的输出; 编译错误时返回 Err, 系统中没有需要的工具时返回 None
+    pub run: fn(&Program, &str) -> Result<Option<String>, String>,
+  }
+
+  /// 虚拟机的输出, 按照后端报告结果的方式加上返回值或者运行时错误
+  fn expect(backend: &Backend, program: &Program) -> String {
+    let codes = Compiler::compile(program).unwrap();
+    let mut out = vec![];
+    let result = VM::execute_with_output(&codes, &mut out);
+    let mut out = String::from_utf8(out).unwrap();
+    match (&backend.report, result) {
+      (Report::Address, Ok(_)) => {}
+      (Report::Address, Err((err, ip))) => out += &format!("运行时错误: {} ({:04X}H)\n", err, ip),
+      (Report::Result, Ok(value)) => out += &format!("{}\n", value),
+      (Report::Result, Err((err, _))) => out += &format!("运行时错误: {}\n", err),
+    }
+    out
+  }
+
+  fn check_program(backend: &Backend, program: &Program, name: &str) {
+    if let Some(output) = (backend.run)(program, name).unwrap_or_else(|err| panic!("{}: {}", name, err)) {
+      assert_eq!(output, expect(backend, program), "{}", name);
+    }
+  }
+
+  pub(crate) fn check(backend: &Backend, source: &str, name: &str) {
+    check_program(backend, &Paser::paser(source).unwrap(), name);
+  }
+
+  /// examples 中的程序, 虚拟机的编译器拒绝的程序后端同样拒绝
+  pub(crate) fn examples(backend: &Backend) {
+    let mut paths = fs::read_dir("examples").unwrap().map(|entry| entry.unwrap().path()).collect::<Vec<_>>();
+    paths.sort();
+    for path in paths {
+      let source = fs::read_to_string(&path).unwrap();
+      // 部分示例中故意包含错误
+      let Ok(program) = Paser::paser(&source) else { continue };
+      let name = path.file_stem().unwrap().to_string_lossy().to_string();
+      if Compiler::compile(&program).is_err() {
+        assert!((backend.run)(&program, &name).is_err(), "{}", path.display());
+        continue;
+      }
+      check_program(backend, &program, &name);
+    }
+  }
+
+  /// 递归, 闭包与静态链, 尾调用, 堆上的作用域, 以及各种运行时错误
+  pub(crate) fn programs(backend: &Backend) {
+    check(
+      backend,
+      "fn fib(n) { if n <= 1 { n } else { fib(n - 1) + fib(n - 2) } } println(fib(20)); helloworld(1, 2)",
+      "fib",
+    );
+    let source = "fn counter() { var n = 0; fn inc() { n += 1 } inc }
+var c = counter(), d = counter();
+c(); c(); d();
+println(c(), d());
+fn sum(n, s) { if n == 0 { s } else { sum(n - 1, s + n) } }
+println(sum(100000, 0))";
+    check(backend, source, "closure");
+    let source = "fn make(x) { var r = 0; if x > 0 { var k = x * 2; fn h(y) { k + y } r = h }; r }
+var g = make(4), z = make(0);
+println(g(1), g(2));
+z(1)";
+    check(backend, source, "scope");
+    let source =
+      "var a = 1; fn f(x) { fn g(y) { fn h() { a + x + y } h() } g(x * 10) } println(f(2), f(3)); a = 5; f(1)";
+    check(backend, source, "chain");
+    check(backend, "var i = 0, s = 0; while i < 10 { i += 1; if i / 2 * 2 == i { s += i } }; print(s, i); s", "while");
+    check(backend, "var x = 1; print(x / (x - 1))", "division");
+    check(backend, "var x = 9223372036854775807; println(x * 2)", "overflow");
+    check(backend, "var f = 5; f(1)", "illegal");
+  }
+
+  /// 随机生成的程序
+  pub(crate) fn differential(backend: &Backend) {
+    for seed in 0..30 {
+      check_program(backend, &Generator::new(seed).program(), &format!("seed-{}", seed));
+    }
+  }
+}
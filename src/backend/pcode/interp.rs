@@ -0,0 +1,234 @@
+use std::{
+  fmt::Display,
+  io::{BufRead, Read, Write},
+};
+
+use crate::vm::RuntimeError;
+
+use super::{opr, Fct, Instruction};
+
+/// 栈的最大长度
+const STACK_LIMIT: usize = 1 << 20;
+
+/// 执行过程中的错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trap {
+  Runtime(RuntimeError), // 与虚拟机相同的运行时错误
+  Message(String),       // 指令不合法, 例如未知的子功能码或者访问栈外的地址
+}
+
+impl Display for Trap {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Trap::Runtime(err) => write!(f, "{}", err),
+      Trap::Message(message) => write!(f, "{}", message),
+    }
+  }
+}
+
+///
+/// 经典 PL/0 的解释程序, 与教科书相同从地址 0 开始执行, 返回到地址 0 时停止
+///
+/// 主程序的栈帧从 0 开始, 三个链接单元都为 0. 算术运算检查溢出, opr 0 16 从 input 读入以空白分隔的整数,
+/// 读取失败时为 0
+///
+/// 出错时返回错误以及发生错误的指令地址
+pub fn run(codes: &[Instruction], input: &mut dyn BufRead, out: &mut dyn Write) -> Result<(), (Trap, usize)> {
+  let mut machine = Machine { s: vec![0; 3], t: 0, b: 0 };
+  let mut p = 0;
+  loop {
+    let ip = p;
+    let &Instruction { f, l, a } = codes.get(p).ok_or((Trap::Runtime(RuntimeError::IllegalFunctionAddress(p)), p))?;
+    p += 1;
+    machine.execute(f, l, a, &mut p, input, out).map_err(|trap| (trap, ip))?;
+    if p == 0 {
+      return Ok(());
+    }
+  }
+}
+
+/// 数据栈, t 为栈顶 (第一个空闲的位置), b 为当前栈帧的基地址
+struct Machine {
+  s: Vec<isize>,
+  t: usize,
+  b: usize,
+}
+
+impl Machine {
+  fn execute(
+    &mut self,
+    f: Fct,
+    l: usize,
+    a: isize,
+    p: &mut usize,
+    input: &mut dyn BufRead,
+    out: &mut dyn Write,
+  ) -> Result<(), Trap> {
+    match f {
+      Fct::Lit => self.push(a)?,
+      Fct::Lod => {
+        let address = self.address(l, a)?;
+        self.push(self.s[address])?;
+      }
+      Fct::Sto => {
+        let (address, value) = (self.address(l, a)?, self.pop()?);
+        self.s[address] = value;
+      }
+      Fct::Cal => {
+        let base = self.base(l)?;
+        self.reserve(self.t + 3)?;
+        self.s[self.t..self.t + 3].copy_from_slice(&[base as isize, self.b as isize, *p as isize]);
+        self.b = self.t;
+        *p = jump(a)?;
+      }
+      Fct::Int => {
+        let t = (self.t as isize).checked_add(a).filter(|t| *t >= 0).ok_or_else(|| message("stack underflow"))?;
+        self.reserve(t as usize)?;
+        self.t = t as usize;
+      }
+      Fct::Jmp => *p = jump(a)?,
+      Fct::Jpc => {
+        if self.pop()? == 0 {
+          *p = jump(a)?;
+        }
+      }
+      Fct::Opr => self.operate(a, p, input, out)?,
+    }
+    Ok(())
+  }
+
+  fn operate(&mut self, a: isize, p: &mut usize, input: &mut dyn BufRead, out: &mut dyn Write) -> Result<(), Trap> {
+    let overflow = || Trap::Runtime(RuntimeError::Overflow);
+    match a {
+      opr::RET => {
+        self.t = self.b;
+        *p = jump(self.s[self.b + 2])?;
+        self.b = usize::try_from(self.s[self.b + 1]).map_err(|_| message("illegal dynamic link"))?;
+      }
+      opr::NEG => {
+        let value = self.pop()?;
+        self.push(value.checked_neg().ok_or_else(overflow)?)?;
+      }
+      opr::ODD => {
+        let value = self.pop()?;
+        self.push((value % 2 != 0) as isize)?;
+      }
+      opr::WRT => {
+        let value = self.pop()?;
+        write!(out, "{}", value).unwrap();
+      }
+      opr::WRL => writeln!(out).unwrap(),
+      opr::RED => {
+        out.flush().unwrap();
+        let word = input
+          .bytes()
+          .map_while(Result::ok)
+          .skip_while(u8::is_ascii_whitespace)
+          .take_while(|byte| !byte.is_ascii_whitespace())
+          .collect::<Vec<_>>();
+        self.push(String::from_utf8_lossy(&word).parse().unwrap_or(0))?;
+      }
+      opr::ADD..=opr::DIV | opr::EQL..=opr::LEQ => {
+        let (right, left) = (self.pop()?, self.pop()?);
+        let value = match a {
+          opr::ADD => left.checked_add(right).ok_or_else(overflow)?,
+          opr::SUB => left.checked_sub(right).ok_or_else(overflow)?,
+          opr::MUL => left.checked_mul(right).ok_or_else(overflow)?,
+          opr::DIV if right == 0 => return Err(Trap::Runtime(RuntimeError::DivisionByZero)),
+          opr::DIV => left.checked_div(right).ok_or_else(overflow)?,
+          opr::EQL => (left == right) as isize,
+          opr::NEQ => (left != right) as isize,
+          opr::LSS => (left < right) as isize,
+          opr::GEQ => (left >= right) as isize,
+          opr::GTR => (left > right) as isize,
+          _ => (left <= right) as isize,
+        };
+        self.push(value)?;
+      }
+      _ => return Err(message(&format!("unknown operation: opr 0 {}", a))),
+    }
+    Ok(())
+  }
+
+  /// 沿静态链向上 l 层的栈帧基地址
+  fn base(&self, l: usize) -> Result<usize, Trap> {
+    let mut b = self.b;
+    for _ in 0..l {
+      b = usize::try_from(self.s[b]).ok().filter(|b| b + 3 <= self.t).ok_or_else(|| message("illegal static link"))?;
+    }
+    Ok(b)
+  }
+
+  fn address(&self, l: usize, a: isize) -> Result<usize, Trap> {
+    let address = self.base(l)? as isize + a;
+    (0..self.t as isize).contains(&address).then_some(address as usize).ok_or_else(|| message("address out of stack"))
+  }
+
+  /// 保证栈中至少有 len 个单元
+  fn reserve(&mut self, len: usize) -> Result<(), Trap> {
+    if len > STACK_LIMIT {
+      return Err(Trap::Runtime(RuntimeError::StackOverflow));
+    }
+    if self.s.len() < len {
+      self.s.resize(len, 0);
+    }
+    Ok(())
+  }
+
+  fn push(&mut self, value: isize) -> Result<(), Trap> {
+    self.reserve(self.t + 1)?;
+    self.s[self.t] = value;
+    self.t += 1;
+    Ok(())
+  }
+
+  fn pop(&mut self) -> Result<isize, Trap> {
+    if self.t == 0 {
+      return Err(message("stack underflow"));
+    }
+    self.t -= 1;
+    Ok(self.s[self.t])
+  }
+}
+
+fn message(message: &str) -> Trap {
+  Trap::Message(message.to_string())
+}
+
+fn jump(a: isize) -> Result<usize, Trap> {
+  usize::try_from(a).map_err(|_| Trap::Runtime(RuntimeError::IllegalFunctionAddress(a as usize)))
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{backend::pcode::parse, vm::RuntimeError};
+
+  use super::{run, Trap};
+
+  fn execute(text: &str, input: &str) -> (String, Result<(), Trap>) {
+    let codes = parse(text).unwrap();
+    let mut out = vec![];
+    let result = run(&codes, &mut input.as_bytes(), &mut out).map_err(|(trap, _)| trap);
+    (String::from_utf8(out).unwrap(), result)
+  }
+
+  #[test]
+  fn test_run() {
+    // 读入两个数, 输出和与奇偶
+    let text = "int 0 5\nopr 0 16\nsto 0 3\nopr 0 16\nsto 0 4\nlod 0 3\nlod 0 4\nopr 0 2\nopr 0 14\nopr 0 15\nlod 0 3\nopr 0 6\nopr 0 14\nopr 0 15\nopr 0 0";
+    assert_eq!(execute(text, " 40\n2 "), ("42\n0\n".to_string(), Ok(())));
+    assert_eq!(execute(text, "-3"), ("-3\n1\n".to_string(), Ok(())));
+  }
+
+  #[test]
+  fn test_traps() {
+    assert_eq!(
+      execute("int 0 3\nlit 0 1\nlit 0 0\nopr 0 5\nopr 0 0", "").1,
+      Err(Trap::Runtime(RuntimeError::DivisionByZero))
+    );
+    assert_eq!(execute("jmp 0 1\nint 0 3\ncal 0 1", "").1, Err(Trap::Runtime(RuntimeError::StackOverflow)));
+    assert_eq!(execute("int 0 3\nopr 0 7", "").1, Err(Trap::Message("unknown operation: opr 0 7".to_string())));
+    assert_eq!(execute("int 0 3\nlod 0 5", "").1, Err(Trap::Message("address out of stack".to_string())));
+    assert_eq!(execute("jmp 0 9", "").1, Err(Trap::Runtime(RuntimeError::IllegalFunctionAddress(9))));
+  }
+}
@@ -0,0 +1,541 @@
+use std::{collections::HashSet, fmt::Display, str::FromStr};
+
+use crate::{
+  ast::{AstNode, Expression, ExpressionKind, Identifier, Infix, Prefix, Program, Statement, StatementKind},
+  vm::builtins::{self, Builtins},
+  SpanOffset,
+};
+
+use super::resolve::{Error, Resolver, Symbol};
+
+pub mod interp;
+
+/// 经典 P-code 的功能码
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fct {
+  Lit, // 将常量 a 压入栈顶
+  Opr, // 运算, a 为子功能码
+  Lod, // 将层差为 l, 偏移为 a 的变量压入栈顶
+  Sto, // 将栈顶保存到层差为 l, 偏移为 a 的变量
+  Cal, // 调用地址为 a 的过程, l 为调用处与过程定义处的层差
+  Int, // 栈顶指针增加 a
+  Jmp, // 无条件跳转到 a
+  Jpc, // 栈顶为 0 时跳转到 a
+}
+
+const FCT_NAMES: [(Fct, &str); 8] = [
+  (Fct::Lit, "lit"),
+  (Fct::Opr, "opr"),
+  (Fct::Lod, "lod"),
+  (Fct::Sto, "sto"),
+  (Fct::Cal, "cal"),
+  (Fct::Int, "int"),
+  (Fct::Jmp, "jmp"),
+  (Fct::Jpc, "jpc"),
+];
+
+/// opr 指令的子功能码, 与教科书中的解释程序相同, 14 到 16 为输入输出的扩展
+pub mod opr {
+  pub const RET: isize = 0; // 过程返回
+  pub const NEG: isize = 1;
+  pub const ADD: isize = 2;
+  pub const SUB: isize = 3;
+  pub const MUL: isize = 4;
+  pub const DIV: isize = 5;
+  pub const ODD: isize = 6;
+  pub const EQL: isize = 8;
+  pub const NEQ: isize = 9;
+  pub const LSS: isize = 10;
+  pub const GEQ: isize = 11;
+  pub const GTR: isize = 12;
+  pub const LEQ: isize = 13;
+  pub const WRT: isize = 14; // 输出栈顶并弹出
+  pub const WRL: isize = 15; // 输出换行
+  pub const RED: isize = 16; // 读入一个整数压入栈顶
+}
+
+/// 一条指令, 文本格式为 f l a
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+  pub f: Fct,
+  pub l: usize,
+  pub a: isize,
+}
+
+impl Display for Instruction {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let name = FCT_NAMES.iter().find(|(fct, _)| *fct == self.f).unwrap().1;
+    write!(f, "{} {} {}", name, self.l, self.a)
+  }
+}
+
+impl FromStr for Instruction {
+  type Err = String;
+
+  /// 解析 f l a, 允许以指令地址开头
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut words = s.split_whitespace().collect::<Vec<_>>();
+    if words.len() == 4 && words[0].parse::<usize>().is_ok() {
+      words.remove(0);
+    }
+    let [name, l, a] = words[..] else {
+      return Err(format!("expect f l a, but get {}", s.trim()));
+    };
+    let f = FCT_NAMES
+      .iter()
+      .find(|(_, item)| item.eq_ignore_ascii_case(name))
+      .map(|(fct, _)| *fct)
+      .ok_or_else(|| format!("unknown function code: {}", name))?;
+    let l = l.parse().map_err(|_| format!("invalid level: {}", l))?;
+    let a = a.parse().map_err(|_| format!("invalid address: {}", a))?;
+    Ok(Instruction { f, l, a })
+  }
+}
+
+/// 每行一条指令
+pub fn listing(codes: &[Instruction]) -> String {
+  codes.iter().map(|code| format!("{}\n", code)).collect()
+}
+
+/// 解析 listing 输出的文本, 忽略空行
+pub fn parse(text: &str) -> Result<Vec<Instruction>, String> {
+  text
+    .lines()
+    .enumerate()
+    .filter(|(_, line)| !line.trim().is_empty())
+    .map(|(index, line)| line.parse().map_err(|err| format!("line {}: {}", index + 1, err)))
+    .collect()
+}
+
+///
+/// 将抽象语法树翻译为经典 PL/0 的 P-code
+///
+/// 栈帧与教科书相同为 [静态链, 动态链, 返回地址, 变量...], 语句块中的变量也分配在所在函数的栈帧中.
+/// 只支持经典 PL/0 中有的功能: 没有参数的函数作为过程调用, 不能使用函数的返回值, 函数不能作为值;
+/// 内建函数只支持 read 与 write. 不支持的功能报告编译错误
+///
+pub fn compile(program: &Program) -> Result<Vec<Instruction>, Vec<Error>> {
+  let mut generator = Generator {
+    resolver: Resolver::new(HashSet::new()),
+    builtins: Builtins::new(),
+    errors: vec![],
+    codes: vec![],
+    depths: vec![0],
+    addresses: vec![0],
+    sizes: vec![3],
+    offsets: vec![],
+  };
+  let frame = generator.emit(Fct::Int, 0, 0);
+  generator.statements(&program.statements);
+  generator.emit(Fct::Opr, 0, opr::RET);
+  generator.codes[frame].a = generator.sizes[0] as isize;
+
+  if generator.errors.is_empty() {
+    Ok(generator.codes)
+  } else {
+    Err(generator.errors)
+  }
+}
+
+struct Generator {
+  resolver: Resolver,
+  builtins: Builtins,
+  errors: Vec<Error>,
+  codes: Vec<Instruction>,
+  depths: Vec<usize>,    // 各个函数的嵌套层数, 主程序为 0
+  addresses: Vec<usize>, // 各个函数的入口地址
+  sizes: Vec<usize>,     // 各个函数栈帧的大小, 包括三个链接单元
+  offsets: Vec<usize>,   // 各个变量在所在函数栈帧中的偏移
+}
+
+impl Generator {
+  fn emit(&mut self, f: Fct, l: usize, a: isize) -> usize {
+    self.codes.push(Instruction { f, l, a });
+    self.codes.len() - 1
+  }
+
+  /// 回填跳转指令的目标为当前地址
+  fn patch(&mut self, index: usize) {
+    self.codes[index].a = self.codes.len() as isize;
+  }
+
+  fn unsupported(&mut self, feature: &str, pos: SpanOffset) {
+    self.errors.push((format!("{} is not supported by classic P-code", feature), pos));
+  }
+
+  /// 为当前函数中新分配的变量分配栈帧中的位置
+  fn allocate(&mut self, variables: impl IntoIterator<Item = usize>) {
+    let function = self.resolver.function();
+    for _ in variables {
+      self.offsets.push(self.sizes[function]);
+      self.sizes[function] += 1;
+    }
+  }
+
+  /// 变量的层差与偏移
+  fn address(&self, variable: usize) -> (usize, isize) {
+    let function = self.resolver.scopes[self.resolver.variables[variable].scope].function;
+    (self.depths[self.resolver.function()] - self.depths[function], self.offsets[variable] as isize)
+  }
+
+  fn statements(&mut self, statements: &[Statement]) {
+    statements.iter().for_each(|statement| self.statement(statement));
+  }
+
+  fn statement(&mut self, statement: &Statement) {
+    match &statement.kind {
+      StatementKind::Empty => {}
+      StatementKind::Const(constants) => {
+        for (ident, e) in constants {
+          match e.kind {
+            ExpressionKind::Integer(value) => self.resolver.constant(&ident.name, value),
+            _ => self.errors.push((format!("only integer can assign to constant, but get {:?}", e.kind), e.pos)),
+          }
+        }
+      }
+
+      StatementKind::Variable(variables) => {
+        for (ident, e) in variables {
+          // 初始值中不能引用正在定义的变量
+          self.value(e);
+          let variable = self.resolver.declare(&ident.name);
+          self.allocate([variable]);
+          let (l, a) = self.address(variable);
+          self.emit(Fct::Sto, l, a);
+        }
+      }
+
+      StatementKind::Function(ident, args, statements) => self.function(ident, args, statements),
+
+      StatementKind::Assign(ident, e) => match self.resolver.find_variable(&ident.name) {
+        Some(variable) => {
+          self.value(e);
+          let (l, a) = self.address(variable);
+          self.emit(Fct::Sto, l, a);
+        }
+        None => self.errors.push((format!("variable is undefined: {:}", ident.name), statement.pos)),
+      },
+
+      StatementKind::Return(None) => {
+        self.emit(Fct::Opr, 0, opr::RET);
+      }
+      StatementKind::Return(Some(e)) => self.unsupported("return value", e.pos),
+
+      StatementKind::Expression(e) => self.effect(e),
+    }
+  }
+
+  /// 函数定义翻译为过程, 定义处跳过过程体
+  fn function(&mut self, ident: &Identifier, args: &[Identifier], statements: &[Statement]) {
+    if self.builtins.lookup(&ident.name).is_some() {
+      self
+        .errors
+        .push((format!("unable define funcation name as same as builtins function: {}", ident.name), ident.pos));
+      return;
+    }
+    if !args.is_empty() {
+      self.unsupported("function parameter", ident.pos);
+    }
+
+    let jump = self.emit(Fct::Jmp, 0, 0);
+    let depth = self.depths[self.resolver.function()] + 1;
+    let id = self.resolver.enter_function(&ident.name, args);
+    self.depths.push(depth);
+    self.addresses.push(self.codes.len());
+    self.sizes.push(3);
+    self.allocate(self.resolver.functions[id].params.clone());
+
+    let frame = self.emit(Fct::Int, 0, 0);
+    self.statements(statements);
+    self.emit(Fct::Opr, 0, opr::RET);
+    self.codes[frame].a = self.sizes[id] as isize;
+    self.resolver.leave_function();
+    self.patch(jump);
+  }
+
+  /// 语句块, 语句块中定义的名字离开语句块后不可见
+  fn block(&mut self, statements: &[Statement]) {
+    self.resolver.enter_block();
+    self.statements(statements);
+    self.resolver.leave_block();
+  }
+
+  /// 表达式语句, 不在栈上留下值
+  fn effect(&mut self, expression: &Expression) {
+    match &expression.kind {
+      ExpressionKind::Call(callee, args) => self.call(callee, args, false),
+
+      ExpressionKind::If(condition, then_s, else_s) => {
+        self.value(condition);
+        let jpc = self.emit(Fct::Jpc, 0, 0);
+        self.block(then_s);
+        match else_s {
+          Some(else_s) => {
+            let jmp = self.emit(Fct::Jmp, 0, 0);
+            self.patch(jpc);
+            self.block(else_s);
+            self.patch(jmp);
+          }
+          None => self.patch(jpc),
+        }
+      }
+
+      ExpressionKind::While(condition, statements) => {
+        let start = self.codes.len();
+        self.value(condition);
+        let jpc = self.emit(Fct::Jpc, 0, 0);
+        self.block(statements);
+        self.emit(Fct::Jmp, 0, start as isize);
+        self.patch(jpc);
+      }
+
+      // 值没有被使用, 求值之后弹出
+      _ => {
+        self.value(expression);
+        self.emit(Fct::Int, 0, -1);
+      }
+    }
+  }
+
+  /// 对表达式求值, 值留在栈顶
+  fn value(&mut self, expression: &Expression) {
+    match &expression.kind {
+      ExpressionKind::Identifier(name) => match self.resolver.find(name) {
+        Some(Symbol::Constant(value)) => {
+          self.emit(Fct::Lit, 0, value);
+        }
+        Some(Symbol::Variable(variable)) => {
+          let (l, a) = self.address(variable);
+          self.emit(Fct::Lod, l, a);
+        }
+        Some(Symbol::Function(_)) => self.unsupported("function value", expression.pos),
+        None => self.errors.push((format!("identifier is not define: {}", name), expression.pos)),
+      },
+
+      ExpressionKind::Integer(value) => {
+        self.emit(Fct::Lit, 0, *value);
+      }
+
+      ExpressionKind::Infix(infix, left, right) => {
+        if let Some(e) = odd(expression) {
+          self.value(e);
+          self.emit(Fct::Opr, 0, opr::ODD);
+          return;
+        }
+        self.value(left);
+        self.value(right);
+        let code = match infix {
+          Infix::Add => opr::ADD,
+          Infix::Sub => opr::SUB,
+          Infix::Mul => opr::MUL,
+          Infix::Div => opr::DIV,
+          Infix::Eq => opr::EQL,
+          Infix::Ne => opr::NEQ,
+          Infix::Lt => opr::LSS,
+          Infix::Gt => opr::GTR,
+          Infix::LtEq => opr::LEQ,
+          Infix::GtEq => opr::GEQ,
+        };
+        self.emit(Fct::Opr, 0, code);
+      }
+
+      ExpressionKind::Prefix(Prefix::Not, e) => {
+        self.value(e);
+        self.emit(Fct::Lit, 0, 0);
+        self.emit(Fct::Opr, 0, opr::EQL);
+      }
+      ExpressionKind::Prefix(Prefix::Neg, e) => {
+        self.value(e);
+        self.emit(Fct::Opr, 0, opr::NEG);
+      }
+
+      ExpressionKind::Call(callee, args) => self.call(callee, args, true),
+      ExpressionKind::Function(..) => self.unsupported("function value", expression.pos),
+      ExpressionKind::If(..) => self.unsupported("value of if expression", expression.pos),
+      ExpressionKind::While(..) => self.unsupported("value of while expression", expression.pos),
+    }
+  }
+
+  /// 函数调用, value 为真时需要在栈顶留下值
+  fn call(&mut self, callee: &Expression, args: &[Expression], value: bool) {
+    let ExpressionKind::Identifier(name) = &callee.kind else {
+      return self.unsupported("indirect call", callee.pos);
+    };
+    if self.builtins.lookup(name).is_some() {
+      match name.as_str() {
+        builtins::READ => {
+          self.emit(Fct::Opr, 0, opr::RED);
+          if !value {
+            self.emit(Fct::Int, 0, -1);
+          }
+        }
+        builtins::WRITE => {
+          for e in args {
+            self.value(e);
+            self.emit(Fct::Opr, 0, opr::WRT);
+            self.emit(Fct::Opr, 0, opr::WRL);
+          }
+          if value {
+            self.emit(Fct::Lit, 0, 0);
+          }
+        }
+        _ => self.unsupported(&format!("builtin function {}", name), callee.pos),
+      }
+      return;
+    }
+
+    match self.resolver.find(name) {
+      Some(Symbol::Function(function)) => {
+        if value {
+          self.unsupported("function return value", callee.pos);
+        }
+        if !args.is_empty() {
+          self.unsupported("function argument", callee.pos);
+        }
+        // 静态链为过程定义所在的函数的栈帧
+        let l = self.depths[self.resolver.function()] + 1 - self.depths[function];
+        self.emit(Fct::Cal, l, self.addresses[function] as isize);
+      }
+      Some(_) => self.unsupported("indirect call", callee.pos),
+      None => self.errors.push((format!("function is not define: {}", name), callee.pos)),
+    }
+  }
+}
+
+/// 经典前端将 odd e 转换为 e / 2 * 2 != e, 识别出来翻译为 opr 0 6
+fn odd(expression: &Expression) -> Option<&Expression> {
+  let ExpressionKind::Infix(Infix::Ne, left, right) = &expression.kind else { return None };
+  let ExpressionKind::Infix(Infix::Mul, half, two) = &left.kind else { return None };
+  let ExpressionKind::Infix(Infix::Div, e, two_) = &half.kind else { return None };
+  let is_two = |e: &Expression| matches!(e.kind, ExpressionKind::Integer(2));
+  (is_two(two) && is_two(two_) && e.unparse() == right.unparse()).then_some(right)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::mem;
+
+  use crate::{
+    ast::{walk_expression_mut, Expression, ExpressionKind, Program, VisitorMut},
+    classic,
+    compiler::Compiler,
+    parser::Paser,
+    vm::{builtins, VM},
+  };
+
+  use super::{
+    compile,
+    interp::{self, Trap},
+    listing, parse,
+  };
+
+  /// 本语言的程序, 其中的 write 调用转换为经典 PL/0 的输出 (用于测试经典 PL/0 中没有的语法)
+  fn native(source: &str) -> Program {
+    struct Write;
+    impl VisitorMut for Write {
+      fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        if let ExpressionKind::Call(callee, _) = &mut expression.kind {
+          if matches!(&callee.kind, ExpressionKind::Identifier(name) if name == "write") {
+            callee.kind = ExpressionKind::Identifier(builtins::WRITE.to_string());
+          }
+        }
+        walk_expression_mut(self, expression)
+      }
+    }
+
+    let mut program = Paser::paser(source).unwrap();
+    Write.visit_program_mut(&mut program);
+    program
+  }
+
+  /// 比较经典解释程序与虚拟机的输出, 运行时错误只比较种类
+  fn check(program: &Program, name: &str) {
+    let codes = Compiler::compile(program).unwrap();
+    let mut expect = vec![];
+    let expect_result = VM::execute_with_output(&codes, &mut expect);
+
+    let codes = compile(program).unwrap();
+    assert_eq!(parse(&listing(&codes)).unwrap(), codes);
+    let mut output = vec![];
+    let result = interp::run(&codes, &mut &b""[..], &mut output);
+    assert_eq!(String::from_utf8(output).unwrap(), String::from_utf8(expect).unwrap(), "{}", name);
+    match (result, expect_result) {
+      (Ok(()), Ok(_)) => {}
+      (Err((Trap::Runtime(err), _)), Err((expect, _))) => {
+        assert_eq!(mem::discriminant(&err), mem::discriminant(&expect), "{}", name)
+      }
+      (result, expect) => panic!("{}: {:?} {:?}", name, result, expect),
+    }
+  }
+
+  #[test]
+  fn test_listing() {
+    let codes =
+      compile(&classic::parse("const c = 2; var x; procedure p; x := x * c; begin x := 3; call p; !x end.").unwrap())
+        .unwrap();
+    let expect = "\
+int 0 4
+lit 0 0
+sto 0 3
+jmp 0 10
+int 0 3
+lod 1 3
+lit 0 2
+opr 0 4
+sto 1 3
+opr 0 0
+lit 0 3
+sto 0 3
+cal 0 4
+lod 0 3
+opr 0 14
+opr 0 15
+opr 0 0
+";
+    assert_eq!(listing(&codes), expect);
+    assert_eq!(parse("0 INT 0 4\n\n1 opr 0 0").unwrap(), [codes[0], codes[16]]);
+    assert!(parse("lit 0").is_err());
+    assert!(parse("mov 0 1").is_err());
+  }
+
+  #[test]
+  fn test_programs() {
+    let source = "
+var n, f;
+procedure fact;
+begin
+  if n > 1 then begin f := f * n; n := n - 1; call fact end
+end;
+begin n := 10; f := 1; call fact; !f end.";
+    check(&classic::parse(source).unwrap(), "fact");
+    let source = "
+var x, y, s;
+procedure outer;
+  var k;
+  procedure inner;
+  begin s := s + k * x; if odd k then !k end;
+begin k := 0; while k < 5 do begin k := k + 1; call inner end end;
+begin x := 3; s := 0; call outer; write(s, -s, x) end.";
+    check(&classic::parse(source).unwrap(), "nested");
+    // 语句块中的变量与遮蔽
+    let source = "var a = 1; if a { var a = 5; write(a) } else { write(0) };
+while a < 4 { var b = a * 2; a += 1; write(b) }; write(a, !a)";
+    check(&native(source), "block");
+    check(&native("var x = 1; write(x / (x - 1))"), "division");
+    check(&native("var x = 9223372036854775807; write(x * 2)"), "overflow");
+  }
+
+  #[test]
+  fn test_errors() {
+    let t = vec![
+      ("fn f(n) { n } f(1)", 2),
+      ("fn f() { 1 } var x = f()", 1),
+      ("var x = if 1 { 2 }; println(x)", 2),
+      ("fn f() { return 1 } var g = f", 2),
+      ("x = 1; y", 2),
+    ];
+    for (source, count) in t {
+      assert_eq!(compile(&Paser::paser(source).unwrap()).unwrap_err().len(), count, "{}", source);
+    }
+  }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashSet;
+
+use crate::{ast::Identifier, SpanOffset};
+
+pub(crate) type Error = (String, SpanOffset);
+
+/// 名字绑定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Symbol {
+  Constant(isize),
+  Variable(usize),
+  Function(usize),
+}
+
+/// 作用域: 函数体或者语句块
+pub(crate) struct Scope {
+  pub function: usize,       // 所在的函数
+  pub parent: Option<usize>, // 外层作用域, 函数体的外层作用域为函数定义所在的作用域
+  pub variables: Vec<usize>,
+  pub memory: bool, // 有变量被内层函数引用, 变量保存在栈帧结构中
+}
+
+pub(crate) struct Variable {
+  pub scope: usize,
+  pub slot: usize, // 在作用域栈帧中的位置, 0 为静态链
+}
+
+pub(crate) struct Function {
+  pub name: String,
+  pub params: Vec<usize>,
+  pub scope: usize, // 函数体的作用域
+}
+
+/// 访问外层作用域栈帧的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Access {
+  Local(usize), // 当前函数中的作用域, 栈帧指针保存在局部变量中
+  Env(usize),   // 从环境参数 (函数定义所在的栈帧) 出发沿静态链向上的次数
+}
+
+///
+/// AST 后端共用的名字解析
+///
+/// 名字的可见性与编译器的符号表相同. 与编译器的逃逸分析类似, 被内层函数引用的变量需要保存在栈帧结构中,
+/// 第一遍生成代码时记录下这些作用域, 然后将它们标记为 memory 重新生成一遍.
+/// 只有 memory 作用域才有栈帧结构, 栈帧的静态链指向外层最近的 memory 作用域
+pub(crate) struct Resolver {
+  names: Vec<(String, Symbol)>,
+  marks: Vec<usize>, // 进入作用域时符号表的长度
+  stack: Vec<usize>, // 当前所在的作用域链
+  functions_stack: Vec<usize>,
+  memory: HashSet<usize>,   // 上一遍发现的需要栈帧结构的作用域
+  captured: HashSet<usize>, // 本遍发现的需要栈帧结构的作用域
+  pub scopes: Vec<Scope>,
+  pub variables: Vec<Variable>,
+  pub functions: Vec<Function>, // 下标 0 为全局作用域
+}
+
+impl Resolver {
+  pub fn new(memory: HashSet<usize>) -> Self {
+    let mut resolver = Resolver {
+      names: vec![],
+      marks: vec![],
+      stack: vec![],
+      functions_stack: vec![0],
+      memory,
+      captured: HashSet::new(),
+      scopes: vec![],
+      variables: vec![],
+      functions: vec![Function { name: "main".to_string(), params: vec![], scope: 0 }],
+    };
+    resolver.enter_scope();
+    resolver
+  }
+
+  /// 本遍发现的需要栈帧结构的作用域, 与上一遍相同时不需要再生成一遍
+  pub fn captured(&self) -> Option<HashSet<usize>> {
+    (self.captured != self.memory).then(|| self.captured.clone())
+  }
+
+  /// 当前作用域
+  pub fn scope(&self) -> usize {
+    *self.stack.last().unwrap()
+  }
+
+  /// 当前函数
+  pub fn function(&self) -> usize {
+    *self.functions_stack.last().unwrap()
+  }
+
+  fn enter_scope(&mut self) -> usize {
+    let id = self.scopes.len();
+    let scope = Scope {
+      function: self.function(),
+      parent: self.stack.last().copied(),
+      variables: vec![],
+      memory: self.memory.contains(&id),
+    };
+    self.scopes.push(scope);
+    self.stack.push(id);
+    self.marks.push(self.names.len());
+    id
+  }
+
+  fn leave_scope(&mut self) {
+    self.stack.pop();
+    let mark = self.marks.pop().unwrap();
+    self.names.truncate(mark);
+  }
+
+  /// 进入语句块, 返回语句块的作用域
+  pub fn enter_block(&mut self) -> usize {
+    self.enter_scope()
+  }
+
+  pub fn leave_block(&mut self) {
+    self.leave_scope();
+  }
+
+  /// 在当前作用域中定义函数并进入函数体, 返回函数编号
+  pub fn enter_function(&mut self, name: &str, params: &[Identifier]) -> usize {
+    let id = self.functions.len();
+    self.names.push((name.to_string(), Symbol::Function(id)));
+    self.functions.push(Function { name: name.to_string(), params: vec![], scope: self.scopes.len() });
+    self.functions_stack.push(id);
+    self.enter_scope();
+
+    self.functions[id].params = params.iter().map(|param| self.declare(&param.name)).collect();
+    id
+  }
+
+  pub fn leave_function(&mut self) {
+    self.leave_scope();
+    self.functions_stack.pop();
+  }
+
+  /// 在当前作用域中定义变量
+  pub fn declare(&mut self, name: &str) -> usize {
+    let (id, scope) = (self.variables.len(), self.scope());
+    self.scopes[scope].variables.push(id);
+    self.variables.push(Variable { scope, slot: self.scopes[scope].variables.len() });
+    self.names.push((name.to_string(), Symbol::Variable(id)));
+    id
+  }
+
+  pub fn constant(&mut self, name: &str, value: isize) {
+    self.names.push((name.to_string(), Symbol::Constant(value)));
+  }
+
+  /// 查找名字, 以后定义的为准
+  pub fn find(&mut self, name: &str) -> Option<Symbol> {
+    let symbol = self.names.iter().rev().find(|(item, _)| item == name).map(|(_, symbol)| *symbol)?;
+    if let Symbol::Variable(id) = symbol {
+      self.reference(id);
+    }
+    Some(symbol)
+  }
+
+  /// 查找变量 (赋值语句), 忽略同名的常量与函数
+  pub fn find_variable(&mut self, name: &str) -> Option<usize> {
+    let id = self.names.iter().rev().find_map(|(item, symbol)| match symbol {
+      Symbol::Variable(id) if item == name => Some(*id),
+      _ => None,
+    })?;
+    self.reference(id);
+    Some(id)
+  }
+
+  /// 引用其他函数中的变量时, 变量所在的作用域需要栈帧结构
+  fn reference(&mut self, variable: usize) {
+    let scope = self.variables[variable].scope;
+    if self.scopes[scope].function != self.function() {
+      self.captured.insert(scope);
+    }
+  }
+
+  /// 变量是否保存在栈帧结构中
+  pub fn in_memory(&self, variable: usize) -> bool {
+    self.scopes[self.variables[variable].scope].memory
+  }
+
+  /// scope 及其外层作用域中最近的 memory 作用域
+  fn memory_frame(&self, scope: Option<usize>) -> Option<usize> {
+    let mut scope = scope;
+    while let Some(id) = scope.filter(|id| !self.scopes[*id].memory) {
+      scope = self.scopes[id].parent;
+    }
+    scope
+  }
+
+  /// 新建作用域栈帧时, 静态链指向的栈帧
+  pub fn parent_frame(&self, scope: usize) -> Option<Access> {
+    self.memory_frame(self.scopes[scope].parent).map(|frame| self.access(frame))
+  }
+
+  /// 调用函数或者生成闭包时的环境: 函数定义所在的作用域的栈帧
+  pub fn environment(&self, function: usize) -> Option<Access> {
+    self.parent_frame(self.functions[function].scope)
+  }
+
+  /// 从当前位置访问 memory 作用域 target 的栈帧
+  pub fn access(&self, target: usize) -> Access {
+    let function = self.function();
+    if self.scopes[target].function == function {
+      return Access::Local(target);
+    }
+
+    let mut frame = self.memory_frame(self.scopes[self.functions[function].scope].parent);
+    let mut hops = 0;
+    while let Some(id) = frame.filter(|id| *id != target) {
+      frame = self.memory_frame(self.scopes[id].parent);
+      hops += 1;
+    }
+    Access::Env(hops)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashSet;
+
+  use super::{Access, Resolver, Symbol};
+  use crate::ast::Identifier;
+
+  #[test]
+  fn test_access() {
+    let param = |name: &str| Identifier { pos: (0, 0).into(), name: name.to_string() };
+    let walk = |memory: HashSet<usize>| {
+      // var a; fn f(x) { if x { fn g() { a + x } } }
+      let mut resolver = Resolver::new(memory);
+      let a = resolver.declare("a");
+      let f = resolver.enter_function("f", &[param("x")]);
+      let block = resolver.enter_block();
+      resolver.enter_function("g", &[]);
+      assert_eq!(resolver.find("a"), Some(Symbol::Variable(a)));
+      assert_eq!(resolver.find("x"), Some(Symbol::Variable(resolver.functions[f].params[0])));
+      let access = (resolver.access(0), resolver.access(resolver.functions[f].scope));
+      resolver.leave_function();
+      assert_eq!(resolver.find("g"), Some(Symbol::Function(2)));
+      let access = (access.0, access.1, resolver.environment(2));
+      resolver.leave_block();
+      assert_eq!(resolver.find("g"), None);
+      (resolver, block, access)
+    };
+
+    let (resolver, _, _) = walk(HashSet::new());
+    let captured = resolver.captured().unwrap();
+    assert_eq!(captured, HashSet::from([0, 1]));
+
+    // 语句块没有被引用的变量, 不需要栈帧结构
+    let (resolver, block, access) = walk(captured);
+    assert_eq!(resolver.captured(), None);
+    assert!(!resolver.scopes[block].memory);
+    assert_eq!(access, (Access::Env(1), Access::Env(0), Some(Access::Local(1))));
+  }
+}
@@ -0,0 +1,92 @@
+/*
+ * x86-64 后端的运行时: 程序入口, 内建函数与运行时错误
+ *
+ * 生成的汇编代码导出 pl0_main, 参数为栈顶地址, 返回程序的返回值
+ */
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <sys/mman.h>
+
+#define PL0_STACK_SIZE ((size_t)1 << 30)
+
+extern int64_t pl0_main(void *stack_top);
+
+/* 与虚拟机相同, 输出参数列表的 Debug 格式 */
+static void pl0_print(int64_t argc, const int64_t *args) {
+  putchar('"');
+  for (int64_t i = 0; i < argc; i++) {
+    printf(i ? " %lld" : "%lld", (long long)args[i]);
+  }
+  putchar('"');
+}
+
+/* 从标准输入读取一个整数, 读取失败时为 0 */
+static int64_t pl0_read(void) {
+  long long value = 0;
+  fflush(stdout);
+  if (scanf("%lld", &value) != 1) {
+    value = 0;
+  }
+  return value;
+}
+
+/* 内建函数, 编号与 Builtins 中的顺序相同, args[0] 为第一个实参 */
+int64_t pl0_builtin(int64_t id, int64_t argc, const int64_t *args) {
+  switch (id) {
+  case 0:
+    for (int64_t i = 0; i < argc; i++) {
+      printf("%lld\n", (long long)args[i]);
+    }
+    puts("hello world");
+    break;
+  case 1:
+    pl0_print(argc, args);
+    fflush(stdout);
+    break;
+  case 2:
+    pl0_print(argc, args);
+    putchar('\n');
+    break;
+  case 3:
+    return pl0_read();
+  case 4:
+    for (int64_t i = 0; i < argc; i++) {
+      printf("%lld\n", (long long)args[i]);
+    }
+    break;
+  }
+  return 0;
+}
+
+/* 运行时错误, 输出与 pl0 run 相同 */
+void pl0_error(int64_t kind, int64_t ip, int64_t arg) {
+  switch (kind) {
+  case 0:
+    printf("运行时错误: illegal function address: %lld (%04llXH)\n", (long long)(arg & 0xffffffff), (long long)ip);
+    break;
+  case 1:
+    printf("运行时错误: division by zero (%04llXH)\n", (long long)ip);
+    break;
+  case 2:
+    printf("运行时错误: integer overflow (%04llXH)\n", (long long)ip);
+    break;
+  default:
+    printf("运行时错误: out of memory (%04llXH)\n", (long long)ip);
+    break;
+  }
+  fflush(stdout);
+  exit(1);
+}
+
+int main(void) {
+  /* 栈只分配地址空间, 使用时才占用内存 */
+  char *stack = mmap(NULL, PL0_STACK_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE, -1, 0);
+  if (stack == MAP_FAILED) {
+    perror("mmap");
+    return 2;
+  }
+  pl0_main(stack + PL0_STACK_SIZE);
+  fflush(stdout);
+  return 0;
+}
@@ -0,0 +1,168 @@
+/*
+ * C 后端的运行时, 内联在生成的 C 文件开头: 栈帧与闭包的表示, 堆, 内建函数, 检查溢出的整数运算与运行时错误
+ *
+ * 生成的代码定义 pl0_main, 返回程序的返回值. 定义 PL0_PRINT_RESULT 时 main 输出程序的返回值
+ */
+#include <stddef.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+/* 堆的大小 (8 字节为单位), 与虚拟机相同只分配不回收 */
+#ifndef PL0_HEAP_WORDS
+#define PL0_HEAP_WORDS ((size_t)1 << 25)
+#endif
+
+/* 闭包的标记, 间接调用时用于检查数值是否为闭包 */
+#define PL0_CLOSURE_TAG INT64_C(0x706c305f636c6f73)
+
+/* 作用域栈帧的公共部分, 各个作用域的栈帧结构以它开头 */
+struct pl0_frame {
+  struct pl0_frame *link; /* 静态链: 外层最近的有栈帧结构的作用域 */
+};
+
+/* PL/0 函数: 定义所在的栈帧, 实参与实参个数 */
+typedef int64_t (*pl0_code)(struct pl0_frame *env, const int64_t *args, int64_t argc);
+
+struct pl0_closure {
+  int64_t tag;
+  pl0_code code;
+  struct pl0_frame *env;
+};
+
+static int64_t pl0_heap[PL0_HEAP_WORDS];
+static size_t pl0_heap_used;
+
+static int64_t pl0_main(void);
+
+/* 运行时错误, 输出与 pl0 run 相同, 但是没有指令地址 */
+static inline void pl0_fail(const char *message) {
+  printf("运行时错误: %s\n", message);
+  fflush(stdout);
+  exit(1);
+}
+
+static inline void *pl0_alloc(size_t size) {
+  size_t words = (size + 7) / 8;
+  if (words > PL0_HEAP_WORDS - pl0_heap_used) {
+    pl0_fail("out of memory");
+  }
+  void *p = pl0_heap + pl0_heap_used;
+  pl0_heap_used += words;
+  return p;
+}
+
+static inline int64_t pl0_closure(pl0_code code, struct pl0_frame *env) {
+  struct pl0_closure *closure = pl0_alloc(sizeof *closure);
+  closure->tag = PL0_CLOSURE_TAG;
+  closure->code = code;
+  closure->env = env;
+  return (int64_t)(intptr_t)closure;
+}
+
+/* 间接调用: 检查数值是否为堆上的闭包 */
+static inline struct pl0_closure *pl0_callee(int64_t value) {
+  uintptr_t address = (uintptr_t)value, base = (uintptr_t)pl0_heap, end = base + pl0_heap_used * 8;
+  if (address < base || address % 8 || address > end - sizeof(struct pl0_closure) ||
+      ((struct pl0_closure *)address)->tag != PL0_CLOSURE_TAG) {
+    printf("运行时错误: illegal function address: %lld\n", (long long)(value & 0xffffffff));
+    fflush(stdout);
+    exit(1);
+  }
+  return (struct pl0_closure *)address;
+}
+
+static inline int64_t pl0_add(int64_t a, int64_t b) {
+  if ((b > 0 && a > INT64_MAX - b) || (b < 0 && a < INT64_MIN - b)) {
+    pl0_fail("integer overflow");
+  }
+  return a + b;
+}
+
+static inline int64_t pl0_sub(int64_t a, int64_t b) {
+  if ((b < 0 && a > INT64_MAX + b) || (b > 0 && a < INT64_MIN + b)) {
+    pl0_fail("integer overflow");
+  }
+  return a - b;
+}
+
+static inline int64_t pl0_mul(int64_t a, int64_t b) {
+  if (a == 0 || b == 0) {
+    return 0;
+  }
+  if (a > 0 ? (b > 0 ? a > INT64_MAX / b : b < INT64_MIN / a) : (b > 0 ? a < INT64_MIN / b : a < INT64_MAX / b)) {
+    pl0_fail("integer overflow");
+  }
+  return a * b;
+}
+
+static inline int64_t pl0_div(int64_t a, int64_t b) {
+  if (b == 0) {
+    pl0_fail("division by zero");
+  }
+  if (a == INT64_MIN && b == -1) {
+    pl0_fail("integer overflow");
+  }
+  return a / b;
+}
+
+/* 与虚拟机相同, 输出参数列表的 Debug 格式 */
+static inline void pl0_format(const int64_t *args, int64_t argc) {
+  putchar('"');
+  for (int64_t i = 0; i < argc; i++) {
+    printf(i ? " %lld" : "%lld", (long long)args[i]);
+  }
+  putchar('"');
+}
+
+/* 内建函数, 与 Builtins 中的同名函数相同, args[0] 为第一个实参 */
+static inline int64_t pl0_helloworld(const int64_t *args, int64_t argc) {
+  for (int64_t i = 0; i < argc; i++) {
+    printf("%lld\n", (long long)args[i]);
+  }
+  puts("hello world");
+  return 0;
+}
+
+static inline int64_t pl0_print(const int64_t *args, int64_t argc) {
+  pl0_format(args, argc);
+  fflush(stdout);
+  return 0;
+}
+
+static inline int64_t pl0_println(const int64_t *args, int64_t argc) {
+  pl0_format(args, argc);
+  putchar('\n');
+  return 0;
+}
+
+/* 从标准输入读取一个整数, 读取失败时为 0 */
+static inline int64_t pl0_read(const int64_t *args, int64_t argc) {
+  long long value = 0;
+  (void)args;
+  (void)argc;
+  fflush(stdout);
+  if (scanf("%lld", &value) != 1) {
+    value = 0;
+  }
+  return value;
+}
+
+/* 每个参数输出一行 */
+static inline int64_t pl0_write(const int64_t *args, int64_t argc) {
+  for (int64_t i = 0; i < argc; i++) {
+    printf("%lld\n", (long long)args[i]);
+  }
+  return 0;
+}
+
+int main(void) {
+  int64_t result = pl0_main();
+#ifdef PL0_PRINT_RESULT
+  printf("%lld\n", (long long)result);
+#else
+  (void)result;
+#endif
+  fflush(stdout);
+  return 0;
+}
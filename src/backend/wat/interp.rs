@@ -0,0 +1,874 @@
+use std::{collections::HashMap, fmt::Display, io::Write};
+
+use crate::vm::{builtins::Builtins, RuntimeError};
+
+use super::{DIVISION_BY_ZERO, ILLEGAL_FUNCTION_ADDRESS, MISSING_ARGUMENTS, OUT_OF_MEMORY, OVERFLOW};
+
+/// 线性内存最多的页数
+const MAX_PAGES: usize = 1 << 14;
+const PAGE_SIZE: usize = 1 << 16;
+/// 调用栈的最大深度
+const MAX_FRAMES: usize = 1 << 20;
+
+/// 执行过程中的陷阱
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trap {
+  Runtime(RuntimeError), // 宿主函数 pl0.error 报告的运行时错误
+  Message(String),       // 其他陷阱, 例如越界访问内存
+}
+
+impl Display for Trap {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Trap::Runtime(err) => write!(f, "{}", err),
+      Trap::Message(message) => write!(f, "{}", message),
+    }
+  }
+}
+
+fn trap<T>(message: &str) -> Result<T, Trap> {
+  Err(Trap::Message(message.to_string()))
+}
+
+/// S 表达式
+#[derive(Debug)]
+enum Sexpr {
+  Atom(String),
+  List(Vec<Sexpr>),
+}
+
+impl Sexpr {
+  fn atom(&self) -> Option<&str> {
+    match self {
+      Sexpr::Atom(atom) => Some(atom),
+      Sexpr::List(_) => None,
+    }
+  }
+
+  /// 以 keyword 开头的列表, 返回其余的元素
+  fn list(&self, keyword: &str) -> Option<&[Sexpr]> {
+    match self {
+      Sexpr::List(items) if items.first().and_then(Sexpr::atom) == Some(keyword) => Some(&items[1..]),
+      _ => None,
+    }
+  }
+}
+
+fn parse_sexprs(text: &str) -> Result<Vec<Sexpr>, String> {
+  let mut stack = vec![vec![]];
+  let mut chars = text.chars().peekable();
+  while let Some(c) = chars.next() {
+    match c {
+      ';' if chars.peek() == Some(&';') => {
+        chars.by_ref().take_while(|c| *c != '\n').for_each(drop);
+      }
+      '(' => stack.push(vec![]),
+      ')' => {
+        let list = stack.pop().filter(|_| !stack.is_empty()).ok_or("unexpected )")?;
+        stack.last_mut().unwrap().push(Sexpr::List(list));
+      }
+      '"' => {
+        let string = chars.by_ref().take_while(|c| *c != '"').collect::<String>();
+        stack.last_mut().unwrap().push(Sexpr::Atom(format!("\"{}\"", string)));
+      }
+      c if c.is_whitespace() => {}
+      _ => {
+        let mut atom = c.to_string();
+        while let Some(&c) = chars.peek().filter(|c| !c.is_whitespace() && **c != '(' && **c != ')') {
+          atom.push(c);
+          chars.next();
+        }
+        stack.last_mut().unwrap().push(Sexpr::Atom(atom));
+      }
+    }
+  }
+
+  match stack.len() {
+    1 => Ok(stack.pop().unwrap()),
+    _ => Err("unclosed (".to_string()),
+  }
+}
+
+/// 整数运算
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+  Eqz,
+  Add,
+  Sub,
+  Mul,
+  DivS,
+  And,
+  Or,
+  Xor,
+  Shl,
+  ShrU,
+  ShrS,
+  Eq,
+  Ne,
+  LtS,
+  LtU,
+  GtS,
+  GtU,
+  LeS,
+  LeU,
+  GeS,
+  GeU,
+}
+
+impl Op {
+  fn parse(name: &str) -> Option<Op> {
+    Some(match name {
+      "eqz" => Op::Eqz,
+      "add" => Op::Add,
+      "sub" => Op::Sub,
+      "mul" => Op::Mul,
+      "div_s" => Op::DivS,
+      "and" => Op::And,
+      "or" => Op::Or,
+      "xor" => Op::Xor,
+      "shl" => Op::Shl,
+      "shr_u" => Op::ShrU,
+      "shr_s" => Op::ShrS,
+      "eq" => Op::Eq,
+      "ne" => Op::Ne,
+      "lt_s" => Op::LtS,
+      "lt_u" => Op::LtU,
+      "gt_s" => Op::GtS,
+      "gt_u" => Op::GtU,
+      "le_s" => Op::LeS,
+      "le_u" => Op::LeU,
+      "ge_s" => Op::GeS,
+      "ge_u" => Op::GeU,
+      _ => return None,
+    })
+  }
+
+  /// 32 位的值以符号扩展的形式保存, 比较运算的结果为 i32 的 0 或 1
+  fn apply(self, a: i64, b: i64, bits: u32) -> Result<i64, Trap> {
+    let mask = if bits == 32 { u32::MAX as u64 } else { u64::MAX };
+    let (ua, ub, shift) = (a as u64 & mask, b as u64 & mask, b as u32 % bits);
+    let value = match self {
+      Op::Eqz => return Ok((a == 0) as i64),
+      Op::Add => a.wrapping_add(b),
+      Op::Sub => a.wrapping_sub(b),
+      Op::Mul => a.wrapping_mul(b),
+      Op::DivS if b == 0 => return trap("integer divide by zero"),
+      Op::DivS if bits == 32 => (a as i32).checked_div(b as i32).map(i64::from).ok_or_else(overflow)?,
+      Op::DivS => a.checked_div(b).ok_or_else(overflow)?,
+      Op::And => a & b,
+      Op::Or => a | b,
+      Op::Xor => a ^ b,
+      Op::Shl => a.wrapping_shl(shift),
+      Op::ShrU => (ua >> shift) as i64,
+      Op::ShrS => a >> shift,
+      Op::Eq => return Ok((a == b) as i64),
+      Op::Ne => return Ok((a != b) as i64),
+      Op::LtS => return Ok((a < b) as i64),
+      Op::LtU => return Ok((ua < ub) as i64),
+      Op::GtS => return Ok((a > b) as i64),
+      Op::GtU => return Ok((ua > ub) as i64),
+      Op::LeS => return Ok((a <= b) as i64),
+      Op::LeU => return Ok((ua <= ub) as i64),
+      Op::GeS => return Ok((a >= b) as i64),
+      Op::GeU => return Ok((ua >= ub) as i64),
+    };
+    Ok(if bits == 32 { value as i32 as i64 } else { value })
+  }
+}
+
+fn overflow() -> Trap {
+  Trap::Message("integer overflow".to_string())
+}
+
+/// 函数签名: 参数个数与返回值个数, 只使用 i32 与 i64, 都以 64 位保存
+type Signature = (usize, usize);
+
+/// 指令, 结构化指令记录对应的 else 与 end 的位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Instr {
+  Unreachable,
+  Nop,
+  Drop,
+  Block { end: usize, arity: usize },
+  Loop,
+  If { else_: usize, end: usize, arity: usize }, // 没有 else 分支时 else_ 等于 end
+  Else { end: usize },
+  End,
+  Br(usize),
+  BrIf(usize),
+  Return,
+  Call(usize),
+  CallIndirect(Signature),
+  ReturnCall(usize),
+  ReturnCallIndirect(Signature),
+  LocalGet(usize),
+  LocalSet(usize),
+  LocalTee(usize),
+  GlobalGet(usize),
+  GlobalSet(usize),
+  I32Load(u32),
+  I64Load(u32),
+  I32Store(u32),
+  I64Store(u32),
+  MemorySize,
+  MemoryGrow,
+  TableSize,
+  Const(i64),
+  I32(Op),
+  I64(Op),
+  ExtendI32U,
+  WrapI64,
+}
+
+struct Function {
+  signature: Signature,
+  import: Option<String>, // 导入的宿主函数名
+  locals: usize,          // 局部变量的个数, 包括参数
+  code: Vec<Instr>,
+}
+
+/// 模块中的名字
+#[derive(Default)]
+struct Names {
+  types: HashMap<String, Signature>,
+  functions: HashMap<String, usize>,
+  globals: HashMap<String, usize>,
+}
+
+/// 统计 (param ...), (result ...) 或者 (local ...) 声明的个数, 记录有名字的项
+fn declarations<'a>(items: &'a [Sexpr], keyword: &str, names: &mut HashMap<&'a str, usize>) -> usize {
+  let mut count = 0;
+  for list in items.iter().filter_map(|item| item.list(keyword)) {
+    match list.first().and_then(Sexpr::atom) {
+      Some(name) if name.starts_with('$') => {
+        names.insert(name, count);
+        count += 1;
+      }
+      _ => count += list.len(),
+    }
+  }
+  count
+}
+
+fn signature(items: &[Sexpr], names: &Names) -> Result<Signature, String> {
+  match items.iter().find_map(|item| item.list("type")) {
+    Some(list) => {
+      let name = list.first().and_then(Sexpr::atom).unwrap_or_default();
+      names.types.get(name).copied().ok_or(format!("unknown type {}", name))
+    }
+    None => Ok((declarations(items, "param", &mut HashMap::new()), declarations(items, "result", &mut HashMap::new()))),
+  }
+}
+
+fn number<T: std::str::FromStr>(item: Option<&Sexpr>) -> Result<T, String> {
+  let atom = item.and_then(Sexpr::atom).ok_or("expect number")?;
+  atom.parse().map_err(|_| format!("invalid number {}", atom))
+}
+
+/// 名字: 第一个以 $ 开头的元素
+fn name(items: &[Sexpr]) -> Option<&str> {
+  items.first().and_then(Sexpr::atom).filter(|name| name.starts_with('$'))
+}
+
+///
+/// WebAssembly 文本格式的解释器, 支持 wat 后端生成的子集, 用于离线测试生成的模块
+///
+/// 解析时检查模块的结构: 名字, 结构化指令的嵌套以及分支的层数. 执行时不使用递归, 支持尾调用.
+/// 导入的宿主函数中 pl0.error 报告运行时错误, 其余为内建函数, 参数为实参在线性内存中的地址与个数
+///
+pub struct Module {
+  functions: Vec<Function>,
+  table: Vec<Option<usize>>,
+  globals: Vec<i64>,
+  memory: usize, // 初始页数
+  exports: HashMap<String, usize>,
+}
+
+impl Module {
+  /// 解析并检查模块
+  pub fn parse(text: &str) -> Result<Module, String> {
+    let sexprs = parse_sexprs(text)?;
+    let fields = match sexprs.as_slice() {
+      [module] => module.list("module").ok_or("expect (module ...)")?,
+      _ => return Err("expect (module ...)".to_string()),
+    };
+
+    // 第一遍: 收集名字, 导入的函数编号在前
+    let mut names = Names::default();
+    let mut module = Module { functions: vec![], table: vec![], globals: vec![], memory: 0, exports: HashMap::new() };
+    for field in fields {
+      if let Some(items) = field.list("type") {
+        let func = items.get(1).and_then(|item| item.list("func")).ok_or("expect (type $name (func ...))")?;
+        let signature = signature(func, &names)?;
+        names.types.insert(name(items).ok_or("expect type name")?.to_string(), signature);
+      } else if let Some(items) = field.list("import") {
+        let func = items.get(2).and_then(|item| item.list("func")).ok_or("only functions can be imported")?;
+        let import = items.get(1).and_then(Sexpr::atom).ok_or("expect import name")?;
+        if let Some(name) = name(func) {
+          names.functions.insert(name.to_string(), module.functions.len());
+        }
+        let signature = signature(func, &names)?;
+        let import = Some(import.trim_matches('"').to_string());
+        module.functions.push(Function { signature, import, locals: 0, code: vec![] });
+      } else if let Some(items) = field.list("global") {
+        let value = items.last().and_then(|item| item.list("i32.const").or_else(|| item.list("i64.const")));
+        module.globals.push(number(value.and_then(|value| value.first()))?);
+        names.globals.insert(name(items).ok_or("expect global name")?.to_string(), module.globals.len() - 1);
+      }
+    }
+    let funcs = fields.iter().filter_map(|field| field.list("func")).collect::<Vec<_>>();
+    let imports = module.functions.len();
+    for (index, items) in funcs.iter().enumerate() {
+      if let Some(name) = name(items) {
+        names.functions.insert(name.to_string(), imports + index);
+      }
+    }
+
+    // 第二遍: 内存, 表与函数体
+    for field in fields {
+      if let Some(items) = field.list("memory") {
+        module.memory = number(items.last())?;
+      } else if let Some(items) = field.list("table") {
+        module.table = vec![None; number(items.first())?];
+      } else if let Some(items) = field.list("elem") {
+        let offset = items.first().and_then(|item| item.list("i32.const")).ok_or("expect elem offset")?;
+        let offset: usize = number(offset.first())?;
+        for (index, item) in items[1..].iter().enumerate() {
+          let function = item.atom().and_then(|name| names.functions.get(name)).ok_or("unknown function in elem")?;
+          *module.table.get_mut(offset + index).ok_or("elem out of table")? = Some(*function);
+        }
+      }
+    }
+    for (index, items) in funcs.into_iter().enumerate() {
+      let function = Self::function(items, &names).map_err(|err| format!("func {}: {}", imports + index, err))?;
+      module.functions.push(function);
+      if let Some(export) = items.iter().find_map(|item| item.list("export")) {
+        let export = export.first().and_then(Sexpr::atom).ok_or("expect export name")?;
+        module.exports.insert(export.trim_matches('"').to_string(), imports + index);
+      }
+    }
+    if module.memory > MAX_PAGES {
+      return Err("memory is too large".to_string());
+    }
+    Ok(module)
+  }
+
+  fn function(items: &[Sexpr], names: &Names) -> Result<Function, String> {
+    // 函数名与声明之后为函数体
+    let keywords = ["type", "export", "param", "result", "local"];
+    let start = items
+      .iter()
+      .position(|item| match item {
+        Sexpr::Atom(atom) => !atom.starts_with('$'),
+        Sexpr::List(_) => !keywords.iter().any(|keyword| item.list(keyword).is_some()),
+      })
+      .unwrap_or(items.len());
+    let (header, body) = items.split_at(start);
+
+    let signature = signature(header, names)?;
+    let mut locals = HashMap::new();
+    if declarations(header, "param", &mut locals) != signature.0 {
+      return Err("params do not match the type".to_string());
+    }
+    let mut declared = HashMap::new();
+    let count = signature.0 + declarations(header, "local", &mut declared);
+    locals.extend(declared.into_iter().map(|(name, index)| (name, signature.0 + index)));
+
+    let mut body = body.iter().peekable();
+
+    let mut code = vec![];
+    let mut blocks: Vec<usize> = vec![]; // 未结束的结构化指令
+    while let Some(item) = body.next() {
+      let name = item.atom().ok_or("folded instructions are not supported")?;
+      let mut immediate = || body.next().and_then(Sexpr::atom).ok_or(format!("{} expects an immediate", name));
+
+      let instr = match name {
+        "unreachable" => Instr::Unreachable,
+        "nop" => Instr::Nop,
+        "drop" => Instr::Drop,
+        "block" | "loop" | "if" => {
+          let arity = match body.peek().and_then(|item| item.list("result")) {
+            Some(list) => {
+              body.next();
+              list.len()
+            }
+            None => 0,
+          };
+          blocks.push(code.len());
+          match name {
+            "block" => Instr::Block { end: 0, arity },
+            "loop" => Instr::Loop,
+            _ => Instr::If { else_: 0, end: 0, arity },
+          }
+        }
+        "else" => {
+          let position = code.len();
+          match blocks.last().map(|start| &mut code[*start]) {
+            Some(Instr::If { else_, .. }) if *else_ == 0 => *else_ = position,
+            _ => return Err("else without if".to_string()),
+          }
+          Instr::Else { end: 0 }
+        }
+        "end" => {
+          let start = blocks.pop().ok_or("unexpected end")?;
+          let end = code.len();
+          match code[start] {
+            Instr::Block { arity, .. } => code[start] = Instr::Block { end, arity },
+            Instr::If { else_: 0, arity, .. } => code[start] = Instr::If { else_: end, end, arity },
+            Instr::If { else_, arity, .. } => {
+              code[start] = Instr::If { else_, end, arity };
+              code[else_] = Instr::Else { end };
+            }
+            _ => {}
+          }
+          Instr::End
+        }
+        "br" | "br_if" => {
+          let depth = immediate()?.parse::<usize>().map_err(|_| "invalid branch depth")?;
+          if depth >= blocks.len() {
+            return Err(format!("invalid branch depth {}", depth));
+          }
+          if name == "br" {
+            Instr::Br(depth)
+          } else {
+            Instr::BrIf(depth)
+          }
+        }
+        "return" => Instr::Return,
+        "call" | "return_call" => {
+          let atom = immediate()?;
+          let function = names.functions.get(atom).copied().ok_or(format!("unknown function {}", atom))?;
+          if name == "call" {
+            Instr::Call(function)
+          } else {
+            Instr::ReturnCall(function)
+          }
+        }
+        "call_indirect" | "return_call_indirect" => {
+          let list = body.next().and_then(|item| item.list("type")).ok_or("expect (type $name)")?;
+          let type_name = list.first().and_then(Sexpr::atom).unwrap_or_default();
+          let signature = names.types.get(type_name).copied().ok_or(format!("unknown type {}", type_name))?;
+          if name == "call_indirect" {
+            Instr::CallIndirect(signature)
+          } else {
+            Instr::ReturnCallIndirect(signature)
+          }
+        }
+        "local.get" | "local.set" | "local.tee" => {
+          let atom = immediate()?;
+          let index = locals.get(atom).copied().or(atom.parse().ok().filter(|index| *index < count));
+          let index = index.ok_or(format!("unknown local {}", atom))?;
+          match name {
+            "local.get" => Instr::LocalGet(index),
+            "local.set" => Instr::LocalSet(index),
+            _ => Instr::LocalTee(index),
+          }
+        }
+        "global.get" | "global.set" => {
+          let atom = immediate()?;
+          let index = names.globals.get(atom).copied().ok_or(format!("unknown global {}", atom))?;
+          if name == "global.get" {
+            Instr::GlobalGet(index)
+          } else {
+            Instr::GlobalSet(index)
+          }
+        }
+        "i32.load" | "i64.load" | "i32.store" | "i64.store" => {
+          let offset = match body.peek().and_then(|item| item.atom()?.strip_prefix("offset=")) {
+            Some(offset) => {
+              body.next();
+              offset.parse::<u32>().map_err(|_| "invalid offset")?
+            }
+            None => 0,
+          };
+          match name {
+            "i32.load" => Instr::I32Load(offset),
+            "i64.load" => Instr::I64Load(offset),
+            "i32.store" => Instr::I32Store(offset),
+            _ => Instr::I64Store(offset),
+          }
+        }
+        "memory.size" => Instr::MemorySize,
+        "memory.grow" => Instr::MemoryGrow,
+        "table.size" => Instr::TableSize,
+        "i32.const" => {
+          let atom = immediate()?;
+          Instr::Const(atom.parse::<i32>().map_err(|_| format!("invalid number {}", atom))? as i64)
+        }
+        "i64.const" => {
+          let atom = immediate()?;
+          Instr::Const(atom.parse::<i64>().map_err(|_| format!("invalid number {}", atom))?)
+        }
+        "i64.extend_i32_u" => Instr::ExtendI32U,
+        "i32.wrap_i64" => Instr::WrapI64,
+        _ => match name.split_once('.').map(|(ty, op)| (ty, Op::parse(op))) {
+          Some(("i32", Some(op))) => Instr::I32(op),
+          Some(("i64", Some(op))) => Instr::I64(op),
+          _ => return Err(format!("unknown instruction {}", name)),
+        },
+      };
+      code.push(instr);
+    }
+    if !blocks.is_empty() {
+      return Err("missing end".to_string());
+    }
+    code.push(Instr::End);
+    Ok(Function { signature, import: None, locals: count, code })
+  }
+
+  /// 执行导出的函数 (没有参数, 返回 i64)
+  pub fn run(&self, export: &str, out: &mut dyn Write) -> Result<isize, Trap> {
+    let function = *self.exports.get(export).ok_or_else(|| Trap::Message(format!("unknown export {}", export)))?;
+    if self.functions[function].signature != (0, 1) {
+      return trap("the export should take no params and return a value");
+    }
+    Instance::new(self, out).run(function).map(|value| value as isize)
+  }
+}
+
+/// 结构化指令的标签
+#[derive(Clone, Copy)]
+struct Label {
+  height: usize, // 进入时操作数栈的高度
+  arity: usize,  // 跳转时保留的值的个数
+  target: usize, // 跳转的目标
+  looping: bool, // loop 的标签在跳转后仍然有效
+}
+
+struct Frame {
+  function: usize,
+  pc: usize,
+  locals: usize, // 局部变量在 locals 中的起始位置
+  height: usize,
+  labels: Vec<Label>,
+}
+
+struct Instance<'a> {
+  module: &'a Module,
+  out: &'a mut dyn Write,
+  builtins: Builtins,
+  memory: Vec<u8>,
+  globals: Vec<i64>,
+  stack: Vec<i64>,
+  locals: Vec<i64>,
+  frames: Vec<Frame>,
+}
+
+impl<'a> Instance<'a> {
+  fn new(module: &'a Module, out: &'a mut dyn Write) -> Self {
+    Instance {
+      module,
+      out,
+      builtins: Builtins::new(),
+      memory: vec![0; module.memory * PAGE_SIZE],
+      globals: module.globals.clone(),
+      stack: vec![],
+      locals: vec![],
+      frames: vec![],
+    }
+  }
+
+  fn pop(&mut self) -> i64 {
+    self.stack.pop().expect("operand stack underflow")
+  }
+
+  fn address(&self, base: i64, offset: u32, size: usize) -> Result<usize, Trap> {
+    let address = base as u32 as usize + offset as usize;
+    if address + size > self.memory.len() {
+      return trap("out of bounds memory access");
+    }
+    Ok(address)
+  }
+
+  fn load(&self, base: i64, offset: u32, size: usize) -> Result<i64, Trap> {
+    let address = self.address(base, offset, size)?;
+    let mut bytes = [0; 8];
+    bytes[..size].copy_from_slice(&self.memory[address..address + size]);
+    let value = i64::from_le_bytes(bytes);
+    Ok(if size == 4 { value as i32 as i64 } else { value })
+  }
+
+  fn store(&mut self, base: i64, offset: u32, size: usize, value: i64) -> Result<(), Trap> {
+    let address = self.address(base, offset, size)?;
+    self.memory[address..address + size].copy_from_slice(&value.to_le_bytes()[..size]);
+    Ok(())
+  }
+
+  /// 调用函数, 实参在操作数栈上
+  fn call(&mut self, function: usize) -> Result<(), Trap> {
+    let module = self.module;
+    let Function { signature: (params, _), import, locals, .. } = &module.functions[function];
+    if let Some(import) = import {
+      return self.host(import);
+    }
+    if self.frames.len() >= MAX_FRAMES {
+      return trap("call stack exhausted");
+    }
+
+    let base = self.locals.len();
+    let args = self.stack.len() - params;
+    self.locals.extend(self.stack.drain(args..));
+    self.locals.resize(base + locals, 0);
+    self.frames.push(Frame { function, pc: 0, locals: base, height: self.stack.len(), labels: vec![] });
+    Ok(())
+  }
+
+  /// 从当前函数返回, 返回值留在调用方的操作数栈上
+  fn ret(&mut self) {
+    let frame = self.frames.pop().unwrap();
+    let results = self.module.functions[frame.function].signature.1;
+    let values = self.stack.split_off(self.stack.len() - results);
+    self.stack.truncate(frame.height);
+    self.stack.extend(values);
+    self.locals.truncate(frame.locals);
+  }
+
+  /// 尾调用: 先返回再调用, 调用栈不增长
+  fn tail_call(&mut self, function: usize) -> Result<(), Trap> {
+    let params = self.module.functions[function].signature.0;
+    let args = self.stack.split_off(self.stack.len() - params);
+    let frame = self.frames.pop().unwrap();
+    self.stack.truncate(frame.height);
+    self.stack.extend(args);
+    self.locals.truncate(frame.locals);
+    self.call(function)
+  }
+
+  /// 取出间接调用的函数并检查签名
+  fn indirect(&mut self, signature: Signature) -> Result<usize, Trap> {
+    let index = self.pop() as u32 as usize;
+    match self.module.table.get(index) {
+      Some(Some(function)) if self.module.functions[*function].signature == signature => Ok(*function),
+      Some(Some(_)) => trap("indirect call type mismatch"),
+      Some(None) => trap("uninitialized element"),
+      None => trap("undefined element"),
+    }
+  }
+
+  /// 跳转到第 depth 层标签
+  fn branch(&mut self, depth: usize) {
+    let frame = self.frames.last_mut().unwrap();
+    let index = frame.labels.len() - 1 - depth;
+    let label = frame.labels[index];
+    let values = self.stack.split_off(self.stack.len() - label.arity);
+    self.stack.truncate(label.height);
+    self.stack.extend(values);
+    frame.labels.truncate(if label.looping { index + 1 } else { index });
+    frame.pc = label.target;
+  }
+
+  /// 宿主函数
+  fn host(&mut self, name: &str) -> Result<(), Trap> {
+    if name == "error" {
+      let (value, kind) = (self.pop(), self.pop() as i32);
+      return Err(match kind {
+        ILLEGAL_FUNCTION_ADDRESS => Trap::Runtime(RuntimeError::IllegalFunctionAddress(value as u32 as usize)),
+        DIVISION_BY_ZERO => Trap::Runtime(RuntimeError::DivisionByZero),
+        OVERFLOW => Trap::Runtime(RuntimeError::Overflow),
+        MISSING_ARGUMENTS => Trap::Message("missing arguments".to_string()),
+        OUT_OF_MEMORY => Trap::Message("out of memory".to_string()),
+        _ => Trap::Message(format!("unknown error {}", kind)),
+      });
+    }
+
+    let id = self
+      .builtins
+      .symbols()
+      .position(|symbol| symbol == name)
+      .ok_or_else(|| Trap::Message(format!("unknown import {}", name)))?;
+    let (argc, args) = (self.pop() as u32, self.pop());
+    let args =
+      (0..argc).map(|index| self.load(args, index * 8, 8).map(|arg| arg as isize)).collect::<Result<_, _>>()?;
+    let value = self.builtins.call(id, args, self.out);
+    self.stack.push(value as i64);
+    Ok(())
+  }
+
+  fn run(&mut self, function: usize) -> Result<i64, Trap> {
+    self.call(function)?;
+    while let Some(frame) = self.frames.last_mut() {
+      let instr = self.module.functions[frame.function].code[frame.pc];
+      frame.pc += 1;
+      match instr {
+        Instr::Unreachable => return trap("unreachable"),
+        Instr::Nop => {}
+        Instr::Drop => {
+          self.pop();
+        }
+        Instr::Block { end, arity } => {
+          frame.labels.push(Label { height: self.stack.len(), arity, target: end + 1, looping: false });
+        }
+        Instr::Loop => {
+          frame.labels.push(Label { height: self.stack.len(), arity: 0, target: frame.pc, looping: true });
+        }
+        Instr::If { else_, end, arity } => {
+          let condition = self.stack.pop().unwrap() as i32;
+          frame.labels.push(Label { height: self.stack.len(), arity, target: end + 1, looping: false });
+          if condition == 0 {
+            // 没有 else 分支时跳到 end, 由 end 弹出标签
+            frame.pc = if else_ == end { end } else { else_ + 1 };
+          }
+        }
+        Instr::Else { end } => frame.pc = end,
+        Instr::End => {
+          if frame.labels.pop().is_none() {
+            self.ret();
+          }
+        }
+        Instr::Br(depth) => self.branch(depth),
+        Instr::BrIf(depth) => {
+          if self.pop() as i32 != 0 {
+            self.branch(depth);
+          }
+        }
+        Instr::Return => self.ret(),
+        Instr::Call(function) => self.call(function)?,
+        Instr::CallIndirect(signature) => {
+          let function = self.indirect(signature)?;
+          self.call(function)?;
+        }
+        Instr::ReturnCall(function) => self.tail_call(function)?,
+        Instr::ReturnCallIndirect(signature) => {
+          let function = self.indirect(signature)?;
+          self.tail_call(function)?;
+        }
+        Instr::LocalGet(index) => self.stack.push(self.locals[frame.locals + index]),
+        Instr::LocalSet(index) => self.locals[frame.locals + index] = self.stack.pop().unwrap(),
+        Instr::LocalTee(index) => self.locals[frame.locals + index] = *self.stack.last().unwrap(),
+        Instr::GlobalGet(index) => self.stack.push(self.globals[index]),
+        Instr::GlobalSet(index) => self.globals[index] = self.pop(),
+        Instr::I32Load(offset) | Instr::I64Load(offset) => {
+          let size = if matches!(instr, Instr::I32Load(_)) { 4 } else { 8 };
+          let base = self.pop();
+          let value = self.load(base, offset, size)?;
+          self.stack.push(value);
+        }
+        Instr::I32Store(offset) | Instr::I64Store(offset) => {
+          let size = if matches!(instr, Instr::I32Store(_)) { 4 } else { 8 };
+          let (value, base) = (self.pop(), self.pop());
+          self.store(base, offset, size, value)?;
+        }
+        Instr::MemorySize => self.stack.push((self.memory.len() / PAGE_SIZE) as i64),
+        Instr::MemoryGrow => {
+          let (delta, pages) = (self.pop() as u32 as usize, self.memory.len() / PAGE_SIZE);
+          if pages + delta > MAX_PAGES {
+            self.stack.push(-1);
+          } else {
+            self.memory.resize((pages + delta) * PAGE_SIZE, 0);
+            self.stack.push(pages as i64);
+          }
+        }
+        Instr::TableSize => self.stack.push(self.module.table.len() as i64),
+        Instr::Const(value) => self.stack.push(value),
+        Instr::I32(Op::Eqz) | Instr::I64(Op::Eqz) => {
+          let value = self.pop();
+          let value = if instr == Instr::I32(Op::Eqz) { value as i32 as i64 } else { value };
+          self.stack.push((value == 0) as i64);
+        }
+        Instr::I32(op) => {
+          let (b, a) = (self.pop(), self.pop());
+          self.stack.push(op.apply(a as i32 as i64, b as i32 as i64, 32)?);
+        }
+        Instr::I64(op) => {
+          let (b, a) = (self.pop(), self.pop());
+          self.stack.push(op.apply(a, b, 64)?);
+        }
+        Instr::ExtendI32U => {
+          let value = self.pop();
+          self.stack.push(value as u32 as i64);
+        }
+        Instr::WrapI64 => {
+          let value = self.pop();
+          self.stack.push(value as i32 as i64);
+        }
+      }
+    }
+    Ok(self.pop())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Module, Trap};
+
+  fn run(text: &str) -> Result<isize, Trap> {
+    Module::parse(text).unwrap().run("main", &mut vec![])
+  }
+
+  #[test]
+  fn test_control() {
+    // 1 + 2 + ... + 10, 以及 if 的结果
+    let text = "(module
+      (func $main (export \"main\") (result i64) (local $i i64) (local $s i64)
+        block
+          loop
+            local.get $i
+            i64.const 10
+            i64.ge_s
+            br_if 1
+            local.get $i
+            i64.const 1
+            i64.add
+            local.tee $i
+            local.get $s
+            i64.add
+            local.set $s
+            br 0
+          end
+        end
+        local.get $s
+        i32.const 0
+        if (result i64)
+          i64.const 1
+        else
+          i64.const 2
+        end
+        i64.mul
+      ))";
+    assert_eq!(run(text), Ok(110));
+  }
+
+  #[test]
+  fn test_tail_call() {
+    // 尾递归不增长调用栈
+    let text = "(module
+      (func $sum (param $n i64) (param $acc i64) (result i64)
+        local.get $n
+        i64.eqz
+        if (result i64)
+          local.get $acc
+        else
+          local.get $n
+          i64.const 1
+          i64.sub
+          local.get $acc
+          local.get $n
+          i64.add
+          return_call $sum
+        end
+      )
+      (func $main (export \"main\") (result i64)
+        i64.const 2000000
+        i64.const 0
+        call $sum
+      ))";
+    assert_eq!(run(text), Ok(2000001000000));
+  }
+
+  #[test]
+  fn test_validate() {
+    let invalid = [
+      "(module (func $main (export \"main\") (result i64) i64.const 0)",
+      "(module (func $main (export \"main\") (result i64) block br 1 end i64.const 0))",
+      "(module (func $main (export \"main\") (result i64) local.get $x))",
+      "(module (func $main (export \"main\") (result i64) if i64.const 0))",
+      "(module (func $main (export \"main\") (result i64) i64.foo))",
+    ];
+    for text in invalid {
+      assert!(Module::parse(text).is_err(), "{}", text);
+    }
+
+    let text = "(module (memory 1) (func $main (export \"main\") (result i64) i32.const 65536 i64.load))";
+    assert_eq!(run(text), Err(Trap::Message("out of bounds memory access".to_string())));
+  }
+}
@@ -0,0 +1,689 @@
+use std::{collections::HashSet, fmt::Write as _};
+
+use crate::{
+  ast::{Expression, ExpressionKind, Identifier, Infix, Prefix, Program, Statement, StatementKind},
+  vm::builtins::Builtins,
+};
+
+use super::resolve::{Access, Error, Resolver, Symbol};
+
+pub mod interp;
+
+/// 影子栈 (传递实参) 的栈顶, 堆从这里开始向高地址增长
+const STACK_TOP: usize = 1 << 20;
+
+// 运行时错误的种类, 为宿主函数 pl0.error 的第一个参数
+pub(crate) const ILLEGAL_FUNCTION_ADDRESS: i32 = 0;
+pub(crate) const DIVISION_BY_ZERO: i32 = 1;
+pub(crate) const OVERFLOW: i32 = 2;
+pub(crate) const MISSING_ARGUMENTS: i32 = 3;
+pub(crate) const OUT_OF_MEMORY: i32 = 4;
+
+/// 模块中固定的辅助函数: 堆分配与检查溢出的整数运算
+const RUNTIME: &str = "  (func $alloc (param $size i32) (result i32) (local $p i32)
+    global.get $heap
+    local.tee $p
+    local.get $size
+    i32.add
+    global.set $heap
+    block
+      loop
+        global.get $heap
+        memory.size
+        i32.const 16
+        i32.shl
+        i32.le_u
+        br_if 1
+        i32.const 1
+        memory.grow
+        i32.const -1
+        i32.eq
+        if
+          i32.const 4
+          i64.const 0
+          call $error
+          unreachable
+        end
+        br 0
+      end
+    end
+    local.get $p
+  )
+  (func $add (param $a i64) (param $b i64) (result i64) (local $r i64)
+    local.get $a
+    local.get $b
+    i64.add
+    local.tee $r
+    local.get $a
+    i64.xor
+    local.get $r
+    local.get $b
+    i64.xor
+    i64.and
+    i64.const 0
+    i64.lt_s
+    if
+      i32.const 2
+      i64.const 0
+      call $error
+      unreachable
+    end
+    local.get $r
+  )
+  (func $sub (param $a i64) (param $b i64) (result i64) (local $r i64)
+    local.get $a
+    local.get $b
+    i64.sub
+    local.tee $r
+    local.get $a
+    i64.xor
+    local.get $a
+    local.get $b
+    i64.xor
+    i64.and
+    i64.const 0
+    i64.lt_s
+    if
+      i32.const 2
+      i64.const 0
+      call $error
+      unreachable
+    end
+    local.get $r
+  )
+  (func $mul (param $a i64) (param $b i64) (result i64) (local $r i64)
+    local.get $a
+    i64.const -1
+    i64.eq
+    if
+      i64.const 0
+      local.get $b
+      call $sub
+      return
+    end
+    local.get $a
+    local.get $b
+    i64.mul
+    local.set $r
+    local.get $a
+    i64.eqz
+    if
+    else
+      local.get $r
+      local.get $a
+      i64.div_s
+      local.get $b
+      i64.ne
+      if
+        i32.const 2
+        i64.const 0
+        call $error
+        unreachable
+      end
+    end
+    local.get $r
+  )
+  (func $div (param $a i64) (param $b i64) (result i64)
+    local.get $b
+    i64.eqz
+    if
+      i32.const 1
+      i64.const 0
+      call $error
+      unreachable
+    end
+    local.get $b
+    i64.const -1
+    i64.eq
+    if
+      i64.const 0
+      local.get $a
+      call $sub
+      return
+    end
+    local.get $a
+    local.get $b
+    i64.div_s
+  )
+";
+
+///
+/// 将抽象语法树翻译为 WebAssembly 文本格式的模块
+///
+/// 每个 PL/0 函数翻译为一个 wasm 函数 (env, args, argc) -> i64: env 为函数定义所在的作用域的栈帧,
+/// 实参由调用方写入线性内存中的影子栈. 被内层函数引用的变量保存在堆上的栈帧中 (第一个元素为静态链),
+/// 其余变量为 wasm 的局部变量. 闭包为 (栈帧 << 32) | 函数在表中的下标, 通过 call_indirect 调用.
+/// 内建函数与运行时错误为导入的宿主函数, 模块导出 main 与 memory
+pub fn compile(program: &Program) -> Result<String, Vec<Error>> {
+  let mut generator = Generator::new(HashSet::new());
+  generator.program(program);
+
+  // 第二遍: 被内层函数引用的作用域使用栈帧
+  if let Some(memory) = generator.resolver.captured().filter(|_| generator.errors.is_empty()) {
+    generator = Generator::new(memory);
+    generator.program(program);
+  }
+
+  if generator.errors.is_empty() {
+    Ok(generator.module())
+  } else {
+    Err(generator.errors)
+  }
+}
+
+/// 正在生成的函数体
+#[derive(Default)]
+struct Body {
+  code: Vec<(usize, String)>, // 缩进层数, 指令
+  depth: usize,
+  frames: Vec<(usize, usize)>, // 栈帧大小待回填的指令位置, 作用域
+}
+
+struct Generator {
+  resolver: Resolver,
+  builtins: Builtins,
+  errors: Vec<Error>,
+  bodies: Vec<String>, // 生成完成的函数, 按照编号排列
+  stack: Vec<Body>,
+}
+
+impl Generator {
+  fn new(memory: HashSet<usize>) -> Self {
+    Generator {
+      resolver: Resolver::new(memory),
+      builtins: Builtins::new(),
+      errors: vec![],
+      bodies: vec![],
+      stack: vec![],
+    }
+  }
+
+  fn module(&self) -> String {
+    let mut out = String::from("(module\n  (type $fn (func (param i32 i32 i32) (result i64)))\n");
+    for name in self.builtins.symbols() {
+      writeln!(out, "  (import \"pl0\" \"{0}\" (func ${0} (param i32 i32) (result i64)))", name).unwrap();
+    }
+    out.push_str("  (import \"pl0\" \"error\" (func $error (param i32 i64)))\n");
+    writeln!(out, "  (memory (export \"memory\") {})", STACK_TOP / 65536 + 1).unwrap();
+    writeln!(out, "  (table {} funcref)", self.bodies.len()).unwrap();
+    if self.bodies.len() > 1 {
+      let functions = (1..self.bodies.len()).map(|id| format!("$f{}", id)).collect::<Vec<_>>();
+      writeln!(out, "  (elem (i32.const 1) {})", functions.join(" ")).unwrap();
+    }
+    writeln!(out, "  (global $sp (mut i32) (i32.const {}))", STACK_TOP).unwrap();
+    writeln!(out, "  (global $heap (mut i32) (i32.const {}))", STACK_TOP).unwrap();
+    out.push_str(RUNTIME);
+    self.bodies.iter().for_each(|body| out.push_str(body));
+    out.push_str(")\n");
+    out
+  }
+
+  fn program(&mut self, program: &Program) {
+    self.bodies.push(String::new());
+    self.stack.push(Body::default());
+    self.enter_frame(0);
+    self.statements(&program.statements, false);
+    self.finish_function(0, "(func $main (export \"main\") (result i64)");
+  }
+
+  /// 生成一条指令, 结构化指令调整缩进
+  fn emit(&mut self, instruction: impl Into<String>) {
+    let instruction = instruction.into();
+    let body = self.stack.last_mut().unwrap();
+    if instruction == "end" || instruction == "else" {
+      body.depth -= 1;
+    }
+    body.code.push((body.depth, instruction.clone()));
+    if ["block", "loop", "if", "else"].iter().any(|prefix| instruction.split(' ').next() == Some(prefix)) {
+      body.depth += 1;
+    }
+  }
+
+  /// 函数生成完毕, 声明局部变量: 不在栈帧中的变量, 以及当前函数中各个作用域的栈帧指针
+  fn finish_function(&mut self, id: usize, header: &str) {
+    let mut body = self.stack.pop().unwrap();
+    for (index, scope) in body.frames.drain(..) {
+      let size = (self.resolver.scopes[scope].variables.len() + 1) * 8;
+      body.code[index].1 = format!("i32.const {}", size);
+    }
+
+    let mut out = format!("  {}\n", header);
+    let scopes = self.resolver.scopes.iter().enumerate().filter(|(_, scope)| scope.function == id);
+    let mut locals = vec!["(local $c i64)".to_string(), "(local $t i64)".to_string()];
+    for (index, scope) in scopes {
+      if scope.memory {
+        locals.push(format!("(local $s{} i32)", index));
+      } else {
+        locals.extend(scope.variables.iter().map(|variable| format!("(local $v{} i64)", variable)));
+      }
+    }
+    writeln!(out, "    {}", locals.join(" ")).unwrap();
+    for (depth, instruction) in body.code {
+      writeln!(out, "    {}{}", "  ".repeat(depth), instruction).unwrap();
+    }
+    out.push_str("  )\n");
+    self.bodies[id] = out;
+  }
+
+  /// 进入作用域时在堆上分配栈帧, 栈帧大小在函数生成完毕后回填
+  fn enter_frame(&mut self, scope: usize) {
+    if !self.resolver.scopes[scope].memory {
+      return;
+    }
+    let body = self.stack.last_mut().unwrap();
+    body.frames.push((body.code.len(), scope));
+    self.emit("i32.const 0");
+    self.emit("call $alloc");
+    self.emit(format!("local.tee $s{}", scope));
+    let parent = self.resolver.parent_frame(scope);
+    self.environment(parent);
+    self.emit("i32.store");
+  }
+
+  /// 栈帧指针
+  fn frame(&mut self, access: Access) {
+    match access {
+      Access::Local(scope) => self.emit(format!("local.get $s{}", scope)),
+      Access::Env(hops) => {
+        self.emit("local.get $env");
+        for _ in 0..hops {
+          self.emit("i32.load");
+        }
+      }
+    }
+  }
+
+  fn environment(&mut self, access: Option<Access>) {
+    match access {
+      Some(access) => self.frame(access),
+      None => self.emit("i32.const 0"),
+    }
+  }
+
+  fn load(&mut self, variable: usize) {
+    if self.resolver.in_memory(variable) {
+      let (scope, slot) = (self.resolver.variables[variable].scope, self.resolver.variables[variable].slot);
+      self.frame(self.resolver.access(scope));
+      self.emit(format!("i64.load offset={}", slot * 8));
+    } else {
+      self.emit(format!("local.get $v{}", variable));
+    }
+  }
+
+  /// 保存变量, 值由 value 生成, 保存之后值留在栈顶
+  fn store(&mut self, variable: usize, value: impl FnOnce(&mut Self)) {
+    if self.resolver.in_memory(variable) {
+      let (scope, slot) = (self.resolver.variables[variable].scope, self.resolver.variables[variable].slot);
+      self.frame(self.resolver.access(scope));
+      value(self);
+      self.emit("local.tee $t");
+      self.emit(format!("i64.store offset={}", slot * 8));
+      self.emit("local.get $t");
+    } else {
+      value(self);
+      self.emit(format!("local.tee $v{}", variable));
+    }
+  }
+
+  ///
+  /// 语句序列, 值为最后一条有值的语句的值 (默认为 0), tail 为真时最后一条表达式语句处于尾部位置
+  ///
+  fn statements(&mut self, statements: &[Statement], tail: bool) {
+    let mut value = false;
+    for (index, statement) in statements.iter().enumerate() {
+      let produce =
+        !matches!(statement.kind, StatementKind::Empty | StatementKind::Const(_) | StatementKind::Function(..));
+      if produce && value {
+        self.emit("drop");
+      }
+      value |= produce;
+      self.statement(statement, tail && index + 1 == statements.len());
+    }
+    if !value {
+      self.emit("i64.const 0");
+    }
+  }
+
+  fn statement(&mut self, statement: &Statement, tail: bool) {
+    match &statement.kind {
+      StatementKind::Empty => {}
+      StatementKind::Const(constants) => {
+        for (ident, e) in constants {
+          match e.kind {
+            ExpressionKind::Integer(value) => self.resolver.constant(&ident.name, value),
+            _ => self.errors.push((format!("only integer can assign to constant, but get {:?}", e.kind), e.pos)),
+          }
+        }
+      }
+
+      StatementKind::Variable(variables) => {
+        for (index, (ident, e)) in variables.iter().enumerate() {
+          if index > 0 {
+            self.emit("drop");
+          }
+          // 初始值中不能引用正在定义的变量
+          self.expression(e);
+          self.emit("local.set $c");
+          let variable = self.resolver.declare(&ident.name);
+          self.store(variable, |generator| generator.emit("local.get $c"));
+        }
+      }
+
+      StatementKind::Function(ident, args, statements) => {
+        self.function(ident, args, statements);
+      }
+
+      StatementKind::Assign(ident, e) => match self.resolver.find_variable(&ident.name) {
+        Some(variable) => self.store(variable, |generator| generator.expression(e)),
+        None => {
+          self.errors.push((format!("variable is undefined: {:}", ident.name), statement.pos));
+          self.emit("i64.const 0");
+        }
+      },
+
+      StatementKind::Return(e) => {
+        match e {
+          Some(e) if self.resolver.function() > 0 => self.tail_expression(e),
+          Some(e) => self.expression(e),
+          None => self.emit("i64.const 0"),
+        }
+        self.emit("return");
+      }
+
+      StatementKind::Expression(e) if tail => self.tail_expression(e),
+      StatementKind::Expression(e) => self.expression(e),
+    }
+  }
+
+  /// 函数定义, 函数名绑定在当前作用域中
+  fn function(&mut self, ident: &Identifier, args: &[Identifier], statements: &[Statement]) -> Option<usize> {
+    if self.builtins.lookup(&ident.name).is_some() {
+      self
+        .errors
+        .push((format!("unable define funcation name as same as builtins function: {}", ident.name), ident.pos));
+      return None;
+    }
+
+    let id = self.resolver.enter_function(&ident.name, args);
+    self.bodies.push(String::new());
+    self.stack.push(Body::default());
+
+    // 实参个数不足时报错, 多余的实参被忽略
+    if !args.is_empty() {
+      self.emit("local.get $argc");
+      self.emit(format!("i32.const {}", args.len()));
+      self.emit("i32.lt_u");
+      self.error_if(MISSING_ARGUMENTS);
+    }
+    let scope = self.resolver.functions[id].scope;
+    self.enter_frame(scope);
+    for (index, variable) in self.resolver.functions[id].params.clone().into_iter().enumerate() {
+      self.store(variable, |generator| {
+        generator.emit("local.get $args");
+        generator.emit(format!("i64.load offset={}", index * 8));
+      });
+      self.emit("drop");
+    }
+
+    self.statements(statements, true);
+    self.resolver.leave_function();
+
+    let header = format!(
+      "(func $f{} (type $fn) (param $env i32) (param $args i32) (param $argc i32) (result i64) ;; {}",
+      id, self.resolver.functions[id].name
+    );
+    self.finish_function(id, &header);
+    Some(id)
+  }
+
+  /// 栈顶 (i32) 不为 0 时报告运行时错误
+  fn error_if(&mut self, kind: i32) {
+    self.emit("if");
+    self.emit(format!("i32.const {}", kind));
+    self.emit("i64.const 0");
+    self.emit("call $error");
+    self.emit("unreachable");
+    self.emit("end");
+  }
+
+  /// 闭包: 函数定义所在的栈帧与函数在表中的下标
+  fn closure(&mut self, function: usize) {
+    let environment = self.resolver.environment(function);
+    self.environment(environment);
+    self.emit("i64.extend_i32_u");
+    self.emit("i64.const 32");
+    self.emit("i64.shl");
+    self.emit(format!("i64.const {}", function));
+    self.emit("i64.or");
+  }
+
+  fn tail_expression(&mut self, expression: &Expression) {
+    match &expression.kind {
+      ExpressionKind::Call(callee, args) => self.call(callee, args, true),
+      ExpressionKind::If(condition, then_s, else_s) => self.condition(condition, then_s, else_s, true),
+      _ => self.expression(expression),
+    }
+  }
+
+  fn expression(&mut self, expression: &Expression) {
+    match &expression.kind {
+      ExpressionKind::Identifier(name) => match self.resolver.find(name) {
+        Some(Symbol::Constant(value)) => self.emit(format!("i64.const {}", value)),
+        Some(Symbol::Variable(variable)) => self.load(variable),
+        Some(Symbol::Function(function)) => self.closure(function),
+        None => {
+          self.errors.push((format!("identifier is not define: {}", name), expression.pos));
+          self.emit("i64.const 0");
+        }
+      },
+
+      ExpressionKind::Integer(value) => self.emit(format!("i64.const {}", value)),
+
+      ExpressionKind::Infix(infix, left, right) => {
+        self.expression(left);
+        self.expression(right);
+        let (instruction, compare) = match infix {
+          Infix::Add => ("call $add", false),
+          Infix::Sub => ("call $sub", false),
+          Infix::Mul => ("call $mul", false),
+          Infix::Div => ("call $div", false),
+          Infix::Eq => ("i64.eq", true),
+          Infix::Ne => ("i64.ne", true),
+          Infix::Lt => ("i64.lt_s", true),
+          Infix::Gt => ("i64.gt_s", true),
+          Infix::LtEq => ("i64.le_s", true),
+          Infix::GtEq => ("i64.ge_s", true),
+        };
+        self.emit(instruction);
+        if compare {
+          self.emit("i64.extend_i32_u");
+        }
+      }
+
+      ExpressionKind::Prefix(Prefix::Not, e) => {
+        self.expression(e);
+        self.emit("i64.eqz");
+        self.emit("i64.extend_i32_u");
+      }
+      ExpressionKind::Prefix(Prefix::Neg, e) => {
+        self.emit("i64.const 0");
+        self.expression(e);
+        self.emit("call $sub");
+      }
+
+      ExpressionKind::Call(callee, args) => self.call(callee, args, false),
+
+      ExpressionKind::Function(ident, args, statements) => match self.function(ident, args, statements) {
+        Some(function) => self.closure(function),
+        None => self.emit("i64.const 0"),
+      },
+
+      ExpressionKind::If(condition, then_s, else_s) => self.condition(condition, then_s, else_s, false),
+
+      ExpressionKind::While(condition, statements) => {
+        self.emit("block");
+        self.emit("loop");
+        self.expression(condition);
+        self.emit("i64.eqz");
+        self.emit("br_if 1");
+        self.block(statements, false);
+        self.emit("drop");
+        self.emit("br 0");
+        self.emit("end");
+        self.emit("end");
+        self.emit("i64.const 0");
+      }
+    }
+  }
+
+  /// if 表达式, 两个分支都恰好留下一个值
+  fn condition(&mut self, condition: &Expression, then_s: &[Statement], else_s: &Option<Vec<Statement>>, tail: bool) {
+    self.expression(condition);
+    self.emit("i64.eqz");
+    self.emit("i32.eqz");
+    self.emit("if (result i64)");
+    self.block(then_s, tail);
+    self.emit("else");
+    match else_s {
+      Some(else_s) => self.block(else_s, tail),
+      None => self.emit("i64.const 0"),
+    }
+    self.emit("end");
+  }
+
+  /// 语句块, 语句块中定义的名字离开语句块后不可见
+  fn block(&mut self, statements: &[Statement], tail: bool) {
+    let scope = self.resolver.enter_block();
+    self.enter_frame(scope);
+    self.statements(statements, tail);
+    self.resolver.leave_block();
+  }
+
+  ///
+  /// 函数调用: 实参逆序求值写入影子栈, 间接调用时再对被调用的表达式求值.
+  /// 调用之前释放影子栈上的实参, 被调函数在执行其他代码之前取出实参, 所以尾调用也可以使用
+  ///
+  fn call(&mut self, callee: &Expression, args: &[Expression], tail: bool) {
+    let call = if tail && self.resolver.function() > 0 { "return_call" } else { "call" };
+    if let ExpressionKind::Identifier(name) = &callee.kind {
+      if let Some(id) = self.builtins.lookup(name) {
+        self.arguments(args);
+        self.release(args.len());
+        self.emit(format!("call ${}", self.builtins.symbol(id)));
+        return;
+      }
+
+      if let Some(Symbol::Function(function)) = self.resolver.find(name) {
+        self.arguments(args);
+        let environment = self.resolver.environment(function);
+        self.environment(environment);
+        self.release(args.len());
+        self.emit(format!("{} $f{}", call, function));
+        return;
+      }
+    }
+
+    // 间接调用: 检查闭包中的函数下标
+    self.arguments(args);
+    self.expression(callee);
+    self.emit("local.tee $c");
+    self.emit("i32.wrap_i64");
+    self.emit("i32.const 1");
+    self.emit("i32.sub");
+    self.emit("table.size");
+    self.emit("i32.const 1");
+    self.emit("i32.sub");
+    self.emit("i32.ge_u");
+    self.emit("if");
+    self.emit(format!("i32.const {}", ILLEGAL_FUNCTION_ADDRESS));
+    self.emit("local.get $c");
+    self.emit("call $error");
+    self.emit("unreachable");
+    self.emit("end");
+
+    self.emit("local.get $c");
+    self.emit("i64.const 32");
+    self.emit("i64.shr_u");
+    self.emit("i32.wrap_i64");
+    self.release(args.len());
+    self.emit("local.get $c");
+    self.emit("i32.wrap_i64");
+    self.emit(format!("{}_indirect (type $fn)", call));
+  }
+
+  /// 在影子栈上分配空间, 逆序求值实参
+  fn arguments(&mut self, args: &[Expression]) {
+    if args.is_empty() {
+      return;
+    }
+    self.emit("global.get $sp");
+    self.emit(format!("i32.const {}", args.len() * 8));
+    self.emit("i32.sub");
+    self.emit("global.set $sp");
+    for (index, e) in args.iter().enumerate().rev() {
+      self.emit("global.get $sp");
+      self.expression(e);
+      self.emit(format!("i64.store offset={}", index * 8));
+    }
+  }
+
+  /// 压入实参的地址与个数, 然后释放影子栈上的实参
+  fn release(&mut self, argc: usize) {
+    self.emit("global.get $sp");
+    self.emit(format!("i32.const {}", argc));
+    if argc > 0 {
+      self.emit("global.get $sp");
+      self.emit(format!("i32.const {}", argc * 8));
+      self.emit("i32.add");
+      self.emit("global.set $sp");
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    ast::Program,
+    backend::tests::{self, Backend, Report},
+    parser::Paser,
+  };
+
+  use super::{compile, interp::Module};
+
+  /// 解释执行生成的模块, 输出之后是返回值或者运行时错误
+  fn run(program: &Program, name: &str) -> Result<Option<String>, String> {
+    let text = compile(program).map_err(|err| format!("{:?}", err))?;
+    let module = Module::parse(&text).unwrap_or_else(|err| panic!("{}: {}\n{}", name, err, text));
+    let mut output = vec![];
+    let result = module.run("main", &mut output);
+    let mut output = String::from_utf8(output).unwrap();
+    match result {
+      Ok(value) => output += &format!("{}\n", value),
+      Err(err) => output += &format!("运行时错误: {}\n", err),
+    }
+    Ok(Some(output))
+  }
+
+  const WAT: Backend = Backend { report: Report::Result, run };
+
+  #[test]
+  fn test_examples() {
+    tests::examples(&WAT);
+  }
+
+  #[test]
+  fn test_programs() {
+    tests::programs(&WAT);
+  }
+
+  #[test]
+  fn test_differential() {
+    tests::differential(&WAT);
+  }
+
+  #[test]
+  fn test_errors() {
+    let program = Paser::paser("x = 1; println(z); fn print() { 0 }").unwrap();
+    assert_eq!(compile(&program).unwrap_err().len(), 3);
+  }
+}
@@ -0,0 +1,416 @@
+use std::{
+  env,
+  fmt::Write as _,
+  fs, io,
+  path::Path,
+  process::{self, Command},
+  sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::vm::Opcode;
+
+/// 运行时的 C 源代码: 程序入口, 内建函数与运行时错误
+pub const RUNTIME: &str = include_str!("runtime.c");
+
+/// 堆的大小 (字节), 与虚拟机相同只分配不回收
+const HEAP_SIZE: usize = 1 << 28;
+
+/// 闭包的标记, 间接调用时用于检查地址是否为闭包
+const CLOSURE_TAG: u64 = 0x706c_305f_636c_6f73;
+
+macro_rules! emit {
+  ($self:ident, $($arg:tt)*) => {
+    writeln!($self.out, "  {}", format_args!($($arg)*)).unwrap()
+  };
+}
+
+///
+/// 将虚拟机指令翻译为 x86-64 System V 的 GNU 汇编 (AT&T 语法)
+///
+/// 每条指令翻译为一段汇编, 与虚拟机使用相同的内存布局, 只是栈向低地址增长:
+/// 地址为 bp + k 的数据位于 rbp - 8k, 栈帧依次为静态链, 动态链与返回地址, 实参位于 rbp + 8(i + 1).
+/// rsp 指向栈顶元素, 堆上的栈帧与作用域分配在 .bss 中, 闭包为堆上的 [标记, 函数地址, 静态链]
+pub fn compile(codes: &[Opcode]) -> String {
+  let mut emitter = Emitter { codes, out: String::new() };
+  emitter.program();
+  emitter.out
+}
+
+/// 汇编并与运行时链接为可执行文件, 需要系统中的 cc
+pub fn build(codes: &[Opcode], output: &Path) -> io::Result<()> {
+  static COUNTER: AtomicUsize = AtomicUsize::new(0);
+  let dir = env::temp_dir().join(format!("pl0-{}-{}", process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+  fs::create_dir_all(&dir)?;
+
+  let (asm, runtime) = (dir.join("program.s"), dir.join("runtime.c"));
+  fs::write(&asm, compile(codes))?;
+  fs::write(&runtime, RUNTIME)?;
+  let result = Command::new("cc").arg("-O2").arg("-o").arg(output).arg(&asm).arg(&runtime).output();
+  fs::remove_dir_all(&dir)?;
+
+  let result = result?;
+  if !result.status.success() {
+    return Err(io::Error::other(String::from_utf8_lossy(&result.stderr).into_owned()));
+  }
+  Ok(())
+}
+
+struct Emitter<'a> {
+  codes: &'a [Opcode],
+  out: String,
+}
+
+impl Emitter<'_> {
+  fn program(&mut self) {
+    self.out.push_str("  .text\n  .globl pl0_main\n  .type pl0_main, @function\npl0_main:\n");
+    // 保存被调用者保存的寄存器, 切换到运行时分配的栈上
+    emit!(self, "push %rbx");
+    emit!(self, "push %rbp");
+    emit!(self, "mov %rsp, pl0_saved_rsp(%rip)");
+    emit!(self, "mov %rdi, %rsp");
+    // 全局作用域的栈帧, 返回地址为 pl0_exit
+    emit!(self, "lea -8(%rsp), %rbp");
+    emit!(self, "movq $0, -8(%rsp)");
+    emit!(self, "movq $0, -16(%rsp)");
+    emit!(self, "leaq pl0_exit(%rip), %rax");
+    emit!(self, "mov %rax, -24(%rsp)");
+
+    for (ip, code) in self.codes.iter().enumerate() {
+      writeln!(self.out, ".L{}: # {:?}", ip, code).unwrap();
+      self.instruction(ip, *code);
+    }
+
+    self.out.push_str("pl0_exit:\n");
+    emit!(self, "pop %rax");
+    emit!(self, "mov pl0_saved_rsp(%rip), %rsp");
+    emit!(self, "pop %rbp");
+    emit!(self, "pop %rbx");
+    emit!(self, "ret");
+
+    // 运行时错误, esi 为指令地址
+    for (kind, name) in ["illegal_function_address", "division_by_zero", "overflow", "out_of_memory"].iter().enumerate()
+    {
+      writeln!(self.out, "pl0_{}:", name).unwrap();
+      emit!(self, "mov ${}, %edi", kind);
+      emit!(self, "and $-16, %rsp");
+      emit!(self, "call pl0_error");
+    }
+
+    self.out.push_str("\n  .bss\n  .p2align 4\n");
+    self.out.push_str("pl0_saved_rsp:\n  .zero 8\npl0_heap_used:\n  .zero 8\n");
+    writeln!(self.out, "pl0_heap:\n  .zero {}", HEAP_SIZE).unwrap();
+    self.out.push_str("  .section .note.GNU-stack,\"\",@progbits\n");
+  }
+
+  fn instruction(&mut self, ip: usize, code: Opcode) {
+    match code {
+      Opcode::None => {}
+      // 函数地址翻译为对应的标号
+      Opcode::Lit(addr)
+        if matches!(self.codes.get(ip + 1), Some(Opcode::Cal(_) | Opcode::Closure(..) | Opcode::TailCall(..))) =>
+      {
+        emit!(self, "leaq .L{}(%rip), %rax", addr);
+        emit!(self, "push %rax");
+      }
+      Opcode::Lit(value) if i32::try_from(value).is_ok() => emit!(self, "push ${}", value),
+      Opcode::Lit(value) => {
+        emit!(self, "movabs ${}, %rax", value);
+        emit!(self, "push %rax");
+      }
+      Opcode::Lod(0, addr) => emit!(self, "push {}(%rbp)", -8 * addr),
+      Opcode::Lod(level, addr) => {
+        self.base(level);
+        emit!(self, "push {}(%rax)", -8 * addr);
+      }
+      Opcode::Lod1(offset) => {
+        emit!(self, "mov {}(%rsp), %rax", 8 * (offset - 1));
+        emit!(self, "push %rax");
+      }
+      Opcode::Sto(level, addr) => {
+        self.base(level);
+        emit!(self, "mov (%rsp), %rcx");
+        emit!(self, "mov %rcx, {}(%rax)", -8 * addr);
+      }
+      Opcode::Int(0) => {}
+      Opcode::Int(size) => emit!(self, "sub ${}, %rsp", 8 * size),
+      Opcode::Jmp(target) => emit!(self, "jmp .L{}", target),
+      Opcode::Jpc(target) => {
+        emit!(self, "pop %rax");
+        emit!(self, "test %rax, %rax");
+        emit!(self, "jz .L{}", target);
+      }
+      Opcode::Cal(level) => {
+        emit!(self, "pop %rcx");
+        self.base(level);
+        self.call(ip);
+      }
+      Opcode::Closure(level, _) => {
+        emit!(self, "pop %rcx");
+        self.alloc(3, ip);
+        self.base(level);
+        emit!(self, "movabs ${}, %r8", CLOSURE_TAG);
+        emit!(self, "mov %r8, (%rdx)");
+        emit!(self, "mov %rcx, -8(%rdx)");
+        emit!(self, "mov %rax, -16(%rdx)");
+        emit!(self, "push %rdx");
+      }
+      Opcode::CallIndirect(_) => {
+        self.unpack_closure(ip);
+        self.call(ip);
+      }
+      Opcode::TailCall(level, argc, depth) => {
+        emit!(self, "pop %rcx");
+        self.base(level);
+        self.tail_call(argc, depth);
+      }
+      Opcode::TailCallIndirect(argc, depth) => {
+        self.unpack_closure(ip);
+        self.tail_call(argc, depth);
+      }
+      Opcode::HeapFrame(argc, size) => {
+        // 复制实参与链接数据, 堆上栈帧的动态链位置记录栈上的栈帧
+        emit!(self, "mov %rbp, %rcx");
+        self.alloc(argc + size, ip);
+        emit!(self, "sub ${}, %rdx", 8 * argc);
+        for index in 1..=argc {
+          emit!(self, "mov {}(%rcx), %rax", 8 * index);
+          emit!(self, "mov %rax, {}(%rdx)", 8 * index);
+        }
+        emit!(self, "mov (%rcx), %rax");
+        emit!(self, "mov %rax, (%rdx)");
+        emit!(self, "mov %rcx, -8(%rdx)");
+        emit!(self, "mov -16(%rcx), %rax");
+        emit!(self, "mov %rax, -16(%rdx)");
+        emit!(self, "lea -16(%rcx), %rsp");
+        emit!(self, "mov %rdx, %rbp");
+      }
+      Opcode::Builtin(id, argc) => {
+        // 实参依次位于栈顶, rbx 保存对齐前的栈顶
+        emit!(self, "mov ${}, %edi", id);
+        emit!(self, "mov ${}, %esi", argc);
+        emit!(self, "mov %rsp, %rdx");
+        emit!(self, "mov %rsp, %rbx");
+        emit!(self, "and $-16, %rsp");
+        emit!(self, "call pl0_builtin");
+        emit!(self, "lea {}(%rbx), %rsp", 8 * argc);
+        emit!(self, "push %rax");
+      }
+      Opcode::Ret => {
+        emit!(self, "pop %rax");
+        emit!(self, "mov %rbp, %rcx");
+        self.stack_frame("%rcx");
+        emit!(self, "mov -8(%rcx), %rbp");
+        emit!(self, "mov -16(%rcx), %rdx");
+        emit!(self, "lea 8(%rcx), %rsp");
+        emit!(self, "push %rax");
+        emit!(self, "jmp *%rdx");
+      }
+      Opcode::CallClean(num) => {
+        emit!(self, "pop %rax");
+        emit!(self, "add ${}, %rsp", 8 * num);
+        emit!(self, "push %rax");
+      }
+      Opcode::EnterScope => {
+        emit!(self, "mov %rbp, -8(%rsp)");
+        emit!(self, "lea -8(%rsp), %rbp");
+      }
+      Opcode::HeapScope(size) => {
+        self.alloc(size, ip);
+        emit!(self, "mov %rbp, (%rdx)");
+        emit!(self, "mov %rsp, -8(%rdx)");
+        emit!(self, "mov %rdx, %rbp");
+      }
+      Opcode::LeaveScope => {
+        emit!(self, "pop %rax");
+        emit!(self, "mov %rbp, %rcx");
+        emit!(self, "lea 8(%rcx), %rsp");
+        self.if_heap("%rcx", "mov -8(%rcx), %rsp");
+        emit!(self, "mov (%rcx), %rbp");
+        emit!(self, "push %rax");
+      }
+      Opcode::Pop => emit!(self, "add $8, %rsp"),
+      Opcode::Not => {
+        emit!(self, "pop %rax");
+        emit!(self, "test %rax, %rax");
+        emit!(self, "sete %al");
+        emit!(self, "movzbl %al, %eax");
+        emit!(self, "push %rax");
+      }
+      _ => self.binary(ip, code),
+    }
+  }
+
+  fn binary(&mut self, ip: usize, code: Opcode) {
+    emit!(self, "pop %rcx");
+    emit!(self, "pop %rax");
+    let condition = match code {
+      Opcode::Add | Opcode::Sub | Opcode::Mul => {
+        let op = match code {
+          Opcode::Add => "add",
+          Opcode::Sub => "sub",
+          _ => "imul",
+        };
+        emit!(self, "{} %rcx, %rax", op);
+        self.error_if("jo", "overflow", ip);
+        emit!(self, "push %rax");
+        return;
+      }
+      Opcode::Div => {
+        emit!(self, "test %rcx, %rcx");
+        self.error_if("jz", "division_by_zero", ip);
+        // 除数为 -1 时 idiv 会因溢出产生异常, 用 neg 代替
+        emit!(self, "cmp $-1, %rcx");
+        emit!(self, "jne 1f");
+        emit!(self, "neg %rax");
+        self.error_if("jo", "overflow", ip);
+        emit!(self, "jmp 2f");
+        self.out.push_str("1:\n");
+        emit!(self, "cqo");
+        emit!(self, "idiv %rcx");
+        self.out.push_str("2:\n");
+        emit!(self, "push %rax");
+        return;
+      }
+      Opcode::Eq => "e",
+      Opcode::Ne => "ne",
+      Opcode::Lt => "l",
+      Opcode::Le => "le",
+      Opcode::Gt => "g",
+      Opcode::Ge => "ge",
+      _ => unreachable!(),
+    };
+    emit!(self, "cmp %rcx, %rax");
+    emit!(self, "set{} %al", condition);
+    emit!(self, "movzbl %al, %eax");
+    emit!(self, "push %rax");
+  }
+
+  /// 条件成立时报告运行时错误
+  fn error_if(&mut self, jump: &str, error: &str, ip: usize) {
+    emit!(self, "mov ${}, %esi", ip);
+    emit!(self, "{} pl0_{}", jump, error);
+  }
+
+  /// 上 level 层的基地址, 结果在 rax 中
+  fn base(&mut self, level: usize) {
+    emit!(self, "mov %rbp, %rax");
+    for _ in 0..level {
+      emit!(self, "mov (%rax), %rax");
+    }
+  }
+
+  /// 地址在堆上时执行 instruction, 使用 r10 与 r11
+  fn if_heap(&mut self, register: &str, instruction: &str) {
+    emit!(self, "leaq pl0_heap(%rip), %r10");
+    emit!(self, "mov {}, %r11", register);
+    emit!(self, "sub %r10, %r11");
+    emit!(self, "cmp ${}, %r11", HEAP_SIZE);
+    emit!(self, "jae 1f");
+    emit!(self, "{}", instruction);
+    self.out.push_str("1:\n");
+  }
+
+  /// 堆上的栈帧中记录了调用时在栈上建立的栈帧
+  fn stack_frame(&mut self, register: &str) {
+    self.if_heap(register, &format!("mov -8({}), {}", register, register));
+  }
+
+  /// 在堆上分配 size 个元素, rdx 为第一个元素 (最高地址) 的地址, 使用 rax
+  fn alloc(&mut self, size: usize, ip: usize) {
+    emit!(self, "mov pl0_heap_used(%rip), %rdx");
+    emit!(self, "add ${}, %rdx", 8 * size);
+    emit!(self, "cmp ${}, %rdx", HEAP_SIZE);
+    self.error_if("ja", "out_of_memory", ip);
+    emit!(self, "mov %rdx, pl0_heap_used(%rip)");
+    emit!(self, "leaq pl0_heap-8(%rip), %rax");
+    emit!(self, "add %rax, %rdx");
+  }
+
+  /// 解开栈顶的闭包, rcx 为函数地址, rax 为静态链
+  fn unpack_closure(&mut self, ip: usize) {
+    emit!(self, "pop %rax");
+    emit!(self, "mov %rax, %rdx");
+    emit!(self, "mov ${}, %esi", ip);
+    emit!(self, "leaq pl0_heap(%rip), %r10");
+    emit!(self, "mov %rax, %r11");
+    emit!(self, "sub %r10, %r11");
+    emit!(self, "cmp ${}, %r11", HEAP_SIZE);
+    emit!(self, "jae pl0_illegal_function_address");
+    emit!(self, "movabs ${}, %r8", CLOSURE_TAG);
+    emit!(self, "cmp %r8, (%rax)");
+    emit!(self, "jne pl0_illegal_function_address");
+    emit!(self, "mov -8(%rax), %rcx");
+    emit!(self, "mov -16(%rax), %rax");
+  }
+
+  /// 建立栈帧并跳转到 rcx, rax 为静态链, 返回地址为下一条指令
+  fn call(&mut self, ip: usize) {
+    emit!(self, "mov %rax, -8(%rsp)");
+    emit!(self, "mov %rbp, -16(%rsp)");
+    emit!(self, "leaq .L{}(%rip), %rax", ip + 1);
+    emit!(self, "mov %rax, -24(%rsp)");
+    emit!(self, "lea -8(%rsp), %rbp");
+    emit!(self, "jmp *%rcx");
+  }
+
+  /// 复用当前函数的栈帧跳转到 rcx, rax 为静态链
+  fn tail_call(&mut self, argc: usize, depth: usize) {
+    emit!(self, "mov %rax, %r9");
+    emit!(self, "mov %rbp, %rdx");
+    for _ in 0..depth {
+      emit!(self, "mov (%rdx), %rdx");
+    }
+    self.stack_frame("%rdx");
+    for index in 1..=argc {
+      emit!(self, "mov {}(%rsp), %rax", 8 * (index - 1));
+      emit!(self, "mov %rax, {}(%rdx)", 8 * index);
+    }
+    emit!(self, "mov %r9, (%rdx)");
+    emit!(self, "lea 8(%rdx), %rsp");
+    emit!(self, "mov %rdx, %rbp");
+    emit!(self, "jmp *%rcx");
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{env, fs, process::Command};
+
+  use crate::{
+    ast::Program,
+    backend::tests::{self, Backend, Report},
+    compiler::Compiler,
+  };
+
+  use super::build;
+
+  /// 编译为可执行文件并运行, 系统中没有 cc 时返回 None
+  fn run(program: &Program, name: &str) -> Result<Option<String>, String> {
+    let codes = Compiler::compile(program).map_err(|err| format!("{:?}", err))?;
+    if Command::new("cc").arg("--version").output().is_err() {
+      return Ok(None);
+    }
+    let path = env::temp_dir().join(format!("pl0-x86_64-{}-{}", std::process::id(), name));
+    build(&codes, &path).unwrap();
+    let output = Command::new(&path).output().unwrap();
+    fs::remove_file(&path).unwrap();
+    Ok(Some(String::from_utf8(output.stdout).unwrap()))
+  }
+
+  const X86_64: Backend = Backend { report: Report::Address, run };
+
+  #[test]
+  fn test_examples() {
+    tests::examples(&X86_64);
+  }
+
+  #[test]
+  fn test_programs() {
+    tests::programs(&X86_64);
+  }
+
+  #[test]
+  fn test_differential() {
+    tests::differential(&X86_64);
+  }
+}
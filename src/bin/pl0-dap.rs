@@ -0,0 +1,9 @@
+//! PL/0 调试适配器, 通过标准输入输出与编辑器通信
+
+use std::io;
+
+use pl0::dap::Server;
+
+fn main() -> io::Result<()> {
+  Server::new(io::stdout().lock()).run(io::stdin().lock())
+}
@@ -0,0 +1,10 @@
+//! PL/0 语言服务器, 通过标准输入输出与编辑器通信
+
+use std::{io, process};
+
+use pl0::lsp::Server;
+
+fn main() -> io::Result<()> {
+  let code = Server::new(io::stdout().lock()).run(io::stdin().lock())?;
+  process::exit(code)
+}
@@ -0,0 +1,532 @@
+//! README 中 PL/0 文法的 BNF 形式, 以及由它构造的 LR(1) 分析表驱动的第二个语法分析器
+//!
+//! 两个分析器得到相同的抽象语法树, 测试保证文法与手写的分析器不会不一致
+
+use std::collections::HashSet;
+
+use crate::{
+  ast::{Expression, ExpressionKind, Identifier, Prefix, Program, Statement, StatementKind},
+  lexer::Lexer,
+  lr1::{Action, Grammar, Lr1, Tree},
+  parser::Paser,
+  token::Token,
+  SpanOffset,
+};
+
+type Result<T> = std::result::Result<T, (String, SpanOffset)>;
+
+pub const NONTERMINALS: [&str; 19] = [
+  "program",
+  "statements",
+  "statement",
+  "semicolons",
+  "body",
+  "constants",
+  "variables",
+  "variable",
+  "params",
+  "param-list",
+  "block",
+  "expression",
+  "relation",
+  "sum",
+  "product",
+  "unary",
+  "postfix",
+  "arguments",
+  "primary",
+];
+
+/// 终结符与 Token 的 Display 相同, 标识符与整数除外
+pub const TERMINALS: [&str; 31] = [
+  "ident", "integer", "!", "+", "-", "*", "/", "<", "<=", ">", ">=", "==", "!=", "=", "+=", "-=", "*=", "/=", "(", ")",
+  "{", "}", ",", ";", "if", "else", "while", "const", "var", "fn", "return",
+];
+
+///
+/// 产生式, 与手写的分析器相同:
+/// 语句之后可以有任意个分号, 以分号开头的是空语句; 表达式按优先级分层, 调用可以跟在任意表达式之后
+///
+/// 文法不是 LR(1) 的, 冲突在 RESOLUTIONS 中逐个声明解决方式
+///
+pub const PRODUCTIONS: [(&str, &str); 58] = [
+  ("program", "statements"),
+  ("statements", ""),
+  ("statements", "statements statement"),
+  ("statement", "body semicolons"),
+  ("semicolons", ""),
+  ("semicolons", "semicolons ;"),
+  ("body", ";"),
+  ("body", "const constants"),
+  ("body", "var variables"),
+  ("body", "fn ident ( params ) block"),
+  ("body", "ident = expression"),
+  ("body", "ident += expression"),
+  ("body", "ident -= expression"),
+  ("body", "ident *= expression"),
+  ("body", "ident /= expression"),
+  ("body", "return"),
+  ("body", "return expression"),
+  ("body", "expression"),
+  ("constants", "ident = integer"),
+  ("constants", "constants , ident = integer"),
+  ("variables", "variable"),
+  ("variables", "variables , variable"),
+  ("variable", "ident"),
+  ("variable", "ident = expression"),
+  ("params", ""),
+  ("params", "param-list"),
+  ("param-list", "ident"),
+  ("param-list", "param-list , ident"),
+  ("block", "{ statements }"),
+  ("expression", "expression == relation"),
+  ("expression", "expression != relation"),
+  ("expression", "relation"),
+  ("relation", "relation < sum"),
+  ("relation", "relation > sum"),
+  ("relation", "relation <= sum"),
+  ("relation", "relation >= sum"),
+  ("relation", "sum"),
+  ("sum", "sum + product"),
+  ("sum", "sum - product"),
+  ("sum", "product"),
+  ("product", "product * unary"),
+  ("product", "product / unary"),
+  ("product", "unary"),
+  ("unary", "! unary"),
+  ("unary", "- unary"),
+  ("unary", "postfix"),
+  ("postfix", "postfix ( )"),
+  ("postfix", "postfix ( arguments )"),
+  ("postfix", "primary"),
+  ("arguments", "expression"),
+  ("arguments", "arguments , expression"),
+  ("primary", "ident"),
+  ("primary", "integer"),
+  ("primary", "( expression )"),
+  ("primary", "if expression block"),
+  ("primary", "if expression block else block"),
+  ("primary", "while expression block"),
+  ("primary", "fn ident ( params ) block"),
+];
+
+/// 冲突的解决方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+  Shift,                // 移进
+  Reduce(&'static str), // 按指定的产生式归约
+}
+
+///
+/// 冲突的解决方式: (冲突中被放弃的归约, 向前看符号, 选择的动作), # 为输入结束
+///
+/// 前四项来自语句之间不需要分隔符, 与普拉特分析器相同, 选择继续解析当前的语句.
+/// 构造分析表时每个冲突都必须有声明, 每个声明也都必须对应冲突
+///
+pub const RESOLUTIONS: [(&str, &str, Resolution); 9] = [
+  // a -b 为减法, 而不是 a 与 -b 两条语句
+  ("relation -> sum", "-", Resolution::Shift),
+  ("relation -> relation < sum", "-", Resolution::Shift),
+  ("relation -> relation > sum", "-", Resolution::Shift),
+  ("relation -> relation <= sum", "-", Resolution::Shift),
+  ("relation -> relation >= sum", "-", Resolution::Shift),
+  // a (b) 为调用, 而不是 a 与 (b) 两条语句
+  ("unary -> postfix", "(", Resolution::Shift),
+  // return 之后可以开始表达式时作为返回值
+  ("body -> return", "! ( - fn ident if integer while", Resolution::Shift),
+  // 语句之后的分号属于该语句, 而不是空语句
+  ("statement -> body semicolons", ";", Resolution::Shift),
+  // 语句开头的 fn 为函数定义语句, 而不是函数表达式
+  (
+    "primary -> fn ident ( params ) block",
+    "! # ( - ; const fn ident if integer return var while }",
+    Resolution::Reduce("body -> fn ident ( params ) block"),
+  ),
+];
+
+/// PL/0 的 BNF 文法
+pub fn grammar() -> Grammar {
+  Grammar::new(&NONTERMINALS, &TERMINALS, &PRODUCTIONS, "program").unwrap()
+}
+
+///
+/// 表驱动的语法分析器, 与 `Paser` 得到相同的抽象语法树
+///
+pub struct TableParser {
+  lr1: Lr1,
+}
+
+impl Default for TableParser {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl TableParser {
+  /// 构造分析表, 按 RESOLUTIONS 解决冲突
+  pub fn new() -> Self {
+    let mut lr1 = Lr1::new(&grammar());
+    resolve(&mut lr1).unwrap_or_else(|err| panic!("{}", err));
+    TableParser { lr1 }
+  }
+
+  pub fn lr1(&self) -> &Lr1 {
+    &self.lr1
+  }
+
+  pub fn parse(&self, input: &str) -> Result<Program> {
+    let (mut lexer, mut tokens, mut terminals) = (Lexer::new(input), vec![], vec![]);
+    let eof = loop {
+      let (token, pos) = lexer.next();
+      if token == Token::EOF {
+        break pos;
+      }
+      terminals.push(self.terminal(&token).ok_or_else(|| (format!("unexpected token: {}", token), pos))?);
+      tokens.push((token, pos));
+    };
+
+    match self.lr1.parse(&terminals) {
+      Ok(tree) => Ok(Builder { tokens: &tokens }.program(&tree)),
+      Err(position) => {
+        let (token, pos) = tokens.get(position).cloned().unwrap_or((Token::EOF, eof));
+        let (steps, _) = self.lr1.trace(&terminals);
+        let state = *steps.last().unwrap().states.last().unwrap();
+        let expected = self.lr1.action[state]
+          .keys()
+          .map(|terminal| self.lr1.grammar.name(*terminal).replace(crate::lr1::END, "eof"))
+          .collect::<Vec<_>>();
+        Err((format!("expect {}, but get {}", expected.join(" or "), token), pos))
+      }
+    }
+  }
+
+  fn terminal(&self, token: &Token) -> Option<usize> {
+    let name = match token {
+      Token::Ident(_) => "ident".to_string(),
+      Token::Integer(_) => "integer".to_string(),
+      token => token.to_string(),
+    };
+    self.lr1.grammar.symbol(&name).filter(|symbol| *symbol < self.lr1.grammar.terminals)
+  }
+}
+
+/// 按 RESOLUTIONS 指定每个冲突使用的动作, 有没有声明的冲突或者没有对应冲突的声明时报错
+fn resolve(lr1: &mut Lr1) -> std::result::Result<(), String> {
+  let mut used = HashSet::new();
+  for conflict in lr1.conflicts.clone() {
+    let terminal = lr1.grammar.name(conflict.terminal).to_string();
+    let reduces = |production: &str| {
+      conflict
+        .actions
+        .iter()
+        .any(|action| matches!(action, Action::Reduce(p) if lr1.grammar.production(*p) == production))
+    };
+    let declared = RESOLUTIONS.iter().enumerate().find(|(_, (production, lookaheads, _))| {
+      lookaheads.split(' ').any(|lookahead| lookahead == terminal) && reduces(production)
+    });
+    let Some((index, &(_, _, resolution))) = declared else {
+      return Err(format!("undeclared conflict: {}", lr1.conflict(&conflict)));
+    };
+    used.insert((index, terminal));
+
+    let action = conflict.actions.iter().copied().find(|action| match (action, resolution) {
+      (Action::Shift(_), Resolution::Shift) => true,
+      (Action::Reduce(p), Resolution::Reduce(production)) => lr1.grammar.production(*p) == production,
+      _ => false,
+    });
+    let action = action.ok_or_else(|| format!("{:?} is not in conflict: {}", resolution, lr1.conflict(&conflict)))?;
+    lr1.resolve(&conflict, action);
+  }
+
+  for (index, (production, lookaheads, _)) in RESOLUTIONS.iter().enumerate() {
+    if let Some(lookahead) = lookaheads.split(' ').find(|lookahead| !used.contains(&(index, lookahead.to_string()))) {
+      return Err(format!("no conflict for resolution: {} on {}", production, lookahead));
+    }
+  }
+  Ok(())
+}
+
+/// 由语法树构造抽象语法树, 节点的位置为它包含的第一个到最后一个单词
+struct Builder<'a> {
+  tokens: &'a [(Token, SpanOffset)],
+}
+
+impl Builder<'_> {
+  fn program(&self, tree: &Tree) -> Program {
+    Program { statements: self.statements(&children(tree)[0]) }
+  }
+
+  fn statements(&self, tree: &Tree) -> Vec<Statement> {
+    self.list(tree).into_iter().map(|items| self.statement(&items[0])).collect()
+  }
+
+  fn statement(&self, tree: &Tree) -> Statement {
+    let body = &children(tree)[0];
+    let kind = match (rhs(body), children(body)) {
+      (";", _) => StatementKind::Empty,
+      ("const constants", [_, constants]) => StatementKind::Const(
+        self
+          .list(constants)
+          .into_iter()
+          .map(|items| {
+            (self.identifier(&items[0]), Expression { pos: self.span(&items[2]), kind: self.integer(&items[2]) })
+          })
+          .collect(),
+      ),
+      ("var variables", [_, variables]) => StatementKind::Variable(
+        self
+          .list(variables)
+          .into_iter()
+          .map(|items| match children(&items[0]) {
+            [ident] => (self.identifier(ident), Expression { pos: self.span(ident), kind: ExpressionKind::Integer(0) }),
+            [ident, _, expression] => (self.identifier(ident), self.expression(expression)),
+            _ => unreachable!(),
+          })
+          .collect(),
+      ),
+      ("fn ident ( params ) block", [_, name, _, params, _, block]) => {
+        StatementKind::Function(self.identifier(name), self.params(params), self.block(block))
+      }
+      ("ident = expression", [ident, _, expression]) => {
+        StatementKind::Assign(self.identifier(ident), self.expression(expression))
+      }
+      // 复合赋值 a += e 即 a = a + e, 右部的位置为 e 的位置
+      (_, [ident, Tree::Leaf { token, .. }, expression]) => {
+        let infix = match self.tokens[*token].0 {
+          Token::AddAssign => Token::Plus,
+          Token::SubAssign => Token::Minus,
+          Token::MulAssign => Token::Asterisk,
+          _ => Token::Slash,
+        };
+        let identifier = self.identifier(ident);
+        let left = Expression { pos: identifier.pos, kind: ExpressionKind::Identifier(identifier.name.clone()) };
+        let kind = ExpressionKind::Infix(
+          Paser::infix_token(&infix).1.unwrap(),
+          Box::new(left),
+          Box::new(self.expression(expression)),
+        );
+        StatementKind::Assign(identifier, Expression { pos: self.span(expression), kind })
+      }
+      ("return", _) => StatementKind::Return(None),
+      ("return expression", [_, expression]) => StatementKind::Return(Some(self.expression(expression))),
+      (_, [expression]) => StatementKind::Expression(self.expression(expression)),
+      _ => unreachable!(),
+    };
+    Statement { pos: self.span(body), kind }
+  }
+
+  fn params(&self, tree: &Tree) -> Vec<Identifier> {
+    match children(tree) {
+      [list] => self.list(list).into_iter().map(|items| self.identifier(&items[0])).collect(),
+      _ => vec![],
+    }
+  }
+
+  fn block(&self, tree: &Tree) -> Vec<Statement> {
+    self.statements(&children(tree)[1])
+  }
+
+  fn expression(&self, tree: &Tree) -> Expression {
+    let kind = match (rhs(tree), children(tree)) {
+      ("ident", [leaf]) => ExpressionKind::Identifier(self.identifier(leaf).name),
+      ("integer", [leaf]) => self.integer(leaf),
+      // 单个非终结符, 例如 sum -> product
+      (_, [child]) => return self.expression(child),
+      ("( expression )", [_, expression, _]) => self.expression(expression).kind,
+      ("! unary", [_, operand]) => ExpressionKind::Prefix(Prefix::Not, Box::new(self.expression(operand))),
+      ("- unary", [_, operand]) => ExpressionKind::Prefix(Prefix::Neg, Box::new(self.expression(operand))),
+      ("postfix ( )", [callee, _, _]) => ExpressionKind::Call(Box::new(self.expression(callee)), vec![]),
+      ("postfix ( arguments )", [callee, _, arguments, _]) => ExpressionKind::Call(
+        Box::new(self.expression(callee)),
+        self.list(arguments).into_iter().map(|items| self.expression(&items[0])).collect(),
+      ),
+      ("if expression block", [_, condition, block]) => {
+        ExpressionKind::If(Box::new(self.expression(condition)), self.block(block), None)
+      }
+      ("if expression block else block", [_, condition, block, _, alternative]) => {
+        ExpressionKind::If(Box::new(self.expression(condition)), self.block(block), Some(self.block(alternative)))
+      }
+      ("while expression block", [_, condition, block]) => {
+        ExpressionKind::While(Box::new(self.expression(condition)), self.block(block))
+      }
+      ("fn ident ( params ) block", [_, name, _, params, _, block]) => {
+        ExpressionKind::Function(self.identifier(name), self.params(params), self.block(block))
+      }
+      // 其余的都是二元运算
+      (_, [left, operator, right]) => ExpressionKind::Infix(
+        Paser::infix_token(&self.tokens[leaf_token(operator)].0).1.unwrap(),
+        Box::new(self.expression(left)),
+        Box::new(self.expression(right)),
+      ),
+      _ => unreachable!(),
+    };
+    Expression { pos: self.span(tree), kind }
+  }
+
+  fn integer(&self, leaf: &Tree) -> ExpressionKind {
+    match self.tokens[leaf_token(leaf)].0 {
+      Token::Integer(value) => ExpressionKind::Integer(value),
+      _ => unreachable!(),
+    }
+  }
+
+  fn identifier(&self, leaf: &Tree) -> Identifier {
+    let (token, pos) = &self.tokens[leaf_token(leaf)];
+    match token {
+      Token::Ident(name) => Identifier { pos: *pos, name: name.clone() },
+      _ => unreachable!(),
+    }
+  }
+
+  ///
+  /// 展开左递归的列表, 例如 `list -> item | list , item`, 返回每一项的符号
+  ///
+  fn list<'t>(&self, mut tree: &'t Tree) -> Vec<&'t [Tree]> {
+    let mut items = vec![];
+    loop {
+      match children(tree) {
+        [first @ Tree::Node { .. }, rest @ ..] if lhs(production_of(first)) == lhs(production_of(tree)) => {
+          items.push(match rest {
+            [Tree::Leaf { token, .. }, rest @ ..] if self.tokens[*token].0 == Token::Comma => rest,
+            rest => rest,
+          });
+          tree = first;
+        }
+        [] => break,
+        item => {
+          items.push(item);
+          break;
+        }
+      }
+    }
+    items.reverse();
+    items
+  }
+
+  fn span(&self, tree: &Tree) -> SpanOffset {
+    let (first, last) = (first_token(tree).unwrap(), last_token(tree).unwrap());
+    (self.tokens[first].1.begin, self.tokens[last].1.end).into()
+  }
+}
+
+/// 语法树的节点在 PRODUCTIONS 中的产生式, 拓广文法的产生式 0 为 S' -> program
+fn production_of(tree: &Tree) -> usize {
+  match tree {
+    Tree::Node { production, .. } => production - 1,
+    Tree::Leaf { .. } => unreachable!(),
+  }
+}
+
+fn lhs(production: usize) -> &'static str {
+  PRODUCTIONS[production].0
+}
+
+fn rhs(tree: &Tree) -> &'static str {
+  PRODUCTIONS[production_of(tree)].1
+}
+
+fn children(tree: &Tree) -> &[Tree] {
+  match tree {
+    Tree::Node { children, .. } => children,
+    Tree::Leaf { .. } => &[],
+  }
+}
+
+fn leaf_token(tree: &Tree) -> usize {
+  match tree {
+    Tree::Leaf { token, .. } => *token,
+    Tree::Node { .. } => unreachable!(),
+  }
+}
+
+fn first_token(tree: &Tree) -> Option<usize> {
+  match tree {
+    Tree::Leaf { token, .. } => Some(*token),
+    Tree::Node { children, .. } => children.iter().find_map(first_token),
+  }
+}
+
+fn last_token(tree: &Tree) -> Option<usize> {
+  match tree {
+    Tree::Leaf { token, .. } => Some(*token),
+    Tree::Node { children, .. } => children.iter().rev().find_map(last_token),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use crate::{
+    ast::AstNode,
+    lr1::{Conflict, Lr1},
+    parser::{Paser, CORPUS},
+  };
+
+  use super::{grammar, resolve, TableParser};
+
+  #[test]
+  fn test_grammar() {
+    let mut lr1 = Lr1::new(&grammar());
+    assert_eq!(grammar().productions.len(), 58);
+    assert_eq!(lr1.states.len(), 507);
+
+    // 每个冲突都在 RESOLUTIONS 中声明, 每个声明都对应冲突
+    assert_eq!(lr1.conflicts.len(), 68);
+    assert_eq!(resolve(&mut lr1), Ok(()));
+
+    // 缺少声明或者声明了不存在的冲突
+    let mut lr1 = Lr1::new(&grammar());
+    let conflict = lr1.conflicts.iter().find(|conflict| !conflict.is_shift_reduce()).unwrap().clone();
+    lr1.conflicts.push(Conflict { terminal: lr1.grammar.symbol("else").unwrap(), ..conflict });
+    assert!(resolve(&mut lr1).unwrap_err().starts_with("undeclared conflict"));
+    let mut lr1 = Lr1::new(&grammar());
+    lr1.conflicts.retain(|conflict| lr1.grammar.name(conflict.terminal) != "#");
+    assert_eq!(
+      resolve(&mut lr1),
+      Err("no conflict for resolution: primary -> fn ident ( params ) block on #".to_string())
+    );
+
+    // 声明的解决方式
+    let parser = TableParser::new();
+    for (input, expected) in [
+      ("a -b", "(a - b);"),
+      ("a (b)", "a(b);"),
+      ("fn f() { return -1 }", "fn f() { return (-1); }"),
+      ("a;; ;", "a;"),
+      ("fn f() {} (1)", "fn f() {  } 1;"),
+    ] {
+      assert_eq!(parser.parse(input).unwrap().unparse(), expected, "{}", input);
+    }
+  }
+
+  #[test]
+  fn test_agree() {
+    let parser = TableParser::new();
+    let mut inputs = CORPUS.iter().map(|(input, _)| input.to_string()).collect::<Vec<_>>();
+    for name in ["a", "fib", "test"] {
+      inputs.push(fs::read_to_string(format!("examples/{}.pl0", name)).unwrap());
+    }
+    inputs.extend(
+      ["; ;; a;; b", "fn f() { return } fn g(a, b, c) { return; }", "while a { b } - c", "if a { b }(1)"]
+        .map(str::to_string),
+    );
+
+    for input in inputs {
+      let expected = Paser::paser(&input).unwrap();
+      let program = parser.parse(&input).unwrap_or_else(|err| panic!("{}: {:?}", input, err));
+      // Debug 的输出包括每个节点的位置
+      assert_eq!(format!("{:?}", program), format!("{:?}", expected), "{}", input);
+    }
+  }
+
+  #[test]
+  fn test_errors() {
+    let parser = TableParser::new();
+    let (message, pos) = parser.parse("a = ;").unwrap_err();
+    assert!(message.ends_with("but get ;"), "{}", message);
+    assert_eq!((pos.begin, pos.end), (4, 5));
+    assert_eq!(parser.parse("const x = y").unwrap_err().0, "expect integer, but get y");
+    assert_eq!(parser.parse("fn f(").unwrap_err().0, "expect ident or ), but get eof");
+  }
+}
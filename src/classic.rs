@@ -0,0 +1,489 @@
+use std::{fmt::Display, path::Path, result};
+
+use crate::{
+  ast::{Expression, ExpressionKind, Identifier, Infix, Prefix, Program, Statement, StatementKind},
+  vm::builtins,
+  SpanOffset,
+};
+
+type Result<T> = result::Result<T, (String, SpanOffset)>;
+
+/// 经典 PL/0 源文件的扩展名
+pub const EXTENSION: &str = "p0";
+
+/// 按扩展名判断是否为经典 PL/0 源文件
+pub fn is_classic(path: &Path) -> bool {
+  path.extension().is_some_and(|extension| extension == EXTENSION)
+}
+
+/// 解析 Wirth 教科书中的经典 PL/0, 转换为与本语言相同的抽象语法树
+///
+/// 过程转换为没有参数的函数, call p 转换为 p(), ?x 与 !e 转换为经典 PL/0 的内建函数 (builtins::READ 与 WRITE) 的调用
+pub fn parse(input: &str) -> Result<Program> {
+  let mut parser = Parser { words: tokenize(input)?, index: 0, prev_end: 0 };
+  let statements = parser.block()?;
+  parser.expect(Word::Symbol("."))?;
+  parser.expect(Word::Eof)?;
+  Ok(Program { statements })
+}
+
+const KEYWORDS: [&str; 12] =
+  ["const", "var", "procedure", "call", "begin", "end", "if", "then", "else", "while", "do", "odd"];
+
+const SYMBOLS: [&str; 18] =
+  [":=", "<=", ">=", "=", "#", "<", ">", "+", "-", "*", "/", "(", ")", ",", ";", ".", "?", "!"];
+
+/// 经典 PL/0 的单词, 关键字不区分大小写
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Word {
+  Ident(String),
+  Number(isize),
+  Keyword(&'static str),
+  Symbol(&'static str),
+  Illegal(char),
+  Eof,
+}
+
+impl Display for Word {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Word::Ident(name) => write!(f, "identifier {}", name),
+      Word::Number(value) => write!(f, "number {}", value),
+      Word::Keyword(keyword) => write!(f, "{}", keyword),
+      Word::Symbol(symbol) => write!(f, "'{}'", symbol),
+      Word::Illegal(c) => write!(f, "illegal character '{}'", c),
+      Word::Eof => write!(f, "EOF"),
+    }
+  }
+}
+
+/// 词法分析, 位置以字符为单位. 注释为 { ... } 或者 (* ... *)
+fn tokenize(input: &str) -> Result<Vec<(Word, SpanOffset)>> {
+  let chars = input.chars().collect::<Vec<_>>();
+  let (mut words, mut i) = (vec![], 0);
+  while i < chars.len() {
+    let begin = i;
+    let c = chars[i];
+    let word = if c.is_whitespace() {
+      i += 1;
+      continue;
+    } else if c == '{' || (c == '(' && chars.get(i + 1) == Some(&'*')) {
+      let close: &[char] = if c == '{' { &['}'] } else { &['*', ')'] };
+      i = (i + 1..chars.len())
+        .find(|&j| chars[j..].starts_with(close))
+        .map(|j| j + close.len())
+        .ok_or_else(|| ("unterminated comment".to_string(), SpanOffset::from((begin, chars.len()))))?;
+      continue;
+    } else if c.is_ascii_alphabetic() {
+      while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+        i += 1;
+      }
+      let name = chars[begin..i].iter().collect::<String>();
+      let lower = name.to_ascii_lowercase();
+      match KEYWORDS.iter().find(|keyword| **keyword == lower) {
+        Some(keyword) => Word::Keyword(keyword),
+        None => Word::Ident(name),
+      }
+    } else if c.is_ascii_digit() {
+      while i < chars.len() && chars[i].is_ascii_digit() {
+        i += 1;
+      }
+      let text = chars[begin..i].iter().collect::<String>();
+      let value = text.parse().map_err(|_| (format!("number too large: {}", text), SpanOffset::from((begin, i))))?;
+      Word::Number(value)
+    } else if let Some(symbol) =
+      SYMBOLS.iter().find(|symbol| chars[i..].starts_with(&symbol.chars().collect::<Vec<_>>()))
+    {
+      i += symbol.len();
+      Word::Symbol(symbol)
+    } else {
+      i += 1;
+      Word::Illegal(c)
+    };
+    words.push((word, (begin, i).into()));
+  }
+  words.push((Word::Eof, (chars.len(), chars.len()).into()));
+  Ok(words)
+}
+
+struct Parser {
+  words: Vec<(Word, SpanOffset)>,
+  index: usize,
+  prev_end: usize, // 上一个单词的结束位置
+}
+
+impl Parser {
+  /// block = [const ident = number {, ident = number} ;] [var ident {, ident} ;] {procedure ident ; block ;} statement
+  fn block(&mut self) -> Result<Vec<Statement>> {
+    let mut statements = vec![];
+    if self.peek() == &Word::Keyword("const") {
+      let begin = self.advance().1.begin;
+      let mut consts = vec![];
+      loop {
+        let ident = self.ident()?;
+        self.expect(Word::Symbol("="))?;
+        let (word, pos) = self.advance();
+        let Word::Number(value) = word else {
+          return Err((format!("only number can assign to constant, but get {}", word), pos));
+        };
+        consts.push((ident, Expression { pos, kind: ExpressionKind::Integer(value) }));
+        if !self.eat(&Word::Symbol(",")) {
+          break;
+        }
+      }
+      self.expect(Word::Symbol(";"))?;
+      statements.push(Statement { pos: self.pos(begin), kind: StatementKind::Const(consts) });
+    }
+    if self.peek() == &Word::Keyword("var") {
+      let begin = self.advance().1.begin;
+      let mut vars = vec![];
+      loop {
+        let ident = self.ident()?;
+        vars.push((ident.clone(), Expression { pos: ident.pos, kind: ExpressionKind::Integer(0) }));
+        if !self.eat(&Word::Symbol(",")) {
+          break;
+        }
+      }
+      self.expect(Word::Symbol(";"))?;
+      statements.push(Statement { pos: self.pos(begin), kind: StatementKind::Variable(vars) });
+    }
+    while self.peek() == &Word::Keyword("procedure") {
+      let begin = self.advance().1.begin;
+      let ident = self.ident()?;
+      self.expect(Word::Symbol(";"))?;
+      let body = self.block()?;
+      self.expect(Word::Symbol(";"))?;
+      statements.push(Statement { pos: self.pos(begin), kind: StatementKind::Function(ident, vec![], body) });
+    }
+    self.statement(&mut statements)?;
+    Ok(statements)
+  }
+
+  /// 解析一条语句, begin ... end 展开为其中的语句, 空语句不产生任何语句
+  fn statement(&mut self, statements: &mut Vec<Statement>) -> Result<()> {
+    let begin = self.words[self.index].1.begin;
+    let kind = match self.peek().clone() {
+      // read 与 write 不是保留字, 之后是 ( 时为输入输出语句, 否则为普通的标识符
+      Word::Ident(name) if name.eq_ignore_ascii_case("read") && self.peek_next() == &Word::Symbol("(") => {
+        self.advance();
+        self.expect(Word::Symbol("("))?;
+        loop {
+          let ident = self.ident()?;
+          let kind = StatementKind::Assign(ident.clone(), self.builtin(builtins::READ, ident.pos, vec![]));
+          statements.push(Statement { pos: ident.pos, kind });
+          if !self.eat(&Word::Symbol(",")) {
+            break;
+          }
+        }
+        self.expect(Word::Symbol(")"))?;
+        return Ok(());
+      }
+      Word::Ident(name) if name.eq_ignore_ascii_case("write") && self.peek_next() == &Word::Symbol("(") => {
+        self.advance();
+        self.expect(Word::Symbol("("))?;
+        let mut args = vec![self.expression()?];
+        while self.eat(&Word::Symbol(",")) {
+          args.push(self.expression()?);
+        }
+        self.expect(Word::Symbol(")"))?;
+        StatementKind::Expression(self.builtin(builtins::WRITE, self.pos(begin), args))
+      }
+      Word::Ident(_) => {
+        let ident = self.ident()?;
+        self.expect(Word::Symbol(":="))?;
+        StatementKind::Assign(ident, self.expression()?)
+      }
+      Word::Keyword("call") => {
+        self.advance();
+        let ident = self.ident()?;
+        let callee = Expression { pos: ident.pos, kind: ExpressionKind::Identifier(ident.name) };
+        StatementKind::Expression(self.expr(begin, ExpressionKind::Call(Box::new(callee), vec![])))
+      }
+      Word::Symbol("?") => {
+        self.advance();
+        let ident = self.ident()?;
+        StatementKind::Assign(ident.clone(), self.builtin(builtins::READ, ident.pos, vec![]))
+      }
+      Word::Symbol("!") => {
+        self.advance();
+        let value = self.expression()?;
+        StatementKind::Expression(self.builtin(builtins::WRITE, self.pos(begin), vec![value]))
+      }
+      Word::Keyword("begin") => {
+        self.advance();
+        self.statement(statements)?;
+        while self.eat(&Word::Symbol(";")) {
+          self.statement(statements)?;
+        }
+        return self.expect(Word::Keyword("end"));
+      }
+      Word::Keyword("if") => {
+        self.advance();
+        let condition = self.condition()?;
+        self.expect(Word::Keyword("then"))?;
+        let mut consequence = vec![];
+        self.statement(&mut consequence)?;
+        let alternative = match self.eat(&Word::Keyword("else")) {
+          true => {
+            let mut alternative = vec![];
+            self.statement(&mut alternative)?;
+            Some(alternative)
+          }
+          false => None,
+        };
+        StatementKind::Expression(self.expr(begin, ExpressionKind::If(Box::new(condition), consequence, alternative)))
+      }
+      Word::Keyword("while") => {
+        self.advance();
+        let condition = self.condition()?;
+        self.expect(Word::Keyword("do"))?;
+        let mut body = vec![];
+        self.statement(&mut body)?;
+        StatementKind::Expression(self.expr(begin, ExpressionKind::While(Box::new(condition), body)))
+      }
+      _ => return Ok(()),
+    };
+    statements.push(Statement { pos: self.pos(begin), kind });
+    Ok(())
+  }
+
+  /// condition = odd expression | expression (= | # | < | <= | > | >=) expression
+  ///
+  /// odd e 转换为 e / 2 * 2 != e
+  fn condition(&mut self) -> Result<Expression> {
+    let begin = self.words[self.index].1.begin;
+    if self.eat(&Word::Keyword("odd")) {
+      let value = self.expression()?;
+      let (pos, two) = (value.pos, || Box::new(Expression { pos: value.pos, kind: ExpressionKind::Integer(2) }));
+      let half = Expression { pos, kind: ExpressionKind::Infix(Infix::Div, Box::new(value.clone()), two()) };
+      let even = Expression { pos, kind: ExpressionKind::Infix(Infix::Mul, Box::new(half), two()) };
+      return Ok(self.expr(begin, ExpressionKind::Infix(Infix::Ne, Box::new(even), Box::new(value))));
+    }
+    let left = self.expression()?;
+    let (word, pos) = self.advance();
+    let infix = match word {
+      Word::Symbol("=") => Infix::Eq,
+      Word::Symbol("#") => Infix::Ne,
+      Word::Symbol("<") => Infix::Lt,
+      Word::Symbol("<=") => Infix::LtEq,
+      Word::Symbol(">") => Infix::Gt,
+      Word::Symbol(">=") => Infix::GtEq,
+      _ => return Err((format!("expect relational operator, but get {}", word), pos)),
+    };
+    let right = self.expression()?;
+    Ok(self.expr(begin, ExpressionKind::Infix(infix, Box::new(left), Box::new(right))))
+  }
+
+  /// expression = [+ | -] term {(+ | -) term}
+  fn expression(&mut self) -> Result<Expression> {
+    let begin = self.words[self.index].1.begin;
+    let mut left = if self.eat(&Word::Symbol("-")) {
+      let term = self.term()?;
+      self.expr(begin, ExpressionKind::Prefix(Prefix::Neg, Box::new(term)))
+    } else {
+      self.eat(&Word::Symbol("+"));
+      self.term()?
+    };
+    loop {
+      let infix = match self.peek() {
+        Word::Symbol("+") => Infix::Add,
+        Word::Symbol("-") => Infix::Sub,
+        _ => return Ok(left),
+      };
+      self.advance();
+      let right = self.term()?;
+      left = self.expr(begin, ExpressionKind::Infix(infix, Box::new(left), Box::new(right)));
+    }
+  }
+
+  /// term = factor {(* | /) factor}
+  fn term(&mut self) -> Result<Expression> {
+    let begin = self.words[self.index].1.begin;
+    let mut left = self.factor()?;
+    loop {
+      let infix = match self.peek() {
+        Word::Symbol("*") => Infix::Mul,
+        Word::Symbol("/") => Infix::Div,
+        _ => return Ok(left),
+      };
+      self.advance();
+      let right = self.factor()?;
+      left = self.expr(begin, ExpressionKind::Infix(infix, Box::new(left), Box::new(right)));
+    }
+  }
+
+  /// factor = ident | number | ( expression )
+  fn factor(&mut self) -> Result<Expression> {
+    let (word, pos) = self.advance();
+    match word {
+      Word::Ident(name) => Ok(Expression { pos, kind: ExpressionKind::Identifier(name) }),
+      Word::Number(value) => Ok(Expression { pos, kind: ExpressionKind::Integer(value) }),
+      Word::Symbol("(") => {
+        let expression = self.expression()?;
+        self.expect(Word::Symbol(")"))?;
+        Ok(expression)
+      }
+      _ => Err((format!("expect expression, but get {}", word), pos)),
+    }
+  }
+
+  /// 内建函数的调用
+  fn builtin(&self, name: &str, pos: SpanOffset, args: Vec<Expression>) -> Expression {
+    let callee = Expression { pos, kind: ExpressionKind::Identifier(name.to_string()) };
+    Expression { pos, kind: ExpressionKind::Call(Box::new(callee), args) }
+  }
+
+  fn ident(&mut self) -> Result<Identifier> {
+    match self.advance() {
+      (Word::Ident(name), pos) => Ok(Identifier { pos, name }),
+      (word, pos) => Err((format!("expect identifier, but get {}", word), pos)),
+    }
+  }
+
+  fn expr(&self, begin: usize, kind: ExpressionKind) -> Expression {
+    Expression { pos: self.pos(begin), kind }
+  }
+
+  /// 从 begin 到上一个单词结束的位置
+  fn pos(&self, begin: usize) -> SpanOffset {
+    (begin, self.prev_end.max(begin)).into()
+  }
+
+  fn peek(&self) -> &Word {
+    &self.words[self.index].0
+  }
+
+  /// 下一个单词之后的单词
+  fn peek_next(&self) -> &Word {
+    &self.words[(self.index + 1).min(self.words.len() - 1)].0
+  }
+
+  fn advance(&mut self) -> (Word, SpanOffset) {
+    let (word, pos) = self.words[self.index].clone();
+    if word != Word::Eof {
+      self.index += 1;
+      self.prev_end = pos.end;
+    }
+    (word, pos)
+  }
+
+  fn eat(&mut self, word: &Word) -> bool {
+    let matched = self.peek() == word;
+    if matched {
+      self.advance();
+    }
+    matched
+  }
+
+  fn expect(&mut self, word: Word) -> Result<()> {
+    let (current, pos) = self.words[self.index].clone();
+    if current != word {
+      return Err((format!("expect {}, but get {}", word, current), pos));
+    }
+    self.advance();
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::parse;
+  use crate::{ast::AstNode, compiler::Compiler, vm::VM};
+
+  #[test]
+  fn test_lowering() {
+    let t = vec![
+      ("x := 1 + 2 * 3.", "x = (1 + (2 * 3));"),
+      ("const a = 1, B = 2; var x, y; x := -a + B.", "const a = 1, B = 2; var x = 0, y = 0; x = ((-a) + B);"),
+      ("procedure p; x := 1; call p.", "fn p() { x = 1; } p();"),
+      ("BEGIN ?x; !x * 2 END.", "x = ?(); !((x * 2));"),
+      ("begin read(a, b); write(a, b + 1) end.", "a = ?(); b = ?(); !(a, (b + 1));"),
+      // read 与 write 不是保留字
+      ("var read; procedure write; read := 1; call write.", "var read = 0; fn write() { read = 1; } write();"),
+      ("if odd x then x := 0.", "if (((x / 2) * 2) != x) { x = 0; };"),
+      ("if x # 1 then .", "if (x != 1) {  };"),
+      ("while x >= 1 do begin x := x - 1; end.", "while (x >= 1) { x = (x - 1); };"),
+      ("{ comment } (* another *) .", ""),
+    ];
+    for (input, expected) in t {
+      assert_eq!(parse(input).unwrap().unparse(), expected, "{}", input);
+    }
+  }
+
+  #[test]
+  fn test_run() {
+    let input = "
+const max = 100;
+var a, b, g, n, count;
+
+procedure gcd;
+  var f;
+  begin
+    f := b;
+    while a # b do
+      if a < b then b := b - a else a := a - b;
+    g := a;
+    b := f
+  end;
+
+procedure primes;
+  var i, p;
+  procedure isprime;
+    var d;
+  begin
+    p := 1; d := 2;
+    while d * d <= i do
+    begin
+      if i / d * d = i then p := 0;
+      d := d + 1
+    end
+  end;
+begin
+  i := 2; count := 0;
+  while i <= max do
+  begin
+    call isprime;
+    if p = 1 then count := count + 1;
+    i := i + 1
+  end
+end;
+
+begin
+  a := 84; b := 36; call gcd; !g;
+  n := 0; a := 0;
+  while a < 10 do begin if odd a then n := n + a; a := a + 1 end;
+  write(n, -n);
+  call primes; !count
+end.";
+    let program = parse(input).unwrap();
+    let codes = Compiler::compile(&program).unwrap();
+    let mut out = vec![];
+    VM::execute_with_output(&codes, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "12\n25\n-25\n25\n");
+
+    // 过程与变量可以命名为 read 与 write, 与输入输出语句不冲突
+    let input = "var read; procedure write; begin read := read + 1; !read end; begin call write; write(read * 10) end.";
+    let codes = Compiler::compile(&parse(input).unwrap()).unwrap();
+    let mut out = vec![];
+    VM::execute_with_output(&codes, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "1\n10\n");
+  }
+
+  #[test]
+  fn test_errors() {
+    let t = vec![
+      ("x := 1", "expect '.', but get EOF"),
+      ("x = 1.", "expect ':=', but get '='"),
+      ("const x = y; .", "only number can assign to constant, but get identifier y"),
+      ("if x then y := 1.", "expect relational operator, but get then"),
+      ("x := 1. y := 2", "expect EOF, but get identifier y"),
+      ("x := 1 % 2.", "expect '.', but get illegal character '%'"),
+      ("{ x := 1.", "unterminated comment"),
+      ("procedure p; x := 1 call p.", "expect ';', but get call"),
+      ("begin write := 1; read end.", "expect ':=', but get end"),
+    ];
+    for (input, expected) in t {
+      assert_eq!(parse(input).unwrap_err().0, expected, "{}", input);
+    }
+  }
+}
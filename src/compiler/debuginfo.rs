@@ -0,0 +1,83 @@
+use std::ops::Range;
+
+use crate::SpanOffset;
+
+/// 作用域的种类
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScopeKind {
+  Program,          // 全局作用域
+  Function(String), // 函数体, 带有函数名
+  Block,            // 语句块
+}
+
+/// 作用域中的变量, 地址相对于作用域的基地址
+#[derive(Debug, Clone)]
+pub struct DebugVariable {
+  pub name: String,
+  pub addr: isize,
+  pub from: usize, // 从该指令地址开始可见 (完成初始化之后)
+}
+
+/// 函数的入口地址 (符号表中过程的值) 与参数个数
+#[derive(Debug, Clone)]
+pub struct DebugFunction {
+  pub name: String,
+  pub entry: usize,
+  pub argc: usize,
+  pub scope: usize, // 函数体的作用域, 其父作用域为函数定义所在的作用域
+}
+
+#[derive(Debug, Clone)]
+pub struct DebugScope {
+  pub kind: ScopeKind,
+  pub parent: Option<usize>,
+  pub variables: Vec<DebugVariable>,
+}
+
+///
+/// 调试信息, 由编译器在生成目标代码的同时记录
+///
+/// 每条指令记录其所在语句的位置, 以及执行该指令之前 bp 所指向的作用域,
+/// 调试器据此沿着 bp 的链接还原出调用栈
+#[derive(Debug, Clone)]
+pub struct DebugInfo {
+  pub spans: Vec<SpanOffset>,               // 每条指令所在语句的位置
+  pub scope_of: Vec<usize>,                 // 每条指令执行之前所在的作用域
+  pub scopes: Vec<DebugScope>,              // 下标 0 为全局作用域
+  pub statements: Vec<(usize, SpanOffset)>, // 每条语句的第一条指令地址与语句的位置
+  pub functions: Vec<DebugFunction>,        // 按照定义的顺序
+}
+
+impl DebugInfo {
+  pub fn new() -> Self {
+    let program = DebugScope { kind: ScopeKind::Program, parent: None, variables: vec![] };
+    DebugInfo { spans: vec![], scope_of: vec![], scopes: vec![program], statements: vec![], functions: vec![] }
+  }
+
+  /// 指令地址是否为某条语句的开始
+  pub fn is_statement(&self, ip: usize) -> bool {
+    self.statements.iter().any(|(addr, _)| *addr == ip)
+  }
+
+  /// 名为 name 的函数 (包括其中嵌套定义的函数) 的指令地址范围, 同名的函数取第一个
+  pub fn function_range(&self, name: &str) -> Option<Range<usize>> {
+    let scope = self.scopes.iter().position(|scope| scope.kind == ScopeKind::Function(name.to_string()))?;
+    let inside = |mut id: usize| loop {
+      match self.scopes[id].parent {
+        _ if id == scope => return true,
+        Some(parent) => id = parent,
+        None => return false,
+      }
+    };
+
+    let mut addresses = (0..self.scope_of.len()).filter(|ip| inside(self.scope_of[*ip]));
+    let begin = addresses.next()?;
+    Some(begin..addresses.next_back().unwrap_or(begin) + 1)
+  }
+}
+
+impl Default for DebugInfo {
+  fn default() -> Self {
+    Self::new()
+  }
+}
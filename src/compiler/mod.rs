@@ -1,14 +1,18 @@
-use std::result;
+use std::{collections::HashSet, mem, result};
 
 use crate::{
-  ast::{AstNode, Expression, ExpressionKind, Infix, Prefix, Program, Statement, StatementKind},
+  ast::{Expression, ExpressionKind, Identifier, Infix, Prefix, Program, Statement, StatementKind},
   vm::{self, builtins::Builtins, Opcode},
   SpanOffset,
 };
 
-use self::nametab::NameTable;
+use self::{
+  debuginfo::{DebugFunction, DebugInfo, DebugScope, DebugVariable, ScopeKind},
+  nametab::NameTable,
+};
 
-mod nametab;
+pub mod debuginfo;
+pub mod nametab;
 
 type Error = (String, SpanOffset);
 type Result<T> = result::Result<T, Vec<Error>>;
@@ -24,53 +28,175 @@ pub struct Compiler {
   cp: usize,
 
   errors: Vec<Error>,
+
+  // 作用域编号: 函数体与语句块按照编译顺序编号, 全局作用域不参与编号
+  scopes: Vec<usize>, // 当前所在的作用域链, 下标 i 对应 level i + 1 的作用域
+  scope_count: usize,
+  heap_scopes: HashSet<usize>, // 需要在堆上分配的作用域
+  captured: HashSet<usize>,    // 本次编译中发现被闭包捕获的作用域
+
+  // 当前所在的函数: 函数体所在的 level 以及参数个数 (全局作用域没有参数)
+  fn_level: usize,
+  fn_argc: Option<usize>,
+
+  // 调试信息: 当前语句的位置, 以及运行时 bp 所指向的作用域
+  debug: DebugInfo,
+  span: SpanOffset,
+  debug_scope: usize,
 }
 
 impl Compiler {
   pub fn new() -> Self {
-    Compiler { nametable: NameTable::new(), builtins: Builtins::new(), codes: vec![], cp: 0, errors: vec![] }
+    Compiler {
+      nametable: NameTable::new(),
+      builtins: Builtins::new(),
+      codes: vec![],
+      cp: 0,
+      errors: vec![],
+      scopes: vec![],
+      scope_count: 0,
+      heap_scopes: HashSet::new(),
+      captured: HashSet::new(),
+      fn_level: 0,
+      fn_argc: None,
+      debug: DebugInfo::new(),
+      span: (0, 0).into(),
+      debug_scope: 0,
+    }
   }
 
   /// 编译 AST
+  ///
+  /// 逃逸分析: 函数作为值使用时, 其定义所在的作用域链在函数返回后可能仍会被访问,
+  /// 第一遍编译时记录下这些作用域, 然后将它们改为在堆上分配并重新编译一遍
   pub fn compile(program: &Program) -> Result<Vec<Opcode>> {
-    let mut compiler = Compiler::new();
-
-    let mut dx = 3;
+    Self::compile_with_debug(program).map(|(codes, _)| codes)
+  }
 
-    let cx_inte = compiler.gen_empty_code();
-    compiler.gen_code(Opcode::Lit(0)); // 程序默认返回 0
+  /// 编译 AST, 同时生成调试信息
+  pub fn compile_with_debug(program: &Program) -> Result<(Vec<Opcode>, DebugInfo)> {
+    let mut compiler = Compiler::new();
+    compiler.compile_program(program);
 
-    for statement in &program.statements {
-      compiler.compile_statement(statement, 0, &mut dx);
+    if compiler.errors.is_empty() && !compiler.captured.is_empty() {
+      let heap_scopes = mem::take(&mut compiler.captured);
+      compiler = Compiler { heap_scopes, ..Compiler::new() };
+      compiler.compile_program(program);
     }
 
     if compiler.errors.is_empty() {
-      compiler.gen_code(Opcode::Ret);
-      compiler.codes[cx_inte] = Opcode::Int(dx);
-
-      Ok(compiler.codes)
+      Ok((compiler.codes, compiler.debug))
     } else {
       Err(compiler.errors)
     }
   }
 
+  /// 编译整个程序, 全局作用域的栈帧与函数栈帧相同
+  fn compile_program(&mut self, program: &Program) {
+    let mut dx = 3;
+
+    let cx_inte = self.gen_empty_code();
+    self.gen_code(Opcode::Lit(0)); // 程序默认返回 0
+
+    self.compile_statements(&program.statements, 0, &mut dx, false);
+
+    self.gen_code(Opcode::Ret);
+    self.codes[cx_inte] = Opcode::Int(dx);
+  }
+
   ///
   /// 编译语句块
   ///
-  pub fn compile_block_statement(&mut self, statements: &Vec<Statement>, level: usize) {
-    let mut dx = 1; // 位置
+  pub fn compile_block_statement(&mut self, statements: &[Statement], level: usize) {
+    self.compile_block(statements, level, false);
+  }
+
+  ///
+  /// 编译语句块, tail 表示语句块的值是否为函数的返回值
+  ///
+  fn compile_block(&mut self, statements: &[Statement], level: usize, tail: bool) {
     let tx0 = self.nametable.tx();
 
-    self.gen_code(Opcode::EnterScope);
-    let cx_ine = self.gen_empty_code();
+    // 堆上的语句块需要额外保存进入时的栈顶
+    let heap = self.enter_scope();
+    let mut dx = if heap { 2 } else { 1 }; // 位置
 
-    for statement in statements {
-      self.compile_statement(statement, level + 1, &mut dx);
+    // EnterScope 或者 HeapScope 执行之后 bp 才指向语句块
+    let debug_scope = self.debug_scope;
+    if !heap {
+      self.gen_code(Opcode::EnterScope);
+      self.enter_debug_scope(ScopeKind::Block);
+    }
+    let cx_ine = self.gen_empty_code();
+    if heap {
+      self.enter_debug_scope(ScopeKind::Block);
     }
+    self.gen_code(Opcode::Lit(0)); // 语句块默认值为 0
+
+    self.compile_statements(statements, level + 1, &mut dx, tail);
 
     self.gen_code(Opcode::LeaveScope);
-    self.codes[cx_ine] = Opcode::Int(dx);
+    self.codes[cx_ine] = if heap { Opcode::HeapScope(dx) } else { Opcode::Int(dx) };
 
+    self.debug_scope = debug_scope;
+    self.leave_scope();
+    self.nametable.rollback(self.nametable.tx() - tx0);
+  }
+
+  ///
+  /// 编译函数定义
+  ///
+  fn compile_function(&mut self, ident: &Identifier, args: &[Identifier], statements: &[Statement], level: usize) {
+    if self.builtins.lookup(&ident.name).is_some() {
+      self
+        .errors
+        .push((format!("unable define funcation name as same as builtins function: {}", ident.name), ident.pos));
+
+      return;
+    }
+
+    let cx_jmp = self.gen_empty_code();
+
+    self.nametable.add_proceduce(&ident.name, level, args.len());
+    let tx0 = self.nametable.tx();
+
+    // 调用函数的时候, 参数在调用方压栈, 相对基地址为负数
+    args.iter().enumerate().for_each(|(index, ident)| {
+      self.nametable.add_variable(&ident.name, level + 1, -1 - index as isize);
+    });
+
+    let heap = self.enter_scope();
+    let mut dx = 3;
+    let (fn_level, fn_argc) = (self.fn_level, self.fn_argc);
+    (self.fn_level, self.fn_argc) = (level + 1, Some(args.len()));
+
+    // 函数被调用时 bp 已经指向新的栈帧
+    let debug_scope = self.debug_scope;
+    self.enter_debug_scope(ScopeKind::Function(ident.name.clone()));
+    for (index, ident) in args.iter().enumerate() {
+      self.debug_variable(&ident.name, -1 - index as isize);
+    }
+
+    self.nametable.items[tx0].value = self.cp as isize;
+    let function =
+      DebugFunction { name: ident.name.clone(), entry: self.cp, argc: args.len(), scope: self.debug_scope };
+    self.debug.functions.push(function);
+    let cx_inte = self.gen_empty_code();
+    self.gen_code(Opcode::Lit(0)); // 默认返回 0
+
+    // 解析代码块, 最后一条表达式语句处于尾部位置
+    self.compile_statements(statements, level + 1, &mut dx, true);
+
+    (self.fn_level, self.fn_argc) = (fn_level, fn_argc);
+
+    self.gen_code(vm::Opcode::Ret);
+    self.codes[cx_inte] = if heap { vm::Opcode::HeapFrame(args.len(), dx) } else { vm::Opcode::Int(dx) };
+    self.codes[cx_jmp] = vm::Opcode::Jmp(self.cp);
+    self.debug_scope = debug_scope;
+
+    // self.nametable.print_nametable();
+    // 编译函数后，清理符号表
+    self.leave_scope();
     self.nametable.rollback(self.nametable.tx() - tx0);
   }
 
@@ -95,57 +221,20 @@ impl Compiler {
 
       StatementKind::Variable(variable) => {
         for (ident, e) in variable {
-          self.compile_expression(&e, level);
+          self.compile_expression(e, level);
 
           self.nametable.add_variable(&ident.name, level, *dx as isize);
 
           self.gen_code(vm::Opcode::Sto(0, *dx as isize));
+          self.debug_variable(&ident.name, *dx as isize);
           *dx += 1;
         }
       }
 
-      StatementKind::Function(ident, args, statements) => {
-        if let Some(_) = self.builtins.lookup(&ident.name) {
-          self.errors.push((
-            format!("unable define funcation name as same as builtins function: {}", ident.name),
-            statement.pos,
-          ));
-
-          return;
-        }
-
-        let cx_jmp = self.gen_empty_code();
-
-        self.nametable.add_proceduce(&ident.name, level);
-        let tx0 = self.nametable.tx();
-
-        // 调用函数的时候, 参数在调用方压栈, 相对基地址为负数
-        args.iter().enumerate().for_each(|(index, ident)| {
-          self.nametable.add_variable(&ident.name, level + 1, -1 - index as isize);
-        });
-
-        let mut dx = 3;
-
-        self.nametable.items[tx0].value = self.cp as isize;
-        let cx_inte = self.gen_empty_code();
-        self.gen_code(Opcode::Lit(0)); // 默认返回 0
-
-        // 解析代码块
-        for statement in statements {
-          self.compile_statement(statement, level + 1, &mut dx);
-        }
-
-        self.gen_code(vm::Opcode::Ret);
-        self.codes[cx_inte] = vm::Opcode::Int(dx);
-        self.codes[cx_jmp] = vm::Opcode::Jmp(self.cp);
-
-        // self.nametable.print_nametable();
-        // 编译函数后，清理符号表
-        self.nametable.rollback(self.nametable.tx() - tx0);
-      }
+      StatementKind::Function(ident, args, statements) => self.compile_function(ident, args, statements, level),
 
       StatementKind::Assign(ident, expression) => {
-        self.compile_expression(&expression, level);
+        self.compile_expression(expression, level);
 
         match self.nametable.find_kind(&ident.name, nametab::NameTableKind::Variable) {
           Some(item) => {
@@ -159,29 +248,47 @@ impl Compiler {
 
       StatementKind::Return(e) => {
         if let Some(e) = e {
-          self.compile_expression(&e, level);
+          if let ExpressionKind::Call(callee, args) = &e.kind {
+            if self.compile_tail_call(callee, args, level) {
+              return;
+            }
+          }
+          self.compile_expression(e, level);
         } else {
           self.gen_code(vm::Opcode::Lit(0)); // 默认返回 0
         }
 
+        // 先离开函数内的所有语句块, 再返回
+        let debug_scope = self.debug_scope;
+        for _ in self.fn_level..level {
+          self.gen_code(vm::Opcode::LeaveScope);
+          self.debug_scope = self.debug.scopes[self.debug_scope].parent.unwrap_or(0);
+        }
+
         // 如果没有就返回 0
         self.gen_code(vm::Opcode::Ret);
+        self.debug_scope = debug_scope;
       }
 
-      StatementKind::Expression(e) => self.compile_expression(&e, level),
+      StatementKind::Expression(e) => self.compile_expression(e, level),
     }
   }
 
   /// 编译表达式
   pub fn compile_expression(&mut self, expression: &Expression, level: usize) {
     match &expression.kind {
-      ExpressionKind::Identifier(ident) => match self.nametable.find(&ident) {
+      ExpressionKind::Identifier(ident) => match self.nametable.find(ident) {
         Some(item) => {
           match item.kind {
-            nametab::NameTableKind::Constant | nametab::NameTableKind::Proceduce => {
-              self.gen_code(vm::Opcode::Lit(item.value))
-            }
+            nametab::NameTableKind::Constant => self.gen_code(vm::Opcode::Lit(item.value)),
             nametab::NameTableKind::Variable => self.gen_code(vm::Opcode::Lod(level - item.level, item.addr)),
+            nametab::NameTableKind::Proceduce => {
+              // 函数作为值使用: 生成闭包, 静态链即为函数定义所在的作用域
+              let (rlevel, addr, define_level, argc) = (level - item.level, item.value, item.level, item.argc);
+              self.gen_code(vm::Opcode::Lit(addr));
+              self.gen_code(vm::Opcode::Closure(rlevel, argc));
+              self.capture(define_level);
+            }
           };
         }
 
@@ -192,8 +299,8 @@ impl Compiler {
 
       ExpressionKind::Integer(integer) => self.gen_code(vm::Opcode::Lit(*integer)),
       ExpressionKind::Infix(infix, left, right) => {
-        self.compile_expression(&left, level);
-        self.compile_expression(&right, level);
+        self.compile_expression(left, level);
+        self.compile_expression(right, level);
 
         self.gen_code(match infix {
           Infix::Add => Opcode::Add,
@@ -213,74 +320,75 @@ impl Compiler {
       ExpressionKind::Prefix(prefix, e) => {
         match prefix {
           Prefix::Not => {
-            self.compile_expression(&e, level);
+            self.compile_expression(e, level);
             self.gen_code(Opcode::Not)
           }
           Prefix::Neg => {
             // 相反数
             self.gen_code(Opcode::Lit(0));
-            self.compile_expression(&e, level);
+            self.compile_expression(e, level);
             self.gen_code(Opcode::Sub)
           }
         };
       }
 
       // 参数压栈: 逆序压栈
-      ExpressionKind::Call(indent, args) => {
-        if let Some(id) = self.builtins.lookup(&indent.name) {
-          // 调用内建函数
-          for e in args.iter().rev() {
-            self.compile_expression(e, level);
-          }
+      ExpressionKind::Call(callee, args) => {
+        if let ExpressionKind::Identifier(name) = &callee.kind {
+          if let Some(id) = self.builtins.lookup(name) {
+            // 调用内建函数
+            for e in args.iter().rev() {
+              self.compile_expression(e, level);
+            }
 
-          self.gen_code(vm::Opcode::Builtin(id, args.len()));
-        } else {
-          // 调用自定义函数
-          match self.nametable.find_kind(&indent.name, nametab::NameTableKind::Proceduce) {
-            Some(item) => {
-              let (rlevel, addr) = (level - item.level, item.value);
-              for e in args.iter().rev() {
-                self.compile_expression(e, level);
-              }
+            self.gen_code(vm::Opcode::Builtin(id, args.len()));
+            return;
+          }
 
-              self.gen_code(vm::Opcode::Lit(addr));
-              self.gen_code(vm::Opcode::Cal(rlevel)); // todo 静态链
-              self.gen_code(vm::Opcode::CallClean(args.len()));
+          // 直接调用自定义函数
+          if let Some(item) = self.nametable.find(name).filter(|item| item.kind == nametab::NameTableKind::Proceduce) {
+            let (rlevel, addr) = (level - item.level, item.value);
+            for e in args.iter().rev() {
+              self.compile_expression(e, level);
             }
 
-            None => {
-              self.errors.push((format!("only support call function ident, but get {}", indent.unparse()), indent.pos));
-            }
+            self.gen_code(vm::Opcode::Lit(addr));
+            self.gen_code(vm::Opcode::Cal(rlevel));
+            self.gen_code(vm::Opcode::CallClean(args.len()));
+            return;
           }
         }
-      }
 
-      ExpressionKind::If(condition, then_s, else_s) => {
-        self.compile_expression(&condition, level);
-
-        let cx_jpc = self.gen_empty_code();
+        // 间接调用: 被调用的表达式求值为闭包, 闭包中带有函数地址与静态链
+        for e in args.iter().rev() {
+          self.compile_expression(e, level);
+        }
 
-        self.gen_code(vm::Opcode::Lit(0));
-        self.compile_block_statement(&then_s, level);
-        self.codes[cx_jpc] = vm::Opcode::Jpc(self.cp);
+        self.compile_expression(callee, level);
+        self.gen_code(vm::Opcode::CallIndirect(args.len()));
+        self.gen_code(vm::Opcode::CallClean(args.len()));
+      }
 
-        if let Some(e) = else_s {
-          let cx_jmp = self.gen_empty_code();
-          self.gen_code(vm::Opcode::Lit(0));
-          self.codes[cx_jpc] = vm::Opcode::Jpc(self.cp);
+      ExpressionKind::Function(ident, args, statements) => {
+        self.compile_function(ident, args, statements, level);
 
-          self.compile_block_statement(&e, level);
-          self.codes[cx_jmp] = vm::Opcode::Jmp(self.cp)
+        if let Some(item) = self.nametable.find(&ident.name) {
+          self.gen_code(vm::Opcode::Lit(item.value));
+          self.gen_code(vm::Opcode::Closure(0, args.len()));
+          self.capture(level);
         }
       }
 
+      ExpressionKind::If(condition, then_s, else_s) => self.compile_if(condition, then_s, else_s, level, false),
+
       ExpressionKind::While(condition, s) => {
         let cx0 = self.cp;
-        self.compile_expression(&condition, level);
+        self.compile_expression(condition, level);
 
         let cx_jpc = self.gen_empty_code();
 
         self.compile_block_statement(s, level);
+        self.gen_code(vm::Opcode::Pop); // 丢弃每次循环语句块的值
         self.gen_code(vm::Opcode::Jmp(cx0));
         self.codes[cx_jpc] = vm::Opcode::Jpc(self.cp);
         self.gen_code(vm::Opcode::Lit(0));
@@ -288,9 +396,151 @@ impl Compiler {
     }
   }
 
+  ///
+  /// 编译 if 表达式, tail 表示 if 表达式的值是否为函数的返回值
+  ///
+  fn compile_if(
+    &mut self,
+    condition: &Expression,
+    then_s: &[Statement],
+    else_s: &Option<Vec<Statement>>,
+    level: usize,
+    tail: bool,
+  ) {
+    self.compile_expression(condition, level);
+
+    let cx_jpc = self.gen_empty_code();
+    self.compile_block(then_s, level, tail);
+
+    // 每个分支都恰好压栈一个值, 没有 else 分支时值为 0
+    let cx_jmp = self.gen_empty_code();
+    self.codes[cx_jpc] = vm::Opcode::Jpc(self.cp);
+
+    match else_s {
+      Some(e) => self.compile_block(e, level, tail),
+      None => self.gen_code(vm::Opcode::Lit(0)),
+    }
+    self.codes[cx_jmp] = vm::Opcode::Jmp(self.cp)
+  }
+
+  ///
+  /// 编译语句序列, tail 为真时最后一条表达式语句处于尾部位置
+  ///
+  fn compile_statements(&mut self, statements: &[Statement], level: usize, dx: &mut usize, tail: bool) {
+    for (index, statement) in statements.iter().enumerate() {
+      let (span, cp) = (mem::replace(&mut self.span, statement.pos), self.cp);
+
+      match &statement.kind {
+        StatementKind::Expression(e) if tail && index + 1 == statements.len() => self.compile_tail_expression(e, level),
+        _ => self.compile_statement(statement, level, dx),
+      }
+
+      // 只记录生成了指令的语句, 按照指令地址有序插入 (嵌套的语句先完成编译)
+      if self.cp > cp {
+        let position = self.debug.statements.partition_point(|(addr, _)| *addr < cp);
+        self.debug.statements.insert(position, (cp, statement.pos));
+      }
+      self.span = span;
+    }
+  }
+
+  ///
+  /// 编译处于尾部位置的表达式
+  ///
+  fn compile_tail_expression(&mut self, expression: &Expression, level: usize) {
+    match &expression.kind {
+      ExpressionKind::Call(callee, args) if self.compile_tail_call(callee, args, level) => {}
+      ExpressionKind::If(condition, then_s, else_s) => self.compile_if(condition, then_s, else_s, level, true),
+      _ => self.compile_expression(expression, level),
+    }
+  }
+
+  ///
+  /// 尝试将函数调用编译为尾调用, 复用当前函数的栈帧, 返回是否成功
+  ///
+  /// 新的实参会移动到当前函数实参的位置, 并且与栈帧对齐, 这样调用方的 CallClean 依然正确,
+  /// 所以实参个数不能多于当前函数的实参个数. 被调函数的静态链也不能指向当前函数在栈上的栈帧:
+  /// 直接调用时要求被调函数定义在当前函数之外; 间接调用时, 若闭包引用了当前函数的作用域,
+  /// 逃逸分析已经将其分配到堆上, 栈帧可以安全复用
+  fn compile_tail_call(&mut self, callee: &Expression, args: &[Expression], level: usize) -> bool {
+    let argc = match self.fn_argc {
+      Some(argc) if args.len() <= argc => args.len(),
+      _ => return false,
+    };
+
+    let target = match &callee.kind {
+      ExpressionKind::Identifier(name) if self.builtins.lookup(name).is_some() => return false,
+      ExpressionKind::Identifier(name) => match self.nametable.find(name) {
+        Some(item) if item.kind == nametab::NameTableKind::Proceduce => {
+          if item.level >= self.fn_level {
+            return false;
+          }
+          Some((level - item.level, item.value))
+        }
+        Some(_) => None,
+        None => return false,
+      },
+      _ => None,
+    };
+
+    for e in args.iter().rev() {
+      self.compile_expression(e, level);
+    }
+
+    let depth = level - self.fn_level;
+    match target {
+      Some((rlevel, addr)) => {
+        self.gen_code(vm::Opcode::Lit(addr));
+        self.gen_code(vm::Opcode::TailCall(rlevel, argc, depth));
+      }
+      None => {
+        self.compile_expression(callee, level);
+        self.gen_code(vm::Opcode::TailCallIndirect(argc, depth));
+      }
+    }
+
+    true
+  }
+
+  /// 进入新的作用域, 返回该作用域是否需要在堆上分配
+  fn enter_scope(&mut self) -> bool {
+    let id = self.scope_count;
+    self.scope_count += 1;
+    self.scopes.push(id);
+
+    self.heap_scopes.contains(&id)
+  }
+
+  /// 离开作用域
+  fn leave_scope(&mut self) {
+    self.scopes.pop();
+  }
+
+  /// 记录定义在 level 层的函数被作为值使用
+  ///
+  /// 闭包的静态链会引用该层及其外层的所有作用域 (全局作用域除外, 它一直存在)
+  fn capture(&mut self, level: usize) {
+    self.captured.extend(&self.scopes[..level]);
+  }
+
+  /// 进入新的调试作用域, 之后生成的指令都属于该作用域
+  fn enter_debug_scope(&mut self, kind: ScopeKind) {
+    let scope = DebugScope { kind, parent: Some(self.debug_scope), variables: vec![] };
+    self.debug.scopes.push(scope);
+    self.debug_scope = self.debug.scopes.len() - 1;
+  }
+
+  /// 记录当前作用域中的变量, 从下一条指令开始可见
+  fn debug_variable(&mut self, name: &str, addr: isize) {
+    let variable = DebugVariable { name: name.to_string(), addr, from: self.cp };
+    self.debug.scopes[self.debug_scope].variables.push(variable);
+  }
+
   /// 生成虚拟机指令
   fn gen_code(&mut self, opcode: vm::Opcode) {
     self.codes.push(opcode);
+    self.debug.spans.push(self.span);
+    self.debug.scope_of.push(self.debug_scope);
     self.cp += 1;
   }
 
@@ -307,92 +557,188 @@ impl Default for Compiler {
   }
 }
 
+/// 编译器的测试用例: 源代码与程序的返回值, 参考解释器也用它们检查与虚拟机的结果是否一致
+#[cfg(test)]
+pub(crate) const CORPUS: &[(&str, isize)] = &[
+  // 语句块
+  ("fn func(n) { if n <= 1 { 1 } else { func(n - 1) + func(n - 2) } }; func(10);", 89),
+  ("if 2 <= 1 { 1 } else { 2 }", 2),
+  // 空语句
+  (";;;", 0),
+  // 常量声明语句
+  ("const a = 10, b = 20; a + b", 30),
+  ("const a = 4; a", 4),
+  // 变量声明语句
+  ("var a; a", 0),
+  ("var a = 4; a", 4),
+  ("var x, y; x + y", 0),
+  ("var x = 1, y = 2; x + y ", 3),
+  ("var x = 1, y; x + y", 1),
+  ("var a = 3; const b = 2;", 3),
+  // 赋值语句
+  ("var x = 1; x += 3;", 4),
+  ("var x = 1; x += 3; x", 4),
+  ("var x = 1; x -= 3; x", -2),
+  ("var x = 1; x *= 3; x", 3),
+  ("var x = 6; x /= 3; x", 2),
+  // 返回语句
+  ("return 1 + 2;", 3),
+  ("return;", 0),
+  // 整数字面量
+  ("1", 1),
+  ("-2", -2),
+  // 算术表达式
+  ("1 + 2;", 3),
+  ("1 - 2;", -1),
+  ("1 * 2;", 2),
+  ("3 / 2;", 1),
+  ("1 + 2 * 3 - 3", 4),
+  ("5 > 4 == 3 < 4;", 1),
+  ("5 < 4 != 3 > 4;", 0),
+  ("1 + (2 + 3) * 4;", 21),
+  ("(5 + 5) * 2;", 20),
+  ("-(5 + 5);", -10),
+  ("10 + -5", 5),
+  ("!0 + !7", 1),
+  // if 表达式
+  ("if 1 { } else { 1 }", 0),
+  ("if 0 { 2 } else { 0 }", 0),
+  ("if 0 { 2 }", 0),
+  ("if 1 { 2 } else { 1 }", 2),
+  ("if 0 { 2 } else { 1 }", 1),
+  ("var x = 5; if 0 { 2 }", 0),
+  ("fn f() { if 1 { } } f()", 0),
+  ("fn f() { if 1 { var a = 7 } } f()", 7),
+  // while 表达式
+  ("var i = 10; while i > 5 { i -= 1; }", 0),
+  ("var i = 10; while i > 5 { i -= 1; }; i ", 5),
+  ("var i = 0; var s = 0; while i < 10 { i += 1; var t = i * i; s += t }; s", 385),
+  // 作用域与静态链
+  ("fn f() { var a = 3 } f()", 3),
+  ("fn f() { var a = 3; fn g() { a } } f()", 3),
+  ("var a = 1; fn f() { var b = 2; fn g() { var c = 3; a + b + c } g() } f()", 6),
+  ("var a = 1; fn f(x) { if x > 0 { var b = x; while b > 0 { b -= 1; a += 1 } } a } f(3)", 4),
+  ("fn f(n) { fn g(m) { if m > 0 { n + g(m - 1) } else { 0 } } g(n) } f(4)", 16),
+  ("var x = 1; fn f() { x } var x = 2; f() * 10 + x", 12),
+  // 函数
+  ("(fn test(n) { n })(3)", 3),
+  ("(fn fib(n) { if n <= 1 { 1 } else { fib(n - 1) + fib(n - 2) } })(5)", 8),
+  ("fn test(n) { n } test(3);", 3),
+  ("fn b(n) {n} fn test(n) { b(n) + b(n) } test(3);", 6),
+  ("fn f(a, b) { a - b } f(10, 3)", 7),
+  ("fn f(a) { a = a * 2; a } f(21)", 42),
+  // 实参的求值顺序
+  ("var x = 0; fn inc() { x += 1 } fn f(a, b) { a * 10 + b } f(inc(), inc())", 21),
+  // 经典 PL/0 的输入输出不占用本语言的名字
+  ("fn write(x) { x * 2 } fn read() { write(3) } read()", 6),
+  // 函数作为参数与变量
+  ("fn apply(f, x) { f(x) } fn double(x) { x * 2 } apply(double, 21)", 42),
+  ("fn double(x) { x * 2 } var f = double; f(4)", 8),
+  ("var f = fn inc(x) { x + 1 }; f(f(1))", 3),
+  ("var f = fn inc(x) { x + 1 }; inc(f(1))", 3),
+  // 闭包捕获定义时的栈帧
+  ("fn adder(n) { fn add(x) { x + n } add } var add3 = adder(3); add3(4) + adder(10)(1)", 18),
+  ("fn counter() { var c = 0; fn inc() { c += 1 } inc } var a = counter(), b = counter(); a(); a(); b(); a()", 3),
+  ("fn outer(a) { fn mid(b) { fn inner(c) { a + b + c } inner } mid } outer(1)(10)(100)", 111),
+  // 被捕获的语句块
+  ("var g; if 1 { var x = 5; fn h() { x } g = h }; g()", 5),
+  // 在堆上的栈帧中调用普通函数
+  ("fn make(n) { fn sq(x) { x * x } fn f() { sq(n) + n } f } make(3)()", 12),
+  // 语句块中的 return
+  ("fn f(n) { if n { return 5 }; 3 } f(1) * 10 + f(0)", 53),
+  ("fn f(n) { while 1 { if n > 3 { return n } n += 1 } } f(0)", 4),
+  // 自递归的尾调用
+  ("fn sum(n, acc) { if n == 0 { acc } else { return sum(n - 1, acc + n) } } sum(100000, 0)", 5000050000),
+  ("fn sum(n, acc) { if n == 0 { acc } else { sum(n - 1, acc + n) } } sum(100000, 0)", 5000050000),
+  ("fn count(n) { if n == 0 { 0 } else { var m = n - 1; count(m) } } count(1000)", 0),
+  // 尾调用的实参个数少于当前函数
+  ("fn one(x) { x } fn two(a, b) { one(a + b) } two(1, 2)", 3),
+  // 尾调用的实参个数多于当前函数, 不能复用栈帧
+  ("fn two(a, b) { a - b } fn one(x) { two(x, 1) } one(5)", 4),
+  // 通过函数值实现的互相递归
+  (
+    "fn even(n, other) { if n == 0 { 1 } else { other(n - 1, even) } }
+     fn odd(n, other) { if n == 0 { 0 } else { other(n - 1, odd) } }
+     even(100001, odd) * 10 + odd(7, even)",
+    1,
+  ),
+  // 嵌套函数的静态链指向当前栈帧, 不能复用栈帧
+  ("fn f(n) { fn g(x) { x + n } g(1) } f(2)", 3),
+  ("fn f(n) { fn g(x) { x + n } if n > 0 { g(1) } } f(2)", 3),
+  // 尾调用闭包
+  ("fn adder(n) { fn add(x) { x + n } add } fn f(x) { adder(1)(x) } f(2)", 3),
+  // 输出
+  ("println(1, 2, 3); print(4); println()", 0),
+  ("var i = 0; while i < 5 { println(i, i * i); i += 1 }", 0),
+  ("fn f(x) { println(x); x } f(1) + f(2)", 3),
+  ("helloworld(7)", 0),
+];
+
 #[cfg(test)]
 mod tests {
-  use crate::{ast::AstNode, compiler::Compiler, parser::Paser, vm::VM};
+  use std::io;
+
+  use crate::{
+    ast::AstNode,
+    compiler::Compiler,
+    parser::Paser,
+    vm::{trace::Trace, Opcode, VM},
+  };
+
+  use super::CORPUS;
 
   #[test]
-  #[rustfmt::skip]
-  fn test_block() {
-    test_eval(vec![
-      ( "fn func(n) { if n <= 1 { 1 } else { func(n - 1) + func(n - 2) } }; func(10);", 89 ),
-      ( "if 2 <= 1 { 1 } else { 2 }", 2 ),
-    ]);
+  fn test_corpus() {
+    test_eval(CORPUS);
   }
 
   #[test]
-  fn test_statement() {
-    test_eval(vec![
-      // 空语句
-      (";;;", 0),
-      // 常量声明语句
-      ("const a = 10, b = 20; a + b", 30),
-      ("const a = 4; a", 4),
-      // 变量声明语句
-      ("var a; a", 0),
-      ("var a = 4; a", 4),
-      ("var x, y; x + y", 0),
-      ("var x = 1, y = 2; x + y ", 3),
-      ("var x = 1, y; x + y", 1),
-      // 赋值语句
-      ("var x = 1; x += 3;", 4),
-      ("var x = 1; x += 3; x", 4),
-      ("var x = 1; x -= 3; x", -2),
-      ("var x = 1; x *= 3; x", 3),
-      ("var x = 6; x /= 3; x", 2),
-      // 返回语句
-      ("return 1 + 2;", 3),
-      ("return;", 0),
-    ])
+  fn test_tail_call() {
+    let program = Paser::paser("fn loop(n) { if n == 0 { 0 } else { loop(n - 1) } }").unwrap();
+    let codes = Compiler::compile(&program).unwrap();
+    assert!(codes.iter().any(|code| matches!(code, Opcode::TailCall(2, 1, 1))));
   }
 
   #[test]
-  fn test_expression() {
-    test_eval(vec![
-      // 整数字面量
-      ("1", 1),
-      ("-2", -2),
-      // 算术表达式
-      ("1 + 2;", 3),
-      ("1 - 2;", -1),
-      ("1 * 2;", 2),
-      ("3 / 2;", 1),
-      ("1 + 2 * 3 - 3", 4),
-      ("5 > 4 == 3 < 4;", 1),
-      ("5 < 4 != 3 > 4;", 0),
-      ("1 + (2 + 3) * 4;", 21),
-      ("(5 + 5) * 2;", 20),
-      ("-(5 + 5);", -10),
-      ("10 + -5", 5),
-      // if 表达式测试
-      ("if 1 { } else { 1 }", 0),
-      ("if 0 { 2 } else { 0 }", 0),
-      ("if 0 { 2 }", 0),
-      ("if 1 { 2 } else { 1 }", 2),
-      ("if 0 { 2 } else { 1 }", 1),
-      // while 表达式测试
-      ("var i = 10; while i > 5 { i -= 1; }", 0),
-      ("var i = 10; while i > 5 { i -= 1; }; i ", 5),
-      // 函数测试
-      // ("(fn test(n) { n })(3)", 3),
-      // ("(fn fib(n) { if n <= 1 { 1 } else { fib(n - 1) + fib(n - 2) } })(5)", 8),
-      ("fn test(n) { n } test(3);", 3),
-      ("fn b(n) {n} fn test(n) { b(n) + b(n) } test(3);", 6),
-    ]);
+  fn test_stack_balance() {
+    // 语句块、if、while 都恰好留下一个值: 循环次数不同时栈的最大深度应该相同
+    // (之前 while 不丢弃循环体的值、没有 else 的 if 多压一个 0, 栈会随循环次数增长)
+    let inputs = [
+      "var i = 0; while i < N { i += 1; }; i",
+      "var i = 0; while i < N { i += 1; if 0 { 1 } }; i",
+      "var i = 0; while i < N { if 1 { 2 }; i += 1 }; i",
+      "var i = 0; while i < N { if i > 1000 { 1 } else { i }; i += 1 }; i",
+      "var i = 0, j = 0; while i < N { i += 1; j = 0; while j < 3 { j += 1 } }; i",
+    ];
+
+    for input in inputs {
+      let depths: Vec<_> = [1, 10, 100]
+        .into_iter()
+        .map(|n| {
+          let program = Paser::paser(&input.replace('N', &n.to_string())).unwrap();
+          let codes = Compiler::compile(&program).unwrap();
+          let mut depth = 0;
+          let result = VM::execute_with_tracer(&codes, &mut io::sink(), &mut |t: &Trace| depth = depth.max(t.sp));
+          assert_eq!(result.unwrap(), n, "{input}");
+          depth
+        })
+        .collect();
+      assert!(depths.iter().all(|&d| d == depths[0]), "{input}: {depths:?}");
+    }
   }
 
   /// 评估程序 给出结果
-  fn test_eval(t: Vec<(&str, isize)>) {
-    for (input, expect) in t {
+  fn test_eval(t: &[(&str, isize)]) {
+    for &(input, expect) in t {
       let program = Paser::paser(input).unwrap();
-      println!("{:}", &program.unparse());
+      println!("{:}", program.unparse());
       // println!("{:#?}", &program);
 
       let codes = Compiler::compile(&program).unwrap();
 
       // VM::print_codes(&codes);
 
-      assert_eq!(VM::execute(&codes), expect);
+      assert_eq!(VM::execute_with_output(&codes, &mut io::sink()).unwrap(), expect, "{}", input);
     }
   }
 }
@@ -1,4 +1,4 @@
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum NameTableKind {
   Constant,  // 常量
   Variable,  // 变量
@@ -12,7 +12,7 @@ pub struct NameTableItem {
   pub value: isize,
   pub level: usize,
   pub addr: isize, // 语句代码地址
-  pub size: usize,
+  pub argc: usize, // 过程的参数个数
 }
 
 /// 符号表
@@ -22,6 +22,12 @@ pub struct NameTable {
   tx: usize, // table pointer 指向最后一项
 }
 
+impl Default for NameTable {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl NameTable {
   pub fn new() -> Self {
     let main_proc = NameTableItem {
@@ -30,7 +36,7 @@ impl NameTable {
       value: 0, // const: ; function: 起始地址
       level: 0, // const 用不到
       addr: 0,  // var: 相对基地址.
-      size: 0,  // procedce, 需要分配的大小
+      argc: 0,  // procedce: 参数个数
     };
 
     NameTable { items: vec![main_proc], tx: 0 }
@@ -49,7 +55,7 @@ impl NameTable {
 
   /// 添加 常量
   pub fn add_const(&mut self, name: &str, level: usize, value: isize) {
-    self.add(NameTableItem { name: name.to_string(), kind: NameTableKind::Constant, value, level, addr: 0, size: 0 })
+    self.add(NameTableItem { name: name.to_string(), kind: NameTableKind::Constant, value, level, addr: 0, argc: 0 })
   }
 
   ///
@@ -62,20 +68,13 @@ impl NameTable {
       value: 0,
       level,
       addr: raddr,
-      size: 0,
+      argc: 0,
     })
   }
 
   ///  添加 过程
-  pub fn add_proceduce(&mut self, name: &str, level: usize) {
-    self.add(NameTableItem {
-      name: name.to_string(),
-      kind: NameTableKind::Proceduce,
-      value: 0,
-      level,
-      addr: 0,
-      size: 0,
-    })
+  pub fn add_proceduce(&mut self, name: &str, level: usize, argc: usize) {
+    self.add(NameTableItem { name: name.to_string(), kind: NameTableKind::Proceduce, value: 0, level, addr: 0, argc })
   }
 
   /// 回退多少 下标
@@ -88,22 +87,17 @@ impl NameTable {
   }
 
   pub fn find(&self, ident: &str) -> Option<&NameTableItem> {
-    self.items.iter().take(self.tx() + 1).rev().find(|&cur| cur.name == ident)
+    self.position(ident).map(|index| &self.items[index])
   }
   ///  从后向前找
   pub fn find_kind(&self, ident: &str, kind: NameTableKind) -> Option<&NameTableItem> {
-    match self.find(ident) {
-      Some(item) if item.kind == kind => Some(item),
-
-      Some(_) => panic!("expect {:?}", kind),
-      None => panic!("expect ident"),
-    }
+    self.find(ident).filter(|item| item.kind == kind)
   }
 
-  // /// 返回在符号表中的位置
-  // pub fn position(&self, ident: &str) -> Option<usize> {
-  //   self.items.iter().rev().position(|cur| cur.name == ident)
-  // }
+  /// 返回在符号表中的位置, 从后向前找
+  pub fn position(&self, ident: &str) -> Option<usize> {
+    self.items.iter().take(self.tx() + 1).rposition(|cur| cur.name == ident)
+  }
 
   #[allow(unused)]
   pub fn print_nametable(&self) {
@@ -0,0 +1,443 @@
+use std::{fmt::Write, result};
+
+use crate::{
+  ast::{Expression, ExpressionKind, Identifier, Infix, Prefix, Program, Statement, StatementKind},
+  parser::Paser,
+  token::{Token, Trivia},
+  SpanOffset,
+};
+
+type Result<T> = result::Result<T, (String, SpanOffset)>;
+
+/// 具体语法树节点的种类
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyntaxKind {
+  Program,
+
+  // 语句
+  EmptyStatement,
+  ConstStatement,
+  VarStatement,
+  FunctionStatement,
+  AssignStatement,
+  ReturnStatement,
+  ExpressionStatement,
+
+  Declarator, // 常量与变量声明中的 name = value
+  ParamList,  // 函数定义的参数列表, 包含括号
+  Block,      // 语句块, 包含大括号
+
+  // 表达式
+  Literal,
+  Name,
+  PrefixExpression,
+  InfixExpression,
+  ParenExpression,
+  CallExpression,
+  ArgList, // 函数调用的实参列表, 包含括号
+  FunctionExpression,
+  IfExpression,
+  WhileExpression,
+}
+
+/// 带有前导空白与注释的 token
+#[derive(Debug, Clone)]
+pub struct SyntaxToken {
+  pub leading: Vec<Trivia>,
+  pub token: Token,
+  pub text: String,
+  pub pos: SpanOffset,
+}
+
+impl SyntaxToken {
+  fn to_identifier(&self) -> Result<Identifier> {
+    match &self.token {
+      Token::Ident(name) => Ok(Identifier { pos: self.pos, name: name.clone() }),
+      token => Err((format!("expect identifier, but get {}", token), self.pos)),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub enum SyntaxElement {
+  Node(SyntaxNode),
+  Token(SyntaxToken),
+}
+
+///
+/// 具体语法树 (CST)
+///
+/// 保留了所有的 token 以及空白与注释, 依次拼接即可得到与输入完全相同的源代码.
+/// 空白与注释总是挂在其后的 token 上, 文件末尾的空白与注释挂在 EOF 上
+#[derive(Debug, Clone)]
+pub struct SyntaxNode {
+  pub kind: SyntaxKind,
+  pub children: Vec<SyntaxElement>,
+}
+
+impl SyntaxNode {
+  /// 解析源代码, 得到具体语法树
+  pub fn parse(input: &str) -> Result<SyntaxNode> {
+    Paser::paser_cst(input).map(|(_, node)| node)
+  }
+
+  /// 还原出源代码
+  pub fn text(&self) -> String {
+    let mut text = String::new();
+    for token in self.tokens() {
+      token.leading.iter().for_each(|trivia| text.push_str(trivia.text()));
+      text.push_str(&token.text);
+    }
+    text
+  }
+
+  /// 由具体语法树得到抽象语法树
+  ///
+  /// 直接由各个节点构造, 与解析器得到的抽象语法树相同 (包括节点的位置):
+  /// 复合赋值展开为二元运算, 括号表达式的位置包含括号. 节点的结构不完整时返回错误
+  pub fn to_program(&self) -> Result<Program> {
+    Ok(Program { statements: self.nodes().map(SyntaxNode::to_statement).collect::<Result<_>>()? })
+  }
+
+  fn to_statement(&self) -> Result<Statement> {
+    let (nodes, tokens) = (self.nodes().collect::<Vec<_>>(), self.direct_tokens().collect::<Vec<_>>());
+
+    // 语句结尾的分号不属于语句, 空语句只包含第一个分号
+    let content = match self.kind {
+      SyntaxKind::EmptyStatement => 1,
+      _ => self
+        .children
+        .iter()
+        .position(|child| matches!(child, SyntaxElement::Token(token) if token.token == Token::Semicolon))
+        .unwrap_or(self.children.len()),
+    };
+    let end = match self.children[..content].last() {
+      Some(SyntaxElement::Node(node)) => node.pos().end,
+      Some(SyntaxElement::Token(token)) => token.pos.end,
+      None => return Err(self.malformed()),
+    };
+
+    let kind = match self.kind {
+      SyntaxKind::EmptyStatement => StatementKind::Empty,
+      SyntaxKind::ConstStatement => StatementKind::Const(
+        nodes
+          .iter()
+          .map(|node| {
+            let tokens = node.direct_tokens().collect::<Vec<_>>();
+            match tokens.as_slice() {
+              [ident, _, SyntaxToken { token: Token::Integer(value), pos, .. }] => {
+                Ok((ident.to_identifier()?, Expression { pos: *pos, kind: ExpressionKind::Integer(*value) }))
+              }
+              _ => Err(node.malformed()),
+            }
+          })
+          .collect::<Result<_>>()?,
+      ),
+      SyntaxKind::VarStatement => StatementKind::Variable(
+        nodes
+          .iter()
+          .map(|node| {
+            let ident = node.direct_tokens().next().ok_or_else(|| node.malformed())?.to_identifier()?;
+            let value = match node.nodes().next() {
+              Some(value) => value.to_expression()?,
+              None => Expression { pos: ident.pos, kind: ExpressionKind::Integer(0) },
+            };
+            Ok((ident, value))
+          })
+          .collect::<Result<_>>()?,
+      ),
+      SyntaxKind::FunctionStatement => {
+        let (ident, args, statements) = self.to_function()?;
+        StatementKind::Function(ident, args, statements)
+      }
+      SyntaxKind::AssignStatement => {
+        let (ident, assign, value) = match (tokens.as_slice(), nodes.as_slice()) {
+          ([ident, assign, ..], [value]) => (ident.to_identifier()?, assign, value.to_expression()?),
+          _ => return Err(self.malformed()),
+        };
+        let infix = match assign.token {
+          Token::Assign => None,
+          Token::AddAssign => Some(Infix::Add),
+          Token::SubAssign => Some(Infix::Sub),
+          Token::MulAssign => Some(Infix::Mul),
+          Token::DivAssign => Some(Infix::Div),
+          _ => return Err(self.malformed()),
+        };
+        // 复合赋值展开为二元运算, 位置为等号右边的表达式
+        let value = match infix {
+          Some(infix) => Expression {
+            pos: value.pos,
+            kind: ExpressionKind::Infix(
+              infix,
+              Box::new(Expression { pos: ident.pos, kind: ExpressionKind::Identifier(ident.name.clone()) }),
+              Box::new(value),
+            ),
+          },
+          None => value,
+        };
+        StatementKind::Assign(ident, value)
+      }
+      SyntaxKind::ReturnStatement => StatementKind::Return(nodes.first().map(|node| node.to_expression()).transpose()?),
+      SyntaxKind::ExpressionStatement => {
+        StatementKind::Expression(nodes.first().ok_or_else(|| self.malformed())?.to_expression()?)
+      }
+      _ => return Err(self.malformed()),
+    };
+
+    Ok(Statement { pos: SpanOffset { begin: self.pos().begin, end }, kind })
+  }
+
+  fn to_expression(&self) -> Result<Expression> {
+    let (nodes, tokens) = (self.nodes().collect::<Vec<_>>(), self.direct_tokens().collect::<Vec<_>>());
+    let operand = |index: usize| -> Result<Box<Expression>> {
+      Ok(Box::new(nodes.get(index).ok_or_else(|| self.malformed())?.to_expression()?))
+    };
+
+    let kind = match (self.kind, tokens.first().map(|token| &token.token)) {
+      (SyntaxKind::Literal, Some(&Token::Integer(value))) => ExpressionKind::Integer(value),
+      (SyntaxKind::Name, Some(Token::Ident(name))) => ExpressionKind::Identifier(name.clone()),
+      (SyntaxKind::PrefixExpression, Some(Token::Bang)) => ExpressionKind::Prefix(Prefix::Not, operand(0)?),
+      (SyntaxKind::PrefixExpression, Some(Token::Minus)) => ExpressionKind::Prefix(Prefix::Neg, operand(0)?),
+      (SyntaxKind::InfixExpression, Some(token)) => match Paser::infix_token(token) {
+        (_, Some(infix)) => ExpressionKind::Infix(infix, operand(0)?, operand(1)?),
+        _ => return Err(self.malformed()),
+      },
+      // 括号表达式的位置包含括号
+      (SyntaxKind::ParenExpression, _) => operand(0)?.kind,
+      (SyntaxKind::CallExpression, _) => {
+        let args = nodes.get(1).filter(|node| node.kind == SyntaxKind::ArgList).ok_or_else(|| self.malformed())?;
+        ExpressionKind::Call(operand(0)?, args.nodes().map(SyntaxNode::to_expression).collect::<Result<_>>()?)
+      }
+      (SyntaxKind::FunctionExpression, _) => {
+        let (ident, args, statements) = self.to_function()?;
+        ExpressionKind::Function(ident, args, statements)
+      }
+      (SyntaxKind::IfExpression, _) => {
+        let else_s = nodes.get(2).map(|node| node.to_block()).transpose()?;
+        ExpressionKind::If(operand(0)?, nodes.get(1).ok_or_else(|| self.malformed())?.to_block()?, else_s)
+      }
+      (SyntaxKind::WhileExpression, _) => {
+        ExpressionKind::While(operand(0)?, nodes.get(1).ok_or_else(|| self.malformed())?.to_block()?)
+      }
+      _ => return Err(self.malformed()),
+    };
+
+    Ok(Expression { pos: self.pos(), kind })
+  }
+
+  /// 函数定义: fn 函数名 参数列表 语句块
+  fn to_function(&self) -> Result<(Identifier, Vec<Identifier>, Vec<Statement>)> {
+    match (self.direct_tokens().nth(1), self.nodes().collect::<Vec<_>>().as_slice()) {
+      (Some(ident), [params, block]) if params.kind == SyntaxKind::ParamList => {
+        let args = params.direct_tokens().filter(|token| matches!(token.token, Token::Ident(_)));
+        Ok((ident.to_identifier()?, args.map(SyntaxToken::to_identifier).collect::<Result<_>>()?, block.to_block()?))
+      }
+      _ => Err(self.malformed()),
+    }
+  }
+
+  fn to_block(&self) -> Result<Vec<Statement>> {
+    if self.kind != SyntaxKind::Block {
+      return Err(self.malformed());
+    }
+    self.nodes().map(SyntaxNode::to_statement).collect()
+  }
+
+  fn malformed(&self) -> (String, SpanOffset) {
+    (format!("malformed syntax node: {:?}", self.kind), self.pos())
+  }
+
+  /// 按照源代码中的顺序返回所有的 token
+  pub fn tokens(&self) -> Vec<&SyntaxToken> {
+    let mut tokens = vec![];
+    self.collect_tokens(&mut tokens);
+    tokens
+  }
+
+  fn collect_tokens<'a>(&'a self, tokens: &mut Vec<&'a SyntaxToken>) {
+    for child in &self.children {
+      match child {
+        SyntaxElement::Node(node) => node.collect_tokens(tokens),
+        SyntaxElement::Token(token) => tokens.push(token),
+      }
+    }
+  }
+
+  /// 直接子节点
+  pub fn nodes(&self) -> impl Iterator<Item = &SyntaxNode> {
+    self.children.iter().filter_map(|child| match child {
+      SyntaxElement::Node(node) => Some(node),
+      SyntaxElement::Token(_) => None,
+    })
+  }
+
+  /// 直接子 token
+  fn direct_tokens(&self) -> impl Iterator<Item = &SyntaxToken> {
+    self.children.iter().filter_map(|child| match child {
+      SyntaxElement::Node(_) => None,
+      SyntaxElement::Token(token) => Some(token),
+    })
+  }
+
+  /// 节点的位置, 不包含前导的空白与注释
+  pub fn pos(&self) -> SpanOffset {
+    let tokens = self.tokens();
+    match (tokens.first(), tokens.last()) {
+      (Some(first), Some(last)) => SpanOffset { begin: first.pos.begin, end: last.pos.end },
+      _ => (0, 0).into(),
+    }
+  }
+
+  /// 以缩进表示层次的调试输出
+  pub fn debug_tree(&self) -> String {
+    let mut output = String::new();
+    self.write_tree(&mut output, 0);
+    output
+  }
+
+  fn write_tree(&self, output: &mut String, indent: usize) {
+    writeln!(output, "{:indent$}{:?}", "", self.kind, indent = indent * 2).unwrap();
+    for child in &self.children {
+      match child {
+        SyntaxElement::Node(node) => node.write_tree(output, indent + 1),
+        SyntaxElement::Token(token) => {
+          for trivia in &token.leading {
+            writeln!(output, "{:indent$}{:?}", "", trivia, indent = indent * 2 + 2).unwrap();
+          }
+          writeln!(output, "{:indent$}{:?}", "", token.text, indent = indent * 2 + 2).unwrap();
+        }
+      }
+    }
+  }
+}
+
+///
+/// 具体语法树的构造器, 由语法解析器在解析的同时调用
+///
+/// 节点的子元素先平铺在 children 中, 节点结束时再收拢为一个节点.
+/// 中缀表达式与函数调用在解析完左边的表达式之后才能确定, 通过 checkpoint 在之前的位置开始节点
+#[derive(Default)]
+pub struct Builder {
+  parents: Vec<(SyntaxKind, usize)>,
+  children: Vec<SyntaxElement>,
+}
+
+impl Builder {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn start_node(&mut self, kind: SyntaxKind) {
+    self.parents.push((kind, self.children.len()));
+  }
+
+  pub fn finish_node(&mut self) {
+    let (kind, start) = self.parents.pop().unwrap();
+    let children = self.children.split_off(start);
+    self.children.push(SyntaxElement::Node(SyntaxNode { kind, children }));
+  }
+
+  pub fn token(&mut self, token: SyntaxToken) {
+    self.children.push(SyntaxElement::Token(token));
+  }
+
+  pub fn checkpoint(&self) -> usize {
+    self.children.len()
+  }
+
+  /// 从 checkpoint 开始一个节点, checkpoint 之后的元素都会成为它的子元素
+  pub fn start_node_at(&mut self, checkpoint: usize, kind: SyntaxKind) {
+    self.parents.push((kind, checkpoint));
+  }
+
+  pub fn finish(mut self) -> SyntaxNode {
+    match self.children.pop() {
+      Some(SyntaxElement::Node(node)) if self.children.is_empty() && self.parents.is_empty() => node,
+      _ => panic!("具体语法树的节点没有正确结束"),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use crate::{
+    ast::AstNode,
+    generator::Generator,
+    parser::{Paser, CORPUS},
+  };
+
+  use super::{SyntaxElement, SyntaxKind, SyntaxNode};
+
+  const PROGRAMS: &[&str] = &[
+    "",
+    "  // 只有注释\n",
+    "var a = 1, b; // 行尾注释\nconst c = 2;;\n\n// 函数\nfn f(x, y) {\n  // 函数体\n  x += y * -c;\n  return (x)\n}\n",
+    "fn adder(n) { fn add(x) { x + n } add } adder(1)(2) // 调用\n",
+    "var i = 0;\twhile i < 3 { if i == 1 { println(i) } else { i = i + 1; }; i += 1 }",
+    "var f = fn id(x) { x }; !f(3) >= 4 / (2 - 1)",
+    "fn g(a) { a *= 2; a /= (1); return; } ;; g(1) - -1",
+  ];
+
+  /// 还原出的源代码与输入完全相同
+  #[test]
+  fn test_lossless() {
+    let mut programs = PROGRAMS.iter().map(|program| program.to_string()).collect::<Vec<_>>();
+    programs.push(fs::read_to_string("examples/a.pl0").unwrap());
+    programs.extend(CORPUS.iter().map(|(input, _)| input.to_string()));
+    programs.extend((0..50).map(|seed| Generator::new(seed).program().unparse()));
+
+    for input in &programs {
+      let node = SyntaxNode::parse(input).unwrap_or_else(|err| panic!("{:?}: {}", err, input));
+      assert_eq!(node.text(), *input);
+
+      // 由具体语法树得到的抽象语法树与直接解析的相同, 包括节点的位置
+      let program = Paser::paser(input).unwrap();
+      assert_eq!(format!("{:?}", node.to_program().unwrap()), format!("{:?}", program), "{}", input);
+
+      // 每个语句节点的起始位置与抽象语法树中的语句一致 (语句节点还包含结尾的分号)
+      assert_eq!(node.nodes().count(), program.statements.len());
+      for (node, statement) in node.nodes().zip(&program.statements) {
+        assert_eq!(node.pos().begin, statement.pos.begin, "{}", input);
+      }
+    }
+  }
+
+  #[test]
+  fn test_tree() {
+    let node = SyntaxNode::parse("// 注释\nf(1 + 2) ;").unwrap();
+    assert_eq!(
+      node.debug_tree(),
+      r#"Program
+  ExpressionStatement
+    CallExpression
+      Name
+        LineComment("// 注释")
+        Whitespace("\n")
+        "f"
+      ArgList
+        "("
+        InfixExpression
+          Literal
+            "1"
+          Whitespace(" ")
+          "+"
+          Literal
+            Whitespace(" ")
+            "2"
+        ")"
+    Whitespace(" ")
+    ";"
+  ""
+"#
+    );
+    assert_eq!(node.nodes().next().unwrap().kind, SyntaxKind::ExpressionStatement);
+
+    // 结构不完整的节点
+    let mut node = node;
+    let SyntaxElement::Node(statement) = &mut node.children[0] else { unreachable!() };
+    statement.kind = SyntaxKind::ConstStatement;
+    assert_eq!(node.to_program().unwrap_err().0, "malformed syntax node: CallExpression");
+  }
+}
@@ -0,0 +1,489 @@
+//!
+//! 调试适配器 (Debug Adapter Protocol)
+//!
+//! 通过标准输入输出与编辑器通信, 消息的格式与语言服务器相同 (Content-Length 头部 + JSON)
+//!
+
+use std::{
+  fs,
+  io::{self, BufRead, Write},
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+  compiler::{debuginfo::ScopeKind, Compiler},
+  lsp::{read_message, write_message},
+  parser::Paser,
+  vm::debugger::{Debugger, Frame, Stop},
+};
+
+/// 只有一个线程
+const THREAD_ID: usize = 1;
+
+/// 正在调试的程序
+struct Session {
+  debugger: Debugger,
+  path: String,
+  stop_on_entry: bool,
+  frames: Vec<Frame>, // 暂停时的调用栈, 栈帧与作用域的编号都是其中的下标 + 1
+}
+
+/// 调试适配器
+pub struct Server<W: Write> {
+  output: W,
+  seq: usize,
+  session: Option<Session>,
+  lines: Vec<usize>, // 启动之前设置的断点
+}
+
+impl<W: Write> Server<W> {
+  pub fn new(output: W) -> Self {
+    Server { output, seq: 0, session: None, lines: vec![] }
+  }
+
+  /// 处理输入中的所有请求, 直到收到 disconnect 请求或者输入结束
+  pub fn run(&mut self, mut input: impl BufRead) -> io::Result<()> {
+    while let Some(request) = read_message(&mut input)? {
+      // 无法解析的请求没有序号, 不能回复, 记录之后继续处理之后的请求
+      let request = match request {
+        Ok(request) => request,
+        Err(err) => {
+          eprintln!("invalid message: {}", err);
+          continue;
+        }
+      };
+      let command = request["command"].as_str().unwrap_or("").to_string();
+      let arguments = &request["arguments"];
+
+      let result = match command.as_str() {
+        "initialize" => Ok(json!({ "supportsConfigurationDoneRequest": true })),
+        "launch" => self.launch(arguments),
+        "setBreakpoints" => Ok(self.set_breakpoints(arguments)),
+        "configurationDone" | "disconnect" => Ok(Value::Null),
+        "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+        "continue" | "next" | "stepIn" | "stepOut" => match self.session {
+          Some(_) => Ok(json!({})),
+          None => Err("program is not launched".to_string()),
+        },
+        "stackTrace" => Ok(self.stack_trace()),
+        "scopes" => Ok(self.scopes(arguments)),
+        "variables" => Ok(self.variables(arguments)),
+        _ => Err(format!("unsupported command: {}", command)),
+      };
+
+      let response = match result {
+        Ok(body) => json!({ "request_seq": request["seq"], "success": true, "command": command, "body": body }),
+        Err(message) => {
+          json!({ "request_seq": request["seq"], "success": false, "command": command, "message": message })
+        }
+      };
+      self.send("response", response)?;
+
+      // 执行在回复之后进行, 结果通过事件通知
+      match command.as_str() {
+        "launch" if self.session.is_some() => self.event("initialized", json!({}))?,
+        // 启动时在第一条语句处暂停
+        "configurationDone" if self.session.as_ref().is_some_and(|session| session.stop_on_entry) => {
+          self.resume(Debugger::step_into, "entry")?
+        }
+        "configurationDone" | "continue" => self.resume(Debugger::cont, "step")?,
+        "next" => self.resume(Debugger::step_over, "step")?,
+        "stepIn" => self.resume(Debugger::step_into, "step")?,
+        "stepOut" => self.resume(Debugger::step_out, "step")?,
+        "disconnect" => break,
+        _ => {}
+      }
+    }
+    Ok(())
+  }
+
+  fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+    let path = arguments["program"].as_str().ok_or("missing program")?.to_string();
+    let input = fs::read_to_string(&path).map_err(|err| format!("{}: {}", path, err))?;
+
+    let program = Paser::paser(&input).map_err(|(err, pos)| format!("语法解析错误: {} ({})", err, pos))?;
+    let (codes, debug) = Compiler::compile_with_debug(&program).map_err(|errors| {
+      let errors = errors.iter().map(|(err, pos)| format!("{} ({})", err, pos)).collect::<Vec<_>>();
+      format!("编译错误: {}", errors.join(", "))
+    })?;
+
+    let mut debugger = Debugger::new(codes, debug, &input);
+    for line in self.lines.drain(..) {
+      debugger.set_line_breakpoint(line);
+    }
+
+    let stop_on_entry = arguments["stopOnEntry"] == true;
+    self.session = Some(Session { debugger, path, stop_on_entry, frames: vec![] });
+    Ok(Value::Null)
+  }
+
+  /// 替换所有的断点, 断点移动到之后最近的有语句的行
+  fn set_breakpoints(&mut self, arguments: &Value) -> Value {
+    let lines = arguments["breakpoints"].as_array().into_iter().flatten();
+    let lines =
+      lines.filter_map(|breakpoint| breakpoint["line"].as_u64()).map(|line| line as usize).collect::<Vec<_>>();
+
+    let Some(session) = &mut self.session else {
+      let breakpoints = lines.iter().map(|line| json!({ "verified": false, "line": line })).collect::<Vec<_>>();
+      self.lines = lines;
+      return json!({ "breakpoints": breakpoints });
+    };
+
+    session.debugger.clear_breakpoints();
+    let breakpoints = lines
+      .iter()
+      .map(|line| match session.debugger.set_line_breakpoint(*line) {
+        Some((line, _)) => json!({ "verified": true, "line": line }),
+        None => json!({ "verified": false, "line": line }),
+      })
+      .collect::<Vec<_>>();
+    json!({ "breakpoints": breakpoints })
+  }
+
+  /// 继续执行, 执行过程中程序的输出即时作为 output 事件发送, 然后发送暂停或者结束事件
+  fn resume(&mut self, run: fn(&mut Debugger, &mut dyn Write) -> Stop, reason: &str) -> io::Result<()> {
+    let Some(session) = &mut self.session else {
+      return Ok(());
+    };
+    let mut out = Output { output: &mut self.output, seq: &mut self.seq, buffer: vec![] };
+    let stop = run(&mut session.debugger, &mut out);
+    out.finish()?;
+    session.frames = session.debugger.frames();
+
+    match stop {
+      Stop::Step => self.stopped(reason, None),
+      Stop::Breakpoint(_) => self.stopped("breakpoint", None),
+      Stop::Exited(result) => {
+        self.event("output", json!({ "category": "console", "output": format!("程序结束, 返回值 {}\n", result) }))?;
+        self.event("exited", json!({ "exitCode": result }))?;
+        self.event("terminated", json!({}))
+      }
+      Stop::Error(err, ip) => self.stopped("exception", Some(format!("运行时错误: {} ({:04X}H)", err, ip))),
+    }
+  }
+
+  fn stopped(&mut self, reason: &str, text: Option<String>) -> io::Result<()> {
+    let body = json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true, "text": text });
+    self.event("stopped", body)
+  }
+
+  ///
+  /// 调用栈
+  ///
+  /// 只有函数与全局作用域作为栈帧, 语句块作为其所在栈帧的作用域
+  fn stack_trace(&self) -> Value {
+    let Some(session) = &self.session else {
+      return json!({ "stackFrames": [], "totalFrames": 0 });
+    };
+
+    let mut frames = vec![];
+    // 语句块所在的栈帧从最内层的语句块开始
+    let mut begin = 0;
+    for (index, frame) in session.frames.iter().enumerate() {
+      let name = match &frame.kind {
+        ScopeKind::Block => continue,
+        ScopeKind::Function(name) => name.clone(),
+        ScopeKind::Program => "<main>".to_string(),
+      };
+      let line = session.frames[begin].line.unwrap_or(1);
+      frames.push(json!({
+        "id": begin + 1,
+        "name": name,
+        "line": line,
+        "column": 1,
+        "source": { "path": session.path },
+        "instructionPointerReference": format!("{:04X}H", frame.ip),
+      }));
+      begin = index + 1;
+    }
+    json!({ "stackFrames": frames, "totalFrames": frames.len() })
+  }
+
+  /// 栈帧中的作用域: 从最内层的语句块到函数本身
+  fn scopes(&self, arguments: &Value) -> Value {
+    let Some(session) = &self.session else {
+      return json!({ "scopes": [] });
+    };
+    let begin = arguments["frameId"].as_u64().unwrap_or(0) as usize;
+
+    let mut scopes = vec![];
+    for (index, frame) in session.frames.iter().enumerate().skip(begin.saturating_sub(1)) {
+      let name = match &frame.kind {
+        ScopeKind::Block => format!("Block {}", scopes.len() + 1),
+        ScopeKind::Function(name) => format!("fn {}", name),
+        ScopeKind::Program => "Globals".to_string(),
+      };
+      scopes.push(json!({ "name": name, "variablesReference": index + 1, "expensive": false }));
+      if !matches!(frame.kind, ScopeKind::Block) {
+        break;
+      }
+    }
+    json!({ "scopes": scopes })
+  }
+
+  fn variables(&self, arguments: &Value) -> Value {
+    let reference = arguments["variablesReference"].as_u64().unwrap_or(0) as usize;
+    let frame = self.session.as_ref().and_then(|session| session.frames.get(reference.wrapping_sub(1)));
+
+    let variables = frame.into_iter().flat_map(|frame| &frame.variables).map(
+      |(name, value)| json!({ "name": name, "value": value.to_string(), "type": "integer", "variablesReference": 0 }),
+    );
+    json!({ "variables": variables.collect::<Vec<_>>() })
+  }
+
+  fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+    self.send("event", json!({ "event": event, "body": body }))
+  }
+
+  fn send(&mut self, kind: &str, message: Value) -> io::Result<()> {
+    send(&mut self.output, &mut self.seq, kind, message)
+  }
+}
+
+///
+/// 程序的输出
+///
+/// 每写完一行立即作为 output 事件发送, 最后不完整的一行在执行暂停时发送
+struct Output<'a, W: Write> {
+  output: &'a mut W,
+  seq: &'a mut usize,
+  buffer: Vec<u8>, // 还没有发送的不完整的行
+}
+
+impl<W: Write> Output<'_, W> {
+  fn event(&mut self, end: usize) -> io::Result<()> {
+    let output = String::from_utf8_lossy(&self.buffer[..end]).to_string();
+    self.buffer.drain(..end);
+    let body = json!({ "category": "stdout", "output": output });
+    send(self.output, self.seq, "event", json!({ "event": "output", "body": body }))
+  }
+
+  fn finish(mut self) -> io::Result<()> {
+    match self.buffer.len() {
+      0 => Ok(()),
+      end => self.event(end),
+    }
+  }
+}
+
+impl<W: Write> Write for Output<'_, W> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.buffer.extend_from_slice(buf);
+    if let Some(index) = self.buffer.iter().rposition(|&b| b == b'\n') {
+      self.event(index + 1)?;
+    }
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// 发送消息, 并加上消息序号与类型
+fn send(output: &mut impl Write, seq: &mut usize, kind: &str, mut message: Value) -> io::Result<()> {
+  *seq += 1;
+  message["seq"] = json!(*seq);
+  message["type"] = json!(kind);
+  write_message(output, &message)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{env, fs, io::Cursor};
+
+  use serde_json::{json, Value};
+
+  use crate::lsp::{read_message, write_message};
+
+  use super::Server;
+
+  const SOURCE: &str = "fn fact(n) {
+  if n <= 1 {
+    var one = 1;
+    one
+  } else {
+    n * fact(n - 1)
+  }
+}
+println(fact(3));
+var done = 1";
+
+  /// 模拟客户端: 依次发送请求, 返回调试适配器的所有输出消息
+  fn session(name: &str, requests: &[(&str, Value)]) -> Vec<Value> {
+    run(&input(name, SOURCE, requests))
+  }
+
+  /// 将源代码写入临时文件, 返回请求编码之后的输入
+  fn input(name: &str, source: &str, requests: &[(&str, Value)]) -> Vec<u8> {
+    let path = env::temp_dir().join(name);
+    fs::write(&path, source).unwrap();
+
+    let mut input = vec![];
+    for (seq, (command, arguments)) in requests.iter().enumerate() {
+      let arguments = match command {
+        &"launch" => json!({ "program": path.to_str().unwrap(), "stopOnEntry": arguments["stopOnEntry"] }),
+        _ => arguments.clone(),
+      };
+      let request = json!({ "seq": seq + 1, "type": "request", "command": command, "arguments": arguments });
+      write_message(&mut input, &request).unwrap();
+    }
+    input
+  }
+
+  /// 调试适配器处理原始的输入
+  fn run(input: &[u8]) -> Vec<Value> {
+    let mut output = vec![];
+    Server::new(&mut output).run(Cursor::new(input)).unwrap();
+
+    let mut messages = vec![];
+    let mut output = Cursor::new(output);
+    while let Some(message) = read_message(&mut output).unwrap() {
+      messages.push(message.unwrap());
+    }
+    messages
+  }
+
+  /// 第 seq 个请求的回复
+  fn response(messages: &[Value], seq: usize) -> &Value {
+    messages.iter().find(|message| message["type"] == "response" && message["request_seq"] == seq).unwrap()
+  }
+
+  fn events<'a>(messages: &'a [Value], event: &str) -> Vec<&'a Value> {
+    messages.iter().filter(|message| message["event"] == event).map(|message| &message["body"]).collect()
+  }
+
+  #[test]
+  fn test_breakpoint() {
+    let messages = session(
+      "dap_breakpoint.pl0",
+      &[
+        ("initialize", json!({})),
+        ("launch", json!({})),
+        ("setBreakpoints", json!({ "source": {}, "breakpoints": [{ "line": 4 }, { "line": 8 }] })),
+        ("configurationDone", json!({})),
+        ("continue", json!({ "threadId": 1 })),
+        ("threads", json!({})),
+        ("stackTrace", json!({ "threadId": 1 })),
+        ("scopes", json!({ "frameId": 1 })),
+        ("variables", json!({ "variablesReference": 1 })),
+        ("variables", json!({ "variablesReference": 2 })),
+        ("scopes", json!({ "frameId": 3 })),
+        ("variables", json!({ "variablesReference": 4 })),
+        ("continue", json!({ "threadId": 1 })),
+        ("disconnect", json!({})),
+      ],
+    );
+
+    assert!(messages.iter().all(|message| message["success"] != false), "{:?}", messages);
+    assert_eq!(events(&messages, "initialized").len(), 1);
+
+    // 第 8 行没有语句, 断点移动到第 9 行
+    let breakpoints = &response(&messages, 3)["body"]["breakpoints"];
+    assert_eq!(breakpoints, &json!([{ "verified": true, "line": 4 }, { "verified": true, "line": 9 }]));
+
+    // 先停在第 9 行, 再停在第 4 行
+    let stopped = events(&messages, "stopped");
+    assert_eq!(stopped.iter().map(|body| body["reason"].as_str().unwrap()).collect::<Vec<_>>(), ["breakpoint"; 2]);
+
+    // fact(3) -> fact(2) -> fact(1), 语句块不作为栈帧
+    let frames = response(&messages, 7)["body"]["stackFrames"].as_array().unwrap();
+    let frames = frames.iter().map(|frame| (frame["name"].clone(), frame["line"].clone())).collect::<Vec<_>>();
+    assert_eq!(
+      frames,
+      [("fact", 4), ("fact", 6), ("fact", 6), ("<main>", 9)].map(|(name, line)| (json!(name), json!(line)))
+    );
+
+    let scopes = response(&messages, 8)["body"]["scopes"].as_array().unwrap();
+    let names = scopes.iter().map(|scope| scope["name"].as_str().unwrap()).collect::<Vec<_>>();
+    assert_eq!(names, ["Block 1", "fn fact"]);
+    assert_eq!(
+      response(&messages, 9)["body"]["variables"][0],
+      json!({ "name": "one", "value": "1", "type": "integer", "variablesReference": 0 })
+    );
+    assert_eq!(response(&messages, 10)["body"]["variables"][0]["value"], "1");
+
+    // 调用者的栈帧中, 语句块与函数作为嵌套的作用域
+    let scopes = response(&messages, 11)["body"]["scopes"].as_array().unwrap();
+    assert_eq!(scopes.iter().map(|scope| scope["name"].as_str().unwrap()).collect::<Vec<_>>(), ["Block 1", "fn fact"]);
+    assert_eq!(response(&messages, 12)["body"]["variables"][0]["value"], "2");
+
+    // 程序的输出通过 output 事件转发
+    assert_eq!(events(&messages, "output")[0], &json!({ "category": "stdout", "output": "\"6\"\n" }));
+    assert_eq!(events(&messages, "exited")[0]["exitCode"], 1);
+    assert_eq!(events(&messages, "terminated").len(), 1);
+  }
+
+  #[test]
+  fn test_step() {
+    let messages = session(
+      "dap_step.pl0",
+      &[
+        ("initialize", json!({})),
+        ("launch", json!({ "stopOnEntry": true })),
+        ("configurationDone", json!({})),
+        ("stepIn", json!({ "threadId": 1 })),
+        ("stepIn", json!({ "threadId": 1 })),
+        ("stackTrace", json!({ "threadId": 1 })),
+        ("stepOut", json!({ "threadId": 1 })),
+        ("next", json!({ "threadId": 1 })),
+        ("stackTrace", json!({ "threadId": 1 })),
+        ("next", json!({ "threadId": 1 })),
+        ("disconnect", json!({})),
+      ],
+    );
+
+    let stopped = events(&messages, "stopped");
+    let reasons = stopped.iter().map(|body| body["reason"].as_str().unwrap()).collect::<Vec<_>>();
+    assert_eq!(reasons, ["entry", "step", "step", "step", "step"]);
+
+    let frames = &response(&messages, 6)["body"]["stackFrames"];
+    assert_eq!((frames[0]["name"].as_str(), frames[0]["line"].as_u64()), (Some("fact"), Some(2)));
+    assert_eq!(stopped[0]["threadId"], 1);
+
+    let frames = &response(&messages, 9)["body"]["stackFrames"];
+    assert_eq!((frames[0]["name"].as_str(), frames[0]["line"].as_u64()), (Some("<main>"), Some(10)));
+    assert_eq!(events(&messages, "output")[0]["output"], "\"6\"\n");
+    assert_eq!(events(&messages, "exited")[0]["exitCode"], 1);
+  }
+
+  #[test]
+  fn test_launch_error() {
+    // 词法错误作为 launch 的失败回复
+    let requests = [("initialize", json!({})), ("launch", json!({})), ("disconnect", json!({}))];
+    let messages = run(&input("dap_launch_error.pl0", "var x = 1; x @", &requests));
+    let launch = response(&messages, 2);
+    assert_eq!(launch["success"], false);
+    assert!(launch["message"].as_str().unwrap().contains("illegal character '@'"), "{:?}", launch);
+    assert_eq!(response(&messages, 3)["success"], true);
+  }
+
+  #[test]
+  fn test_output() {
+    let requests = [
+      ("initialize", json!({})),
+      ("launch", json!({})),
+      ("setBreakpoints", json!({ "source": {}, "breakpoints": [{ "line": 3 }] })),
+      ("configurationDone", json!({})),
+      ("continue", json!({ "threadId": 1 })),
+      ("disconnect", json!({})),
+    ];
+    // 无法解析的消息被忽略
+    let mut input = b"Content-Length: 2\r\n\r\n{,".to_vec();
+    input.extend(self::input("dap_output.pl0", "println(1); print(2, 3);\nprintln(4);\nvar x = 5", &requests));
+    let messages = run(&input);
+    assert!(messages.iter().all(|message| message["success"] != false), "{:?}", messages);
+
+    // 每一行输出都是单独的事件, 在暂停事件之前发送
+    let names = messages
+      .iter()
+      .filter(|message| message["type"] == "event")
+      .map(|message| match message["event"].as_str().unwrap() {
+        "output" => message["body"]["output"].as_str().unwrap(),
+        event => event,
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(names[..4], ["initialized", "\"1\"\n", "\"2 3\"\"4\"\n", "stopped"]);
+  }
+}
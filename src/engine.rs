@@ -0,0 +1,200 @@
+use std::{
+  fmt::Display,
+  io::{self, Write},
+};
+
+use crate::{
+  compiler::{debuginfo::DebugInfo, Compiler},
+  parser::Paser,
+  vm::{Limits, RuntimeError, Status, VM},
+  SpanOffset,
+};
+
+/// 全局函数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+  pub name: String,
+  pub arity: usize, // 参数个数
+  entry: usize,     // 入口地址
+}
+
+#[derive(Debug, Clone)]
+pub enum EngineError {
+  Syntax(String, SpanOffset),                            // 语法解析错误
+  Compile(Vec<(String, SpanOffset)>),                    // 编译错误
+  Runtime(RuntimeError, usize),                          // 运行时错误, 以及出错的指令地址
+  UndefinedFunction(String),                             // 没有该名字的全局函数
+  Arity { name: String, expected: usize, found: usize }, // 实参个数与形参个数不同
+}
+
+impl Display for EngineError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      EngineError::Syntax(err, pos) => write!(f, "syntax error: {} ({})", err, pos),
+      EngineError::Compile(errors) => {
+        let errors = errors.iter().map(|(err, pos)| format!("{} ({})", err, pos)).collect::<Vec<_>>();
+        write!(f, "compile error: {}", errors.join(", "))
+      }
+      EngineError::Runtime(err, ip) => write!(f, "runtime error: {} ({:04X}H)", err, ip),
+      EngineError::UndefinedFunction(name) => write!(f, "function is undefined: {}", name),
+      EngineError::Arity { name, expected, found } => {
+        write!(f, "function {} takes {} arguments but {} were given", name, expected, found)
+      }
+    }
+  }
+}
+
+type Result<T> = std::result::Result<T, EngineError>;
+
+///
+/// 嵌入 API: 编译一次源代码, 然后在 Rust 中按名字多次调用其中的全局函数
+///
+/// 创建时先执行一遍整个程序 (初始化全局变量), 之后的每次调用都在同一个虚拟机中进行,
+/// 全局变量的值在多次调用之间保留
+///
+/// ```
+/// use pl0::engine::Engine;
+///
+/// let mut engine = Engine::new("fn fib(n) { if n <= 1 { n } else { fib(n - 1) + fib(n - 2) } }").unwrap();
+/// assert_eq!(engine.call("fib", &[10]).unwrap(), 55);
+/// ```
+pub struct Engine {
+  vm: VM,
+  debug: DebugInfo,
+  functions: Vec<Function>,
+  result: isize, // 程序的返回值
+}
+
+impl Engine {
+  /// 编译并执行程序, 内建函数的输出写入到标准输出
+  pub fn new(source: &str) -> Result<Self> {
+    Self::with_output(source, Limits::default(), &mut io::stdout())
+  }
+
+  /// 在资源限制下编译并执行程序, 内建函数的输出写入到 out 中, 资源限制同样作用于之后的每次调用
+  pub fn with_output(source: &str, limits: Limits, out: &mut dyn Write) -> Result<Self> {
+    let program = Paser::paser(source).map_err(|(err, pos)| EngineError::Syntax(err, pos))?;
+    let (codes, debug) = Compiler::compile_with_debug(&program).map_err(EngineError::Compile)?;
+
+    // 全局作用域中定义的函数, 同名的函数以后定义的为准
+    let mut functions: Vec<Function> = vec![];
+    for function in debug.functions.iter().filter(|function| debug.scopes[function.scope].parent == Some(0)) {
+      functions.retain(|item| item.name != function.name);
+      functions.push(Function { name: function.name.clone(), arity: function.argc, entry: function.entry });
+    }
+
+    let mut vm = VM::with_limits(limits);
+    vm.load(codes);
+    let result = match vm.run_for(u64::MAX, out) {
+      Status::Finished(result) => result,
+      Status::Error(err, ip) => return Err(EngineError::Runtime(err, ip)),
+      Status::Paused => unreachable!(),
+    };
+
+    Ok(Engine { vm, debug, functions, result })
+  }
+
+  /// 程序的返回值
+  pub fn result(&self) -> isize {
+    self.result
+  }
+
+  /// 所有的全局函数
+  pub fn functions(&self) -> &[Function] {
+    &self.functions
+  }
+
+  pub fn function(&self, name: &str) -> Option<&Function> {
+    self.functions.iter().find(|function| function.name == name)
+  }
+
+  /// 全局变量的当前值
+  pub fn global(&self, name: &str) -> Option<isize> {
+    let variable = self.debug.scopes[0].variables.iter().rev().find(|variable| variable.name == name)?;
+    self.vm.global(variable.addr as usize)
+  }
+
+  /// 调用全局函数, 内建函数的输出写入到标准输出
+  pub fn call(&mut self, name: &str, args: &[isize]) -> Result<isize> {
+    self.call_with_output(name, args, &mut io::stdout())
+  }
+
+  /// 调用全局函数, 内建函数的输出写入到 out 中
+  pub fn call_with_output(&mut self, name: &str, args: &[isize], out: &mut dyn Write) -> Result<isize> {
+    let function = self.function(name).ok_or_else(|| EngineError::UndefinedFunction(name.to_string()))?;
+    if function.arity != args.len() {
+      return Err(EngineError::Arity { name: name.to_string(), expected: function.arity, found: args.len() });
+    }
+
+    let entry = function.entry;
+    self.vm.invoke(entry, args, out).map_err(|(err, ip)| EngineError::Runtime(err, ip))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::vm::{Limits, RuntimeError};
+
+  use super::{Engine, EngineError};
+
+  const SOURCE: &str = "var count = 0, total;
+fn fib(n) {
+  count += 1;
+  if n <= 1 { n } else { fib(n - 1) + fib(n - 2) }
+}
+fn add(x, y) {
+  total += x;
+  println(total);
+  x - y
+}
+fn div(x, y) { x / y }
+if 1 {
+  fn hidden() { 1 }
+};
+total = 100";
+
+  #[test]
+  fn test_call() {
+    let mut out = vec![];
+    let mut engine = Engine::with_output(SOURCE, Limits::default(), &mut out).unwrap();
+    assert_eq!(engine.result(), 100);
+
+    // 只导出全局作用域中定义的函数
+    let functions =
+      engine.functions().iter().map(|function| (function.name.as_str(), function.arity)).collect::<Vec<_>>();
+    assert_eq!(functions, [("fib", 1), ("add", 2), ("div", 2)]);
+
+    assert_eq!(engine.call_with_output("fib", &[10], &mut out).ok(), Some(55));
+    assert_eq!(engine.call_with_output("fib", &[10], &mut out).ok(), Some(55));
+    assert_eq!(engine.global("count"), Some(177 * 2));
+
+    // 全局变量在多次调用之间保留, 实参的顺序与 PL/0 中的调用相同
+    assert_eq!(engine.call_with_output("add", &[5, 2], &mut out).ok(), Some(3));
+    assert_eq!(engine.call_with_output("add", &[7, 10], &mut out).ok(), Some(-3));
+    assert_eq!(engine.global("total"), Some(112));
+    assert_eq!(String::from_utf8(out).unwrap(), "\"105\"\n\"112\"\n");
+  }
+
+  #[test]
+  fn test_errors() {
+    let mut engine = Engine::with_output(SOURCE, Limits::default(), &mut vec![]).unwrap();
+    assert!(matches!(engine.call("hidden", &[]), Err(EngineError::UndefinedFunction(_))));
+    assert!(matches!(engine.call("fib", &[1, 2]), Err(EngineError::Arity { expected: 1, found: 2, .. })));
+
+    // 运行时错误之后仍然可以继续调用
+    assert!(matches!(engine.call("div", &[1, 0]), Err(EngineError::Runtime(RuntimeError::DivisionByZero, _))));
+    assert_eq!(engine.call("div", &[6, 3]).ok(), Some(2));
+    assert_eq!(engine.global("total"), Some(100));
+
+    // 资源限制作用于每次调用
+    let limits = Limits { instructions: Some(10_000), ..Limits::default() };
+    let mut engine = Engine::with_output(SOURCE, limits, &mut vec![]).unwrap();
+    assert_eq!(engine.call("fib", &[10]).ok(), Some(55));
+    assert_eq!(engine.call("fib", &[10]).ok(), Some(55));
+    assert!(matches!(engine.call("fib", &[20]), Err(EngineError::Runtime(RuntimeError::InstructionLimit, _))));
+
+    assert!(matches!(Engine::new("var a = ;"), Err(EngineError::Syntax(..))));
+    assert!(matches!(Engine::new("a = 1"), Err(EngineError::Compile(_))));
+    assert!(matches!(Engine::new("1 / 0"), Err(EngineError::Runtime(RuntimeError::DivisionByZero, _))));
+  }
+}
@@ -0,0 +1,774 @@
+use std::mem;
+
+use crate::{
+  cst::{SyntaxElement, SyntaxKind, SyntaxNode, SyntaxToken},
+  parser::{Paser, Precedence},
+  token::{Token, Trivia},
+  SpanOffset,
+};
+
+/// 缩进的空格数
+const INDENT: usize = 2;
+
+/// 格式化选项
+#[derive(Debug, Clone)]
+pub struct Options {
+  pub width: usize,    // 每行的最大宽度
+  pub semicolon: bool, // 语句是否以分号结尾, 为假时只在解析需要的地方保留分号
+}
+
+impl Default for Options {
+  fn default() -> Self {
+    Options { width: 100, semicolon: true }
+  }
+}
+
+/// 格式化源代码
+pub fn format(input: &str, options: &Options) -> Result<String, (String, SpanOffset)> {
+  Ok(format_node(&SyntaxNode::parse(input)?, options))
+}
+
+/// 格式化具体语法树
+pub fn format_node(node: &SyntaxNode, options: &Options) -> String {
+  let mut formatter = Formatter::new(node, options);
+  let doc = formatter.program(node);
+  Printer::print(&doc, options.width)
+}
+
+///
+/// 排版文档
+///
+/// 先将语法树转换为文档, 再根据行宽决定每个 Group 是在一行内输出还是在 Line 处换行
+enum Doc {
+  Text(String),
+  Line,               // 一行时为空格, 否则换行
+  SoftLine,           // 一行时为空, 否则换行
+  HardLine,           // 总是换行, 所在的 Group 不能在一行内输出
+  BlankLine,          // 换行并且保留一个空行
+  LineSuffix(String), // 行尾注释, 推迟到换行之前输出
+  Nest(Box<Doc>),     // 换行后增加缩进
+  Group(Box<Doc>),
+  Concat(Vec<Doc>),
+}
+
+impl Doc {
+  fn text(text: &str) -> Doc {
+    Doc::Text(text.to_string())
+  }
+
+  fn nest(doc: Doc) -> Doc {
+    Doc::Nest(Box::new(doc))
+  }
+
+  fn group(doc: Doc) -> Doc {
+    Doc::Group(Box::new(doc))
+  }
+
+  fn is_empty(&self) -> bool {
+    match self {
+      Doc::Concat(docs) => docs.iter().all(Doc::is_empty),
+      Doc::Text(text) => text.is_empty(),
+      _ => false,
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+  Flat,
+  Break,
+}
+
+/// 将文档输出为字符串
+struct Printer {
+  output: String,
+  column: usize,
+  indent: usize,         // 当前行的缩进
+  line_empty: bool,      // 当前行还没有输出任何内容, 缩进在输出内容时才写入
+  suffixes: Vec<String>, // 等待输出的行尾注释
+}
+
+impl Printer {
+  fn print(doc: &Doc, width: usize) -> String {
+    let mut printer = Printer { output: String::new(), column: 0, indent: 0, line_empty: true, suffixes: vec![] };
+    let mut stack = vec![(0, Mode::Break, doc)];
+
+    while let Some((indent, mode, doc)) = stack.pop() {
+      match doc {
+        // 换行输出时, 行尾注释之后的单词 (关键字, 标识符与数字) 另起一行, 注释留在它原来所在的 token 之后;
+        // 运算符与标点仍然跟在 token 之后, 在一行内输出的 Group 中注释推迟到行尾
+        Doc::Text(text)
+          if mode == Mode::Break
+            && !printer.suffixes.is_empty()
+            && text.starts_with(|c: char| c.is_alphanumeric() || c == '_') =>
+        {
+          printer.output.truncate(printer.output.trim_end_matches(' ').len());
+          printer.newline(indent);
+          printer.text(text);
+        }
+        Doc::Text(text) => printer.text(text),
+        Doc::Line if mode == Mode::Flat => printer.text(" "),
+        Doc::SoftLine if mode == Mode::Flat => {}
+        Doc::Line | Doc::SoftLine | Doc::HardLine => printer.newline(indent),
+        Doc::BlankLine => {
+          printer.newline(indent);
+          if !printer.output.is_empty() && !printer.output.ends_with("\n\n") {
+            printer.output.push('\n');
+          }
+        }
+        Doc::LineSuffix(text) => printer.suffixes.push(text.clone()),
+        Doc::Nest(doc) => stack.push((indent + INDENT, mode, doc)),
+        Doc::Group(doc) => {
+          let remain = width as isize - printer.column as isize;
+          let flat = mode == Mode::Flat || Self::fits(remain, vec![(Mode::Flat, doc)], &stack);
+          stack.push((indent, if flat { Mode::Flat } else { Mode::Break }, doc));
+        }
+        Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|doc| (indent, mode, doc))),
+      }
+    }
+
+    printer.newline(0);
+    printer.output
+  }
+
+  /// Group 在一行内输出时, 到下一次换行为止的内容能否放进剩余的宽度
+  fn fits<'d>(mut remain: isize, mut docs: Vec<(Mode, &'d Doc)>, rest: &[(usize, Mode, &'d Doc)]) -> bool {
+    let mut rest = rest.iter().rev();
+    let mut suffix = false; // Group 中出现了行尾注释
+    let mut inside = true; // 是否还在 Group 之内
+
+    while remain >= 0 {
+      let (mode, doc) = match docs.pop() {
+        Some(doc) => doc,
+        None => match rest.next() {
+          Some((_, mode, doc)) => {
+            inside = false;
+            (*mode, *doc)
+          }
+          None => return true,
+        },
+      };
+
+      match doc {
+        // 行尾注释之后 Group 中还有内容时需要换行, Group 之后的内容 (比如补充的分号) 不影响注释的位置
+        Doc::Text(text) if suffix && inside && !text.is_empty() => return false,
+        Doc::Text(text) => remain -= text.chars().count() as isize,
+        Doc::Line if mode == Mode::Flat => remain -= 1,
+        Doc::SoftLine if mode == Mode::Flat => {}
+        Doc::Line | Doc::SoftLine => return true,
+        // 包含强制换行的 Group 不能在一行内输出
+        Doc::HardLine | Doc::BlankLine => return mode == Mode::Break,
+        Doc::LineSuffix(_) => suffix |= inside,
+        Doc::Nest(doc) | Doc::Group(doc) => docs.push((mode, doc)),
+        Doc::Concat(children) => docs.extend(children.iter().rev().map(|doc| (mode, doc))),
+      }
+    }
+    false
+  }
+
+  fn text(&mut self, text: &str) {
+    if self.line_empty {
+      self.output.extend(std::iter::repeat_n(' ', self.indent));
+      self.column = self.indent;
+      self.line_empty = false;
+    }
+    self.output.push_str(text);
+    self.column += text.chars().count();
+  }
+
+  /// 换行, 下一行的缩进为 indent
+  fn newline(&mut self, indent: usize) {
+    for (index, suffix) in mem::take(&mut self.suffixes).into_iter().enumerate() {
+      // 同一行只能有一个行尾注释, 其余的注释各占一行
+      if index > 0 {
+        self.output.push('\n');
+        self.line_empty = true;
+      }
+      let suffix = if self.line_empty { suffix.trim_start().to_string() } else { suffix };
+      self.text(&suffix);
+    }
+    if !self.line_empty {
+      self.output.push('\n');
+      self.line_empty = true;
+      self.column = 0;
+    }
+    self.indent = indent;
+  }
+}
+
+/// token 上的注释
+#[derive(Default)]
+struct Comments {
+  leading: Vec<(String, bool)>, // 独占一行的注释, 以及之前是否有空行
+  trailing: Option<String>,     // 与 token 在同一行的行尾注释
+  blank: bool,                  // token 之前是否有空行
+}
+
+///
+/// 格式化器
+///
+/// 按照源代码中的顺序访问每一个 token, 被省略的 token (多余的括号与分号) 上的注释仍然会输出
+struct Formatter<'o> {
+  options: &'o Options,
+  comments: Vec<Comments>, // 每个 token 上的注释, 下标为 token 的顺序
+  cursor: usize,           // 下一个访问的 token
+}
+
+impl<'o> Formatter<'o> {
+  fn new(node: &SyntaxNode, options: &'o Options) -> Self {
+    let tokens = node.tokens();
+    let mut comments = tokens.iter().map(|_| Comments::default()).collect::<Vec<_>>();
+
+    for (index, token) in tokens.iter().enumerate() {
+      let mut newlines = 0;
+      for trivia in &token.leading {
+        match trivia {
+          Trivia::Whitespace(text) => newlines += text.matches('\n').count(),
+          // 与上一个 token 在同一行的注释
+          Trivia::LineComment(text) if newlines == 0 && index > 0 => comments[index - 1].trailing = Some(text.clone()),
+          Trivia::LineComment(text) => {
+            comments[index].leading.push((text.clone(), newlines >= 2));
+            newlines = 0;
+          }
+        }
+      }
+      comments[index].blank = newlines >= 2;
+    }
+
+    Formatter { options, comments, cursor: 0 }
+  }
+
+  fn program(&mut self, node: &SyntaxNode) -> Doc {
+    let statements = node.nodes().collect::<Vec<_>>();
+    let mut docs = vec![self.statements(&statements, false)];
+
+    // 文件末尾的注释
+    docs.push(self.closing_comments());
+    self.cursor += 1;
+    Doc::Concat(docs)
+  }
+
+  ///
+  /// 语句序列, 每条语句一行
+  ///
+  /// tail 为真时最后一条表达式语句是语句块的值, 不需要分号
+  fn statements(&mut self, statements: &[&SyntaxNode], tail: bool) -> Doc {
+    let mut items = vec![];
+    for node in statements {
+      // 语句之前独占一行的注释放在语句之外, 避免影响语句的排版
+      let (comments, blank) = self.take_leading();
+      let statement = self.statement(node);
+
+      // 省略空语句
+      if !comments.is_empty() || !statement.is_empty() {
+        items.push((*node, comments, blank, statement));
+      }
+    }
+
+    // 是否需要分号取决于下一条语句格式化之后的开头
+    let firsts = items.iter().map(|(_, _, _, statement)| first_text(statement).to_string()).collect::<Vec<_>>();
+    let mut docs = vec![];
+    for (index, (node, comments, blank, statement)) in items.into_iter().enumerate() {
+      if index > 0 {
+        docs.push(if blank { Doc::BlankLine } else { Doc::HardLine });
+      }
+      docs.push(comments);
+      docs.push(statement);
+
+      let next = firsts.get(index + 1).map(String::as_str);
+      if self.terminate(node, next, tail && next.is_none()) {
+        docs.push(Doc::text(";"));
+      }
+    }
+
+    Doc::Concat(docs)
+  }
+
+  /// 语句是否需要以分号结尾, next 为下一条语句的开头
+  fn terminate(&self, node: &SyntaxNode, next: Option<&str>, tail: bool) -> bool {
+    match node.kind {
+      SyntaxKind::FunctionStatement | SyntaxKind::EmptyStatement => false,
+      _ if need_semicolon(node, next) => true,
+      _ if !self.options.semicolon => false,
+      SyntaxKind::ExpressionStatement => !tail && !ends_with_block(node),
+      _ => true,
+    }
+  }
+
+  /// 语句, 不包含结尾的分号
+  fn statement(&mut self, node: &SyntaxNode) -> Doc {
+    let mut docs = vec![];
+
+    let mut children = node.children.iter().peekable();
+
+    match node.kind {
+      SyntaxKind::EmptyStatement => {}
+      SyntaxKind::ConstStatement | SyntaxKind::VarStatement => {
+        docs.push(self.token(next_token(&mut children)));
+        docs.push(Doc::text(" "));
+
+        let mut declarators = vec![];
+        while let Some(SyntaxElement::Node(declarator)) = children.peek() {
+          children.next();
+          declarators.push(self.declarator(declarator));
+
+          if let Some(SyntaxElement::Token(SyntaxToken { token: Token::Comma, .. })) = children.peek() {
+            declarators.push(self.token(next_token(&mut children)));
+            declarators.push(Doc::nest(Doc::Line));
+          }
+        }
+        docs.push(Doc::group(Doc::Concat(declarators)));
+      }
+      SyntaxKind::FunctionStatement => docs.push(self.function(&mut children)),
+      SyntaxKind::AssignStatement => {
+        docs.push(self.token(next_token(&mut children)));
+        docs.push(Doc::text(" "));
+        docs.push(self.token(next_token(&mut children)));
+        docs.push(Doc::text(" "));
+        docs.push(self.expression(next_node(&mut children), None));
+      }
+      SyntaxKind::ReturnStatement => {
+        docs.push(self.token(next_token(&mut children)));
+        if let Some(SyntaxElement::Node(e)) = children.peek() {
+          children.next();
+          docs.push(Doc::text(" "));
+          docs.push(self.expression(e, None));
+        }
+      }
+      SyntaxKind::ExpressionStatement => {
+        let e = next_node(&mut children);
+        // 以 fn 开头的表达式语句会被解析为函数定义语句, 需要保留括号
+        docs.push(if e.kind == SyntaxKind::ParenExpression && e.tokens()[1].token == Token::Function {
+          self.paren(e, true, None)
+        } else {
+          self.expression(e, None)
+        });
+      }
+      kind => unreachable!("{:?}", kind),
+    }
+
+    // 原有的分号全部省略, 再根据需要添加
+    for child in children {
+      match child {
+        SyntaxElement::Token(token) => docs.push(self.skip(token)),
+        SyntaxElement::Node(_) => unreachable!(),
+      }
+    }
+
+    Doc::Concat(docs)
+  }
+
+  /// 常量与变量声明中的 name = value
+  fn declarator(&mut self, node: &SyntaxNode) -> Doc {
+    let mut docs = vec![];
+    for child in &node.children {
+      match child {
+        SyntaxElement::Token(token) if token.token == Token::Assign => {
+          docs.push(Doc::text(" "));
+          docs.push(self.token(token));
+          docs.push(Doc::text(" "));
+        }
+        SyntaxElement::Token(token) => docs.push(self.token(token)),
+        SyntaxElement::Node(e) => docs.push(self.expression(e, None)),
+      }
+    }
+    Doc::Concat(docs)
+  }
+
+  /// fn name(args) { ... }
+  fn function<'a>(&mut self, children: &mut impl Iterator<Item = &'a SyntaxElement>) -> Doc {
+    let keyword = self.token(next_token(children));
+    let name = self.token(next_token(children));
+    let params = self.list(next_node(children));
+    let block = self.block(next_node(children));
+
+    Doc::Concat(vec![keyword, Doc::text(" "), name, params, Doc::text(" "), block])
+  }
+
+  /// 括号中以逗号分隔的参数或者实参
+  fn list(&mut self, node: &SyntaxNode) -> Doc {
+    let mut docs = vec![];
+    let mut children = node.children.iter();
+
+    let open = self.token(next_token(&mut children));
+    for child in children.by_ref() {
+      match child {
+        SyntaxElement::Token(token) if token.token == Token::Rparen => {
+          let close = self.token(token);
+          if docs.is_empty() {
+            return Doc::Concat(vec![open, close]);
+          }
+
+          return Doc::group(Doc::Concat(vec![
+            open,
+            Doc::nest(Doc::Concat(vec![Doc::SoftLine, Doc::Concat(docs)])),
+            Doc::SoftLine,
+            close,
+          ]));
+        }
+        SyntaxElement::Token(token) if token.token == Token::Comma => {
+          docs.push(self.token(token));
+          docs.push(Doc::Line);
+        }
+        SyntaxElement::Token(token) => docs.push(self.token(token)),
+        SyntaxElement::Node(e) => docs.push(self.expression(e, None)),
+      }
+    }
+    unreachable!()
+  }
+
+  /// 语句块, 只有一条语句时可以在一行内输出
+  fn block(&mut self, node: &SyntaxNode) -> Doc {
+    let mut children = node.children.iter();
+    let open = self.token(next_token(&mut children));
+
+    let statements = node.nodes().collect::<Vec<_>>();
+    let body = self.statements(&statements, true);
+    let comments = self.closing_comments();
+    let close = self.token(node.tokens().last().unwrap());
+
+    if body.is_empty() && comments.is_empty() {
+      return Doc::Concat(vec![open, close]);
+    }
+    Doc::group(Doc::Concat(vec![open, Doc::nest(Doc::Concat(vec![Doc::Line, body, comments])), Doc::Line, close]))
+  }
+
+  ///
+  /// 表达式
+  ///
+  /// parent 为所在的运算符的优先级, 以及是否为右操作数, 用于判断括号是否可以省略
+  fn expression(&mut self, node: &SyntaxNode, parent: Option<(Precedence, bool)>) -> Doc {
+    let mut children = node.children.iter();
+
+    match node.kind {
+      SyntaxKind::Literal | SyntaxKind::Name => self.token(next_token(&mut children)),
+      SyntaxKind::PrefixExpression => {
+        let op = next_token(&mut children);
+        let (minus, op) = (op.token == Token::Minus, self.token(op));
+        let e = self.expression(next_node(&mut children), Some((Precedence::Prefix, false)));
+
+        // 连续的负号之间保留空格, 避免输出 --x
+        if minus && first_text(&e).starts_with('-') {
+          return Doc::Concat(vec![op, Doc::text(" "), e]);
+        }
+        Doc::Concat(vec![op, e])
+      }
+      SyntaxKind::InfixExpression => {
+        let left = next_node(&mut children);
+        let op = next_token(&mut children);
+        let right = next_node(&mut children);
+        let precedence = Paser::infix_token(&op.token).0;
+
+        let left = self.expression(left, Some((precedence, false)));
+        let op = self.token(op);
+        let right = self.expression(right, Some((precedence, true)));
+        Doc::group(Doc::Concat(vec![left, Doc::text(" "), op, Doc::nest(Doc::Concat(vec![Doc::Line, right]))]))
+      }
+      SyntaxKind::ParenExpression => self.paren(node, false, parent),
+      SyntaxKind::CallExpression => {
+        let callee = self.expression(next_node(&mut children), Some((Precedence::Suffix, false)));
+        Doc::Concat(vec![callee, self.list(next_node(&mut children))])
+      }
+      SyntaxKind::FunctionExpression => self.function(&mut children),
+      SyntaxKind::IfExpression => {
+        let mut docs = vec![self.token(next_token(&mut children)), Doc::text(" ")];
+        docs.push(self.expression(next_node(&mut children), None));
+        docs.push(Doc::text(" "));
+        docs.push(self.block(next_node(&mut children)));
+
+        if let Some(SyntaxElement::Token(token)) = children.next() {
+          docs.push(Doc::text(" "));
+          docs.push(self.token(token));
+          docs.push(Doc::text(" "));
+          docs.push(self.block(next_node(&mut children)));
+        }
+        Doc::Concat(docs)
+      }
+      SyntaxKind::WhileExpression => {
+        let keyword = self.token(next_token(&mut children));
+        let condition = self.expression(next_node(&mut children), None);
+        let block = self.block(next_node(&mut children));
+        Doc::Concat(vec![keyword, Doc::text(" "), condition, Doc::text(" "), block])
+      }
+      kind => unreachable!("{:?}", kind),
+    }
+  }
+
+  /// 括号表达式, 不影响运算顺序的括号会被省略
+  fn paren(&mut self, node: &SyntaxNode, keep: bool, parent: Option<(Precedence, bool)>) -> Doc {
+    let mut children = node.children.iter();
+    let (open, e, close) = (next_token(&mut children), next_node(&mut children), next_token(&mut children));
+
+    // if, while 与函数定义表达式总是保留括号
+    let keep = keep
+      || matches!(e.kind, SyntaxKind::FunctionExpression | SyntaxKind::IfExpression | SyntaxKind::WhileExpression)
+      || match (precedence(e), parent) {
+        (Some(precedence), Some((parent, right))) => precedence < parent || (right && precedence == parent),
+        _ => false,
+      };
+
+    if keep {
+      let open = self.token(open);
+      let e = self.expression(e, None);
+      Doc::Concat(vec![open, e, self.token(close)])
+    } else {
+      let open = self.skip(open);
+      let e = self.expression(e, parent);
+      Doc::Concat(vec![open, e, self.skip(close)])
+    }
+  }
+
+  /// 输出 token 以及其上的注释
+  fn token(&mut self, token: &SyntaxToken) -> Doc {
+    let mut docs = vec![self.comments_doc()];
+    docs.push(Doc::text(&token.text));
+    docs.push(self.trailing_doc());
+    self.cursor += 1;
+    Doc::Concat(docs)
+  }
+
+  /// 省略 token, 只输出其上的注释
+  fn skip(&mut self, _token: &SyntaxToken) -> Doc {
+    let docs = vec![self.comments_doc(), self.trailing_doc()];
+    self.cursor += 1;
+    Doc::Concat(docs)
+  }
+
+  /// 表达式中间独占一行的注释
+  fn comments_doc(&mut self) -> Doc {
+    let mut docs = vec![];
+    for (comment, _) in mem::take(&mut self.comments[self.cursor].leading) {
+      docs.push(Doc::HardLine);
+      docs.push(Doc::Text(comment));
+      docs.push(Doc::HardLine);
+    }
+    Doc::Concat(docs)
+  }
+
+  fn trailing_doc(&mut self) -> Doc {
+    match self.comments[self.cursor].trailing.take() {
+      Some(comment) => Doc::LineSuffix(format!(" {}", comment)),
+      None => Doc::Concat(vec![]),
+    }
+  }
+
+  /// 取出下一个 token 之前独占一行的注释, 以及之前是否有空行
+  fn take_leading(&mut self) -> (Doc, bool) {
+    let comments = &mut self.comments[self.cursor];
+    let leading = mem::take(&mut comments.leading);
+    let blank = mem::take(&mut comments.blank);
+
+    let first_blank = leading.first().map_or(blank, |(_, blank)| *blank);
+    let mut docs = vec![];
+    for (index, (comment, blank)) in leading.iter().enumerate() {
+      if index > 0 {
+        docs.push(if *blank { Doc::BlankLine } else { Doc::HardLine });
+      }
+      docs.push(Doc::text(comment));
+    }
+    if !leading.is_empty() {
+      docs.push(if blank { Doc::BlankLine } else { Doc::HardLine });
+    }
+    (Doc::Concat(docs), first_blank)
+  }
+
+  /// 语句块与文件末尾的注释, 放在最后一条语句之后
+  fn closing_comments(&mut self) -> Doc {
+    let mut docs = vec![];
+    for (comment, blank) in mem::take(&mut self.comments[self.cursor].leading) {
+      docs.push(if blank { Doc::BlankLine } else { Doc::HardLine });
+      docs.push(Doc::Text(comment));
+    }
+    Doc::Concat(docs)
+  }
+}
+
+fn next_token<'a>(children: &mut impl Iterator<Item = &'a SyntaxElement>) -> &'a SyntaxToken {
+  match children.next() {
+    Some(SyntaxElement::Token(token)) => token,
+    _ => unreachable!(),
+  }
+}
+
+fn next_node<'a>(children: &mut impl Iterator<Item = &'a SyntaxElement>) -> &'a SyntaxNode {
+  match children.next() {
+    Some(SyntaxElement::Node(node)) => node,
+    _ => unreachable!(),
+  }
+}
+
+/// 表达式的优先级, 不需要括号的表达式为 None
+fn precedence(node: &SyntaxNode) -> Option<Precedence> {
+  match node.kind {
+    SyntaxKind::InfixExpression => node.children.iter().find_map(|child| match child {
+      SyntaxElement::Token(token) => Some(Paser::infix_token(&token.token).0),
+      SyntaxElement::Node(_) => None,
+    }),
+    SyntaxKind::PrefixExpression => Some(Precedence::Prefix),
+    SyntaxKind::CallExpression => Some(Precedence::Suffix),
+    SyntaxKind::ParenExpression => node.nodes().next().and_then(|e| match e.kind {
+      SyntaxKind::FunctionExpression | SyntaxKind::IfExpression | SyntaxKind::WhileExpression => None,
+      _ => precedence(e),
+    }),
+    _ => None,
+  }
+}
+
+/// 没有分号时下一条语句会被解析为当前语句的一部分
+fn need_semicolon(node: &SyntaxNode, next: Option<&str>) -> bool {
+  let continued = next.is_some_and(|next| next.starts_with(['(', '-']));
+  match node.kind {
+    // 没有初始值的变量声明之后必须是逗号或者分号
+    SyntaxKind::VarStatement => {
+      node.nodes().last().is_some_and(|declarator| declarator.children.len() == 1) || continued
+    }
+    SyntaxKind::ReturnStatement if node.nodes().next().is_none() => next.is_some(),
+    SyntaxKind::AssignStatement | SyntaxKind::ReturnStatement | SyntaxKind::ExpressionStatement => continued,
+    _ => false,
+  }
+}
+
+/// 文档输出的第一段文本
+fn first_text(doc: &Doc) -> &str {
+  match doc {
+    Doc::Text(text) if !text.is_empty() => text,
+    Doc::Nest(doc) | Doc::Group(doc) => first_text(doc),
+    Doc::Concat(docs) => docs.iter().map(first_text).find(|text| !text.is_empty()).unwrap_or(""),
+    _ => "",
+  }
+}
+
+/// 以语句块结尾的表达式语句 (if 与 while), 不需要分号
+fn ends_with_block(node: &SyntaxNode) -> bool {
+  matches!(node.nodes().next().map(|e| e.kind), Some(SyntaxKind::IfExpression | SyntaxKind::WhileExpression))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use crate::{
+    ast::AstNode,
+    cst::SyntaxNode,
+    generator::Generator,
+    parser::Paser,
+    token::{Token, Trivia},
+  };
+
+  use super::{format, Options};
+
+  #[test]
+  fn test_format() {
+    let options = Options::default();
+    for (input, expect) in [
+      ("var a=1,b;a+=2*(3+4)", "var a = 1, b;\na += 2 * (3 + 4);\n"),
+      ("((1 + 2)) + (3 * 4) - (5 - 6)", "1 + 2 + 3 * 4 - (5 - 6);\n"),
+      ("-(-a) + !(b == c) * (f)(1)(2)", "- -a + !(b == c) * f(1)(2);\n"),
+      ("- -a; -(-(-1)); !!a; -!-a", "- -a;\n- - -1;\n!!a;\n-!-a;\n"),
+      ("fn   f ( x,y ) {x+y}", "fn f(x, y) { x + y }\n"),
+      ("fn f(x) { var y = x; y }", "fn f(x) {\n  var y = x;\n  y\n}\n"),
+      ("if a {1} else {if b {2}};;;", "if a { 1 } else { if b { 2 } }\n"),
+      ("while i < 3 { i += 1 } return", "while i < 3 { i += 1; }\nreturn;\n"),
+      ("(fn f(x) { x })(1); var f = (fn g() { });", "(fn f(x) { x })(1);\nvar f = (fn g() {});\n"),
+      ("x = (if a { 1 }) + (1)", "x = (if a { 1 }) + 1;\n"),
+      // 注释与空行
+      (
+        "// 开头\n\n\na = 1; // 行尾\n\n\n// 函数\nfn f() {\n// 函数体\n}\n// 结尾",
+        "// 开头\n\na = 1; // 行尾\n\n// 函数\nfn f() {\n  // 函数体\n}\n// 结尾\n",
+      ),
+      ("f(1, // 第一个\n2)", "f(\n  1, // 第一个\n  2\n);\n"),
+      ("a = (1 // 括号\n)", "a = 1; // 括号\n"),
+      ("fn f() { a; // 行尾\n b }", "fn f() {\n  a; // 行尾\n  b\n}\n"),
+      // 补充的分号不会使带有行尾注释的 Group 换行
+      ("println(x, 1) // c\nf(a, b) // d", "println(x, 1); // c\nf(a, b); // d\n"),
+      // 行尾注释之后的关键字另起一行, 不会把注释推迟到 else 分支之后
+      ("if 1 { 2 } // a\nelse { 3 }", "if 1 { 2 } // a\nelse { 3 }\n"),
+      ("x = // b\n1; var y = 1 // c\n + 2", "x = // b\n1;\nvar y = 1 + // c\n  2;\n"),
+    ] {
+      assert_eq!(format(input, &options).unwrap(), expect, "{}", input);
+    }
+  }
+
+  #[test]
+  fn test_no_semicolon() {
+    let options = Options { semicolon: false, ..Options::default() };
+    for (input, expect) in [
+      ("var a = 1; var b; a = 2; const c = 3;", "var a = 1\nvar b;\na = 2\nconst c = 3\n"),
+      // 下一条语句以 ( 或者 - 开头时需要保留分号
+      ("a; (f)(1); -b; !c", "a\nf(1);\n-b\n!c\n"),
+      ("(fn f() { 1 }); -1", "(fn f() { 1 });\n-1\n"),
+      ("fn f() { if a { return; }; 1 }", "fn f() {\n  if a { return }\n  1\n}\n"),
+      ("fn f() { return; 1 }", "fn f() {\n  return;\n  1\n}\n"),
+    ] {
+      assert_eq!(format(input, &options).unwrap(), expect, "{}", input);
+    }
+  }
+
+  #[test]
+  fn test_width() {
+    let options = Options { width: 30, ..Options::default() };
+    for (input, expect) in [
+      ("function(argument1, argument2, argument3)", "function(\n  argument1,\n  argument2,\n  argument3\n);\n"),
+      ("var x = aaaaaaaaaa + bbbbbbbbbb + cccccccccc", "var x = aaaaaaaaaa +\n  bbbbbbbbbb +\n  cccccccccc;\n"),
+      ("var first = 1, second = 2, third = 3", "var first = 1,\n  second = 2,\n  third = 3;\n"),
+      ("fn f(x) { if x { 1 } else { 2 } }", "fn f(x) {\n  if x { 1 } else { 2 }\n}\n"),
+    ] {
+      assert_eq!(format(input, &options).unwrap(), expect, "{}", input);
+    }
+  }
+
+  /// 所有的注释, 以及注释之后的第一个单词 (关键字, 标识符与数字), 标点可能被省略或者补充, 不作比较
+  fn comments(input: &str) -> Vec<(String, Option<String>)> {
+    let node = SyntaxNode::parse(input).unwrap();
+    let tokens = node.tokens();
+    let word = |index: usize| {
+      let is_word = |text: &str| text.starts_with(|c: char| c.is_alphanumeric() || c == '_');
+      tokens[index..].iter().find(|token| is_word(&token.text)).map(|token| token.text.clone())
+    };
+
+    let mut comments = vec![];
+    for (index, token) in tokens.iter().enumerate() {
+      for trivia in &token.leading {
+        if let Trivia::LineComment(comment) = trivia {
+          comments.push((comment.clone(), word(index)));
+        }
+      }
+    }
+    comments
+  }
+
+  /// 格式化不改变语义, 不丢失注释, 并且再次格式化结果不变
+  #[test]
+  fn test_stable() {
+    let mut programs = vec![
+      fs::read_to_string("examples/a.pl0").unwrap(),
+      "var a = 1 // a\n, b = 2; // b\nfn f(x // x\n) { // f\n  if x { // if\n    1 // 1\n  } // }\n}".to_string(),
+      "f(// 实参\n1)(2 // 二\n); g(fn h(a) { // h\n a })".to_string(),
+      "x = -(-a) - -b; -(-(-1)) // c\nprintln(-(-x), 1) // d".to_string(),
+      "fn f(x) { if x { 1 } // a\n else { if x { 2 } // b\n else { 3 } } } var y = if 1 { f } // c\n else { f }"
+        .to_string(),
+    ];
+    programs.extend((0..100).map(|seed| Generator::new(seed).program().unparse()));
+
+    for input in &programs {
+      for options in [
+        Options::default(),
+        Options { semicolon: false, ..Options::default() },
+        Options { width: 20, semicolon: true },
+        Options { width: 40, semicolon: false },
+      ] {
+        let output = format(input, &options).unwrap_or_else(|err| panic!("{:?}: {}", err, input));
+        let program = Paser::paser(&output).unwrap_or_else(|err| panic!("{:?}: {}\n{}", err, input, output));
+
+        assert_eq!(program.unparse(), Paser::paser(input).unwrap().unparse(), "{}\n{}", input, output);
+        assert_eq!(comments(&output), comments(input), "{}", output);
+        assert_eq!(format(&output, &options).unwrap(), output, "{}", input);
+      }
+    }
+
+    // 空程序与词法错误
+    assert_eq!(format("", &Options::default()).unwrap(), "");
+    assert_eq!(format("var x = 1; x @", &Options::default()).unwrap_err().0, "illegal character '@'");
+    assert!(SyntaxNode::parse("").unwrap().tokens().iter().all(|token| token.token == Token::EOF));
+  }
+}
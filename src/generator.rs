@@ -0,0 +1,587 @@
+use crate::{
+  ast::{Expression, ExpressionKind, Identifier, Infix, Prefix, Program, Statement, StatementKind},
+  SpanOffset,
+};
+
+/// 生成的程序中表达式的最大嵌套深度
+const MAX_EXPRESSION_DEPTH: usize = 3;
+/// 语句块的最大嵌套深度
+const MAX_BLOCK_DEPTH: usize = 3;
+/// 函数定义的最大嵌套深度
+const MAX_FUNCTION_DEPTH: usize = 2;
+/// 每个函数体 (以及全局作用域) 中最多生成的调用个数, 限制程序的执行时间
+const MAX_CALLS: usize = 3;
+
+/// xorshift 伪随机数生成器, 相同的种子生成相同的程序
+struct Rng(u64);
+
+impl Rng {
+  fn next(&mut self) -> u64 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    self.0
+  }
+
+  /// [0, n) 之间的随机数
+  fn below(&mut self, n: usize) -> usize {
+    (self.next() % n as u64) as usize
+  }
+
+  /// 以 percent% 的概率返回真
+  fn chance(&mut self, percent: usize) -> bool {
+    self.below(100) < percent
+  }
+}
+
+/// 名字的种类
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Symbol {
+  Constant,
+  Variable(bool),                 // 整数变量, 是否可以赋值 (循环计数器与 fuel 参数不可赋值)
+  Closure(usize),                 // 保存闭包的变量 (闭包的参数个数), 不可赋值
+  Function(usize, Option<usize>), // 函数 (参数个数, 返回闭包时闭包的参数个数)
+}
+
+/// 正在生成的函数
+struct Frame {
+  name: Option<String>,   // 函数名, 全局作用域为 None
+  fuel: Option<String>,   // 第一个参数, 递归调用的深度
+  returns: Option<usize>, // 返回闭包时闭包的参数个数
+  recursive: bool,        // 是否已经生成过递归调用
+  calls: usize,           // 已经生成的调用个数
+}
+
+///
+/// 随机程序生成器
+///
+/// 直接生成抽象语法树, 通过 unparse 得到源代码. 生成的程序一定能通过编译, 并且一定会结束:
+/// 循环的次数不超过常量, 函数的第一个参数为 fuel, 只有在 fuel > 0 时才会以 fuel - 1 递归调用自身,
+/// 调用其它函数时 fuel 为字面量, 函数只能调用在它之前定义完成的函数.
+/// 除法与乘法仍然可能产生除零与溢出, 两者都是运行时错误
+pub struct Generator {
+  rng: Rng,
+  scopes: Vec<Vec<(String, Symbol)>>, // 作用域链上可见的名字
+  frames: Vec<Frame>,
+  names: usize, // 名字编号, 生成的名字互不相同
+  depth: usize, // 表达式嵌套深度
+  blocks: usize,
+}
+
+impl Generator {
+  pub fn new(seed: u64) -> Self {
+    Generator {
+      rng: Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1),
+      scopes: vec![],
+      frames: vec![],
+      names: 0,
+      depth: 0,
+      blocks: 0,
+    }
+  }
+
+  /// 生成一个程序
+  pub fn program(&mut self) -> Program {
+    self.scopes.push(vec![]);
+    self.frames.push(Frame { name: None, fuel: None, returns: None, recursive: true, calls: 0 });
+
+    let count = 2 + self.rng.below(6);
+    let statements = self.statements(count, None);
+
+    self.frames.pop();
+    self.scopes.pop();
+    Program { statements }
+  }
+
+  ///
+  /// 生成语句序列, 最后一条语句为值是整数 (returns 为 None) 或者闭包的表达式语句,
+  /// 保证语句序列的值不会是闭包
+  ///
+  fn statements(&mut self, count: usize, returns: Option<usize>) -> Vec<Statement> {
+    let mut statements = vec![];
+    for _ in 0..count {
+      statements.append(&mut self.statement());
+    }
+
+    statements.push(statement(StatementKind::Expression(match returns {
+      None => self.expression(),
+      Some(argc) => self.closure(argc),
+    })));
+    statements
+  }
+
+  /// 生成语句块
+  fn block(&mut self, returns: Option<usize>) -> Vec<Statement> {
+    self.scopes.push(vec![]);
+    self.blocks += 1;
+
+    let count = self.rng.below(3);
+    let statements = self.statements(count, returns);
+
+    self.blocks -= 1;
+    self.scopes.pop();
+    statements
+  }
+
+  /// 生成一条或者多条语句 (循环需要先定义计数器)
+  fn statement(&mut self) -> Vec<Statement> {
+    let kind = match self.rng.below(12) {
+      0 | 1 => {
+        let name = self.name("v");
+        let e = self.expression();
+        self.define(&name, Symbol::Variable(true));
+        StatementKind::Variable(vec![(identifier(&name), e)])
+      }
+
+      2 => {
+        let name = self.name("k");
+        let value = self.rng.below(10) as isize;
+        self.define(&name, Symbol::Constant);
+        StatementKind::Const(vec![(identifier(&name), integer(value))])
+      }
+
+      3 if self.frames.len() <= MAX_FUNCTION_DEPTH => return vec![self.function()],
+
+      4 => {
+        let argc = 1 + self.rng.below(3);
+        let name = self.name("c");
+        let e = self.closure(argc);
+        self.define(&name, Symbol::Closure(argc));
+        StatementKind::Variable(vec![(identifier(&name), e)])
+      }
+
+      5 | 6 => match self.lookup(|symbol| symbol == Symbol::Variable(true)) {
+        Some(name) => StatementKind::Assign(identifier(&name), self.expression()),
+        None => StatementKind::Expression(self.expression()), // 连续的空语句会被合并, 不生成空语句
+      },
+
+      7 if self.blocks < MAX_BLOCK_DEPTH => return self.loop_statement(),
+
+      8 => {
+        let count = self.rng.below(3);
+        let args = (0..count).map(|_| self.expression()).collect();
+        let name = if self.rng.chance(50) { "print" } else { "println" };
+        StatementKind::Expression(call(variable(name), args))
+      }
+
+      // 只有返回整数的函数 (以及全局作用域) 可以使用 return
+      9 if self.frames.last().unwrap().returns.is_none() && self.rng.chance(30) => {
+        StatementKind::Return(if self.rng.chance(80) { Some(self.expression()) } else { None })
+      }
+
+      _ => StatementKind::Expression(self.expression()),
+    };
+
+    vec![statement(kind)]
+  }
+
+  /// 生成循环: var i = 0; while i < K { ...; i = i + 1 }
+  fn loop_statement(&mut self) -> Vec<Statement> {
+    let counter = self.name("i");
+    self.define(&counter, Symbol::Variable(false));
+
+    self.scopes.push(vec![]);
+    self.blocks += 1;
+    let count = self.rng.below(3);
+    let mut body = vec![];
+    for _ in 0..count {
+      body.append(&mut self.statement());
+    }
+    self.blocks -= 1;
+    self.scopes.pop();
+
+    body
+      .push(statement(StatementKind::Assign(identifier(&counter), infix(Infix::Add, variable(&counter), integer(1)))));
+
+    let condition = infix(Infix::Lt, variable(&counter), integer(1 + self.rng.below(3) as isize));
+    vec![
+      statement(StatementKind::Variable(vec![(identifier(&counter), integer(0))])),
+      statement(StatementKind::Expression(expression(ExpressionKind::While(Box::new(condition), body)))),
+    ]
+  }
+
+  /// 生成函数定义语句, 一部分函数返回闭包
+  fn function(&mut self) -> Statement {
+    let argc = 1 + self.rng.below(3);
+    let returns = if self.rng.chance(30) { Some(1 + self.rng.below(3)) } else { None };
+
+    let name = self.name(if returns.is_some() { "m" } else { "f" });
+    let (args, statements) = self.function_body(Some(&name), argc, returns);
+    self.define(&name, Symbol::Function(argc, returns));
+
+    statement(StatementKind::Function(identifier(&name), args, statements))
+  }
+
+  ///
+  /// 生成函数的参数与函数体
+  ///
+  /// 返回闭包的函数在函数体内定义一个函数, 并将其作为函数体最后的表达式
+  fn function_body(
+    &mut self,
+    name: Option<&str>,
+    argc: usize,
+    returns: Option<usize>,
+  ) -> (Vec<Identifier>, Vec<Statement>) {
+    let fuel = self.name("d");
+    let mut args = vec![fuel.clone()];
+    args.extend((1..argc).map(|_| self.name("a")));
+
+    self.scopes.push(vec![]);
+    if let Some(name) = name {
+      self.define(name, Symbol::Function(argc, returns));
+    }
+    self.define(&fuel, Symbol::Variable(false));
+    args.iter().skip(1).for_each(|arg| self.define(arg, Symbol::Variable(true)));
+
+    self.frames.push(Frame {
+      name: name.map(str::to_string),
+      fuel: Some(fuel),
+      returns,
+      recursive: returns.is_some(), // 返回闭包的函数不递归调用自身
+      calls: 0,
+    });
+    let blocks = std::mem::replace(&mut self.blocks, 0);
+
+    let count = self.rng.below(4);
+    let statements = match returns {
+      None => self.statements(count, None),
+      Some(closure_argc) => {
+        let mut statements = vec![];
+        for _ in 0..count {
+          statements.append(&mut self.statement());
+        }
+
+        let inner = self.name("g");
+        let (inner_args, inner_statements) = self.function_body(Some(&inner), closure_argc, None);
+        statements.push(statement(StatementKind::Function(identifier(&inner), inner_args, inner_statements)));
+        statements.push(statement(StatementKind::Expression(variable(&inner))));
+        statements
+      }
+    };
+
+    self.blocks = blocks;
+    self.frames.pop();
+    self.scopes.pop();
+
+    (args.iter().map(|arg| identifier(arg)).collect(), statements)
+  }
+
+  /// 生成值为整数的表达式
+  fn expression(&mut self) -> Expression {
+    if self.depth >= MAX_EXPRESSION_DEPTH {
+      return self.atom();
+    }
+
+    self.depth += 1;
+    let e = match self.rng.below(16) {
+      0..=3 => self.atom(),
+      4..=7 => {
+        let op = match self.rng.below(9) {
+          0 => Infix::Add,
+          1 => Infix::Sub,
+          2 => Infix::Mul,
+          3 => Infix::Eq,
+          4 => Infix::Ne,
+          5 => Infix::Lt,
+          6 => Infix::Gt,
+          7 => Infix::LtEq,
+          _ => Infix::GtEq,
+        };
+        let (left, right) = (self.expression(), self.expression());
+        infix(op, left, right)
+      }
+      8 => {
+        // 除数一般为非零的字面量, 偶尔为任意表达式以产生除零错误
+        let left = self.expression();
+        let right = if self.rng.chance(80) { integer(1 + self.rng.below(9) as isize) } else { self.expression() };
+        infix(Infix::Div, left, right)
+      }
+      9 => {
+        let prefix = if self.rng.chance(50) { Prefix::Not } else { Prefix::Neg };
+        expression(ExpressionKind::Prefix(prefix, Box::new(self.expression())))
+      }
+      10 | 11 if self.blocks < MAX_BLOCK_DEPTH => {
+        let condition = self.expression();
+        let then_s = self.block(None);
+        let else_s = if self.rng.chance(70) { Some(self.block(None)) } else { None };
+        expression(ExpressionKind::If(Box::new(condition), then_s, else_s))
+      }
+      12 | 13 => self.recursive_call().unwrap_or_else(|| self.atom()),
+      _ => self.call().unwrap_or_else(|| self.atom()),
+    };
+    self.depth -= 1;
+
+    e
+  }
+
+  /// 生成字面量, 常量或者整数变量
+  fn atom(&mut self) -> Expression {
+    if self.rng.chance(50) {
+      if let Some(name) = self.lookup(|symbol| matches!(symbol, Symbol::Constant | Symbol::Variable(_))) {
+        return variable(&name);
+      }
+    }
+
+    // 偶尔生成较大的数以产生溢出
+    integer(if self.rng.chance(3) { 1 << 40 } else { self.rng.below(20) as isize })
+  }
+
+  ///
+  /// 生成值为整数的函数调用
+  ///
+  /// 被调用的可以是函数, 保存闭包的变量, 返回闭包的函数调用或者函数定义表达式
+  fn call(&mut self) -> Option<Expression> {
+    if self.frames.last().unwrap().calls >= MAX_CALLS {
+      return None;
+    }
+    self.frames.last_mut().unwrap().calls += 1;
+
+    let (callee, argc) = match self.rng.below(4) {
+      0 => {
+        let name = self.lookup_callable(|symbol| matches!(symbol, Symbol::Function(_, None)))?;
+        match self.find(&name) {
+          Some(Symbol::Function(argc, None)) => (variable(&name), argc),
+          _ => unreachable!(),
+        }
+      }
+      1 => {
+        let name = self.lookup(|symbol| matches!(symbol, Symbol::Closure(_)))?;
+        match self.find(&name) {
+          Some(Symbol::Closure(argc)) => (variable(&name), argc),
+          _ => unreachable!(),
+        }
+      }
+      2 => {
+        let name = self.lookup_callable(|symbol| matches!(symbol, Symbol::Function(_, Some(_))))?;
+        match self.find(&name) {
+          Some(Symbol::Function(maker_argc, Some(argc))) => (self.call_with_fuel(variable(&name), maker_argc), argc),
+          _ => unreachable!(),
+        }
+      }
+      _ => {
+        let argc = 1 + self.rng.below(3);
+        (self.function_expression(argc), argc)
+      }
+    };
+
+    Some(self.call_with_fuel(callee, argc))
+  }
+
+  /// 生成递归调用: if d > 0 { f(d - 1, ...) } else { ... }
+  fn recursive_call(&mut self) -> Option<Expression> {
+    let frame = self.frames.last().unwrap();
+    if frame.recursive || self.blocks >= MAX_BLOCK_DEPTH {
+      return None;
+    }
+    let (name, fuel) = (frame.name.clone()?, frame.fuel.clone()?);
+    let Some(Symbol::Function(argc, None)) = self.find(&name) else {
+      return None;
+    };
+    self.frames.last_mut().unwrap().recursive = true;
+
+    let mut args = vec![infix(Infix::Sub, variable(&fuel), integer(1))];
+    args.extend((1..argc).map(|_| self.expression()));
+    let then_s = vec![statement(StatementKind::Expression(call(variable(&name), args)))];
+    let else_s = self.block(None);
+
+    let condition = infix(Infix::Gt, variable(&fuel), integer(0));
+    Some(expression(ExpressionKind::If(Box::new(condition), then_s, Some(else_s))))
+  }
+
+  /// 以字面量作为 fuel 调用
+  fn call_with_fuel(&mut self, callee: Expression, argc: usize) -> Expression {
+    let fuel = if self.frames.len() == 1 { 4 } else { 2 };
+    let mut args = vec![integer(self.rng.below(fuel) as isize)];
+    args.extend((1..argc).map(|_| self.expression()));
+    call(callee, args)
+  }
+
+  /// 生成值为闭包的表达式
+  fn closure(&mut self, argc: usize) -> Expression {
+    match self.rng.below(4) {
+      0 => {
+        if let Some(name) = self.lookup_callable(|symbol| symbol == Symbol::Function(argc, None)) {
+          return variable(&name);
+        }
+      }
+      1 => {
+        if let Some(name) = self.lookup(|symbol| symbol == Symbol::Closure(argc)) {
+          return variable(&name);
+        }
+      }
+      2 if self.frames.last().unwrap().calls < MAX_CALLS => {
+        if let Some(name) = self.lookup_callable(|symbol| matches!(symbol, Symbol::Function(_, Some(n)) if n == argc)) {
+          if let Some(Symbol::Function(maker_argc, _)) = self.find(&name) {
+            self.frames.last_mut().unwrap().calls += 1;
+            return self.call_with_fuel(variable(&name), maker_argc);
+          }
+        }
+      }
+      _ => {}
+    }
+
+    self.function_expression(argc)
+  }
+
+  /// 生成函数定义表达式, 函数名不会被引用
+  fn function_expression(&mut self, argc: usize) -> Expression {
+    let name = self.name("h");
+    let (args, statements) = self.function_body(None, argc, None);
+    expression(ExpressionKind::Function(identifier(&name), args, statements))
+  }
+
+  /// 生成新的名字
+  fn name(&mut self, prefix: &str) -> String {
+    self.names += 1;
+    format!("{}{}", prefix, self.names)
+  }
+
+  fn define(&mut self, name: &str, symbol: Symbol) {
+    self.scopes.last_mut().unwrap().push((name.to_string(), symbol));
+  }
+
+  fn find(&self, name: &str) -> Option<Symbol> {
+    self.scopes.iter().flatten().find(|(n, _)| n == name).map(|(_, symbol)| *symbol)
+  }
+
+  /// 随机选择一个可见的名字
+  fn lookup(&mut self, filter: impl Fn(Symbol) -> bool) -> Option<String> {
+    self.choose(|_, symbol| filter(symbol))
+  }
+
+  /// 随机选择一个可以调用的函数, 正在生成的函数不能调用, 否则可能无限递归
+  fn lookup_callable(&mut self, filter: impl Fn(Symbol) -> bool) -> Option<String> {
+    let active = self.frames.iter().filter_map(|frame| frame.name.clone()).collect::<Vec<_>>();
+    self.choose(|name, symbol| filter(symbol) && !active.iter().any(|active| active == name))
+  }
+
+  fn choose(&mut self, filter: impl Fn(&str, Symbol) -> bool) -> Option<String> {
+    let names = self.scopes.iter().flatten().filter(|(name, symbol)| filter(name, *symbol)).collect::<Vec<_>>();
+    if names.is_empty() {
+      None
+    } else {
+      Some(names[self.rng.below(names.len())].0.clone())
+    }
+  }
+}
+
+fn pos() -> SpanOffset {
+  (0, 0).into()
+}
+
+fn statement(kind: StatementKind) -> Statement {
+  Statement { pos: pos(), kind }
+}
+
+fn expression(kind: ExpressionKind) -> Expression {
+  Expression { pos: pos(), kind }
+}
+
+fn identifier(name: &str) -> Identifier {
+  Identifier { pos: pos(), name: name.to_string() }
+}
+
+fn variable(name: &str) -> Expression {
+  expression(ExpressionKind::Identifier(name.to_string()))
+}
+
+fn integer(value: isize) -> Expression {
+  expression(ExpressionKind::Integer(value))
+}
+
+fn infix(op: Infix, left: Expression, right: Expression) -> Expression {
+  expression(ExpressionKind::Infix(op, Box::new(left), Box::new(right)))
+}
+
+fn call(callee: Expression, args: Vec<Expression>) -> Expression {
+  expression(ExpressionKind::Call(Box::new(callee), args))
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    ast::{walk_expression, walk_statement, AstNode, Expression, ExpressionKind, Identifier, Statement, Visitor},
+    compiler::Compiler,
+    interp::Interpreter,
+    parser::Paser,
+    vm::VM,
+    SpanOffset,
+  };
+
+  use super::Generator;
+
+  const SEEDS: u64 = 300;
+
+  /// unparse -> paser -> unparse 结果不变
+  #[test]
+  fn test_round_trip() {
+    for seed in 0..SEEDS {
+      let source = Generator::new(seed).program().unparse();
+      let program = Paser::paser(&source).unwrap_or_else(|err| panic!("seed {}: {:?}\n{}", seed, err, source));
+      assert_eq!(program.unparse(), source, "seed {}", seed);
+    }
+  }
+
+  /// 每个语法树节点的位置截取出的源代码, 重新解析后与节点相同
+  #[test]
+  fn test_span() {
+    for seed in 0..SEEDS {
+      let source = Generator::new(seed).program().unparse();
+      let program = Paser::paser(&source).unwrap();
+      SpanChecker { source: &source }.visit_program(&program);
+    }
+  }
+
+  /// 编译后在虚拟机上执行, 与解释器的结果, 运行时错误与输出都相同
+  #[test]
+  fn test_differential() {
+    for seed in 0..SEEDS {
+      let source = Generator::new(seed).program().unparse();
+      let program = Paser::paser(&source).unwrap();
+      let codes =
+        Compiler::compile(&program).unwrap_or_else(|errors| panic!("seed {}: {:?}\n{}", seed, errors, source));
+
+      let mut vm_output = vec![];
+      let vm_result = VM::execute_with_output(&codes, &mut vm_output).map_err(|(err, _)| err.to_string());
+
+      let mut interp_output = vec![];
+      let interp_result = Interpreter::eval_with_output(&program, &mut interp_output).map_err(|(err, _)| err);
+
+      assert_eq!(vm_result, interp_result, "seed {}\n{}", seed, source);
+      assert_eq!(vm_output, interp_output, "seed {}\n{}", seed, source);
+    }
+  }
+
+  fn slice(source: &str, pos: SpanOffset) -> String {
+    source.chars().skip(pos.begin).take(pos.end - pos.begin).collect()
+  }
+
+  /// 检查每个节点的位置
+  struct SpanChecker<'a> {
+    source: &'a str,
+  }
+
+  impl Visitor for SpanChecker<'_> {
+    fn visit_identifier(&mut self, ident: &Identifier) {
+      assert_eq!(slice(self.source, ident.pos), ident.name);
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+      let text = slice(self.source, statement.pos);
+      let reparsed = Paser::paser(&text).unwrap_or_else(|err| panic!("{:?}: {}", err, text));
+      assert_eq!(reparsed.unparse(), statement.unparse(), "{}", text);
+      walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+      // 加上括号按照表达式解析, 避免函数定义表达式被解析为函数定义语句
+      let text = slice(self.source, expression.pos);
+      let reparsed = Paser::paser(&format!("({})", text)).unwrap_or_else(|err| panic!("{:?}: {}", err, text));
+      assert_eq!(reparsed.unparse(), format!("{};", expression.unparse()), "{}", text);
+      if let ExpressionKind::Identifier(name) = &expression.kind {
+        assert_eq!(&text, name);
+      }
+      walk_expression(self, expression);
+    }
+  }
+}
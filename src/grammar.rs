@@ -0,0 +1,614 @@
+//! 文法分析工具: 从文本读入文法, 求 nullable / FIRST / FOLLOW 集与 LL(1) 预测分析表, 检查左递归,
+//! 以及消除左递归与提取左公因子. 结果可以输出为文本, Markdown 或者与 `lr1/lr1-lib.typ` 相同的 Typst 表格
+
+use std::{
+  collections::{BTreeMap, BTreeSet, VecDeque},
+  fmt::Write,
+  str::FromStr,
+};
+
+use crate::lr1::{Grammar, Lr1, Sets};
+
+/// 空串
+pub const EPSILON: &str = "ε";
+
+///
+/// 读入文法, 每行为一个非终结符的产生式, 例如 `E -> E + T | T`, 以 `|` 开头的行接着上一行
+///
+/// 符号以空白分隔, 出现在左部的为非终结符, 其余为终结符, 第一个左部为开始符号.
+/// ε 或者空的候选式为空串, 以 # 开头的行为注释
+///
+pub fn parse(text: &str) -> Result<Grammar, String> {
+  let (mut rules, mut current) = (Rules::new(), None);
+  for (number, line) in text.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+    let rest = if let Some(rest) = line.strip_prefix('|') {
+      current.ok_or_else(|| format!("line {}: no production to continue", number + 1))?;
+      rest
+    } else {
+      let (lhs, rest) = line
+        .split_once("->")
+        .or_else(|| line.split_once('→'))
+        .ok_or_else(|| format!("line {}: expect ->", number + 1))?;
+      let lhs = lhs.trim();
+      if lhs.is_empty() || lhs.contains(char::is_whitespace) {
+        return Err(format!("line {}: illegal left hand side: {}", number + 1, lhs));
+      }
+      let index = rules.iter().position(|(name, _)| name == lhs).unwrap_or_else(|| {
+        rules.push((lhs.to_string(), vec![]));
+        rules.len() - 1
+      });
+      current = Some(index);
+      rest
+    };
+
+    let rhss = &mut rules[current.unwrap()].1;
+    for alternative in rest.split('|') {
+      rhss.push(alternative.split_whitespace().filter(|symbol| *symbol != EPSILON).map(str::to_string).collect());
+    }
+  }
+
+  let Some((start, _)) = rules.first() else { return Err("empty grammar".to_string()) };
+  let mut terminals = vec![];
+  for symbol in rules.iter().flat_map(|(_, rhss)| rhss.iter().flatten()) {
+    if !rules.iter().any(|(name, _)| name == symbol) && !terminals.contains(symbol) {
+      terminals.push(symbol.clone());
+    }
+  }
+  build(&rules, &terminals, start)
+}
+
+/// 按左部分组的产生式
+type Rules = Vec<(String, Vec<Vec<String>>)>;
+
+fn rules(grammar: &Grammar) -> Rules {
+  grammar
+    .nonterminals()
+    .map(|nonterminal| {
+      let rhss = grammar
+        .productions
+        .iter()
+        .filter(|production| production.lhs == nonterminal)
+        .map(|production| production.rhs.iter().map(|symbol| grammar.name(*symbol).to_string()).collect())
+        .collect();
+      (grammar.name(nonterminal).to_string(), rhss)
+    })
+    .collect()
+}
+
+fn build(rules: &Rules, terminals: &[String], start: &str) -> Result<Grammar, String> {
+  let nonterminals = rules.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+  let terminals = terminals.iter().map(String::as_str).collect::<Vec<_>>();
+  let productions =
+    rules.iter().flat_map(|(lhs, rhss)| rhss.iter().map(move |rhs| (lhs.as_str(), rhs.join(" ")))).collect::<Vec<_>>();
+  let productions = productions.iter().map(|(lhs, rhs)| (*lhs, rhs.as_str())).collect::<Vec<_>>();
+  Grammar::new(&nonterminals, &terminals, &productions, start)
+}
+
+/// 变换后的文法, 终结符不变
+fn rebuild(grammar: &Grammar, rules: &Rules) -> Grammar {
+  let terminals = (0..grammar.terminals).map(|symbol| grammar.name(symbol).to_string()).collect::<Vec<_>>();
+  build(rules, &terminals, grammar.name(grammar.start)).unwrap()
+}
+
+///
+/// LL(1) 预测分析表
+///
+/// 产生式 A -> α 填入 M[A, a], a ∈ FIRST(α), α 能推导出 ε 时还有 a ∈ FOLLOW(A)
+///
+pub struct Ll1 {
+  pub grammar: Grammar,
+  pub sets: Sets,
+  pub table: BTreeMap<(usize, usize), Vec<usize>>, // (非终结符, 终结符) -> 产生式
+}
+
+impl Ll1 {
+  pub fn new(grammar: &Grammar) -> Self {
+    let sets = grammar.sets();
+    let mut table = BTreeMap::<_, Vec<_>>::new();
+    for (index, production) in grammar.productions.iter().enumerate() {
+      let (mut select, nullable) = sets.first_of(&production.rhs);
+      if nullable {
+        select.extend(sets.follow[production.lhs].iter().copied());
+      }
+      for terminal in select {
+        table.entry((production.lhs, terminal)).or_default().push(index);
+      }
+    }
+    Ll1 { grammar: grammar.clone(), sets, table }
+  }
+
+  /// 有多个产生式的格
+  pub fn conflicts(&self) -> impl Iterator<Item = (&(usize, usize), &Vec<usize>)> {
+    self.table.iter().filter(|(_, productions)| productions.len() > 1)
+  }
+
+  /// 是否为 LL(1) 文法
+  pub fn valid(&self) -> bool {
+    self.conflicts().next().is_none()
+  }
+}
+
+///
+/// 左递归: 能推导出以自身开头的句型的非终结符, 以及最短的推导经过的非终结符, 例如 [A, B, A]
+///
+/// 右部中 A 之前的符号都能推导出 ε 时, A 也在句型的开头
+///
+pub fn left_recursion(grammar: &Grammar) -> Vec<Vec<usize>> {
+  let sets = grammar.sets();
+  let mut edges = BTreeMap::<usize, BTreeSet<usize>>::new();
+  for production in &grammar.productions {
+    for &symbol in &production.rhs {
+      if grammar.is_terminal(symbol) {
+        break;
+      }
+      edges.entry(production.lhs).or_default().insert(symbol);
+      if !sets.nullable[symbol] {
+        break;
+      }
+    }
+  }
+
+  let mut cycles = vec![];
+  for nonterminal in grammar.nonterminals() {
+    // 广度优先搜索回到自身的最短路径
+    let mut from = BTreeMap::new();
+    let mut queue = VecDeque::from([nonterminal]);
+    'search: while let Some(symbol) = queue.pop_front() {
+      for &next in edges.get(&symbol).into_iter().flatten() {
+        if next == nonterminal {
+          let mut path = vec![symbol];
+          while let Some(&prev) = from.get(path.last().unwrap()) {
+            path.push(prev);
+          }
+          path.reverse();
+          path.push(nonterminal);
+          cycles.push(path);
+          break 'search;
+        }
+        if next != nonterminal && !from.contains_key(&next) {
+          from.insert(next, symbol);
+          queue.push_back(next);
+        }
+      }
+    }
+  }
+  cycles
+}
+
+///
+/// 消除左递归: 按非终结符的顺序把 Ai -> Aj γ (j < i) 中的 Aj 代入, 然后消除直接左递归
+/// A -> A α | β 变为 A -> β A', A' -> α A' | ε
+///
+/// 文法中有 ε 产生式或者 A =>+ A 的环时结果不一定没有左递归
+///
+pub fn eliminate_left_recursion(grammar: &Grammar) -> Grammar {
+  let mut rules = rules(grammar);
+  let mut names = grammar.symbols.clone();
+  let mut primes = vec![];
+  for i in 0..rules.len() {
+    for j in 0..i {
+      let (name, replacements) = rules[j].clone();
+      let mut rhss = vec![];
+      for rhs in &rules[i].1 {
+        if rhs.first() == Some(&name) {
+          rhss.extend(replacements.iter().map(|replacement| [replacement, &rhs[1..]].concat()));
+        } else {
+          rhss.push(rhs.clone());
+        }
+      }
+      dedup(&mut rhss);
+      rules[i].1 = rhss;
+    }
+
+    let (name, rhss) = &rules[i];
+    let (recursive, others): (Vec<_>, Vec<_>) = rhss.iter().partition(|rhs| rhs.first() == Some(name));
+    // A -> A 去掉后不影响语言
+    let recursive = recursive.into_iter().filter(|rhs| rhs.len() > 1).collect::<Vec<_>>();
+    if recursive.is_empty() {
+      let rhss = others.into_iter().cloned().collect();
+      rules[i].1 = rhss;
+      primes.push(None);
+      continue;
+    }
+    let prime = unique(&mut names, name);
+    let rhss = others.into_iter().map(|rhs| [rhs.clone(), vec![prime.clone()]].concat()).collect();
+    let mut prime_rhss =
+      recursive.into_iter().map(|rhs| [rhs[1..].to_vec(), vec![prime.clone()]].concat()).collect::<Vec<_>>();
+    prime_rhss.push(vec![]);
+    rules[i].1 = rhss;
+    primes.push(Some((prime, prime_rhss)));
+  }
+
+  let rules = rules.into_iter().zip(primes).flat_map(|(rule, prime)| [Some(rule), prime]).flatten().collect();
+  rebuild(grammar, &rules)
+}
+
+///
+/// 提取左公因子: 有相同前缀 α 的候选式 A -> α β1 | α β2 变为 A -> α A', A' -> β1 | β2,
+/// 每次提取最长的公共前缀, 直到所有候选式的首符号都不同
+///
+pub fn left_factor(grammar: &Grammar) -> Grammar {
+  let mut rules = rules(grammar);
+  let mut names = grammar.symbols.clone();
+  let mut index = 0;
+  while index < rules.len() {
+    loop {
+      let rhss = &rules[index].1;
+      let Some(group) = rhss.iter().enumerate().filter(|(_, rhs)| !rhs.is_empty()).find_map(|(position, rhs)| {
+        let group = (position..rhss.len()).filter(|other| rhss[*other].first() == rhs.first()).collect::<Vec<_>>();
+        (group.len() > 1).then_some(group)
+      }) else {
+        break;
+      };
+
+      let mut prefix = rhss[group[0]].clone();
+      for &position in &group[1..] {
+        let common = prefix.iter().zip(&rhss[position]).take_while(|(a, b)| a == b).count();
+        prefix.truncate(common);
+      }
+      let prime = unique(&mut names, &rules[index].0);
+      let suffixes = group.iter().map(|position| rhss[*position][prefix.len()..].to_vec()).collect::<Vec<_>>();
+
+      let rhss = &mut rules[index].1;
+      rhss[group[0]] = [prefix, vec![prime.clone()]].concat();
+      for position in group[1..].iter().rev() {
+        rhss.remove(*position);
+      }
+      rules.insert(index + 1, (prime, suffixes));
+    }
+    index += 1;
+  }
+  rebuild(grammar, &rules)
+}
+
+/// 新的非终结符, 在名字之后加上 ' 直到与已有的符号都不同
+fn unique(names: &mut Vec<String>, name: &str) -> String {
+  let mut name = name.to_string();
+  while names.contains(&name) {
+    name.push('\'');
+  }
+  names.push(name.clone());
+  name
+}
+
+fn dedup(rhss: &mut Vec<Vec<String>>) {
+  let mut seen = BTreeSet::new();
+  rhss.retain(|rhs| seen.insert(rhs.clone()));
+}
+
+/// 输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  Text,
+  Markdown,
+  Typst, // 与 lr1/lr1-lib.typ 相同的表格, 冲突的格为红色
+}
+
+impl FromStr for Format {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "text" => Ok(Format::Text),
+      "markdown" | "md" => Ok(Format::Markdown),
+      "typst" | "typ" => Ok(Format::Typst),
+      _ => Err(format!("unknown format: {}", s)),
+    }
+  }
+}
+
+/// 表格的一格, conflict 为 true 时突出显示
+struct Cell {
+  text: String,
+  conflict: bool,
+}
+
+impl From<String> for Cell {
+  fn from(text: String) -> Self {
+    Cell { text, conflict: false }
+  }
+}
+
+impl Format {
+  fn heading(self, out: &mut String, title: &str) {
+    match self {
+      Format::Text => writeln!(out, "{}\n", title),
+      Format::Markdown => writeln!(out, "## {}\n", title),
+      Format::Typst => writeln!(out, "== {}\n", title),
+    }
+    .unwrap();
+  }
+
+  fn paragraph(self, out: &mut String, lines: &[String]) {
+    for line in lines {
+      match self {
+        Format::Text => writeln!(out, "{}", line),
+        Format::Markdown => writeln!(out, "- {}", line.replace('|', "\\|")),
+        Format::Typst => writeln!(out, "- #{}", typst_string(line)),
+      }
+      .unwrap();
+    }
+    out.push('\n');
+  }
+
+  fn table(self, out: &mut String, header: &[String], rows: &[Vec<Cell>]) {
+    match self {
+      Format::Text => {
+        let width = |column: usize| {
+          rows
+            .iter()
+            .map(|row| display_width(&row[column].text) + if row[column].conflict { 2 } else { 0 })
+            .chain([display_width(&header[column])])
+            .max()
+            .unwrap()
+        };
+        let widths = (0..header.len()).map(width).collect::<Vec<_>>();
+        let line = |cells: Vec<String>| {
+          let cells = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{}{}", cell, " ".repeat(width - display_width(cell))))
+            .collect::<Vec<_>>();
+          cells.join("  ").trim_end().to_string()
+        };
+        writeln!(out, "{}", line(header.to_vec())).unwrap();
+        for row in rows {
+          let cells =
+            row.iter().map(|cell| if cell.conflict { format!("*{}*", cell.text) } else { cell.text.clone() }).collect();
+          writeln!(out, "{}", line(cells)).unwrap();
+        }
+      }
+      Format::Markdown => {
+        let escape = |text: &str| text.replace('|', "\\|").replace('*', "\\*");
+        writeln!(out, "| {} |", header.iter().map(|text| escape(text)).collect::<Vec<_>>().join(" | ")).unwrap();
+        writeln!(out, "|{}", " --- |".repeat(header.len())).unwrap();
+        for row in rows {
+          let cells = row
+            .iter()
+            .map(|cell| match (cell.conflict, cell.text.is_empty()) {
+              (true, false) => format!("**{}**", escape(&cell.text)),
+              _ => escape(&cell.text),
+            })
+            .collect::<Vec<_>>();
+          writeln!(out, "| {} |", cells.join(" | ")).unwrap();
+        }
+      }
+      Format::Typst => {
+        writeln!(out, "#table(\n  columns: {},\n  align: center + horizon,\n  stroke: 0.5pt,\n", header.len()).unwrap();
+        let header = header.iter().map(|text| typst_string(text)).collect::<Vec<_>>();
+        writeln!(out, "  table.header({}),", header.join(", ")).unwrap();
+        for row in rows {
+          let cells = row
+            .iter()
+            .map(|cell| {
+              if cell.conflict {
+                format!("text(red, {})", typst_string(&cell.text))
+              } else {
+                typst_string(&cell.text)
+              }
+            })
+            .collect::<Vec<_>>();
+          writeln!(out, "  {},", cells.join(", ")).unwrap();
+        }
+        writeln!(out, ")").unwrap();
+      }
+    }
+    out.push('\n');
+  }
+}
+
+/// 终端中的显示宽度, 汉字占两列
+fn display_width(text: &str) -> usize {
+  text.chars().map(|char| if char >= '\u{2e80}' { 2 } else { 1 }).sum()
+}
+
+/// Typst 的字符串, 作为内容时不会被当作标记
+fn typst_string(text: &str) -> String {
+  format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+///
+/// 文法的分析报告: 产生式, nullable / FIRST / FOLLOW 集, 左递归, LL(1) 预测分析表与冲突,
+/// 以及是否为 LR(1) 文法. LR(1) 文法一定没有二义性, 有冲突时文法可能有二义性
+///
+pub fn report(grammar: &Grammar, format: Format) -> String {
+  let mut out = String::new();
+  let ll1 = Ll1::new(grammar);
+  let names = |set: &BTreeSet<usize>| set.iter().map(|symbol| grammar.name(*symbol)).collect::<Vec<_>>().join(", ");
+
+  format.heading(&mut out, "产生式");
+  let rows = (0..grammar.productions.len())
+    .map(|index| vec![Cell::from(index.to_string()), Cell::from(grammar.production(index))])
+    .collect::<Vec<_>>();
+  format.table(&mut out, &["编号".to_string(), "产生式".to_string()], &rows);
+
+  format.heading(&mut out, "nullable, FIRST 与 FOLLOW 集");
+  let rows = grammar
+    .nonterminals()
+    .map(|nonterminal| {
+      vec![
+        Cell::from(grammar.name(nonterminal).to_string()),
+        Cell::from(ll1.sets.nullable[nonterminal].to_string()),
+        Cell::from(format!("{{ {} }}", names(&ll1.sets.first[nonterminal]))),
+        Cell::from(format!("{{ {} }}", names(&ll1.sets.follow[nonterminal]))),
+      ]
+    })
+    .collect::<Vec<_>>();
+  let header = ["非终结符", "nullable", "FIRST", "FOLLOW"].map(str::to_string);
+  format.table(&mut out, &header, &rows);
+
+  format.heading(&mut out, "左递归");
+  let cycles = left_recursion(grammar)
+    .iter()
+    .map(|cycle| cycle.iter().map(|symbol| grammar.name(*symbol)).collect::<Vec<_>>().join(" => "))
+    .collect::<Vec<_>>();
+  if cycles.is_empty() {
+    format.paragraph(&mut out, &["没有左递归".to_string()]);
+  } else {
+    format.paragraph(&mut out, &cycles);
+  }
+
+  format.heading(&mut out, "LL(1) 预测分析表");
+  let terminals = (0..=grammar.terminals).collect::<Vec<_>>();
+  let header = [String::new()]
+    .into_iter()
+    .chain(terminals.iter().map(|terminal| grammar.name(*terminal).to_string()))
+    .collect::<Vec<_>>();
+  let rows = grammar
+    .nonterminals()
+    .map(|nonterminal| {
+      [Cell::from(grammar.name(nonterminal).to_string())]
+        .into_iter()
+        .chain(terminals.iter().map(|terminal| {
+          let productions = ll1.table.get(&(nonterminal, *terminal)).map_or(&[][..], Vec::as_slice);
+          let text = productions.iter().map(|index| grammar.production(*index)).collect::<Vec<_>>();
+          Cell { text: text.join("; "), conflict: productions.len() > 1 }
+        }))
+        .collect()
+    })
+    .collect::<Vec<_>>();
+  format.table(&mut out, &header, &rows);
+
+  let mut lines = ll1
+    .conflicts()
+    .map(|((nonterminal, terminal), productions)| {
+      let productions = productions.iter().map(|index| grammar.production(*index)).collect::<Vec<_>>();
+      format!("M[{}, {}] 冲突: {}", grammar.name(*nonterminal), grammar.name(*terminal), productions.join("; "))
+    })
+    .collect::<Vec<_>>();
+  lines.push(if ll1.valid() { "是 LL(1) 文法".to_string() } else { "不是 LL(1) 文法".to_string() });
+  let lr1 = Lr1::new(grammar);
+  lines.push(if lr1.valid() {
+    "是 LR(1) 文法, 没有二义性".to_string()
+  } else {
+    format!("不是 LR(1) 文法, 有 {} 个冲突, 可能有二义性", lr1.conflicts.len())
+  });
+  format.heading(&mut out, "结论");
+  format.paragraph(&mut out, &lines);
+
+  out.trim_end().to_string() + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{eliminate_left_recursion, left_factor, left_recursion, parse, report, Format, Ll1};
+  use crate::lr1::Grammar;
+
+  const ARITHMETIC: &str = "\
+# 四则运算表达式
+E -> E + T | E - T | T
+T -> T * F | T / F
+  | F
+F -> ( E ) | n
+";
+
+  fn productions(grammar: &Grammar) -> Vec<String> {
+    (0..grammar.productions.len()).map(|index| grammar.production(index)).collect()
+  }
+
+  #[test]
+  fn test_parse() {
+    let grammar = parse(ARITHMETIC).unwrap();
+    assert_eq!(grammar.symbols, ["+", "-", "*", "/", "(", ")", "n", "#", "E", "T", "F"]);
+    assert_eq!(grammar.name(grammar.start), "E");
+    assert_eq!(productions(&grammar)[5], "T -> F");
+
+    let grammar = parse("S -> a S | ε\nS -> b |").unwrap();
+    assert_eq!(productions(&grammar), ["S -> a S", "S -> ε", "S -> b", "S -> ε"]);
+
+    assert_eq!(parse("").unwrap_err(), "empty grammar");
+    assert_eq!(parse("| a").unwrap_err(), "line 1: no production to continue");
+    assert_eq!(parse("S a").unwrap_err(), "line 1: expect ->");
+  }
+
+  #[test]
+  fn test_ll1() {
+    let grammar = parse(ARITHMETIC).unwrap();
+    let ll1 = Ll1::new(&grammar);
+    assert!(!ll1.valid());
+
+    let grammar = eliminate_left_recursion(&grammar);
+    assert_eq!(
+      productions(&grammar),
+      [
+        "E -> T E'",
+        "E' -> + T E'",
+        "E' -> - T E'",
+        "E' -> ε",
+        "T -> F T'",
+        "T' -> * F T'",
+        "T' -> / F T'",
+        "T' -> ε",
+        "F -> ( E )",
+        "F -> n",
+      ]
+    );
+    let ll1 = Ll1::new(&grammar);
+    assert!(ll1.valid());
+    let cell = |nonterminal, terminal| {
+      let key = (grammar.symbol(nonterminal).unwrap(), grammar.symbol(terminal).unwrap());
+      ll1.table.get(&key).map(|productions| grammar.production(productions[0]))
+    };
+    assert_eq!(cell("E'", ")").as_deref(), Some("E' -> ε"));
+    assert_eq!(cell("E'", "#").as_deref(), Some("E' -> ε"));
+    assert_eq!(cell("T'", "*").as_deref(), Some("T' -> * F T'"));
+    assert_eq!(cell("F", "+"), None);
+  }
+
+  #[test]
+  fn test_left_recursion() {
+    let grammar = parse(ARITHMETIC).unwrap();
+    let names = |grammar: &Grammar| {
+      left_recursion(grammar)
+        .iter()
+        .map(|cycle| cycle.iter().map(|symbol| grammar.name(*symbol)).collect::<Vec<_>>().join(" "))
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(names(&grammar), ["E E", "T T"]);
+
+    // 间接左递归, 以及经过能推导出 ε 的符号的左递归
+    let grammar = parse("S -> A a | b\nA -> A c | S d | B A e\nB -> ε").unwrap();
+    assert_eq!(names(&grammar), ["S A S", "A A"]);
+
+    let grammar = eliminate_left_recursion(&parse("S -> A a | b\nA -> A c | S d | f").unwrap());
+    assert_eq!(
+      productions(&grammar),
+      ["S -> A a", "S -> b", "A -> b d A'", "A -> f A'", "A' -> c A'", "A' -> a d A'", "A' -> ε"]
+    );
+    assert!(left_recursion(&grammar).is_empty());
+  }
+
+  #[test]
+  fn test_left_factor() {
+    let grammar = parse("S -> i E t S | i E t S e S | a\nE -> b").unwrap();
+    let grammar = left_factor(&grammar);
+    assert_eq!(productions(&grammar), ["S -> i E t S S'", "S -> a", "S' -> ε", "S' -> e S", "E -> b"]);
+
+    let grammar = left_factor(&parse("A -> a b c | a b d | a e | f").unwrap());
+    assert_eq!(productions(&grammar), ["A -> a A'", "A -> f", "A' -> b A''", "A' -> e", "A'' -> c", "A'' -> d"]);
+  }
+
+  #[test]
+  fn test_report() {
+    let grammar = left_factor(&parse("S -> i E t S | i E t S e S | a\nE -> b").unwrap());
+    let text = report(&grammar, Format::Text);
+    assert!(text.contains("S'        true      { e }     { e, # }"), "{}", text);
+    assert!(text.contains("*S' -> ε; S' -> e S*"), "{}", text);
+    assert!(text.contains("M[S', e] 冲突: S' -> ε; S' -> e S"), "{}", text);
+    assert!(text.ends_with("不是 LL(1) 文法\n不是 LR(1) 文法, 有 1 个冲突, 可能有二义性\n"), "{}", text);
+
+    let markdown = report(&grammar, Format::Markdown);
+    assert!(markdown.contains("| S' |  |  | **S' -> ε; S' -> e S** |  |  | S' -> ε |"), "{}", markdown);
+
+    let typst = report(&grammar, Format::Typst);
+    assert!(typst.contains("  columns: 7,"), "{}", typst);
+    assert!(typst.contains("text(red, \"S' -> ε; S' -> e S\")"), "{}", typst);
+
+    let text = report(&parse("S -> ( S ) S | ε").unwrap(), Format::Text);
+    assert!(text.ends_with("是 LL(1) 文法\n是 LR(1) 文法, 没有二义性\n"), "{}", text);
+  }
+}
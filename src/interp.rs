@@ -0,0 +1,436 @@
+use std::{
+  cell::Cell,
+  io::{self, Write},
+  rc::Rc,
+  result,
+};
+
+use crate::{
+  ast::{Expression, ExpressionKind, Identifier, Infix, Prefix, Program, Statement, StatementKind},
+  vm::{builtins::Builtins, RuntimeError},
+  SpanOffset,
+};
+
+type Error = (String, SpanOffset);
+type Result<T> = result::Result<T, Error>;
+
+/// 函数定义: 函数名, 参数, 函数体
+type Function<'p> = (&'p Identifier, &'p [Identifier], &'p [Statement]);
+
+/// 作用域链
+type Env<'p> = Option<Rc<Scope<'p>>>;
+
+/// 名字绑定
+enum Binding<'p> {
+  Constant(isize),
+  Variable(Rc<Cell<isize>>),
+  Proceduce(Function<'p>),
+}
+
+/// 作用域链上的一个节点, 每个节点只绑定一个名字
+///
+/// 节点创建后不再改变, 函数捕获的作用域链恰好是函数定义时可见的名字, 与编译时的名字解析一致
+struct Scope<'p> {
+  name: &'p str,
+  binding: Binding<'p>,
+  parent: Env<'p>,
+}
+
+/// 求值的结果
+enum Flow<'p> {
+  Value(isize),                                // 表达式的值
+  Return(isize),                               // return 语句
+  TailCall(Function<'p>, Env<'p>, Vec<isize>), // 尾调用, 交给调用方执行
+}
+
+/// 取出表达式的值, 遇到 return 或者尾调用时直接向上传递
+macro_rules! value {
+  ($flow: expr) => {
+    match $flow? {
+      Flow::Value(value) => value,
+      flow => return Ok(flow),
+    }
+  };
+}
+
+///
+/// 树遍历解释器
+///
+/// 直接对抽象语法树求值, 语义与 编译 + 虚拟机 执行保持一致, 用作差分测试的参考实现:
+/// if / while 表达式的值, 函数隐式返回最后一条表达式语句的值, 默认值 0, 以及相同的内建函数.
+/// 实参个数不足等虚拟机无法检查的错误会直接报错
+pub struct Interpreter<'p, 'o> {
+  builtins: Builtins,
+  closures: Vec<(Function<'p>, Env<'p>)>, // 函数值即为闭包在该表中的下标
+  out: &'o mut dyn Write,
+}
+
+impl<'p, 'o> Interpreter<'p, 'o> {
+  pub fn new(out: &'o mut dyn Write) -> Self {
+    Interpreter { builtins: Builtins::new(), closures: vec![], out }
+  }
+
+  /// 解释执行程序
+  pub fn eval(program: &'p Program) -> Result<isize> {
+    Interpreter::eval_with_output(program, &mut io::stdout())
+  }
+
+  /// 解释执行程序, 内建函数的输出写入到 out 中
+  pub fn eval_with_output(program: &'p Program, out: &'o mut dyn Write) -> Result<isize> {
+    let mut interpreter = Interpreter::new(out);
+    let mut env = None;
+
+    match interpreter.eval_statements(&program.statements, &mut env, false)? {
+      Flow::Value(value) | Flow::Return(value) => Ok(value),
+      // 全局作用域中的 return f(...)
+      Flow::TailCall(function, env, args) => interpreter.call(function, env, args, function.0.pos),
+    }
+  }
+
+  ///
+  /// 执行语句序列, 返回最后一条有值的语句的值 (默认为 0)
+  ///
+  /// tail 为真时最后一条表达式语句处于尾部位置
+  fn eval_statements(&mut self, statements: &'p [Statement], env: &mut Env<'p>, tail: bool) -> Result<Flow<'p>> {
+    let mut last = 0;
+
+    for (index, statement) in statements.iter().enumerate() {
+      let flow = match &statement.kind {
+        StatementKind::Expression(e) if tail && index + 1 == statements.len() => Some(self.eval_tail(e, env)?),
+        _ => self.eval_statement(statement, env)?,
+      };
+
+      match flow {
+        Some(Flow::Value(value)) => last = value,
+        Some(flow) => return Ok(flow),
+        None => {}
+      }
+    }
+
+    Ok(Flow::Value(last))
+  }
+
+  ///
+  /// 执行语句, 没有值的语句 (空语句, 常量与函数定义) 返回 None
+  ///
+  fn eval_statement(&mut self, statement: &'p Statement, env: &mut Env<'p>) -> Result<Option<Flow<'p>>> {
+    let flow = match &statement.kind {
+      StatementKind::Empty => None,
+      StatementKind::Const(constants) => {
+        for (ident, e) in constants {
+          match e.kind {
+            ExpressionKind::Integer(value) => Self::bind(env, &ident.name, Binding::Constant(value)),
+            _ => return Err((format!("only integer can assign to constant, but get {:?}", e.kind), e.pos)),
+          }
+        }
+        None
+      }
+
+      StatementKind::Variable(variables) => {
+        let mut last = 0;
+        for (ident, e) in variables {
+          last = match self.eval_expression(e, env)? {
+            Flow::Value(value) => value,
+            flow => return Ok(Some(flow)),
+          };
+          Self::bind(env, &ident.name, Binding::Variable(Rc::new(Cell::new(last))));
+        }
+        Some(Flow::Value(last))
+      }
+
+      StatementKind::Function(ident, args, statements) => {
+        self.define(env, (ident, args, statements))?;
+        None
+      }
+
+      StatementKind::Assign(ident, e) => {
+        let value = match self.eval_expression(e, env)? {
+          Flow::Value(value) => value,
+          flow => return Ok(Some(flow)),
+        };
+
+        match Self::lookup(env, &ident.name).as_deref() {
+          Some(Scope { binding: Binding::Variable(cell), .. }) => cell.set(value),
+          _ => return Err((format!("variable is undefined: {:}", ident.name), statement.pos)),
+        }
+        Some(Flow::Value(value))
+      }
+
+      StatementKind::Return(Some(e)) => Some(match self.eval_tail(e, env)? {
+        Flow::Value(value) => Flow::Return(value),
+        flow => flow,
+      }),
+      StatementKind::Return(None) => Some(Flow::Return(0)),
+
+      StatementKind::Expression(e) => Some(self.eval_expression(e, env)?),
+    };
+
+    Ok(flow)
+  }
+
+  ///
+  /// 对处于尾部位置的表达式求值
+  ///
+  fn eval_tail(&mut self, expression: &'p Expression, env: &mut Env<'p>) -> Result<Flow<'p>> {
+    match &expression.kind {
+      ExpressionKind::Call(callee, args) => self.eval_call(callee, args, env, true),
+      ExpressionKind::If(condition, then_s, else_s) => self.eval_if(condition, then_s, else_s, env, true),
+      _ => self.eval_expression(expression, env),
+    }
+  }
+
+  ///
+  /// 表达式求值
+  ///
+  fn eval_expression(&mut self, expression: &'p Expression, env: &mut Env<'p>) -> Result<Flow<'p>> {
+    let value = match &expression.kind {
+      ExpressionKind::Identifier(name) => match Self::lookup(env, name) {
+        Some(scope) => match &scope.binding {
+          Binding::Constant(value) => *value,
+          Binding::Variable(cell) => cell.get(),
+          Binding::Proceduce(function) => self.closure(*function, Some(scope.clone())),
+        },
+        None => return Err((format!("identifier is not define: {}", name), expression.pos)),
+      },
+
+      ExpressionKind::Integer(integer) => *integer,
+
+      ExpressionKind::Infix(infix, left, right) => {
+        let op1 = value!(self.eval_expression(left, env));
+        let op2 = value!(self.eval_expression(right, env));
+
+        let result = match infix {
+          Infix::Add => op1.checked_add(op2),
+          Infix::Sub => op1.checked_sub(op2),
+          Infix::Mul => op1.checked_mul(op2),
+          Infix::Div if op2 == 0 => return Err((RuntimeError::DivisionByZero.to_string(), expression.pos)),
+          Infix::Div => op1.checked_div(op2),
+          Infix::Eq => Some((op1 == op2) as isize),
+          Infix::Ne => Some((op1 != op2) as isize),
+          Infix::Lt => Some((op1 < op2) as isize),
+          Infix::Gt => Some((op1 > op2) as isize),
+          Infix::LtEq => Some((op1 <= op2) as isize),
+          Infix::GtEq => Some((op1 >= op2) as isize),
+        };
+        result.ok_or_else(|| (RuntimeError::Overflow.to_string(), expression.pos))?
+      }
+
+      ExpressionKind::Prefix(prefix, e) => {
+        let value = value!(self.eval_expression(e, env));
+        match prefix {
+          Prefix::Not => (value == 0) as isize,
+          Prefix::Neg => {
+            0isize.checked_sub(value).ok_or_else(|| (RuntimeError::Overflow.to_string(), expression.pos))?
+          }
+        }
+      }
+
+      ExpressionKind::Call(callee, args) => return self.eval_call(callee, args, env, false),
+
+      ExpressionKind::Function(ident, args, statements) => {
+        // 函数名同时绑定在当前作用域中
+        self.define(env, (ident, args, statements))?;
+        self.closure((ident, args, statements), env.clone())
+      }
+
+      ExpressionKind::If(condition, then_s, else_s) => return self.eval_if(condition, then_s, else_s, env, false),
+
+      ExpressionKind::While(condition, statements) => {
+        while value!(self.eval_expression(condition, env)) != 0 {
+          value!(self.eval_block(statements, env, false));
+        }
+        0
+      }
+    };
+
+    Ok(Flow::Value(value))
+  }
+
+  ///
+  /// if 表达式求值, 没有执行的分支值为 0
+  ///
+  fn eval_if(
+    &mut self,
+    condition: &'p Expression,
+    then_s: &'p [Statement],
+    else_s: &'p Option<Vec<Statement>>,
+    env: &mut Env<'p>,
+    tail: bool,
+  ) -> Result<Flow<'p>> {
+    if value!(self.eval_expression(condition, env)) != 0 {
+      self.eval_block(then_s, env, tail)
+    } else if let Some(else_s) = else_s {
+      self.eval_block(else_s, env, tail)
+    } else {
+      Ok(Flow::Value(0))
+    }
+  }
+
+  ///
+  /// 语句块求值, 语句块中定义的名字离开语句块后不可见
+  ///
+  fn eval_block(&mut self, statements: &'p [Statement], env: &mut Env<'p>, tail: bool) -> Result<Flow<'p>> {
+    self.eval_statements(statements, &mut env.clone(), tail)
+  }
+
+  ///
+  /// 函数调用: 实参逆序求值, 然后再对被调用的表达式求值
+  ///
+  fn eval_call(
+    &mut self,
+    callee: &'p Expression,
+    args: &'p [Expression],
+    env: &mut Env<'p>,
+    tail: bool,
+  ) -> Result<Flow<'p>> {
+    let mut values = vec![];
+    for e in args.iter().rev() {
+      values.push(value!(self.eval_expression(e, env)));
+    }
+    values.reverse();
+
+    if let ExpressionKind::Identifier(name) = &callee.kind {
+      if let Some(id) = self.builtins.lookup(name) {
+        return Ok(Flow::Value(self.builtins.call(id, values, self.out)));
+      }
+    }
+
+    let direct = match &callee.kind {
+      ExpressionKind::Identifier(name) => Self::lookup(env, name).and_then(|scope| match scope.binding {
+        Binding::Proceduce(function) => Some((function, Some(scope.clone()))),
+        _ => None,
+      }),
+      _ => None,
+    };
+    let (function, closure_env) = match direct {
+      Some(closure) => closure,
+      None => {
+        let value = value!(self.eval_expression(callee, env));
+        self.closure_of(value, callee.pos)?
+      }
+    };
+
+    if tail {
+      Ok(Flow::TailCall(function, closure_env, values))
+    } else {
+      self.call(function, closure_env, values, callee.pos).map(Flow::Value)
+    }
+  }
+
+  ///
+  /// 调用函数, 尾调用在这里循环执行, 不会增加调用深度
+  ///
+  fn call(&mut self, function: Function<'p>, env: Env<'p>, args: Vec<isize>, pos: SpanOffset) -> Result<isize> {
+    let (mut function, mut env, mut args) = (function, env, args);
+
+    loop {
+      let (ident, params, statements) = function;
+      // 多余的实参会被忽略, 与虚拟机一致
+      if params.len() > args.len() {
+        return Err((
+          format!("function {} expect {} arguments, but get {}", ident.name, params.len(), args.len()),
+          pos,
+        ));
+      }
+
+      for (param, value) in params.iter().zip(args) {
+        Self::bind(&mut env, &param.name, Binding::Variable(Rc::new(Cell::new(value))));
+      }
+
+      match self.eval_statements(statements, &mut env, true)? {
+        Flow::Value(value) | Flow::Return(value) => return Ok(value),
+        Flow::TailCall(f, e, a) => (function, env, args) = (f, e, a),
+      }
+    }
+  }
+
+  /// 定义函数
+  fn define(&mut self, env: &mut Env<'p>, function: Function<'p>) -> Result<()> {
+    let ident = function.0;
+    if self.builtins.lookup(&ident.name).is_some() {
+      return Err((format!("unable define funcation name as same as builtins function: {}", ident.name), ident.pos));
+    }
+
+    Self::bind(env, &ident.name, Binding::Proceduce(function));
+    Ok(())
+  }
+
+  /// 生成闭包, 返回其在闭包表中的下标
+  fn closure(&mut self, function: Function<'p>, env: Env<'p>) -> isize {
+    self.closures.push((function, env));
+    (self.closures.len() - 1) as isize
+  }
+
+  /// 通过函数值取出闭包
+  fn closure_of(&self, value: isize, pos: SpanOffset) -> Result<(Function<'p>, Env<'p>)> {
+    match usize::try_from(value).ok().and_then(|index| self.closures.get(index)) {
+      Some((function, env)) => Ok((*function, env.clone())),
+      None => Err((format!("illegal function value: {}", value), pos)),
+    }
+  }
+
+  /// 在作用域链上绑定名字
+  fn bind(env: &mut Env<'p>, name: &'p str, binding: Binding<'p>) {
+    *env = Some(Rc::new(Scope { name, binding, parent: env.take() }));
+  }
+
+  /// 从作用域链上查找名字
+  ///
+  /// 函数绑定所在的节点即为函数捕获的作用域链 (包含函数自身, 以支持递归)
+  fn lookup(env: &Env<'p>, name: &str) -> Option<Rc<Scope<'p>>> {
+    let mut scope = env.clone();
+    while let Some(current) = scope {
+      if current.name == name {
+        return Some(current);
+      }
+      scope = current.parent.clone();
+    }
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use super::Interpreter;
+  use crate::{
+    compiler::{Compiler, CORPUS},
+    parser::Paser,
+    vm::VM,
+  };
+
+  /// 差分测试: 编译器的测试用例与示例程序, 虚拟机与解释器的返回值与输出相同
+  #[test]
+  fn test_differential() {
+    let mut programs = CORPUS.iter().map(|(program, _)| program.to_string()).collect::<Vec<_>>();
+    programs.push(fs::read_to_string("examples/a.pl0").unwrap());
+
+    for input in &programs {
+      let program = Paser::paser(input).unwrap();
+      let codes = Compiler::compile(&program).unwrap_or_else(|errors| panic!("{}: {:?}", input, errors));
+
+      let mut vm_output = vec![];
+      let vm_result = VM::execute_with_output(&codes, &mut vm_output).unwrap();
+
+      let mut interp_output = vec![];
+      let interp_result = Interpreter::eval_with_output(&program, &mut interp_output).unwrap();
+
+      assert_eq!(vm_result, interp_result, "{}", input);
+      assert_eq!(String::from_utf8(vm_output).unwrap(), String::from_utf8(interp_output).unwrap(), "{}", input);
+    }
+  }
+
+  #[test]
+  fn test_error() {
+    for (input, error) in [
+      ("a + 1", "identifier is not define: a"),
+      ("const a = 1; a = 2", "variable is undefined: a"),
+      ("fn f(x) { x } f()", "function f expect 1 arguments, but get 0"),
+      ("var f = 7; f()", "illegal function value: 7"),
+    ] {
+      let program = Paser::paser(input).unwrap();
+      assert_eq!(Interpreter::eval_with_output(&program, &mut vec![]).unwrap_err().0, error);
+    }
+  }
+}
@@ -1,6 +1,9 @@
 use std::str::pattern::{Pattern, SearchStep, Searcher};
 
-use crate::{token::Token, SpanOffset};
+use crate::{
+  token::{Token, Trivia},
+  SpanOffset,
+};
 
 pub struct Lexer<'a> {
   string: &'a str,
@@ -15,73 +18,98 @@ impl<'a> Lexer<'a> {
 
   /// 获取下一个 token
   ///
-  /// 返回 (token, 位置)
+  /// 返回 (token, 位置), 空白与注释被丢弃
+  #[allow(clippy::should_implement_trait)]
   pub fn next(&mut self) -> (Token, SpanOffset) {
-    self.offset += self.eat_whitespace().chars().count();
+    while self.trivia().is_some() {}
+    self.token()
+  }
+
+  /// 获取下一个 token, 同时保留它之前的空白与注释
+  ///
+  /// 返回 (前导的空白与注释, token, token 的原文, 位置), 依次拼接所有的原文即可得到输入
+  pub fn next_with_trivia(&mut self) -> (Vec<Trivia>, Token, &'a str, SpanOffset) {
+    let mut leading = vec![];
+    while let Some(trivia) = self.trivia() {
+      leading.push(trivia);
+    }
+
+    let start = self.cursor;
+    let (token, pos) = self.token();
+    (leading, token, self.from(start), pos)
+  }
+
+  /// 读取一段连续的空白或者一条行注释
+  fn trivia(&mut self) -> Option<Trivia> {
+    let start = self.cursor;
 
+    let trivia = if self.after().starts_with("//") {
+      Trivia::LineComment(self.eat_until('\n').to_string())
+    } else {
+      match self.eat_whitespace() {
+        "" => return None,
+        whitespace => Trivia::Whitespace(whitespace.to_string()),
+      }
+    };
+
+    self.offset += self.from(start).chars().count();
+    Some(trivia)
+  }
+
+  fn token(&mut self) -> (Token, SpanOffset) {
     let pos = self.cursor;
     let token = match self.peek_char() {
       None => Token::EOF,
-      Some(ch) => {
-        match ch {
-          'a'..='z' | 'A'..='Z' | '_' => match self.eat_while(char::is_alphanumeric) {
-            "if" => Token::If,
-            "else" => Token::Else,
-            "while" => Token::While,
-            "const" => Token::Const,
-            "var" => Token::Var,
-            "fn" => Token::Function,
-            "return" => Token::Return,
-            s => Token::Ident(s.to_string()),
-          },
-
-          '0'..='9' => {
-            // todo: 处理越界
-            Token::Integer(self.eat_while(|ch| matches!(ch, '0'..='9')).parse().unwrap())
-          }
-
-          '=' if self.eat_if("==") => Token::Eq,
-          '!' if self.eat_if("!=") => Token::Ne,
-          '+' if self.eat_if("+=") => Token::AddAssign,
-          '-' if self.eat_if("-=") => Token::SubAssign,
-          '*' if self.eat_if("*=") => Token::MulAssign,
-          '/' if self.eat_if("/=") => Token::DivAssign,
-          '<' if self.eat_if("<=") => Token::LtEq,
-          '>' if self.eat_if(">=") => Token::GtEq,
-          '/' if self.eat_if("//") => {
-            // Token::LineComment(self.eat_until('\n').to_string())
-            self.offset += self.eat_until('\n').chars().count() + 2;
-            return self.next();
-          }
+      Some(ch) => match ch {
+        'a'..='z' | 'A'..='Z' | '_' => match self.eat_while(|ch: char| ch.is_alphanumeric() || ch == '_') {
+          "if" => Token::If,
+          "else" => Token::Else,
+          "while" => Token::While,
+          "const" => Token::Const,
+          "var" => Token::Var,
+          "fn" => Token::Function,
+          "return" => Token::Return,
+          s => Token::Ident(s.to_string()),
+        },
+
+        '0'..='9' => {
+          let digits = self.eat_while(|ch: char| ch.is_ascii_digit());
+          digits.parse().map_or_else(|_| Token::TooLarge(digits.to_string()), Token::Integer)
+        }
 
-          ch => {
-            self.eat();
-
-            match ch {
-              '!' => Token::Bang,
-              '+' => Token::Plus,
-              '-' => Token::Minus,
-              '*' => Token::Asterisk,
-              '/' => Token::Slash,
-              '<' => Token::Lt,
-              '>' => Token::Gt,
-              '=' => Token::Assign,
-              '(' => Token::Lparen,
-              ')' => Token::Rparen,
-              '[' => Token::Lbracket,
-              ']' => Token::Rbracket,
-              '{' => Token::Lbrace,
-              '}' => Token::Rbrace,
-              ',' => Token::Comma,
-              ';' => Token::Semicolon,
-              _ => {
-                println!("{}", ch);
-                unreachable!()
-              }
-            }
+        '=' if self.eat_if("==") => Token::Eq,
+        '!' if self.eat_if("!=") => Token::Ne,
+        '+' if self.eat_if("+=") => Token::AddAssign,
+        '-' if self.eat_if("-=") => Token::SubAssign,
+        '*' if self.eat_if("*=") => Token::MulAssign,
+        '/' if self.eat_if("/=") => Token::DivAssign,
+        '<' if self.eat_if("<=") => Token::LtEq,
+        '>' if self.eat_if(">=") => Token::GtEq,
+
+        ch => {
+          self.eat();
+
+          match ch {
+            '!' => Token::Bang,
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Asterisk,
+            '/' => Token::Slash,
+            '<' => Token::Lt,
+            '>' => Token::Gt,
+            '=' => Token::Assign,
+            '(' => Token::Lparen,
+            ')' => Token::Rparen,
+            '[' => Token::Lbracket,
+            ']' => Token::Rbracket,
+            '{' => Token::Lbrace,
+            '}' => Token::Rbrace,
+            ',' => Token::Comma,
+            ';' => Token::Semicolon,
+            ch => Token::Illegal(ch),
           }
         }
-      }
+      },
     };
 
     let begin = self.offset;
@@ -162,7 +190,7 @@ impl<'a> Lexer<'a> {
 
 #[cfg(test)]
 mod tests {
-  use crate::token::Token;
+  use crate::token::{Token, Trivia};
 
   use super::Lexer;
 
@@ -199,6 +227,53 @@ mod tests {
     }
   }
 
+  #[test]
+  fn test_trivia() {
+    let src = "  // 注释\nvar a = 1; // 行尾\n";
+    let mut lexer = Lexer::new(src);
+
+    let (leading, token, text, pos) = lexer.next_with_trivia();
+    assert_eq!(
+      leading,
+      [
+        Trivia::Whitespace("  ".to_string()),
+        Trivia::LineComment("// 注释".to_string()),
+        Trivia::Whitespace("\n".to_string())
+      ]
+    );
+    assert_eq!((token, text, pos.begin, pos.end), (Token::Var, "var", 8, 11));
+
+    // 依次拼接原文得到输入
+    let mut output = format!("{}{}", leading.iter().map(Trivia::text).collect::<String>(), text);
+    loop {
+      let (leading, token, text, _) = lexer.next_with_trivia();
+      output.extend(leading.iter().map(Trivia::text));
+      output.push_str(text);
+      if token == Token::EOF {
+        break;
+      }
+    }
+    assert_eq!(output, src);
+  }
+
+  /// 不识别的字符与过大的整数返回错误 token, 由语法分析报告错误
+  #[test]
+  fn test_error() {
+    let mut lexer = Lexer::new("x @ 99999999999999999999999 é 1");
+    for (token, text) in [
+      (Token::Ident("x".to_string()), "x"),
+      (Token::Illegal('@'), "illegal character '@'"),
+      (Token::TooLarge("99999999999999999999999".to_string()), "number too large: 99999999999999999999999"),
+      (Token::Illegal('é'), "illegal character 'é'"),
+      (Token::Integer(1), "integer"),
+    ] {
+      let (next, _) = lexer.next();
+      assert_eq!((&next, next.to_string().as_str()), (&token, text));
+    }
+    let (token, pos) = lexer.next();
+    assert_eq!((token, pos.begin), (Token::EOF, 31));
+  }
+
   #[test]
   fn test_keyworld() {
     let mut lexer = Lexer::new("if while const var fn");
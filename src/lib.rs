@@ -3,8 +3,20 @@
 use std::fmt::Display;
 
 pub mod ast;
+pub mod backend;
+pub mod bnf;
+pub mod classic;
 pub mod compiler;
+pub mod cst;
+pub mod dap;
+pub mod engine;
+pub mod formatter;
+pub mod generator;
+pub mod grammar;
+pub mod interp;
 pub mod lexer;
+pub mod lr1;
+pub mod lsp;
 pub mod parser;
 pub mod token;
 pub mod vm;
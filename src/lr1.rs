@@ -0,0 +1,612 @@
+//! LR(1) 语法分析器的生成, 与 `lr1/lr1-lib.typ` 相同: 由文法四元组构造项目集规范族与分析表, 然后分析符号串
+
+use std::{
+  collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+  fmt::{Display, Write},
+};
+
+/// 输入串的结束符
+pub const END: &str = "#";
+
+/// 产生式, 右部为空时为 ε 产生式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Production {
+  pub lhs: usize,
+  pub rhs: Vec<usize>,
+}
+
+///
+/// 文法四元组 (非终结符, 终结符, 产生式, 开始符号)
+///
+/// 符号用编号表示: 先是终结符, 然后是结束符 #, 最后是非终结符
+///
+#[derive(Debug, Clone)]
+pub struct Grammar {
+  pub symbols: Vec<String>,
+  pub terminals: usize, // 终结符的个数, 也是结束符的编号
+  pub productions: Vec<Production>,
+  pub start: usize,
+}
+
+/// 非终结符的 nullable, FIRST 与 FOLLOW 集, 下标为符号编号
+#[derive(Debug, Clone)]
+pub struct Sets {
+  pub nullable: Vec<bool>,
+  pub first: Vec<BTreeSet<usize>>, // 终结符的 FIRST 集为它自身
+  pub follow: Vec<BTreeSet<usize>>,
+}
+
+impl Grammar {
+  /// 产生式的右部为空格分隔的符号, 空字符串为 ε
+  pub fn new(
+    nonterminals: &[&str],
+    terminals: &[&str],
+    productions: &[(&str, &str)],
+    start: &str,
+  ) -> Result<Self, String> {
+    let symbols = terminals.iter().chain([&END]).chain(nonterminals).map(|name| name.to_string()).collect::<Vec<_>>();
+    if let Some(name) = symbols.iter().enumerate().find(|(index, name)| symbols[..*index].contains(name)) {
+      return Err(format!("symbol is defined twice: {}", name.1));
+    }
+    let mut grammar = Grammar { symbols, terminals: terminals.len(), productions: vec![], start: 0 };
+    grammar.start = grammar.nonterminal(start)?;
+    for (lhs, rhs) in productions {
+      let lhs = grammar.nonterminal(lhs)?;
+      let rhs = rhs
+        .split_whitespace()
+        .map(|name| grammar.symbol(name).filter(|symbol| *symbol != grammar.terminals))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| format!("unknown symbol in production: {}", rhs))?;
+      grammar.productions.push(Production { lhs, rhs });
+    }
+    Ok(grammar)
+  }
+
+  pub fn symbol(&self, name: &str) -> Option<usize> {
+    self.symbols.iter().position(|symbol| symbol == name)
+  }
+
+  fn nonterminal(&self, name: &str) -> Result<usize, String> {
+    self
+      .symbol(name)
+      .filter(|symbol| !self.is_terminal(*symbol))
+      .ok_or_else(|| format!("unknown nonterminal: {}", name))
+  }
+
+  /// 终结符或者结束符
+  pub fn is_terminal(&self, symbol: usize) -> bool {
+    symbol <= self.terminals
+  }
+
+  pub fn nonterminals(&self) -> impl Iterator<Item = usize> {
+    self.terminals + 1..self.symbols.len()
+  }
+
+  pub fn name(&self, symbol: usize) -> &str {
+    &self.symbols[symbol]
+  }
+
+  /// 产生式的文本, 例如 E -> E + T
+  pub fn production(&self, production: usize) -> String {
+    let Production { lhs, rhs } = &self.productions[production];
+    let rhs = rhs.iter().map(|symbol| self.name(*symbol)).collect::<Vec<_>>();
+    format!("{} -> {}", self.name(*lhs), if rhs.is_empty() { "ε".to_string() } else { rhs.join(" ") })
+  }
+
+  /// 拓广文法: 新的开始符号 S' 与产生式 0: S' -> S
+  pub fn augment(&self) -> Grammar {
+    let mut name = self.name(self.start).to_string();
+    while self.symbol(&name).is_some() {
+      name.push('\'');
+    }
+    let mut grammar = self.clone();
+    grammar.symbols.push(name);
+    grammar.start = grammar.symbols.len() - 1;
+    grammar.productions.insert(0, Production { lhs: grammar.start, rhs: vec![self.start] });
+    grammar
+  }
+
+  /// 迭代到不动点求 nullable, FIRST 与 FOLLOW 集
+  pub fn sets(&self) -> Sets {
+    let size = self.symbols.len();
+    let mut sets = Sets {
+      nullable: vec![false; size],
+      first: (0..size)
+        .map(|symbol| if self.is_terminal(symbol) { BTreeSet::from([symbol]) } else { BTreeSet::new() })
+        .collect(),
+      follow: vec![BTreeSet::new(); size],
+    };
+
+    let mut changed = true;
+    while changed {
+      changed = false;
+      for Production { lhs, rhs } in &self.productions {
+        let (first, nullable) = sets.first_of(rhs);
+        let old = (sets.first[*lhs].len(), sets.nullable[*lhs]);
+        sets.first[*lhs].extend(first);
+        sets.nullable[*lhs] |= nullable;
+        changed |= old != (sets.first[*lhs].len(), sets.nullable[*lhs]);
+      }
+    }
+
+    sets.follow[self.start].insert(self.terminals);
+    changed = true;
+    while changed {
+      changed = false;
+      for Production { lhs, rhs } in &self.productions {
+        for (index, symbol) in rhs.iter().enumerate().filter(|(_, symbol)| !self.is_terminal(**symbol)) {
+          let (mut follow, nullable) = sets.first_of(&rhs[index + 1..]);
+          if nullable {
+            follow.extend(sets.follow[*lhs].iter().copied());
+          }
+          let old = sets.follow[*symbol].len();
+          sets.follow[*symbol].extend(follow);
+          changed |= old != sets.follow[*symbol].len();
+        }
+      }
+    }
+    sets
+  }
+}
+
+impl Sets {
+  /// 符号串的 FIRST 集, 以及符号串能否推导出 ε
+  pub fn first_of(&self, symbols: &[usize]) -> (BTreeSet<usize>, bool) {
+    let mut first = BTreeSet::new();
+    for symbol in symbols {
+      first.extend(self.first[*symbol].iter().copied());
+      if !self.nullable[*symbol] {
+        return (first, false);
+      }
+    }
+    (first, true)
+  }
+}
+
+/// LR(1) 项目: 产生式, 圆点的位置与向前看符号
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Item {
+  pub production: usize,
+  pub dot: usize,
+  pub lookahead: usize,
+}
+
+/// 项目集, 前 kernel 个为核心项目
+#[derive(Debug, Clone)]
+pub struct State {
+  pub kernel: usize,
+  pub items: Vec<Item>,
+}
+
+/// 分析动作, 同一格中有多个动作时按此顺序排列
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Action {
+  Shift(usize),  // 移进并转到状态
+  Reduce(usize), // 按产生式归约
+  Accept,
+}
+
+impl Display for Action {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Action::Shift(state) => write!(f, "s{}", state),
+      Action::Reduce(production) => write!(f, "r{}", production),
+      Action::Accept => write!(f, "acc"),
+    }
+  }
+}
+
+/// 分析表中有多个动作的格
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+  pub state: usize,
+  pub terminal: usize,
+  pub actions: Vec<Action>,
+}
+
+impl Conflict {
+  /// 移进-归约冲突, 否则为归约-归约冲突
+  pub fn is_shift_reduce(&self) -> bool {
+    matches!(self.actions[0], Action::Shift(_))
+  }
+}
+
+/// 分析过程中的一步: 状态栈, 符号栈, 剩余输入的位置, 动作与 GOTO
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+  pub states: Vec<usize>,
+  pub symbols: Vec<usize>,
+  pub position: usize,
+  pub action: Option<Action>, // None 为出错
+  pub goto: Option<usize>,
+}
+
+/// 语法树, 叶子为输入中的第 token 个单词
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tree {
+  Leaf { symbol: usize, token: usize },
+  Node { production: usize, children: Vec<Tree> },
+}
+
+///
+/// LR(1) 分析器
+///
+/// 与 yacc 相同, 有冲突时仍然可以分析: 移进优先于归约, 归约-归约冲突选择编号小的产生式,
+/// 也可以通过 resolve 为每个冲突指定动作
+///
+pub struct Lr1 {
+  pub grammar: Grammar, // 拓广文法
+  pub sets: Sets,
+  pub states: Vec<State>,                        // 项目集规范族
+  pub transitions: Vec<BTreeMap<usize, usize>>,  // 状态转换: 符号 -> 状态
+  pub action: Vec<BTreeMap<usize, Vec<Action>>>, // ACTION 表: 终结符 -> 动作
+  pub goto: Vec<BTreeMap<usize, usize>>,         // GOTO 表: 非终结符 -> 状态
+  pub conflicts: Vec<Conflict>,
+}
+
+impl Lr1 {
+  pub fn new(grammar: &Grammar) -> Self {
+    let grammar = grammar.augment();
+    let sets = grammar.sets();
+    let mut lr1 =
+      Lr1 { grammar, sets, states: vec![], transitions: vec![], action: vec![], goto: vec![], conflicts: vec![] };
+    lr1.build_states();
+    lr1.build_table();
+    lr1
+  }
+
+  /// 是否为 LR(1) 文法
+  pub fn valid(&self) -> bool {
+    self.conflicts.is_empty()
+  }
+
+  /// 指定有冲突的格中使用的动作, 分析时使用格中的第一个动作
+  pub fn resolve(&mut self, conflict: &Conflict, action: Action) {
+    let actions = self.action[conflict.state].get_mut(&conflict.terminal).unwrap();
+    let index = actions.iter().position(|item| *item == action).unwrap();
+    actions[..=index].rotate_right(1);
+  }
+
+  /// 项目集闭包, 核心项目之后的项目排序
+  fn closure(&self, kernel: Vec<Item>, by_lhs: &[Vec<usize>]) -> State {
+    let (size, mut items) = (kernel.len(), kernel);
+    let mut seen = items.iter().copied().collect::<HashSet<_>>();
+    let mut index = 0;
+    while index < items.len() {
+      let Item { production, dot, lookahead } = items[index];
+      index += 1;
+      let rhs = &self.grammar.productions[production].rhs;
+      let Some(&next) = rhs.get(dot).filter(|symbol| !self.grammar.is_terminal(**symbol)) else { continue };
+      let (mut lookaheads, nullable) = self.sets.first_of(&rhs[dot + 1..]);
+      if nullable {
+        lookaheads.insert(lookahead);
+      }
+      for &production in &by_lhs[next] {
+        for &lookahead in &lookaheads {
+          let item = Item { production, dot: 0, lookahead };
+          if seen.insert(item) {
+            items.push(item);
+          }
+        }
+      }
+    }
+    items[size..].sort();
+    State { kernel: size, items }
+  }
+
+  /// 从 I0 开始按符号分组求出所有项目集, 核心项目相同的项目集为同一个
+  fn build_states(&mut self) {
+    let mut by_lhs = vec![vec![]; self.grammar.symbols.len()];
+    for (index, production) in self.grammar.productions.iter().enumerate() {
+      by_lhs[production.lhs].push(index);
+    }
+
+    let start = vec![Item { production: 0, dot: 0, lookahead: self.grammar.terminals }];
+    let mut index = HashMap::from([(start.clone(), 0)]);
+    self.states.push(self.closure(start, &by_lhs));
+    let mut from = 0;
+    while from < self.states.len() {
+      let mut groups = BTreeMap::<usize, Vec<Item>>::new();
+      for item in &self.states[from].items {
+        if let Some(&next) = self.grammar.productions[item.production].rhs.get(item.dot) {
+          groups.entry(next).or_default().push(Item { dot: item.dot + 1, ..*item });
+        }
+      }
+
+      let mut transitions = BTreeMap::new();
+      for (symbol, mut kernel) in groups {
+        kernel.sort();
+        let to = match index.get(&kernel) {
+          Some(to) => *to,
+          None => {
+            index.insert(kernel.clone(), self.states.len());
+            self.states.push(self.closure(kernel, &by_lhs));
+            self.states.len() - 1
+          }
+        };
+        transitions.insert(symbol, to);
+      }
+      self.transitions.push(transitions);
+      from += 1;
+    }
+  }
+
+  fn build_table(&mut self) {
+    for (index, state) in self.states.iter().enumerate() {
+      let mut action = BTreeMap::<usize, Vec<Action>>::new();
+      let mut goto = BTreeMap::new();
+      for (&symbol, &to) in &self.transitions[index] {
+        if self.grammar.is_terminal(symbol) {
+          action.entry(symbol).or_default().push(Action::Shift(to));
+        } else {
+          goto.insert(symbol, to);
+        }
+      }
+      for item in &state.items {
+        if item.dot == self.grammar.productions[item.production].rhs.len() {
+          let reduce = if item.production == 0 { Action::Accept } else { Action::Reduce(item.production) };
+          action.entry(item.lookahead).or_default().push(reduce);
+        }
+      }
+
+      for (&terminal, actions) in &mut action {
+        actions.sort();
+        actions.dedup();
+        if actions.len() > 1 {
+          self.conflicts.push(Conflict { state: index, terminal, actions: actions.clone() });
+        }
+      }
+      self.action.push(action);
+      self.goto.push(goto);
+    }
+  }
+
+  /// 将终结符的名字转换为编号
+  pub fn tokens(&self, names: &[&str]) -> Result<Vec<usize>, String> {
+    names
+      .iter()
+      .map(|name| {
+        self
+          .grammar
+          .symbol(name)
+          .filter(|symbol| *symbol < self.grammar.terminals)
+          .ok_or(format!("unknown terminal: {}", name))
+      })
+      .collect()
+  }
+
+  /// 分析终结符串, 出错时返回出错的单词位置 (结束符为 tokens.len())
+  pub fn parse(&self, tokens: &[usize]) -> Result<Tree, usize> {
+    self.run(tokens, None)
+  }
+
+  /// 分析终结符串, 同时记录每一步
+  pub fn trace(&self, tokens: &[usize]) -> (Vec<Step>, Result<Tree, usize>) {
+    let mut steps = vec![];
+    let result = self.run(tokens, Some(&mut steps));
+    (steps, result)
+  }
+
+  fn run(&self, tokens: &[usize], mut steps: Option<&mut Vec<Step>>) -> Result<Tree, usize> {
+    let (mut states, mut symbols, mut trees, mut position) = (vec![0], vec![], vec![], 0);
+    loop {
+      let lookahead = tokens.get(position).copied().unwrap_or(self.grammar.terminals);
+      let action = self.action[*states.last().unwrap()].get(&lookahead).map(|actions| actions[0]);
+      let mut step =
+        steps.as_ref().map(|_| Step { states: states.clone(), symbols: symbols.clone(), position, action, goto: None });
+
+      let result = match action {
+        None => Some(Err(position)),
+        Some(Action::Shift(to)) => {
+          states.push(to);
+          symbols.push(lookahead);
+          trees.push(Tree::Leaf { symbol: lookahead, token: position });
+          position += 1;
+          None
+        }
+        Some(Action::Reduce(production)) => {
+          let Production { lhs, rhs } = &self.grammar.productions[production];
+          states.truncate(states.len() - rhs.len());
+          symbols.truncate(symbols.len() - rhs.len());
+          let children = trees.split_off(trees.len() - rhs.len());
+          let to = self.goto[*states.last().unwrap()][lhs];
+          states.push(to);
+          symbols.push(*lhs);
+          trees.push(Tree::Node { production, children });
+          if let Some(step) = &mut step {
+            step.goto = Some(to);
+          }
+          None
+        }
+        Some(Action::Accept) => Some(Ok(trees.pop().unwrap())),
+      };
+
+      if let (Some(steps), Some(step)) = (&mut steps, step) {
+        steps.push(step);
+      }
+      if let Some(result) = result {
+        return result;
+      }
+    }
+  }
+
+  /// 项目的文本, 例如 E -> E · + T, #
+  pub fn item(&self, item: &Item) -> String {
+    let Production { lhs, rhs } = &self.grammar.productions[item.production];
+    let mut names = rhs.iter().map(|symbol| self.grammar.name(*symbol)).collect::<Vec<_>>();
+    names.insert(item.dot, "·");
+    format!("{} -> {}, {}", self.grammar.name(*lhs), names.join(" "), self.grammar.name(item.lookahead))
+  }
+
+  /// 冲突的文本, 例如 state 5, '+': shift/reduce conflict (s7, r1)
+  pub fn conflict(&self, conflict: &Conflict) -> String {
+    let kind = if conflict.is_shift_reduce() { "shift/reduce" } else { "reduce/reduce" };
+    let actions = conflict.actions.iter().map(Action::to_string).collect::<Vec<_>>();
+    format!(
+      "state {}, '{}': {} conflict ({})",
+      conflict.state,
+      self.grammar.name(conflict.terminal),
+      kind,
+      actions.join(", ")
+    )
+  }
+
+  /// 分析过程的表格: 步骤, 状态栈, 符号栈, 输入串, ACTION, GOTO
+  pub fn format_steps(&self, tokens: &[usize], steps: &[Step]) -> String {
+    let mut out = String::new();
+    for (index, step) in steps.iter().enumerate() {
+      let states = step.states.iter().map(usize::to_string).collect::<Vec<_>>();
+      let symbols = step.symbols.iter().map(|symbol| self.grammar.name(*symbol));
+      let input = tokens[step.position.min(tokens.len())..].iter().map(|symbol| self.grammar.name(*symbol));
+      let action = step.action.map_or("error".to_string(), |action| action.to_string());
+      let goto = step.goto.map_or(String::new(), |goto| goto.to_string());
+      writeln!(
+        out,
+        "{:<4} {:<16} {:<16} {:<16} {:<6} {}",
+        index + 1,
+        states.join(" "),
+        [END].into_iter().chain(symbols).collect::<Vec<_>>().join(" "),
+        input.chain([END]).collect::<Vec<_>>().join(" "),
+        action,
+        goto
+      )
+      .unwrap();
+    }
+    out
+  }
+
+  /// 语法树的 S 表达式, 例如 (F ( (E (T (F n))) ))
+  pub fn format_tree(&self, tree: &Tree) -> String {
+    match tree {
+      Tree::Leaf { symbol, .. } => self.grammar.name(*symbol).to_string(),
+      Tree::Node { production, children } => {
+        let lhs = self.grammar.name(self.grammar.productions[*production].lhs);
+        let children = children.iter().map(|child| format!(" {}", self.format_tree(child))).collect::<String>();
+        format!("({}{})", lhs, children)
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Action, Grammar, Lr1};
+
+  /// lr1/demo.typ 中的四则运算表达式文法
+  fn arithmetic() -> Grammar {
+    Grammar::new(
+      &["E", "T", "F"],
+      &["+", "-", "*", "/", "(", ")", "n"],
+      &[
+        ("E", "E + T"),
+        ("E", "E - T"),
+        ("E", "T"),
+        ("T", "T * F"),
+        ("T", "T / F"),
+        ("T", "F"),
+        ("F", "( E )"),
+        ("F", "n"),
+      ],
+      "E",
+    )
+    .unwrap()
+  }
+
+  fn names(lr1: &Lr1, set: &std::collections::BTreeSet<usize>) -> Vec<String> {
+    set.iter().map(|symbol| lr1.grammar.name(*symbol).to_string()).collect()
+  }
+
+  #[test]
+  fn test_sets() {
+    let lr1 = Lr1::new(&arithmetic());
+    let symbol = |name| lr1.grammar.symbol(name).unwrap();
+    for nonterminal in ["E", "T", "F"] {
+      assert_eq!(names(&lr1, &lr1.sets.first[symbol(nonterminal)]), ["(", "n"]);
+      assert!(!lr1.sets.nullable[symbol(nonterminal)]);
+    }
+    assert_eq!(names(&lr1, &lr1.sets.follow[symbol("E")]), ["+", "-", ")", "#"]);
+    assert_eq!(names(&lr1, &lr1.sets.follow[symbol("T")]), ["+", "-", "*", "/", ")", "#"]);
+    assert_eq!(names(&lr1, &lr1.sets.follow[symbol("F")]), ["+", "-", "*", "/", ")", "#"]);
+
+    let grammar = Grammar::new(&["S", "A"], &["a", "b"], &[("S", "A b"), ("A", "a A"), ("A", "")], "S").unwrap();
+    let sets = grammar.sets();
+    assert!(sets.nullable[grammar.symbol("A").unwrap()]);
+    assert_eq!(sets.first_of(&[grammar.symbol("A").unwrap()]), ([0].into(), true));
+    assert_eq!(sets.first[grammar.symbol("S").unwrap()], [0, 1].into());
+  }
+
+  #[test]
+  fn test_table() {
+    let lr1 = Lr1::new(&arithmetic());
+    assert!(lr1.valid());
+    assert_eq!(lr1.grammar.production(0), "E' -> E");
+    assert_eq!(lr1.grammar.production(7), "F -> ( E )");
+    // 内外两层括号中的项目集向前看符号不同, 不能合并
+    assert_eq!(lr1.states.len(), 30);
+    assert_eq!(lr1.item(&lr1.states[0].items[0]), "E' -> · E, #");
+
+    let (symbol, state) =
+      (|name| lr1.grammar.symbol(name).unwrap(), lr1.transitions[0][&lr1.grammar.symbol("E").unwrap()]);
+    assert_eq!(lr1.goto[0][&symbol("E")], state);
+    assert_eq!(lr1.action[state][&symbol("#")], [Action::Accept]);
+    assert!(matches!(lr1.action[state][&symbol("+")][..], [Action::Shift(_)]));
+    assert!(!lr1.action[state].contains_key(&symbol(")")));
+
+    // 经典的 LR(1) 文法 (非 LALR(1)) 也是 22 个项目集
+    let grammar = Grammar::new(
+      &["E", "T", "F"],
+      &["+", "*", "(", ")", "n"],
+      &[("E", "E + T"), ("E", "T"), ("T", "T * F"), ("T", "F"), ("F", "( E )"), ("F", "n")],
+      "E",
+    )
+    .unwrap();
+    assert_eq!(Lr1::new(&grammar).states.len(), 22);
+  }
+
+  #[test]
+  fn test_conflicts() {
+    let grammar = Grammar::new(&["E"], &["+", "n"], &[("E", "E + E"), ("E", "n")], "E").unwrap();
+    let lr1 = Lr1::new(&grammar);
+    assert!(!lr1.valid());
+    assert!(lr1.conflicts.iter().all(|conflict| conflict.is_shift_reduce()));
+    assert_eq!(lr1.conflict(&lr1.conflicts[0]), "state 4, '+': shift/reduce conflict (s3, r1)");
+    // 移进优先: 右结合
+    let tokens = lr1.tokens(&["n", "+", "n", "+", "n"]).unwrap();
+    assert_eq!(lr1.format_tree(&lr1.parse(&tokens).unwrap()), "(E (E n) + (E (E n) + (E n)))");
+
+    let grammar =
+      Grammar::new(&["S", "A", "B"], &["x"], &[("S", "A"), ("S", "B"), ("A", "x"), ("B", "x")], "S").unwrap();
+    let lr1 = Lr1::new(&grammar);
+    assert_eq!(lr1.conflicts.len(), 1);
+    assert!(!lr1.conflicts[0].is_shift_reduce());
+    assert_eq!(lr1.conflicts[0].actions, [Action::Reduce(3), Action::Reduce(4)]);
+
+    assert!(Grammar::new(&["S"], &["x"], &[("S", "y")], "S").is_err());
+    assert!(Grammar::new(&["S"], &["S"], &[], "S").is_err());
+    assert!(Grammar::new(&["S"], &["x"], &[("x", "S")], "S").is_err());
+  }
+
+  #[test]
+  fn test_parse() {
+    let lr1 = Lr1::new(&arithmetic());
+    let tokens = lr1.tokens(&["(", "n", "+", "n", ")", "*", "n", "-", "n", "/", "n"]).unwrap();
+    let (steps, tree) = lr1.trace(&tokens);
+    let tree = tree.unwrap();
+    assert_eq!(
+      lr1.format_tree(&tree),
+      "(E (E (T (T (F ( (E (E (T (F n))) + (T (F n))) ))) * (F n))) - (T (T (F n)) / (F n)))"
+    );
+    assert_eq!(steps.len(), 11 + 16 + 1);
+    assert_eq!(steps.last().unwrap().action, Some(Action::Accept));
+    let trace = lr1.format_steps(&tokens, &steps);
+    assert!(trace.starts_with("1    0                #                ( n + n ) * n - n / n # s"), "{}", trace);
+
+    let tokens = lr1.tokens(&["n", "+", "*", "n"]).unwrap();
+    let (steps, result) = lr1.trace(&tokens);
+    assert_eq!(result, Err(2));
+    assert_eq!(steps.last().unwrap().action, None);
+    assert_eq!(lr1.parse(&lr1.tokens(&["n", "n"]).unwrap()), Err(1));
+    assert_eq!(lr1.parse(&lr1.tokens(&["(", "n"]).unwrap()), Err(2));
+    assert!(lr1.tokens(&["x"]).is_err());
+  }
+}
@@ -0,0 +1,356 @@
+use crate::{
+  ast::{Expression, ExpressionKind, Identifier, Program, Statement, StatementKind},
+  compiler::{
+    nametab::{NameTable, NameTableKind},
+    Compiler,
+  },
+  parser::Paser,
+  vm::builtins::Builtins,
+  SpanOffset,
+};
+
+/// 符号的种类
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+  Constant,
+  Variable,
+  Parameter,
+  Function,
+  Builtin,
+}
+
+/// 源代码中定义的符号以及内建函数
+#[derive(Debug, Clone)]
+pub struct Symbol {
+  pub name: String,
+  pub kind: SymbolKind,
+  pub pos: Option<SpanOffset>, // 定义处标识符的位置, 内建函数没有位置
+  pub scope: SpanOffset,       // 可见的范围: 从定义处到所在作用域结束
+  pub value: isize,            // 常量的值
+  pub params: Vec<String>,     // 函数的参数
+}
+
+impl Symbol {
+  /// 悬停时显示的说明
+  pub fn describe(&self) -> String {
+    match self.kind {
+      SymbolKind::Constant => format!("const {} = {}", self.name, self.value),
+      SymbolKind::Variable => format!("var {}", self.name),
+      SymbolKind::Parameter => format!("param {}", self.name),
+      SymbolKind::Function => {
+        format!("fn {}({})\n\n参数个数: {}", self.name, self.params.join(", "), self.params.len())
+      }
+      SymbolKind::Builtin => format!("builtin fn {}(...)\n\n内建函数, 参数个数不限", self.name),
+    }
+  }
+}
+
+///
+/// 源代码的语义信息
+///
+/// 按照与编译器相同的顺序遍历语法树, 通过符号表 (NameTable) 将每个标识符解析到其定义
+pub struct Analysis {
+  pub diagnostics: Vec<(String, SpanOffset)>, // 语法解析与编译错误
+  pub parsed: bool,                           // 是否解析成功, 失败时没有符号信息
+  pub symbols: Vec<Symbol>,
+  pub references: Vec<(SpanOffset, usize)>, // 标识符的位置与其引用的符号, 包括定义处
+}
+
+impl Analysis {
+  pub fn new(input: &str) -> Self {
+    let builtins = Builtins::new();
+    let mut analysis = Analysis { diagnostics: vec![], parsed: false, symbols: vec![], references: vec![] };
+
+    for name in builtins.names() {
+      analysis.symbols.push(Symbol {
+        name: name.to_string(),
+        kind: SymbolKind::Builtin,
+        pos: None,
+        scope: (0, usize::MAX).into(),
+        value: 0,
+        params: vec![],
+      });
+    }
+
+    let program = match Paser::paser(input) {
+      Ok(program) => program,
+      Err(err) => {
+        analysis.diagnostics.push(err);
+        return analysis;
+      }
+    };
+    analysis.parsed = true;
+
+    if let Err(errors) = Compiler::compile(&program) {
+      analysis.diagnostics.extend(errors);
+    }
+
+    let mut resolver =
+      Resolver { analysis, builtins, nametable: NameTable::new(), ids: vec![None], scope_end: usize::MAX };
+    resolver.program(&program);
+    resolver.analysis
+  }
+
+  /// 位置上的标识符所引用的符号
+  pub fn symbol_at(&self, offset: usize) -> Option<&Symbol> {
+    self.reference_at(offset).map(|(_, id)| &self.symbols[id])
+  }
+
+  fn reference_at(&self, offset: usize) -> Option<(SpanOffset, usize)> {
+    self.references.iter().copied().find(|(pos, _)| pos.begin <= offset && offset <= pos.end)
+  }
+
+  /// 定义处的位置
+  pub fn definition(&self, offset: usize) -> Option<SpanOffset> {
+    self.symbol_at(offset).and_then(|symbol| symbol.pos)
+  }
+
+  /// 引用了同一个符号的所有位置, include_declaration 为假时不包括定义处
+  pub fn references(&self, offset: usize, include_declaration: bool) -> Vec<SpanOffset> {
+    let Some((_, id)) = self.reference_at(offset) else {
+      return vec![];
+    };
+    let declaration = self.symbols[id].pos.map(|pos| pos.begin);
+
+    self
+      .references
+      .iter()
+      .filter(|(pos, other)| *other == id && (include_declaration || Some(pos.begin) != declaration))
+      .map(|(pos, _)| *pos)
+      .collect()
+  }
+
+  /// 位置上可见的符号, 同名的符号只保留最内层的
+  pub fn completions(&self, offset: usize) -> Vec<&Symbol> {
+    let mut symbols: Vec<&Symbol> = vec![];
+    for symbol in &self.symbols {
+      if symbol.scope.begin <= offset && offset <= symbol.scope.end {
+        symbols.retain(|other| other.name != symbol.name);
+        symbols.push(symbol);
+      }
+    }
+    symbols
+  }
+}
+
+/// 名字解析, 与编译器的符号表操作保持一致
+struct Resolver {
+  analysis: Analysis,
+  builtins: Builtins,
+  nametable: NameTable,
+  ids: Vec<Option<usize>>, // 符号表每一项对应的符号
+  scope_end: usize,        // 当前作用域结束的位置
+}
+
+impl Resolver {
+  fn program(&mut self, program: &Program) {
+    self.statements(&program.statements, 0);
+  }
+
+  fn statements(&mut self, statements: &[Statement], level: usize) {
+    for statement in statements {
+      self.statement(statement, level);
+    }
+  }
+
+  /// 语句块, 结束后回退符号表
+  fn block(&mut self, statements: &[Statement], level: usize, end: usize) {
+    let (tx0, scope_end) = (self.nametable.tx(), self.scope_end);
+    self.scope_end = end;
+    self.statements(statements, level + 1);
+    self.scope_end = scope_end;
+    self.nametable.rollback(self.nametable.tx() - tx0);
+  }
+
+  fn statement(&mut self, statement: &Statement, level: usize) {
+    match &statement.kind {
+      StatementKind::Empty => {}
+      StatementKind::Const(constants) => {
+        for (ident, e) in constants {
+          if let ExpressionKind::Integer(value) = e.kind {
+            self.nametable.add_const(&ident.name, level, value);
+            self.define(ident, SymbolKind::Constant, ident.pos.end, value, vec![]);
+          }
+        }
+      }
+      StatementKind::Variable(variables) => {
+        for (ident, e) in variables {
+          self.expression(e, level);
+          self.nametable.add_variable(&ident.name, level, 0);
+          self.define(ident, SymbolKind::Variable, e.pos.end.max(ident.pos.end), 0, vec![]);
+        }
+      }
+      StatementKind::Function(ident, args, statements) => {
+        self.function(ident, args, statements, level, statement.pos.end)
+      }
+      StatementKind::Assign(ident, e) => {
+        self.expression(e, level);
+        if self.nametable.find_kind(&ident.name, NameTableKind::Variable).is_some() {
+          self.reference(ident);
+        }
+      }
+      StatementKind::Return(e) => {
+        if let Some(e) = e {
+          self.expression(e, level);
+        }
+      }
+      StatementKind::Expression(e) => self.expression(e, level),
+    }
+  }
+
+  /// 函数定义, 函数名在函数体内与定义之后可见, 参数只在函数体内可见
+  fn function(&mut self, ident: &Identifier, args: &[Identifier], statements: &[Statement], level: usize, end: usize) {
+    if self.builtins.lookup(&ident.name).is_some() {
+      return;
+    }
+
+    self.nametable.add_proceduce(&ident.name, level, args.len());
+    let params = args.iter().map(|arg| arg.name.clone()).collect();
+    self.define(ident, SymbolKind::Function, ident.pos.begin, 0, params);
+
+    let (tx0, scope_end) = (self.nametable.tx(), self.scope_end);
+    self.scope_end = end;
+    for arg in args {
+      self.nametable.add_variable(&arg.name, level + 1, 0);
+      self.define(arg, SymbolKind::Parameter, arg.pos.end, 0, vec![]);
+    }
+    self.statements(statements, level + 1);
+
+    self.scope_end = scope_end;
+    self.nametable.rollback(self.nametable.tx() - tx0);
+  }
+
+  fn expression(&mut self, expression: &Expression, level: usize) {
+    match &expression.kind {
+      ExpressionKind::Identifier(name) => {
+        self.reference(&Identifier { pos: expression.pos, name: name.clone() });
+      }
+      ExpressionKind::Integer(_) => {}
+      ExpressionKind::Infix(_, left, right) => {
+        self.expression(left, level);
+        self.expression(right, level);
+      }
+      ExpressionKind::Prefix(_, e) => self.expression(e, level),
+      ExpressionKind::Call(callee, args) => {
+        args.iter().for_each(|e| self.expression(e, level));
+
+        // 内建函数优先于同名的符号
+        match &callee.kind {
+          ExpressionKind::Identifier(name) if self.builtins.lookup(name).is_some() => {
+            let id = self
+              .analysis
+              .symbols
+              .iter()
+              .position(|symbol| symbol.kind == SymbolKind::Builtin && symbol.name == *name);
+            self.analysis.references.extend(id.map(|id| (callee.pos, id)));
+          }
+          _ => self.expression(callee, level),
+        }
+      }
+      ExpressionKind::Function(ident, args, statements) => {
+        self.function(ident, args, statements, level, expression.pos.end)
+      }
+      ExpressionKind::If(condition, then_s, else_s) => {
+        self.expression(condition, level);
+
+        // 语法树中没有大括号的位置, then 语句块到 else 语句块的第一条语句为止
+        let else_begin = else_s.as_ref().and_then(|s| s.first()).map(|s| s.pos.begin);
+        self.block(then_s, level, else_begin.unwrap_or(expression.pos.end));
+        if let Some(else_s) = else_s {
+          self.block(else_s, level, expression.pos.end);
+        }
+      }
+      ExpressionKind::While(condition, s) => {
+        self.expression(condition, level);
+        self.block(s, level, expression.pos.end);
+      }
+    }
+  }
+
+  /// 记录刚加入符号表的符号, 从 begin 开始可见
+  fn define(&mut self, ident: &Identifier, kind: SymbolKind, begin: usize, value: isize, params: Vec<String>) {
+    let id = self.analysis.symbols.len();
+    self.analysis.symbols.push(Symbol {
+      name: ident.name.clone(),
+      kind,
+      pos: Some(ident.pos),
+      scope: (begin, self.scope_end).into(),
+      value,
+      params,
+    });
+    self.analysis.references.push((ident.pos, id));
+
+    let tx = self.nametable.tx();
+    self.ids.resize(self.ids.len().max(tx + 1), None);
+    self.ids[tx] = Some(id);
+  }
+
+  /// 通过符号表解析标识符, 未定义的标识符已经由编译器报告
+  fn reference(&mut self, ident: &Identifier) {
+    if let Some(id) = self.nametable.position(&ident.name).and_then(|index| self.ids[index]) {
+      self.analysis.references.push((ident.pos, id));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Analysis, SymbolKind};
+
+  /// 第 n 次出现的 pattern 的位置 (字符偏移)
+  fn offset(input: &str, pattern: &str, n: usize) -> usize {
+    let (index, _) = input.match_indices(pattern).nth(n).unwrap();
+    input[..index].chars().count()
+  }
+
+  #[test]
+  fn test_resolve() {
+    let input = "var x = 1; fn f(x) { x + 1 } fn g() { var x = x; x } f(x) + g()";
+    let analysis = Analysis::new(input);
+    assert!(analysis.diagnostics.is_empty());
+
+    // 参数 x 遮蔽全局变量 x
+    assert_eq!(analysis.definition(offset(input, "x", 2)).unwrap().begin, offset(input, "x", 1));
+    // 局部变量的初始值引用外层的 x
+    assert_eq!(analysis.definition(offset(input, "x", 4)).unwrap().begin, offset(input, "x", 0));
+    assert_eq!(analysis.definition(offset(input, "x", 5)).unwrap().begin, offset(input, "x", 3));
+    assert_eq!(analysis.definition(offset(input, "x", 6)).unwrap().begin, offset(input, "x", 0));
+
+    let references = analysis.references(offset(input, "x", 0), true);
+    assert_eq!(references.iter().map(|pos| pos.begin).collect::<Vec<_>>(), [0, 4, 6].map(|n| offset(input, "x", n)));
+    assert_eq!(analysis.references(offset(input, "f", 1), false).len(), 1);
+
+    let symbol = analysis.symbol_at(offset(input, "f(x)", 0)).unwrap();
+    assert_eq!((symbol.kind, symbol.describe()), (SymbolKind::Function, "fn f(x)\n\n参数个数: 1".to_string()));
+    assert_eq!(analysis.symbol_at(offset(input, "x", 2)).unwrap().kind, SymbolKind::Parameter);
+  }
+
+  #[test]
+  fn test_completions() {
+    let input = "const c = 1; fn f(a) { if a { var b = 2; b } } var v;";
+    let analysis = Analysis::new(input);
+    let names = |offset| {
+      let mut names = analysis.completions(offset).iter().map(|symbol| symbol.name.clone()).collect::<Vec<_>>();
+      names.sort();
+      names
+    };
+
+    assert_eq!(names(offset(input, "b }", 0)), ["a", "b", "c", "f", "helloworld", "print", "println"]);
+    assert_eq!(names(input.len()), ["c", "f", "helloworld", "print", "println", "v"]);
+  }
+
+  #[test]
+  fn test_diagnostics() {
+    let analysis = Analysis::new("var x = 1; y = 2; const c = 1; c = 2");
+    assert_eq!(analysis.diagnostics.len(), 2);
+    assert!(analysis.parsed);
+
+    let analysis = Analysis::new("var = 1");
+    assert_eq!(analysis.diagnostics.len(), 1);
+    assert!(!analysis.parsed);
+
+    // 调用内建函数
+    let analysis = Analysis::new("println(1)");
+    assert_eq!(analysis.symbol_at(0).unwrap().kind, SymbolKind::Builtin);
+  }
+}
@@ -0,0 +1,489 @@
+//!
+//! 语言服务器 (Language Server Protocol)
+//!
+//! 通过标准输入输出以 JSON-RPC 通信, 提供诊断, 跳转到定义, 查找引用, 悬停提示, 补全与格式化
+//!
+
+use std::{
+  collections::HashMap,
+  io::{self, BufRead, Write},
+};
+
+use serde_json::{json, Value};
+
+use crate::{formatter, SpanOffset};
+
+use self::analysis::{Analysis, SymbolKind};
+
+pub mod analysis;
+
+/// 打开的文档
+struct Document {
+  text: String,
+  lines: LineIndex,
+  analysis: Analysis,
+  last_parsed: Option<Analysis>, // 最近一次解析成功的结果, 文档暂时有语法错误时用于补全
+}
+
+impl Document {
+  fn new(text: String, last_parsed: Option<Analysis>) -> Self {
+    let lines = LineIndex::new(&text);
+    let analysis = Analysis::new(&text);
+    Document { text, lines, analysis, last_parsed }
+  }
+
+  /// 用于补全的语义信息
+  fn completion_analysis(&self) -> &Analysis {
+    match &self.last_parsed {
+      Some(analysis) if !self.analysis.parsed => analysis,
+      _ => &self.analysis,
+    }
+  }
+}
+
+///
+/// 行号索引
+///
+/// 源代码中的位置是字符偏移, LSP 中的位置是行号与 UTF-16 列号
+struct LineIndex {
+  chars: Vec<char>,
+  lines: Vec<usize>, // 每一行开头的字符偏移
+}
+
+impl LineIndex {
+  fn new(text: &str) -> Self {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut lines = vec![0];
+    lines.extend(chars.iter().enumerate().filter(|(_, &c)| c == '\n').map(|(index, _)| index + 1));
+    LineIndex { chars, lines }
+  }
+
+  fn position(&self, offset: usize) -> Value {
+    let offset = offset.min(self.chars.len());
+    let line = self.lines.partition_point(|&begin| begin <= offset) - 1;
+    let character = self.chars[self.lines[line]..offset].iter().map(|c| c.len_utf16()).sum::<usize>();
+    json!({ "line": line, "character": character })
+  }
+
+  fn offset(&self, position: &Value) -> usize {
+    let line = position["line"].as_u64().unwrap_or(0) as usize;
+    let character = position["character"].as_u64().unwrap_or(0) as usize;
+    let Some(&begin) = self.lines.get(line) else {
+      return self.chars.len();
+    };
+
+    let mut offset = begin;
+    let mut column = 0;
+    while offset < self.chars.len() && self.chars[offset] != '\n' && column < character {
+      column += self.chars[offset].len_utf16();
+      offset += 1;
+    }
+    offset
+  }
+
+  fn range(&self, pos: SpanOffset) -> Value {
+    json!({ "start": self.position(pos.begin), "end": self.position(pos.end) })
+  }
+}
+
+/// 语言服务器
+pub struct Server<W: Write> {
+  output: W,
+  documents: HashMap<String, Document>,
+  shutdown: bool,
+}
+
+impl<W: Write> Server<W> {
+  pub fn new(output: W) -> Self {
+    Server { output, documents: HashMap::new(), shutdown: false }
+  }
+
+  ///
+  /// 处理输入中的所有消息, 直到收到 exit 通知或者输入结束
+  ///
+  /// 返回进程的退出码: 先收到 shutdown 请求时为 0, 否则为 1
+  pub fn run(&mut self, mut input: impl BufRead) -> io::Result<i32> {
+    while let Some(message) = read_message(&mut input)? {
+      let message = match message {
+        Ok(message) => message,
+        // 无法解析的消息没有 id, 回复错误之后继续处理之后的消息
+        Err(err) => {
+          let error = json!({ "code": -32700, "message": format!("parse error: {}", err) });
+          write_message(&mut self.output, &json!({ "jsonrpc": "2.0", "id": null, "error": error }))?;
+          continue;
+        }
+      };
+      if message["method"] == "exit" {
+        return Ok(if self.shutdown { 0 } else { 1 });
+      }
+      self.handle(&message)?;
+    }
+    Ok(1)
+  }
+
+  fn handle(&mut self, message: &Value) -> io::Result<()> {
+    let params = &message["params"];
+    let method = message["method"].as_str().unwrap_or("");
+
+    // 通知没有 id, 不需要回复
+    let Some(id) = message.get("id") else {
+      return self.notification(method, params);
+    };
+
+    let result = match method {
+      "initialize" => Ok(json!({
+        "capabilities": {
+          "textDocumentSync": 1,
+          "definitionProvider": true,
+          "referencesProvider": true,
+          "hoverProvider": true,
+          "completionProvider": {},
+          "documentFormattingProvider": true,
+        },
+        "serverInfo": { "name": "pl0-lsp" },
+      })),
+      "shutdown" => {
+        self.shutdown = true;
+        Ok(Value::Null)
+      }
+      "textDocument/definition" => Ok(self.definition(params)),
+      "textDocument/references" => Ok(self.references(params)),
+      "textDocument/hover" => Ok(self.hover(params)),
+      "textDocument/completion" => Ok(self.completion(params)),
+      "textDocument/formatting" => Ok(self.formatting(params)),
+      _ => Err(json!({ "code": -32601, "message": format!("method not found: {}", method) })),
+    };
+
+    let response = match result {
+      Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+      Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+    };
+    write_message(&mut self.output, &response)
+  }
+
+  fn notification(&mut self, method: &str, params: &Value) -> io::Result<()> {
+    let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+
+    match method {
+      "textDocument/didOpen" => {
+        let text = params["textDocument"]["text"].as_str().unwrap_or("").to_string();
+        self.open(uri, text)
+      }
+      // 只支持全量同步, 最后一次修改即为完整的文档
+      "textDocument/didChange" => match params["contentChanges"].as_array().and_then(|changes| changes.last()) {
+        Some(change) => self.open(uri, change["text"].as_str().unwrap_or("").to_string()),
+        None => Ok(()),
+      },
+      "textDocument/didClose" => {
+        self.documents.remove(&uri);
+        self.publish(&uri, json!([]))
+      }
+      _ => Ok(()),
+    }
+  }
+
+  /// 打开或者更新文档, 并发布诊断
+  fn open(&mut self, uri: String, text: String) -> io::Result<()> {
+    let last_parsed = self.documents.remove(&uri).and_then(|document| match document.analysis.parsed {
+      true => Some(document.analysis),
+      false => document.last_parsed,
+    });
+    let document = Document::new(text, last_parsed);
+
+    let diagnostics = document
+      .analysis
+      .diagnostics
+      .iter()
+      .map(|(message, pos)| {
+        json!({ "range": document.lines.range(*pos), "severity": 1, "source": "pl0", "message": message })
+      })
+      .collect();
+    self.documents.insert(uri.clone(), document);
+    self.publish(&uri, Value::Array(diagnostics))
+  }
+
+  fn publish(&mut self, uri: &str, diagnostics: Value) -> io::Result<()> {
+    let notification = json!({
+      "jsonrpc": "2.0",
+      "method": "textDocument/publishDiagnostics",
+      "params": { "uri": uri, "diagnostics": diagnostics },
+    });
+    write_message(&mut self.output, &notification)
+  }
+
+  /// 请求中的文档与位置
+  fn document<'p>(&self, params: &'p Value) -> Option<(&'p str, &Document, usize)> {
+    let uri = params["textDocument"]["uri"].as_str()?;
+    let document = self.documents.get(uri)?;
+    let offset = document.lines.offset(&params["position"]);
+    Some((uri, document, offset))
+  }
+
+  fn definition(&self, params: &Value) -> Value {
+    let Some((uri, document, offset)) = self.document(params) else {
+      return Value::Null;
+    };
+    match document.analysis.definition(offset) {
+      Some(pos) => json!({ "uri": uri, "range": document.lines.range(pos) }),
+      None => Value::Null,
+    }
+  }
+
+  fn references(&self, params: &Value) -> Value {
+    let Some((uri, document, offset)) = self.document(params) else {
+      return Value::Null;
+    };
+    let include_declaration = params["context"]["includeDeclaration"].as_bool().unwrap_or(true);
+    let references = document.analysis.references(offset, include_declaration);
+    references.into_iter().map(|pos| json!({ "uri": uri, "range": document.lines.range(pos) })).collect()
+  }
+
+  fn hover(&self, params: &Value) -> Value {
+    let Some((_, document, offset)) = self.document(params) else {
+      return Value::Null;
+    };
+    match document.analysis.symbol_at(offset) {
+      Some(symbol) => json!({ "contents": { "kind": "plaintext", "value": symbol.describe() } }),
+      None => Value::Null,
+    }
+  }
+
+  fn completion(&self, params: &Value) -> Value {
+    let Some((_, document, offset)) = self.document(params) else {
+      return Value::Null;
+    };
+
+    let items = document.completion_analysis().completions(offset).into_iter().map(|symbol| {
+      // CompletionItemKind: Function = 3, Variable = 6, Constant = 21
+      let kind = match symbol.kind {
+        SymbolKind::Constant => 21,
+        SymbolKind::Variable | SymbolKind::Parameter => 6,
+        SymbolKind::Function | SymbolKind::Builtin => 3,
+      };
+      json!({ "label": symbol.name, "kind": kind, "detail": symbol.describe().lines().next() })
+    });
+    Value::Array(items.collect())
+  }
+
+  /// 整个文档替换为格式化后的结果, 有语法错误时不修改
+  fn formatting(&self, params: &Value) -> Value {
+    let Some(document) = params["textDocument"]["uri"].as_str().and_then(|uri| self.documents.get(uri)) else {
+      return Value::Null;
+    };
+    match formatter::format(&document.text, &formatter::Options::default()) {
+      Ok(text) if text != document.text => {
+        let range = document.lines.range((0, document.lines.chars.len()).into());
+        json!([{ "range": range, "newText": text }])
+      }
+      _ => json!([]),
+    }
+  }
+}
+
+///
+/// 读取一条消息, 输入结束时返回 None
+///
+/// 消息缺少 Content-Length 或者内容不是合法的 JSON 时返回 Some(Err(..)), 此时这条消息已经读完, 可以继续读取之后的消息
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Result<Value, String>>> {
+  let mut length = None;
+  loop {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+      return Ok(None);
+    }
+
+    let line = line.trim_end();
+    if line.is_empty() {
+      break;
+    }
+    if let Some((name, value)) = line.split_once(':') {
+      if name.eq_ignore_ascii_case("Content-Length") {
+        length = value.trim().parse::<usize>().ok();
+      }
+    }
+  }
+
+  let Some(length) = length else {
+    return Ok(Some(Err("missing Content-Length".to_string())));
+  };
+  let mut content = vec![0; length];
+  input.read_exact(&mut content)?;
+  Ok(Some(serde_json::from_slice(&content).map_err(|err| err.to_string())))
+}
+
+/// 写入一条消息
+pub fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+  let content = message.to_string();
+  write!(output, "Content-Length: {}\r\n\r\n{}", content.len(), content)?;
+  output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+
+  use serde_json::{json, Value};
+
+  use super::{read_message, write_message, Server};
+
+  const URI: &str = "file:///test.pl0";
+
+  /// 模拟客户端: 依次发送请求, 返回服务器的所有输出消息
+  fn session(requests: &[Value]) -> (i32, Vec<Value>) {
+    let mut input = vec![];
+    for request in requests {
+      write_message(&mut input, request).unwrap();
+    }
+    run(input)
+  }
+
+  /// 服务器处理原始的输入
+  fn run(input: Vec<u8>) -> (i32, Vec<Value>) {
+    let mut output = vec![];
+    let code = Server::new(&mut output).run(Cursor::new(input)).unwrap();
+
+    let mut messages = vec![];
+    let mut output = Cursor::new(output);
+    while let Some(message) = read_message(&mut output).unwrap() {
+      messages.push(message.unwrap());
+    }
+    (code, messages)
+  }
+
+  fn request(id: usize, method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+  }
+
+  fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+  }
+
+  fn position(id: usize, method: &str, line: usize, character: usize) -> Value {
+    let params = json!({
+      "textDocument": { "uri": URI },
+      "position": { "line": line, "character": character },
+      "context": { "includeDeclaration": true },
+    });
+    request(id, method, params)
+  }
+
+  fn response(messages: &[Value], id: usize) -> &Value {
+    &messages.iter().find(|message| message["id"] == id).unwrap()["result"]
+  }
+
+  fn range(line: usize, begin: usize, end: usize) -> Value {
+    json!({ "start": { "line": line, "character": begin }, "end": { "line": line, "character": end } })
+  }
+
+  #[test]
+  fn test_session() {
+    let text = "// 斐波那契\nfn fib(n) { if n <= 1 { 1 } else { fib(n - 1) + fib(n - 2) } }\nfib(10)";
+    let (code, messages) = session(&[
+      request(1, "initialize", json!({ "capabilities": {} })),
+      notification("initialized", json!({})),
+      notification("textDocument/didOpen", json!({ "textDocument": { "uri": URI, "text": text } })),
+      position(2, "textDocument/definition", 2, 1),
+      position(3, "textDocument/references", 0, 0),
+      position(4, "textDocument/references", 1, 4),
+      position(5, "textDocument/hover", 1, 16),
+      position(6, "textDocument/completion", 2, 0),
+      request(
+        7,
+        "textDocument/formatting",
+        json!({ "textDocument": { "uri": URI }, "options": { "tabSize": 2, "insertSpaces": true } }),
+      ),
+      request(8, "unknown", json!({})),
+      request(9, "shutdown", Value::Null),
+      notification("exit", Value::Null),
+    ]);
+    assert_eq!(code, 0);
+
+    assert_eq!(response(&messages, 1)["capabilities"]["hoverProvider"], true);
+    assert_eq!(messages[1]["params"]["diagnostics"], json!([]));
+
+    assert_eq!(response(&messages, 2), &json!({ "uri": URI, "range": range(1, 3, 6) }));
+    assert_eq!(response(&messages, 3), &json!([]));
+    let references = response(&messages, 4).as_array().unwrap().iter().map(|r| r["range"].clone()).collect::<Vec<_>>();
+    assert_eq!(references, [range(1, 3, 6), range(1, 35, 38), range(1, 48, 51), range(2, 0, 3)]);
+
+    assert_eq!(response(&messages, 5)["contents"]["value"], "param n");
+    let labels =
+      response(&messages, 6).as_array().unwrap().iter().map(|item| item["label"].clone()).collect::<Vec<_>>();
+    assert_eq!(labels, ["helloworld", "print", "println", "fib"]);
+
+    let edits = response(&messages, 7).as_array().unwrap();
+    assert_eq!(edits[0]["range"]["end"], json!({ "line": 2, "character": 7 }));
+    assert!(edits[0]["newText"].as_str().unwrap().ends_with("}\nfib(10);\n"));
+
+    assert_eq!(messages.iter().find(|message| message["id"] == 8).unwrap()["error"]["code"], -32601);
+  }
+
+  #[test]
+  fn test_diagnostics() {
+    let (code, messages) = session(&[
+      notification("textDocument/didOpen", json!({ "textDocument": { "uri": URI, "text": "var a = 1;\nb = a" } })),
+      notification(
+        "textDocument/didChange",
+        json!({ "textDocument": { "uri": URI }, "contentChanges": [{ "text": "var a = 1;\na = (" }] }),
+      ),
+      // 文档有语法错误时, 补全使用上一次解析成功的结果
+      position(1, "textDocument/completion", 1, 0),
+      notification("exit", Value::Null),
+    ]);
+    assert_eq!(code, 1);
+
+    let diagnostics = &messages[0]["params"]["diagnostics"];
+    assert_eq!(diagnostics[0]["range"], range(1, 0, 5));
+    assert_eq!(diagnostics[0]["message"], "variable is undefined: b");
+
+    let diagnostics = messages[1]["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["range"]["start"]["line"], 1);
+
+    let labels =
+      response(&messages, 1).as_array().unwrap().iter().map(|item| item["label"].clone()).collect::<Vec<_>>();
+    assert!(labels.contains(&json!("a")));
+  }
+
+  #[test]
+  fn test_lexical_error() {
+    // 不识别的字符与过大的整数作为诊断信息报告, 服务器继续运行
+    let (code, messages) = session(&[
+      notification("textDocument/didOpen", json!({ "textDocument": { "uri": URI, "text": "var x = 1; x @" } })),
+      notification(
+        "textDocument/didChange",
+        json!({ "textDocument": { "uri": URI }, "contentChanges": [{ "text": "var x = 99999999999999999999999;" }] }),
+      ),
+      request(1, "shutdown", Value::Null),
+      notification("exit", Value::Null),
+    ]);
+    assert_eq!(code, 0);
+    assert_eq!(messages.len(), 3);
+
+    let diagnostics = &messages[0]["params"]["diagnostics"];
+    assert_eq!(diagnostics[0]["range"], range(0, 13, 14));
+    assert_eq!(diagnostics[0]["message"], "illegal character '@'");
+    let diagnostics = &messages[1]["params"]["diagnostics"];
+    assert_eq!(diagnostics[0]["range"], range(0, 8, 31));
+    assert_eq!(diagnostics[0]["message"], "number too large: 99999999999999999999999");
+  }
+
+  #[test]
+  fn test_parse_error() {
+    // 内容不是合法 JSON 的消息与缺少 Content-Length 的消息, 服务器回复错误之后继续处理之后的消息
+    let mut input = b"Content-Length: 9\r\n\r\n{\"id\": 1,".to_vec();
+    input.extend_from_slice(b"Content-Type: application/json\r\n\r\n");
+    write_message(&mut input, &request(1, "shutdown", Value::Null)).unwrap();
+    write_message(&mut input, &notification("exit", Value::Null)).unwrap();
+
+    let (code, messages) = run(input);
+    assert_eq!(code, 0);
+    assert_eq!(messages.len(), 3);
+    for message in &messages[..2] {
+      assert_eq!(message["id"], Value::Null);
+      assert_eq!(message["error"]["code"], -32700);
+    }
+    assert_eq!(messages[2]["id"], 1);
+    assert_eq!(messages[2]["result"], Value::Null);
+  }
+}
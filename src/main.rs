@@ -1,31 +1,519 @@
 use std::{
+  env, fs,
   fs::File,
-  io::{Error, Read},
+  io::{self, BufRead, Error, Read, Write},
+  ops::Range,
+  path::{Path, PathBuf},
+  process,
+  str::FromStr,
 };
 
 use ariadne::{Label, Report, ReportKind, Source};
-use pl0::{compiler::Compiler, parser::Paser, vm::VM, SpanOffset};
+use pl0::{
+  ast::{export, Program},
+  backend::{c, pcode, wat, x86_64},
+  classic,
+  compiler::{debuginfo::ScopeKind, Compiler},
+  formatter,
+  grammar::{self, Format},
+  parser::Paser,
+  vm::{
+    debugger::{Debugger, Stop},
+    profile::Profiler,
+    trace::{JsonTracer, Trace, Tracer},
+    Limits, VM,
+  },
+  SpanOffset,
+};
 
 /// 打印错误
 fn print_errors(title: &str, errors: &[(String, SpanOffset)], input: &str) {
+  report(title, errors, input).print(Source::from(&input)).unwrap();
+}
+
+/// 将错误打印到标准错误, 用于标准输出是程序结果的场合 (例如 pl0 fmt 从标准输入读取时)
+fn eprint_errors(title: &str, errors: &[(String, SpanOffset)], input: &str) {
+  report(title, errors, input).eprint(Source::from(&input)).unwrap();
+}
+
+/// 导入的 JSON 中的位置指向原来的源文件, 超出 input 的部分截断到 input 的末尾
+fn report(title: &str, errors: &[(String, SpanOffset)], input: &str) -> Report<'static, Range<usize>> {
+  let len = input.chars().count();
   Report::build(ReportKind::Error, (), 0)
-    .with_labels(
-      errors.iter().map(|(err, pos)| Label::new(pos.begin..pos.end).with_message(err).with_color(ariadne::Color::Red)),
-    )
+    .with_labels(errors.iter().map(|(err, pos)| {
+      Label::new(pos.begin.min(len)..pos.end.min(len)).with_message(err).with_color(ariadne::Color::Red)
+    }))
     .with_message(title)
     .finish()
-    .print(Source::from(&input))
-    .unwrap();
+}
+
+/// 语法解析, 指定 --classic 或者扩展名为 .p0 时按经典 PL/0 解析, 扩展名为 .json 时导入 pl0 ast --format json 的输出
+fn parse(input: &str, path: &str, classic: bool) -> Result<Program, (String, SpanOffset)> {
+  if classic || classic::is_classic(Path::new(path)) {
+    classic::parse(input)
+  } else if Path::new(path).extension().is_some_and(|extension| extension == "json") {
+    let value = serde_json::from_str(input).map_err(|err| (err.to_string(), SpanOffset::from((0, 0))))?;
+    export::from_json(&value).map_err(|err| (err, (0, 0).into()))
+  } else {
+    Paser::paser(input)
+  }
 }
 
 fn main() -> Result<(), Error> {
-  let input = "examples/fib.pl0";
+  let args = env::args().skip(1).collect::<Vec<_>>();
+  match args.first().map(String::as_str) {
+    Some("fmt") => fmt(&args[1..]),
+    Some("debug") if args.len() == 2 => debug(&args[1]),
+    Some("debug") => usage(),
+    Some("run") => run_command(&args[1..]),
+    Some("build") => build(&args[1..]),
+    Some("pcode") if args.len() == 2 => run_pcode(&args[1]),
+    Some("pcode") => usage(),
+    Some("grammar") => grammar(&args[1..]),
+    Some("ast") => ast(&args[1..]),
+    Some(path) => run(path, &RunOptions::default()),
+    None => run("examples/fib.pl0", &RunOptions::default()),
+  }
+}
+
+/// pl0 fmt [--check] [--no-semicolon] [--width N] [files...]
+///
+/// 格式化源文件并写回, 没有给出文件时从标准输入读取并输出到标准输出.
+/// --check 只检查文件是否已经格式化, 存在未格式化的文件时以状态码 1 退出
+fn fmt(args: &[String]) -> Result<(), Error> {
+  let mut options = formatter::Options::default();
+  let mut check = false;
+  let mut files = vec![];
+
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--check" => check = true,
+      "--no-semicolon" => options.semicolon = false,
+      "--width" => match args.next().and_then(|width| width.parse().ok()) {
+        Some(width) => options.width = width,
+        None => usage(),
+      },
+      _ if arg.starts_with("--") => usage(),
+      _ => files.push(arg.clone()),
+    }
+  }
+
+  if files.is_empty() {
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input)?;
+    match formatter::format(&input, &options) {
+      Ok(output) if check => process::exit(if output == input { 0 } else { 1 }),
+      Ok(output) => io::stdout().write_all(output.as_bytes())?,
+      Err(err) => {
+        eprint_errors("语法解析错误", &[err], &input);
+        process::exit(2);
+      }
+    }
+    return Ok(());
+  }
+
+  let mut changed = false;
+  let mut failed = false;
+  for file in &files {
+    let input = fs::read_to_string(file)?;
+    let output = match formatter::format(&input, &options) {
+      Ok(output) => output,
+      Err(err) => {
+        eprint_errors(&format!("语法解析错误: {}", file), &[err], &input);
+        failed = true;
+        continue;
+      }
+    };
+
+    if output != input {
+      changed = true;
+      if check {
+        println!("{}", file);
+      } else {
+        fs::write(file, output)?;
+      }
+    }
+  }
+
+  if failed {
+    process::exit(2);
+  }
+  if check && changed {
+    process::exit(1);
+  }
+  Ok(())
+}
+
+const USAGE: &str = "\
+用法: pl0 [file]
+      pl0 run [--classic] [--trace <out.jsonl>] [--trace-fn <name>] [--profile]
+              [--max-instructions N] [--max-stack N] [--max-depth N] [--max-heap N] <file>
+      pl0 fmt [--check] [--no-semicolon] [--width N] [files...]
+      pl0 debug <file>
+      pl0 build [--classic] [--target x86_64|c|wat|pcode] [-S] [-o <output>] <file>
+      pl0 pcode <file>
+      pl0 ast [--classic] [--format sexpr|json|dot] <file>
+      pl0 grammar [--format text|markdown|typst] [--eliminate-left-recursion] [--left-factor] <file>
+
+扩展名为 .p0 的文件或者指定 --classic 时按经典 PL/0 (Wirth) 的语法解析,
+扩展名为 .json 的文件为 pl0 ast --format json 导出的抽象语法树";
+
+fn usage() -> ! {
+  eprintln!("{}", USAGE);
+  process::exit(2);
+}
+
+/// pl0 build [--classic] [--target x86_64|c|wat|pcode] [-S] [-o <output>] <file>
+///
+/// 编译为本地可执行文件, 默认输出到源文件去掉扩展名的位置. -S 只输出汇编代码.
+/// --target c 经过 C 代码编译, -S 只输出 C 代码; --target wat 输出 WebAssembly 文本格式的模块;
+/// --target pcode 输出经典 PL/0 的 P-code
+fn build(args: &[String]) -> Result<(), Error> {
+  let (mut target, mut asm, mut output, mut file, mut classic) = ("x86_64", false, None, None, false);
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--target" => match args.next().map(String::as_str) {
+        Some(name @ ("x86_64" | "c" | "wat" | "pcode")) => target = name,
+        _ => usage(),
+      },
+      "-S" => asm = true,
+      "--classic" => classic = true,
+      "-o" => output = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+      _ if arg.starts_with('-') || file.is_some() => usage(),
+      _ => file = Some(arg.clone()),
+    }
+  }
+  let file = file.unwrap_or_else(|| usage());
+  let extension = match target {
+    "wat" => "wat",
+    "pcode" => "pcode",
+    "c" if asm => "c",
+    _ if asm => "s",
+    _ => "",
+  };
+  let output = output.unwrap_or_else(|| Path::new(&file).with_extension(extension));
+
+  let input = fs::read_to_string(&file)?;
+  let program = match parse(&input, &file, classic) {
+    Ok(program) => program,
+    Err(err) => {
+      print_errors("语法解析错误", &[err], &input);
+      process::exit(2);
+    }
+  };
+  if target == "pcode" {
+    return match pcode::compile(&program) {
+      Ok(codes) => fs::write(output, pcode::listing(&codes)),
+      Err(errors) => {
+        print_errors("编译错误", &errors, &input);
+        process::exit(2);
+      }
+    };
+  }
+  if target != "x86_64" {
+    let result = if target == "c" { c::compile(&program) } else { wat::compile(&program) };
+    return match result {
+      Ok(text) if target == "c" && !asm => c::build(&text, &output),
+      Ok(text) => fs::write(output, text),
+      Err(errors) => {
+        print_errors("编译错误", &errors, &input);
+        process::exit(2);
+      }
+    };
+  }
+  let codes = match Compiler::compile(&program) {
+    Ok(codes) => codes,
+    Err(errors) => {
+      print_errors("编译错误", &errors, &input);
+      process::exit(2);
+    }
+  };
+
+  if asm {
+    fs::write(output, x86_64::compile(&codes))
+  } else {
+    x86_64::build(&codes, &output)
+  }
+}
+
+/// pl0 pcode <file>
+///
+/// 用经典 PL/0 的解释程序执行 P-code 文件 (每行一条 f l a 指令)
+fn run_pcode(path: &str) -> Result<(), Error> {
+  let codes = match pcode::parse(&fs::read_to_string(path)?) {
+    Ok(codes) => codes,
+    Err(err) => {
+      eprintln!("{}: {}", path, err);
+      process::exit(2);
+    }
+  };
+  if let Err((err, ip)) = pcode::interp::run(&codes, &mut io::stdin().lock(), &mut io::stdout()) {
+    println!("运行时错误: {} ({})", err, ip);
+  }
+  Ok(())
+}
+
+/// pl0 ast [--classic] [--format sexpr|json|dot] <file>
+///
+/// 输出抽象语法树: S 表达式, 带有节点位置的 JSON 或者 Graphviz DOT
+fn ast(args: &[String]) -> Result<(), Error> {
+  let (mut format, mut classic, mut file) = ("sexpr", false, None);
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--format" => match args.next().map(String::as_str) {
+        Some(name @ ("sexpr" | "json" | "dot")) => format = name,
+        _ => usage(),
+      },
+      "--classic" => classic = true,
+      _ if arg.starts_with('-') || file.is_some() => usage(),
+      _ => file = Some(arg.clone()),
+    }
+  }
+  let file = file.unwrap_or_else(|| usage());
+
+  let input = fs::read_to_string(&file)?;
+  let program = match parse(&input, &file, classic) {
+    Ok(program) => program,
+    Err(err) => {
+      print_errors("语法解析错误", &[err], &input);
+      process::exit(2);
+    }
+  };
+  match format {
+    "json" => println!("{}", serde_json::to_string_pretty(&export::to_json(&program))?),
+    "dot" => print!("{}", export::to_dot(&program)),
+    _ => println!("{}", export::to_sexpr(&program)),
+  }
+  Ok(())
+}
+
+/// pl0 grammar [--format text|markdown|typst] [--eliminate-left-recursion] [--left-factor] <file>
+///
+/// 读入文法文件, 输出 nullable / FIRST / FOLLOW 集, 左递归与 LL(1) 预测分析表.
+/// 先按选项消除左递归, 再提取左公因子, 输出的是变换后的文法
+fn grammar(args: &[String]) -> Result<(), Error> {
+  let (mut format, mut eliminate, mut factor, mut file) = (Format::Text, false, false, None);
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--format" => format = args.next().and_then(|format| format.parse().ok()).unwrap_or_else(|| usage()),
+      "--eliminate-left-recursion" => eliminate = true,
+      "--left-factor" => factor = true,
+      _ if arg.starts_with('-') || file.is_some() => usage(),
+      _ => file = Some(arg.clone()),
+    }
+  }
+  let file = file.unwrap_or_else(|| usage());
+
+  let mut grammar = match grammar::parse(&fs::read_to_string(&file)?) {
+    Ok(grammar) => grammar,
+    Err(err) => {
+      eprintln!("{}: {}", file, err);
+      process::exit(2);
+    }
+  };
+  if eliminate {
+    grammar = grammar::eliminate_left_recursion(&grammar);
+  }
+  if factor {
+    grammar = grammar::left_factor(&grammar);
+  }
+  print!("{}", grammar::report(&grammar, format));
+  Ok(())
+}
+
+const DEBUG_HELP: &str = "\
+b <line> | b *<addr>   在行或者指令地址上设置断点
+d <line> | d *<addr>   删除断点
+c                      继续执行
+s                      单步执行, 进入函数
+n                      单步执行, 不进入函数
+finish                 执行到当前函数返回
+si                     执行一条指令
+bt                     调用栈
+locals                 当前作用域的变量
+q                      退出";
+
+/// pl0 debug <file>
+///
+/// 交互式调试器, 从标准输入读取命令
+fn debug(path: &str) -> Result<(), Error> {
+  let input = fs::read_to_string(path)?;
+  let program = match parse(&input, path, false) {
+    Ok(program) => program,
+    Err(err) => {
+      print_errors("语法解析错误", &[err], &input);
+      process::exit(2);
+    }
+  };
+  let (codes, debug) = match Compiler::compile_with_debug(&program) {
+    Ok(result) => result,
+    Err(errors) => {
+      print_errors("编译错误", &errors, &input);
+      process::exit(2);
+    }
+  };
+
+  let lines = input.lines().collect::<Vec<_>>();
+  let mut debugger = Debugger::new(codes, debug, &input);
+  let mut out = io::stdout();
+  let show = |debugger: &Debugger| {
+    let ip = debugger.ip();
+    match debugger.line_of(ip) {
+      Some(line) => println!("{:04X}H {:>4} | {}", ip, line, lines.get(line - 1).unwrap_or(&"")),
+      None => println!("{:04X}H", ip),
+    }
+  };
+  let name = |kind: &ScopeKind| match kind {
+    ScopeKind::Program => "<main>".to_string(),
+    ScopeKind::Function(name) => format!("fn {}", name),
+    ScopeKind::Block => "{block}".to_string(),
+  };
 
-  let mut file = File::open(input)?;
+  println!("输入 help 查看命令");
+  show(&debugger);
+  for line in io::stdin().lock().lines() {
+    let line = line?;
+    let words = line.split_whitespace().collect::<Vec<_>>();
+
+    let stop = match words.as_slice() {
+      [] => continue,
+      ["help" | "h"] => {
+        println!("{}", DEBUG_HELP);
+        continue;
+      }
+      ["q" | "quit"] => break,
+      ["b" | "break", target] => {
+        match parse_target(target) {
+          Some(Target::Addr(addr)) if debugger.set_breakpoint(addr) => println!("断点 {:04X}H", addr),
+          Some(Target::Line(line)) => match debugger.set_line_breakpoint(line) {
+            Some((line, addresses)) => println!("断点 第 {} 行 ({} 处)", line, addresses.len()),
+            None => println!("第 {} 行之后没有语句", line),
+          },
+          _ => println!("无效的断点: {}", target),
+        }
+        continue;
+      }
+      ["d" | "delete", target] => {
+        let addresses = match parse_target(target) {
+          Some(Target::Addr(addr)) => vec![addr],
+          Some(Target::Line(line)) => debugger.line_addresses(line),
+          None => vec![],
+        };
+        let count = addresses.into_iter().filter(|addr| debugger.remove_breakpoint(*addr)).count();
+        println!("删除了 {} 个断点", count);
+        continue;
+      }
+      ["bt" | "backtrace"] => {
+        for (index, frame) in debugger.frames().iter().enumerate() {
+          let line = frame.line.map(|line| format!("第 {} 行", line)).unwrap_or_default();
+          println!("#{} {} {:04X}H {}", index, name(&frame.kind), frame.ip, line);
+        }
+        continue;
+      }
+      ["locals"] => {
+        // 当前函数内的所有语句块以及函数本身的变量
+        for frame in debugger.frames() {
+          for (name, value) in &frame.variables {
+            println!("{} = {}", name, value);
+          }
+          if !matches!(frame.kind, ScopeKind::Block) {
+            break;
+          }
+        }
+        continue;
+      }
+      ["c" | "continue"] => debugger.cont(&mut out),
+      ["s" | "step"] => debugger.step_into(&mut out),
+      ["n" | "next"] => debugger.step_over(&mut out),
+      ["finish"] => debugger.step_out(&mut out),
+      ["si"] => debugger.step_instruction(&mut out),
+      _ => {
+        println!("未知的命令: {}, 输入 help 查看命令", line);
+        continue;
+      }
+    };
+
+    match stop {
+      Stop::Step => show(&debugger),
+      Stop::Breakpoint(_) => {
+        print!("断点: ");
+        show(&debugger);
+      }
+      Stop::Exited(result) => println!("程序结束, 返回值 {}", result),
+      Stop::Error(err, ip) => println!("运行时错误: {} ({:04X}H)", err, ip),
+    }
+  }
+
+  Ok(())
+}
+
+/// 断点的位置
+enum Target {
+  Line(usize),
+  Addr(usize),
+}
+
+/// 行号, 或者以 * 开头的十六进制指令地址
+fn parse_target(target: &str) -> Option<Target> {
+  match target.strip_prefix('*') {
+    Some(addr) => usize::from_str_radix(addr.trim_end_matches(['h', 'H']), 16).ok().map(Target::Addr),
+    None => target.parse().ok().filter(|line| *line > 0).map(Target::Line),
+  }
+}
+
+/// 运行选项
+#[derive(Default)]
+struct RunOptions {
+  trace: Option<String>,    // 执行记录 (JSON lines) 的输出文件
+  trace_fn: Option<String>, // 只记录该函数中的指令
+  profile: bool,            // 输出性能分析报告
+  limits: Limits,           // 虚拟机的资源限制
+  classic: bool,            // 按经典 PL/0 的语法解析
+}
+
+/// pl0 run [--classic] [--trace <out.jsonl>] [--trace-fn <name>] [--profile] [--max-instructions N] [--max-stack N] [--max-depth N] [--max-heap N] <file>
+fn run_command(args: &[String]) -> Result<(), Error> {
+  let mut options = RunOptions::default();
+  let mut file = None;
+
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--trace" => options.trace = Some(args.next().cloned().unwrap_or_else(|| usage())),
+      "--trace-fn" => options.trace_fn = Some(args.next().cloned().unwrap_or_else(|| usage())),
+      "--profile" => options.profile = true,
+      "--classic" => options.classic = true,
+      "--max-instructions" => options.limits.instructions = Some(number(args.next())),
+      "--max-stack" => options.limits.stack = Some(number(args.next())),
+      "--max-depth" => options.limits.depth = Some(number(args.next())),
+      "--max-heap" => options.limits.heap = Some(number(args.next())),
+      _ if arg.starts_with("--") || file.is_some() => usage(),
+      _ => file = Some(arg.clone()),
+    }
+  }
+
+  match file {
+    Some(file) => run(&file, &options),
+    None => usage(),
+  }
+}
+
+/// 解析命令行参数中的数字
+fn number<T: FromStr>(arg: Option<&String>) -> T {
+  arg.and_then(|arg| arg.parse().ok()).unwrap_or_else(|| usage())
+}
+
+/// 编译并运行源文件, 输出抽象语法树与目标代码
+fn run(path: &str, options: &RunOptions) -> Result<(), Error> {
+  let mut file = File::open(path)?;
   let mut input = String::new();
   file.read_to_string(&mut input)?;
 
-  let program = match Paser::paser(&input) {
+  let program = match parse(&input, path, options.classic) {
     Ok(program) => program,
     Err(err) => {
       print_errors("语法解析错误", &[err], &input);
@@ -35,9 +523,9 @@ fn main() -> Result<(), Error> {
   };
 
   println!("{:^-20}", "抽象语法树");
-  println!("{:?}", &program);
+  println!("{}", export::to_sexpr(&program));
 
-  let codes = match Compiler::compile(&program) {
+  let (codes, debug) = match Compiler::compile_with_debug(&program) {
     Ok(program) => program,
     Err(errors) => {
       print_errors("编译错误", &errors, &input);
@@ -49,8 +537,46 @@ fn main() -> Result<(), Error> {
   println!("{:^-20}", "目标代码");
   VM::print_codes(&codes);
 
+  let mut tracer = match &options.trace {
+    Some(path) => Some(JsonTracer::new(io::BufWriter::new(File::create(path)?))),
+    None => None,
+  };
+  if let (Some(tracer), Some(name)) = (&mut tracer, &options.trace_fn) {
+    match debug.function_range(name) {
+      Some(range) => tracer.set_range(range),
+      None => {
+        eprintln!("未定义的函数: {}", name);
+        process::exit(2);
+      }
+    }
+  }
+  let mut profiler = options.profile.then(|| Profiler::new(&codes, &debug.functions));
+
   println!("{:^-20}", "程序执行结果");
-  VM::execute(&codes);
+  let mut vm = VM::with_limits(options.limits);
+  let result = if tracer.is_none() && profiler.is_none() {
+    vm.run(&codes, &mut io::stdout())
+  } else {
+    vm.run_with_tracer(&codes, &mut io::stdout(), &mut |trace: &Trace| {
+      if let Some(tracer) = &mut tracer {
+        tracer.trace(trace);
+      }
+      if let Some(profiler) = &mut profiler {
+        profiler.trace(trace);
+      }
+    })
+  };
+  if let Err((err, ip)) = result {
+    println!("运行时错误: {} ({:04X}H)", err, ip);
+  }
+
+  if let Some(tracer) = tracer {
+    tracer.finish()?;
+  }
+  if let Some(profiler) = profiler {
+    println!("{:^-20}", "性能分析");
+    profiler.report(&codes, 10, &mut io::stdout())?;
+  }
 
   Ok(())
 }
@@ -1,9 +1,10 @@
 use std::{mem, result};
 
 use crate::{
-  ast::{AstNode, Expression, ExpressionKind, Identifier, Infix, Prefix, Program, Statement, StatementKind},
+  ast::{Expression, ExpressionKind, Identifier, Infix, Prefix, Program, Statement, StatementKind},
+  cst::{Builder, SyntaxKind, SyntaxNode, SyntaxToken},
   lexer::Lexer,
-  token::Token,
+  token::{Token, Trivia},
   SpanOffset,
 };
 
@@ -18,25 +19,52 @@ pub struct Paser<'a> {
 
   current_token: (Token, SpanOffset),
   next_token: (Token, SpanOffset),
+
+  // 当前与下一个 token 的前导空白, 注释以及原文
+  current_trivia: (Vec<Trivia>, &'a str),
+  next_trivia: (Vec<Trivia>, &'a str),
+
+  cst: Option<Builder>, // 需要具体语法树时, 解析的同时构造
 }
 
 impl<'a> Paser<'a> {
   pub fn new(input: &'a str) -> Self {
     let mut lexer = Lexer::new(input);
 
-    let current_token = lexer.next();
-    let next_token = lexer.next();
+    let (leading, token, text, pos) = lexer.next_with_trivia();
+    let (current_token, current_trivia) = ((token, pos), (leading, text));
+    let (leading, token, text, pos) = lexer.next_with_trivia();
+    let (next_token, next_trivia) = ((token, pos), (leading, text));
 
-    Paser { lexer, prev_pos: (0, 0).into(), current_token, next_token }
+    Paser { lexer, prev_pos: (0, 0).into(), current_token, next_token, current_trivia, next_trivia, cst: None }
   }
 
   pub fn paser(input: &'a str) -> Result<Program> {
+    Paser::new(input).paser_program()
+  }
+
+  /// 解析源代码, 同时得到抽象语法树与具体语法树
+  pub fn paser_cst(input: &'a str) -> Result<(Program, SyntaxNode)> {
     let mut paser = Paser::new(input);
+    paser.cst = Some(Builder::new());
+
+    paser.start_node(SyntaxKind::Program);
+    let program = paser.paser_program()?;
+
+    // 文件末尾的空白与注释挂在 EOF 上
+    let (leading, _) = mem::take(&mut paser.current_trivia);
+    let mut cst = paser.cst.take().unwrap();
+    cst.token(SyntaxToken { leading, token: Token::EOF, text: String::new(), pos: paser.current_token.1 });
+    cst.finish_node();
+
+    Ok((program, cst.finish()))
+  }
 
+  fn paser_program(&mut self) -> Result<Program> {
     let mut statements = vec![];
 
-    while paser.current_token.0 != Token::EOF {
-      statements.push(paser.paser_statement()?);
+    while self.current_token.0 != Token::EOF {
+      statements.push(self.paser_statement()?);
     }
     Ok(Program { statements })
   }
@@ -45,6 +73,23 @@ impl<'a> Paser<'a> {
   fn paser_statement(&mut self) -> Result<Statement> {
     let pos = self.current_token.1;
 
+    self.start_node(match self.current_token.0 {
+      Token::Const => SyntaxKind::ConstStatement,
+      Token::Var => SyntaxKind::VarStatement,
+      Token::Function => SyntaxKind::FunctionStatement,
+      Token::Ident(_)
+        if matches!(
+          self.next_token.0,
+          Token::Assign | Token::AddAssign | Token::SubAssign | Token::MulAssign | Token::DivAssign
+        ) =>
+      {
+        SyntaxKind::AssignStatement
+      }
+      Token::Semicolon => SyntaxKind::EmptyStatement,
+      Token::Return => SyntaxKind::ReturnStatement,
+      _ => SyntaxKind::ExpressionStatement,
+    });
+
     let kind = match self.current_token.0 {
       Token::Const => self.paser_const()?,
       Token::Var => self.paser_variable()?,
@@ -91,12 +136,15 @@ impl<'a> Paser<'a> {
         }
       }
 
-      Token::Semicolon => StatementKind::Empty,
+      Token::Semicolon => {
+        self.next_token();
+        StatementKind::Empty
+      }
       Token::Return => {
         self.next_token();
 
         StatementKind::Return(match self.current_token.0 {
-          Token::Semicolon => None,
+          Token::Semicolon | Token::Rbrace | Token::EOF => None,
           _ => Some(self.paser_expression(Precedence::Lowest)?),
         })
       }
@@ -109,6 +157,7 @@ impl<'a> Paser<'a> {
     while let Token::Semicolon = self.current_token.0 {
       self.next_token();
     }
+    self.finish_node();
 
     Ok(Statement { pos: SpanOffset { begin: pos.begin, end }, kind })
   }
@@ -119,19 +168,24 @@ impl<'a> Paser<'a> {
     let mut constants = vec![];
 
     loop {
-      let (token, pos) = self.next_token();
+      self.start_node(SyntaxKind::Declarator);
+      let (token, ident_pos) = self.next_token();
       if let Token::Ident(ident) = token {
-        self.expect(Token::Assign);
+        self.expect(Token::Assign)?;
 
         let (token, pos) = self.next_token();
         if let Token::Integer(value) = token {
-          constants.push((Identifier { pos, name: ident }, Expression { pos, kind: ExpressionKind::Integer(value) }))
+          constants.push((
+            Identifier { pos: ident_pos, name: ident },
+            Expression { pos, kind: ExpressionKind::Integer(value) },
+          ))
         } else {
-          return Err((format!("only integer can assign to constant, but get {}", token), pos))?;
+          Err((format!("only integer can assign to constant, but get {}", token), pos))?;
         }
       } else {
-        return Err((format!("const define need identifier, but get {}", token), pos));
+        return Err((format!("const define need identifier, but get {}", token), ident_pos));
       }
+      self.finish_node();
 
       if Token::Comma == self.current_token.0 {
         self.next_token();
@@ -149,6 +203,7 @@ impl<'a> Paser<'a> {
     let mut variables = vec![];
 
     loop {
+      self.start_node(SyntaxKind::Declarator);
       let (token, pos) = self.next_token();
       if let Token::Ident(ident) = token {
         variables.push((
@@ -156,13 +211,14 @@ impl<'a> Paser<'a> {
           if matches!(self.current_token.0, Token::Comma | Token::Semicolon) {
             Expression { pos, kind: ExpressionKind::Integer(0) }
           } else {
-            self.expect(Token::Assign);
+            self.expect(Token::Assign)?;
             self.paser_expression(Precedence::Lowest)?
           },
         ));
       } else {
         return Err((format!("nead identifier, but get {}", token), pos));
       }
+      self.finish_node();
 
       if Token::Comma == self.current_token.0 {
         self.next_token();
@@ -179,17 +235,27 @@ impl<'a> Paser<'a> {
   ///
   fn paser_function(&mut self) -> Result<StatementKind> {
     self.next_token();
+    let (name, args, statements) = self.paser_function_define()?;
+
+    Ok(StatementKind::Function(name, args, statements))
+  }
+
+  ///
+  /// 解析 fn 关键字之后的函数定义: 函数名, 参数列表以及函数体
+  ///
+  fn paser_function_define(&mut self) -> Result<(Identifier, Vec<Identifier>, Vec<Statement>)> {
     let (token, pos) = self.next_token();
 
     if let Token::Ident(name) = token {
-      self.expect(Token::Lparen);
+      self.start_node(SyntaxKind::ParamList);
+      self.expect(Token::Lparen)?;
 
       let mut args = vec![];
-      if let (Token::Ident(_), ident_pos) = self.current_token {
+      if let Token::Ident(_) = self.current_token.0 {
         loop {
           let (token, pos) = self.next_token();
           if let Token::Ident(name) = token {
-            args.push(Identifier { pos: ident_pos, name });
+            args.push(Identifier { pos, name });
           } else {
             return Err((format!("function argument declare expect identifier, but get {}", token), pos));
           }
@@ -202,15 +268,27 @@ impl<'a> Paser<'a> {
         }
       }
 
-      self.expect(Token::Rparen);
-      self.expect(Token::Lbrace);
+      self.expect(Token::Rparen)?;
+      self.finish_node();
 
-      Ok(StatementKind::Function(Identifier { pos, name }, args, self.parse_block_statement()?))
+      Ok((Identifier { pos, name }, args, self.paser_block()?))
     } else {
       Err((format!("expect function identifier, but get {}", token), pos))
     }
   }
 
+  ///
+  /// 解析带有大括号的语句块
+  ///
+  fn paser_block(&mut self) -> Result<Vec<Statement>> {
+    self.start_node(SyntaxKind::Block);
+    self.expect(Token::Lbrace)?;
+    let statements = self.parse_block_statement()?;
+    self.finish_node();
+
+    Ok(statements)
+  }
+
   ///
   /// 解析语句块
   ///
@@ -220,7 +298,7 @@ impl<'a> Paser<'a> {
       statements.push(self.paser_statement()?);
     }
 
-    self.next_token();
+    self.expect(Token::Rbrace)?;
     Ok(statements)
   }
 
@@ -228,50 +306,63 @@ impl<'a> Paser<'a> {
   /// 解析表达式
   ///
   fn paser_expression(&mut self, precedence: Precedence) -> Result<Expression> {
+    let checkpoint = self.checkpoint();
     let (token, pos) = self.next_token();
 
-    let kind = match token {
-      Token::Integer(integer) => ExpressionKind::Integer(integer),
-      Token::Bang => ExpressionKind::Prefix(Prefix::Not, Box::new(self.paser_expression(Precedence::Prefix)?)),
-      Token::Minus => ExpressionKind::Prefix(Prefix::Neg, Box::new(self.paser_expression(Precedence::Prefix)?)),
-      Token::Lparen => self.paser_group_expression(Token::Rparen)?,
+    let (kind, syntax_kind) = match token {
+      Token::Integer(integer) => (ExpressionKind::Integer(integer), SyntaxKind::Literal),
+      Token::Bang => (
+        ExpressionKind::Prefix(Prefix::Not, Box::new(self.paser_expression(Precedence::Prefix)?)),
+        SyntaxKind::PrefixExpression,
+      ),
+      Token::Minus => (
+        ExpressionKind::Prefix(Prefix::Neg, Box::new(self.paser_expression(Precedence::Prefix)?)),
+        SyntaxKind::PrefixExpression,
+      ),
+      Token::Lparen => (self.paser_group_expression(Token::Rparen)?, SyntaxKind::ParenExpression),
 
       // Token::Lbracket => Expression::Array(self.paser_expressions(Token::Rbracket)),
       // Token::Lbrace => todo!("hash"),
-      Token::If => self.paser_if()?,
-      Token::While => self.paser_while()?,
-      Token::Ident(ref ident) => ExpressionKind::Identifier(ident.to_string()),
+      Token::If => (self.paser_if()?, SyntaxKind::IfExpression),
+      Token::While => (self.paser_while()?, SyntaxKind::WhileExpression),
+      Token::Function => {
+        let (name, args, statements) = self.paser_function_define()?;
+        (ExpressionKind::Function(name, args, statements), SyntaxKind::FunctionExpression)
+      }
+      Token::Ident(ref ident) => (ExpressionKind::Identifier(ident.to_string()), SyntaxKind::Name),
 
+      // 词法错误
+      x @ (Token::Illegal(_) | Token::TooLarge(_)) => return Err((x.to_string(), pos)),
       x => {
         return Err((format!("current position for this expression get unexpected token: {}", x), pos));
       }
     };
+    self.wrap_node(checkpoint, syntax_kind);
 
     let mut expression = Expression { pos: SpanOffset { begin: pos.begin, end: self.prev_pos.end }, kind };
 
     // 如果下一个运算符优先级
     while self.current_token.0 != Token::Semicolon && precedence < self.token_precedence(&self.current_token.0) {
-      let (token, pos) = self.next_token();
+      let args_checkpoint = self.checkpoint();
+      let (token, _) = self.next_token();
+      let begin = expression.pos.begin;
 
-      let kind = if let (p, Some(infix)) = self.infix_token(&token) {
+      let kind = if let (p, Some(infix)) = Self::infix_token(&token) {
         // 中缀表达式
-        ExpressionKind::Infix(infix, Box::new(expression), Box::new(self.paser_expression(p)?))
+        let kind = ExpressionKind::Infix(infix, Box::new(expression), Box::new(self.paser_expression(p)?));
+        self.wrap_node(checkpoint, SyntaxKind::InfixExpression);
+        kind
       } else if token == Token::Lparen {
-        // 函数调用表达式
-
-        if let ExpressionKind::Identifier(ident) = &expression.kind {
-          ExpressionKind::Call(
-            Identifier { pos: expression.pos, name: ident.to_string() },
-            self.paser_expressions(Token::Rparen)?,
-          )
-        } else {
-          return Err((format!("only support call function ident, but get {}", expression.unparse()), pos));
-        }
+        // 函数调用表达式: 任意表达式的值都可以作为函数调用
+        let kind = ExpressionKind::Call(Box::new(expression), self.paser_expressions(Token::Rparen)?);
+        self.wrap_node(args_checkpoint, SyntaxKind::ArgList);
+        self.wrap_node(checkpoint, SyntaxKind::CallExpression);
+        kind
       } else {
         unreachable!()
       };
 
-      expression = Expression { pos: SpanOffset { begin: pos.begin, end: self.current_token.1.end }, kind }
+      expression = Expression { pos: SpanOffset { begin, end: self.prev_pos.end }, kind }
     }
 
     Ok(expression)
@@ -283,16 +374,13 @@ impl<'a> Paser<'a> {
   fn paser_if(&mut self) -> Result<ExpressionKind> {
     let condition = self.paser_expression(Precedence::Lowest)?;
 
-    self.expect(Token::Lbrace);
-
     Ok(ExpressionKind::If(
       Box::new(condition),
-      self.parse_block_statement()?,
+      self.paser_block()?,
       if self.current_token.0 == Token::Else {
         self.next_token();
-        self.expect(Token::Lbrace);
 
-        Some(self.parse_block_statement()?)
+        Some(self.paser_block()?)
       } else {
         None
       },
@@ -305,15 +393,13 @@ impl<'a> Paser<'a> {
   fn paser_while(&mut self) -> Result<ExpressionKind> {
     let condition = self.paser_expression(Precedence::Lowest)?;
 
-    self.expect(Token::Lbrace);
-
-    Ok(ExpressionKind::While(Box::new(condition), self.parse_block_statement()?))
+    Ok(ExpressionKind::While(Box::new(condition), self.paser_block()?))
   }
 
   /// 解析 [] ()
   fn paser_group_expression(&mut self, close_token: Token) -> Result<ExpressionKind> {
     let e = self.paser_expression(Precedence::Lowest)?;
-    self.expect(close_token);
+    self.expect(close_token)?;
     Ok(e.kind)
   }
 
@@ -333,7 +419,7 @@ impl<'a> Paser<'a> {
       }
     }
 
-    self.expect(close_token);
+    self.expect(close_token)?;
 
     Ok(expressions)
   }
@@ -344,32 +430,65 @@ impl<'a> Paser<'a> {
   fn next_token(&mut self) -> (Token, SpanOffset) {
     self.prev_pos = self.current_token.1;
 
+    let (leading, token, text, pos) = self.lexer.next_with_trivia();
+    let (leading, text) = mem::replace(&mut self.current_trivia, mem::replace(&mut self.next_trivia, (leading, text)));
+
     // println!("{:?}", self.current_token);
-    mem::replace(&mut self.current_token, mem::replace(&mut self.next_token, self.lexer.next()))
+    let token = mem::replace(&mut self.current_token, mem::replace(&mut self.next_token, (token, pos)));
+    if let Some(cst) = &mut self.cst {
+      cst.token(SyntaxToken { leading, token: token.0.clone(), text: text.to_string(), pos: token.1 });
+    }
+    token
+  }
+
+  fn start_node(&mut self, kind: SyntaxKind) {
+    if let Some(cst) = &mut self.cst {
+      cst.start_node(kind);
+    }
+  }
+
+  fn finish_node(&mut self) {
+    if let Some(cst) = &mut self.cst {
+      cst.finish_node();
+    }
+  }
+
+  fn checkpoint(&self) -> usize {
+    self.cst.as_ref().map_or(0, Builder::checkpoint)
+  }
+
+  /// 将 checkpoint 之后的元素包装为一个节点
+  fn wrap_node(&mut self, checkpoint: usize, kind: SyntaxKind) {
+    if let Some(cst) = &mut self.cst {
+      cst.start_node_at(checkpoint, kind);
+      cst.finish_node();
+    }
   }
 
   /// 期待当前 token
   /// 并且获取下一个token
-  fn expect(&mut self, token: Token) {
+  fn expect(&mut self, token: Token) -> Result<()> {
     if self.current_token.0 != token {
-      panic!("expect {:?}, but get {:?}", token, self.current_token);
+      return Err((format!("expect {}, but get {}", token, self.current_token.0), self.current_token.1));
     }
     self.next_token();
+    Ok(())
   }
 
+  /// 当前 token 作为中缀或者后缀运算符时的优先级
   fn token_precedence(&self, token: &Token) -> Precedence {
-    if let (precedence, Some(_)) = self.infix_token(token) {
+    if let (precedence, Some(_)) = Self::infix_token(token) {
       precedence
     } else {
       match token {
-        Token::Minus | Token::Bang => Precedence::Prefix,
         Token::Lparen => Precedence::Suffix,
         _ => Precedence::Lowest,
       }
     }
   }
 
-  fn infix_token(&self, token: &Token) -> (Precedence, Option<Infix>) {
+  /// 中缀运算符的优先级
+  pub fn infix_token(token: &Token) -> (Precedence, Option<Infix>) {
     match token {
       Token::Eq => (Precedence::Equals, Some(Infix::Eq)),
       Token::Ne => (Precedence::Equals, Some(Infix::Ne)),
@@ -388,7 +507,7 @@ impl<'a> Paser<'a> {
 }
 
 /// 操作符优先级
-#[derive(PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Precedence {
   Lowest,
   Equals,      // 条件运算符
@@ -399,63 +518,70 @@ pub enum Precedence {
   Suffix,      // 后缀表达式
 }
 
+/// 语法分析的测试用例: 源代码与反解析的结果, 表驱动的分析器也用它们检查两个分析器是否一致
+#[cfg(test)]
+pub(crate) const CORPUS: &[(&str, &str)] = &[
+  // 赋值表达式
+  ("a += -5", "a = (a + (-5));"),
+  ("a += 1 + 2", "a = (a + (1 + 2));"),
+  ("a = -5", "a = (-5);"),
+  ("var a = 1 + 4 * 2 + 2", "var a = ((1 + (4 * 2)) + 2);"),
+  // 算术表达式
+  ("10 + -5", "(10 + (-5));"),
+  ("-a * b;", "((-a) * b);"),
+  ("!-a;", "(!(-a));"),
+  ("a + b + c;", "((a + b) + c);"),
+  ("a + b - c;", "((a + b) - c);"),
+  ("a * b * c;", "((a * b) * c);"),
+  ("a * b / c;", "((a * b) / c);"),
+  ("a + b / c;", "(a + (b / c));"),
+  ("a + b * c + d / e - f;", "(((a + (b * c)) + (d / e)) - f);"),
+  ("3 + 4; -5 * 5;", "(3 + 4); ((-5) * 5);"),
+  ("5 > 4 == 3 < 4;", "((5 > 4) == (3 < 4));"),
+  ("5 < 4 != 3 > 4;", "((5 < 4) != (3 > 4));"),
+  ("3 + 4 * 5 == 3 * 1 + 4 * 5;", "((3 + (4 * 5)) == ((3 * 1) + (4 * 5)));"),
+  ("1 + (2 + 3) + 4;", "((1 + (2 + 3)) + 4);"),
+  ("(5 + 5) * 2;", "((5 + 5) * 2);"),
+  ("2 / (5 + 5);", "(2 / (5 + 5));"),
+  ("-(5 + 5);", "(-(5 + 5));"),
+  // if 表达式
+  ("if (x < y) { x; };", "if (x < y) { x; };"),
+  ("if (x < y) { x; } else { y; };", "if (x < y) { x; } else { y; };"),
+  // return 表达式
+  ("return x;", "return x;"),
+  ("return x; return 2 * 3;", "return x; return (2 * 3);"),
+  ("return 2 * 4 + 5;", "return ((2 * 4) + 5);"),
+  // 函数声明定义表达式
+  ("fn xx(x) { x * 9; };", "fn xx(x) { (x * 9); }"),
+  ("fn xx(x, y) { x + y; };", "fn xx(x, y) { (x + y); }"),
+  // 函数调用表达式
+  ("call();", "call();"),
+  ("add(1, 2 * 3, 4 + 5);", "add(1, (2 * 3), (4 + 5));"),
+  ("a + add(b * c) + d;", "((a + add((b * c))) + d);"),
+  ("add(a, b, 1, 2 * 3, 4 + 5, add(6, 7 * 8));", "add(a, b, 1, (2 * 3), (4 + 5), add(6, (7 * 8)));"),
+  ("add(a + b + c * d / f + g)", "add((((a + b) + ((c * d) / f)) + g));"),
+  ("adder(1)(2);", "adder(1)(2);"),
+  ("(fn test(n) { n })(3)", "(fn test(n) { n; })(3);"),
+  ("var f = fn id(x) { x }", "var f = (fn id(x) { x; });"),
+  // 常量, 变量声明
+  ("const x = 3;", "const x = 3;"),
+  ("var x;", "var x = 0;"),
+];
+
 #[cfg(test)]
 mod tests {
 
   use crate::ast::AstNode;
 
-  use super::Paser;
+  use super::{Paser, CORPUS};
 
   #[test]
   fn test_infix_expression() {
-    test_parsing(vec![
-      // 赋值表达式
-      ("a += -5", "a = (a + (-5));"),
-      ("a += 1 + 2", "a = (a + (1 + 2));"),
-      ("a = -5", "a = (-5);"),
-      ("var a = 1 + 4 * 2 + 2", "var a = ((1 + (4 * 2)) + 2);"),
-      // 算术表达式
-      ("10 + -5", "(10 + (-5));"),
-      ("-a * b;", "((-a) * b);"),
-      ("!-a;", "(!(-a));"),
-      ("a + b + c;", "((a + b) + c);"),
-      ("a + b - c;", "((a + b) - c);"),
-      ("a * b * c;", "((a * b) * c);"),
-      ("a * b / c;", "((a * b) / c);"),
-      ("a + b / c;", "(a + (b / c));"),
-      ("a + b * c + d / e - f;", "(((a + (b * c)) + (d / e)) - f);"),
-      ("3 + 4; -5 * 5;", "(3 + 4); ((-5) * 5);"),
-      ("5 > 4 == 3 < 4;", "((5 > 4) == (3 < 4));"),
-      ("5 < 4 != 3 > 4;", "((5 < 4) != (3 > 4));"),
-      ("3 + 4 * 5 == 3 * 1 + 4 * 5;", "((3 + (4 * 5)) == ((3 * 1) + (4 * 5)));"),
-      ("1 + (2 + 3) + 4;", "((1 + (2 + 3)) + 4);"),
-      ("(5 + 5) * 2;", "((5 + 5) * 2);"),
-      ("2 / (5 + 5);", "(2 / (5 + 5));"),
-      ("-(5 + 5);", "(-(5 + 5));"),
-      // if 表达式
-      ("if (x < y) { x; };", "if (x < y) { x; };"),
-      ("if (x < y) { x; } else { y; };", "if (x < y) { x; } else { y; };"),
-      // return 表达式
-      ("return x;", "return x;"),
-      ("return x; return 2 * 3;", "return x; return (2 * 3);"),
-      ("return 2 * 4 + 5;", "return ((2 * 4) + 5);"),
-      // 函数声明定义表达式
-      ("fn xx(x) { x * 9; };", "fn xx(x) { (x * 9); }"),
-      ("fn xx(x, y) { x + y; };", "fn xx(x, y) { (x + y); }"),
-      // 函数调用表达式
-      ("call();", "call();"),
-      ("add(1, 2 * 3, 4 + 5);", "add(1, (2 * 3), (4 + 5));"),
-      ("a + add(b * c) + d;", "((a + add((b * c))) + d);"),
-      ("add(a, b, 1, 2 * 3, 4 + 5, add(6, 7 * 8));", "add(a, b, 1, (2 * 3), (4 + 5), add(6, (7 * 8)));"),
-      ("add(a + b + c * d / f + g)", "add((((a + b) + ((c * d) / f)) + g));"),
-      // 常量, 变量声明
-      ("const x = 3;", "const x = 3;"),
-      ("var x;", "var x = 0;"),
-    ]);
+    test_parsing(CORPUS);
   }
 
-  fn test_parsing(t: Vec<(&str, &str)>) {
-    for (input, unparse) in t {
+  fn test_parsing(t: &[(&str, &str)]) {
+    for &(input, unparse) in t {
       let program = Paser::paser(input).unwrap();
 
       // println!("{}", input);
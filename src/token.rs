@@ -1,10 +1,11 @@
 use std::fmt::Display;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Token {
-  Illegal,        // 不识别符号
-  Ident(String),  // 标识符
-  Integer(isize), // 整数
+  Illegal(char),    // 不识别的字符
+  TooLarge(String), // 超出范围的整数
+  Ident(String),    // 标识符
+  Integer(isize),   // 整数
 
   //
   Bang,      // !
@@ -49,9 +50,6 @@ pub enum Token {
 }
 
 impl Token {
-  /// 是否是语句开始符号
-  #[rustfmt::skip]
-
   /// 是否是表达式的开始符号
   pub fn is_expression_begin(&self) -> bool {
     matches!(
@@ -73,7 +71,8 @@ impl Token {
 impl Display for Token {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     f.write_str(match self {
-      Token::Illegal => "unknown",
+      Token::Illegal(ch) => return write!(f, "illegal character '{}'", ch),
+      Token::TooLarge(digits) => return write!(f, "number too large: {}", digits),
       Token::Ident(_s) => _s,
       Token::Integer(_i) => "integer",
       Token::Plus => "+",
@@ -96,8 +95,8 @@ impl Display for Token {
       Token::Const => "const",
       Token::Var => "var",
       Token::Function => "fn",
-      Token::Lbrace => "}",
-      Token::Rbrace => "{",
+      Token::Lbrace => "{",
+      Token::Rbrace => "}",
       Token::Lbracket => "[",
       Token::Rbracket => "]",
       Token::Bang => "!",
@@ -111,3 +110,18 @@ impl Display for Token {
     })
   }
 }
+
+/// 空白与注释, 不影响语义, 只在需要保留原文的时候使用 (格式化, 编辑器等)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trivia {
+  Whitespace(String),  // 连续的空白字符
+  LineComment(String), // 行注释, 包含开头的 // 但不包含换行符
+}
+
+impl Trivia {
+  pub fn text(&self) -> &str {
+    match self {
+      Trivia::Whitespace(text) | Trivia::LineComment(text) => text,
+    }
+  }
+}
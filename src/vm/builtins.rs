@@ -1,57 +1,105 @@
 use std::{
   collections::HashMap,
-  io::{self, Write},
+  io::{self, Read, Write},
 };
 
+/// 内建函数的实现, 输出写入到 out 中
+type BuiltinFn = fn(Vec<isize>, &mut dyn Write) -> isize;
+
+/// 经典 PL/0 的 ?x 与 !e 对应的内建函数, 名字不是合法的标识符, 本语言的程序中不能使用, 也不会与定义的名字冲突
+pub const READ: &str = "?";
+pub const WRITE: &str = "!";
+
 /// 内建函数
 pub struct Builtins {
-  map: HashMap<String, usize>,
-  arr: Vec<(fn(Vec<isize>) -> isize, String)>,
+  map: HashMap<String, usize>,   // 程序中使用的名字
+  arr: Vec<(BuiltinFn, String)>, // 实现, 以及生成的代码中使用的名字
+}
+
+impl Default for Builtins {
+  fn default() -> Self {
+    Self::new()
+  }
 }
 
 impl Builtins {
   pub fn new() -> Self {
-    let arr: Vec<(fn(Vec<isize>) -> isize, String)> = vec![
-      (Self::helloworld, "helloworld".to_string()),
-      (Self::print, "print".to_string()),
-      (Self::println, "println".to_string()),
-    ];
-    let mut map = HashMap::new();
+    let native: [(BuiltinFn, &str); 3] =
+      [(Self::helloworld, "helloworld"), (Self::print, "print"), (Self::println, "println")];
+    // 经典 PL/0 的输入输出, 程序中的名字为 READ 与 WRITE
+    let classic: [(BuiltinFn, &str, &str); 2] = [(Self::read, READ, "read"), (Self::write, WRITE, "write")];
 
-    arr.iter().enumerate().for_each(|(index, (_, name))| {
-      map.insert(name.to_string(), index);
-    });
+    let mut map = HashMap::new();
+    let mut arr: Vec<(BuiltinFn, String)> = vec![];
+    for (function, name, symbol) in native.into_iter().map(|(function, name)| (function, name, name)).chain(classic) {
+      map.insert(name.to_string(), arr.len());
+      arr.push((function, symbol.to_string()));
+    }
 
     Builtins { map, arr }
   }
 
   /// 查询内建函数
   pub fn lookup(&self, name: &str) -> Option<usize> {
-    self.map.get(name).map(|&x| x)
+    self.map.get(name).copied()
+  }
+
+  /// 本语言中可以使用的内建函数的名字
+  pub fn names(&self) -> impl Iterator<Item = &str> {
+    self.arr.iter().map(|(_, name)| name.as_str()).filter(|name| self.map.contains_key(*name))
+  }
+
+  /// 生成的代码中内建函数 id 的名字 (C 与 WebAssembly 运行时中的函数名)
+  pub fn symbol(&self, id: usize) -> &str {
+    &self.arr[id].1
+  }
+
+  /// 所有内建函数在生成的代码中的名字, 顺序与 id 相同
+  pub fn symbols(&self) -> impl Iterator<Item = &str> {
+    self.arr.iter().map(|(_, name)| name.as_str())
   }
 
   /// 调用函数
-  pub fn call(&self, id: usize, args: Vec<isize>) -> isize {
-    self.arr[id].0(args)
+  pub fn call(&self, id: usize, args: Vec<isize>, out: &mut dyn Write) -> isize {
+    self.arr[id].0(args, out)
   }
 
-  fn helloworld(_args: Vec<isize>) -> isize {
+  fn helloworld(_args: Vec<isize>, out: &mut dyn Write) -> isize {
     _args.iter().for_each(|arg| {
-      println!("{:?}", arg);
+      writeln!(out, "{:?}", arg).unwrap();
     });
-    println!("hello world");
+    writeln!(out, "hello world").unwrap();
     0
   }
 
-  fn print(args: Vec<isize>) -> isize {
-    print!("{:?}", args.into_iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" "));
-    io::stdout().flush().unwrap();
+  fn print(args: Vec<isize>, out: &mut dyn Write) -> isize {
+    write!(out, "{:?}", args.into_iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" ")).unwrap();
+    out.flush().unwrap();
     0
   }
 
-  fn println(args: Vec<isize>) -> isize {
-    Self::print(args);
-    println!();
+  fn println(args: Vec<isize>, out: &mut dyn Write) -> isize {
+    Self::print(args, out);
+    writeln!(out).unwrap();
+    0
+  }
+
+  /// 从标准输入读取一个整数 (以空白分隔), 读取失败时为 0
+  fn read(_args: Vec<isize>, out: &mut dyn Write) -> isize {
+    out.flush().unwrap();
+    let word = io::stdin()
+      .lock()
+      .bytes()
+      .map_while(Result::ok)
+      .skip_while(u8::is_ascii_whitespace)
+      .take_while(|byte| !byte.is_ascii_whitespace())
+      .collect::<Vec<_>>();
+    String::from_utf8_lossy(&word).parse().unwrap_or(0)
+  }
+
+  /// 每个参数输出一行, 与经典 PL/0 的 ! 语句相同
+  fn write(args: Vec<isize>, out: &mut dyn Write) -> isize {
+    args.iter().for_each(|arg| writeln!(out, "{}", arg).unwrap());
     0
   }
 }
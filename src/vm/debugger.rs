@@ -0,0 +1,355 @@
+use std::{
+  collections::{BTreeSet, HashSet},
+  io::Write,
+};
+
+use crate::compiler::debuginfo::{DebugInfo, ScopeKind};
+
+use super::{Opcode, RuntimeError, HEAP_BASE, VM};
+
+/// 调试器暂停的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+  Step,                       // 单步执行完成
+  Breakpoint(usize),          // 到达断点
+  Exited(isize),              // 程序结束, 以及返回值
+  Error(RuntimeError, usize), // 运行时错误, 以及出错的指令地址
+}
+
+/// 调用栈中的一层: 函数, 语句块或者全局作用域
+#[derive(Debug, Clone)]
+pub struct Frame {
+  pub kind: ScopeKind,
+  pub ip: usize,                       // 该层正在执行 (或者返回后继续执行) 的指令地址
+  pub line: Option<usize>,             // ip 所在的行号, 从 1 开始
+  pub base: usize,                     // 基地址
+  pub variables: Vec<(String, isize)>, // 已经初始化的变量与参数
+}
+
+///
+/// 虚拟机调试器
+///
+/// 逐条执行指令, 根据编译器生成的调试信息将指令映射到源代码中的语句,
+/// 调用栈沿着 bp 的链接还原: 语句块的第 0 个单元为上一层的基地址, 函数栈帧中为动态链与返回地址
+pub struct Debugger {
+  vm: VM,
+  codes: Vec<Opcode>,
+  debug: DebugInfo,
+  lines: Vec<usize>,          // 每一行开头的字符偏移
+  statements: HashSet<usize>, // 语句的第一条指令地址
+  breakpoints: BTreeSet<usize>,
+  depth: usize,          // 函数调用的层数
+  stopped: Option<Stop>, // 程序已经结束或者出错
+}
+
+impl Debugger {
+  pub fn new(codes: Vec<Opcode>, debug: DebugInfo, source: &str) -> Self {
+    let mut lines = vec![0];
+    lines.extend(source.chars().enumerate().filter(|(_, c)| *c == '\n').map(|(index, _)| index + 1));
+    let statements = debug.statements.iter().map(|(addr, _)| *addr).collect();
+
+    Debugger { vm: VM::new(), codes, debug, lines, statements, breakpoints: BTreeSet::new(), depth: 0, stopped: None }
+  }
+
+  /// 下一条要执行的指令地址
+  pub fn ip(&self) -> usize {
+    self.vm.ip
+  }
+
+  pub fn codes(&self) -> &[Opcode] {
+    &self.codes
+  }
+
+  /// 程序是否已经结束 (正常结束或者出错)
+  pub fn stopped(&self) -> Option<Stop> {
+    self.stopped
+  }
+
+  /// 指令所在语句的行号, 从 1 开始
+  pub fn line_of(&self, ip: usize) -> Option<usize> {
+    let span = self.debug.spans.get(ip)?;
+    Some(self.lines.partition_point(|&begin| begin <= span.begin))
+  }
+
+  /// 从该行开始的语句的第一条指令地址
+  pub fn line_addresses(&self, line: usize) -> Vec<usize> {
+    let mut addresses = vec![];
+    let mut spans = vec![];
+    for (addr, span) in &self.debug.statements {
+      // 同一条语句只取第一条指令 (语句按照指令地址排序)
+      if self.line_of(*addr) == Some(line) && !spans.contains(&span.begin) {
+        spans.push(span.begin);
+        addresses.push(*addr);
+      }
+    }
+    addresses
+  }
+
+  /// 在指令地址上设置断点
+  pub fn set_breakpoint(&mut self, addr: usize) -> bool {
+    addr < self.codes.len() && self.breakpoints.insert(addr)
+  }
+
+  /// 在源代码的行上设置断点, 返回对应的指令地址
+  ///
+  /// 该行没有语句开始时, 断点设置在之后最近的一行上
+  pub fn set_line_breakpoint(&mut self, line: usize) -> Option<(usize, Vec<usize>)> {
+    let (line, addresses) = (line..=self.lines.len())
+      .map(|line| (line, self.line_addresses(line)))
+      .find(|(_, addresses)| !addresses.is_empty())?;
+    self.breakpoints.extend(&addresses);
+    Some((line, addresses))
+  }
+
+  pub fn remove_breakpoint(&mut self, addr: usize) -> bool {
+    self.breakpoints.remove(&addr)
+  }
+
+  pub fn clear_breakpoints(&mut self) {
+    self.breakpoints.clear();
+  }
+
+  pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+    self.breakpoints.iter().copied()
+  }
+
+  /// 执行一条指令
+  pub fn step_instruction(&mut self, out: &mut dyn Write) -> Stop {
+    self.run(out, |_| true)
+  }
+
+  /// 执行到下一条语句, 进入被调用的函数
+  pub fn step_into(&mut self, out: &mut dyn Write) -> Stop {
+    self.run(out, |debugger| debugger.at_statement())
+  }
+
+  /// 执行到当前函数中 (或者返回之后) 的下一条语句, 不进入被调用的函数
+  pub fn step_over(&mut self, out: &mut dyn Write) -> Stop {
+    let depth = self.depth;
+    self.run(out, |debugger| debugger.depth <= depth && debugger.at_statement())
+  }
+
+  /// 执行到当前函数返回
+  pub fn step_out(&mut self, out: &mut dyn Write) -> Stop {
+    let depth = self.depth;
+    self.run(out, |debugger| debugger.depth < depth)
+  }
+
+  /// 继续执行, 直到遇到断点或者程序结束
+  pub fn cont(&mut self, out: &mut dyn Write) -> Stop {
+    self.run(out, |_| false)
+  }
+
+  fn at_statement(&self) -> bool {
+    self.statements.contains(&self.vm.ip)
+  }
+
+  /// 至少执行一条指令, 直到 done 为真, 遇到断点或者程序结束
+  fn run(&mut self, out: &mut dyn Write, done: impl Fn(&Self) -> bool) -> Stop {
+    if let Some(stop) = self.stopped {
+      return stop;
+    }
+
+    loop {
+      let depth = match self.codes[self.vm.ip] {
+        Opcode::Cal(_) | Opcode::CallIndirect(_) => self.depth + 1,
+        Opcode::Ret => self.depth.saturating_sub(1),
+        _ => self.depth,
+      };
+
+      match self.vm.step(&self.codes, out) {
+        Ok(None) => self.depth = depth,
+        Ok(Some(result)) => return *self.stopped.insert(Stop::Exited(result)),
+        Err((err, ip)) => return *self.stopped.insert(Stop::Error(err, ip)),
+      }
+
+      if self.breakpoints.contains(&self.vm.ip) {
+        return Stop::Breakpoint(self.vm.ip);
+      }
+      if done(self) {
+        return Stop::Step;
+      }
+    }
+  }
+
+  ///
+  /// 当前的调用栈, 最内层在前
+  ///
+  /// 每条指令所在的作用域决定了 bp 指向的栈帧的布局:
+  /// 语句块的基地址中保存着上一层作用域的基地址, 函数栈帧中保存着动态链与返回地址,
+  /// 堆上的函数栈帧还额外记录了调用时在栈上建立的栈帧
+  pub fn frames(&self) -> Vec<Frame> {
+    let mut frames = vec![];
+    if self.stopped.is_some() {
+      return frames;
+    }
+
+    let (mut ip, mut bp) = (self.vm.ip, self.vm.bp);
+    let mut id = self.debug.scope_of[ip];
+    loop {
+      let scope = &self.debug.scopes[id];
+      let variables = scope
+        .variables
+        .iter()
+        .filter(|variable| variable.from <= ip)
+        .map(|variable| (variable.name.clone(), self.vm.read((bp as isize + variable.addr) as usize)))
+        .collect();
+      frames.push(Frame { kind: scope.kind.clone(), ip, line: self.line_of(ip), base: bp, variables });
+
+      match scope.kind {
+        ScopeKind::Program => break,
+        // 语句块的上一层一定是其所在的作用域
+        ScopeKind::Block => {
+          bp = self.vm.read(bp) as usize;
+          id = scope.parent.unwrap_or(0);
+        }
+        ScopeKind::Function(_) => {
+          let frame = if bp >= HEAP_BASE { self.vm.read(bp + 1) as usize } else { bp };
+          bp = self.vm.stack[frame + 1] as usize;
+          ip = self.vm.stack[frame + 2] as usize;
+          id = self.debug.scope_of[ip];
+        }
+      }
+    }
+    frames
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    ast::AstNode,
+    compiler::{debuginfo::ScopeKind, Compiler},
+    generator::Generator,
+    parser::Paser,
+    vm::VM,
+  };
+
+  use super::{Debugger, Stop};
+
+  const FIB: &str = "fn fib(n) {
+  if n <= 1 {
+    return 1
+  };
+  var a = fib(n - 1);
+  a + fib(n - 2)
+}
+var result = fib(5);
+println(result);
+result";
+
+  fn debugger(source: &str) -> Debugger {
+    let program = Paser::paser(source).unwrap();
+    let (codes, debug) = Compiler::compile_with_debug(&program).unwrap();
+    Debugger::new(codes, debug, source)
+  }
+
+  /// 调用栈中每一层的名字与行号
+  fn backtrace(debugger: &Debugger) -> Vec<(String, Option<usize>)> {
+    let name = |kind: &ScopeKind| match kind {
+      ScopeKind::Program => "<main>".to_string(),
+      ScopeKind::Function(name) => name.clone(),
+      ScopeKind::Block => "{}".to_string(),
+    };
+    debugger.frames().iter().map(|frame| (name(&frame.kind), frame.line)).collect()
+  }
+
+  #[test]
+  fn test_breakpoint() {
+    let mut debugger = debugger(FIB);
+    let mut out = vec![];
+
+    // 第 3 行在 if 语句块中
+    assert_eq!(debugger.set_line_breakpoint(3).map(|(line, _)| line), Some(3));
+    assert_eq!(debugger.cont(&mut out), Stop::Breakpoint(debugger.ip()));
+    assert_eq!(debugger.line_of(debugger.ip()), Some(3));
+
+    // fib(5) -> fib(4) -> fib(3) -> fib(2) -> fib(1)
+    let trace = backtrace(&debugger);
+    assert_eq!(trace[0], ("{}".to_string(), Some(3)));
+    assert_eq!(trace.iter().filter(|(name, _)| name == "fib").count(), 5);
+    assert_eq!(trace[trace.len() - 1], ("<main>".to_string(), Some(8)));
+
+    let frames = debugger.frames();
+    assert_eq!(frames[1].variables, [("n".to_string(), 1)]);
+    assert_eq!(frames[5].variables, [("n".to_string(), 5)]);
+    assert!(frames[6].variables.is_empty());
+
+    // 断点在指令地址上
+    debugger.clear_breakpoints();
+    let addr = debugger.line_addresses(9)[0];
+    assert!(debugger.set_breakpoint(addr));
+    assert_eq!(debugger.cont(&mut out), Stop::Breakpoint(addr));
+    assert_eq!(debugger.frames()[0].variables, [("result".to_string(), 8)]);
+
+    assert_eq!(debugger.cont(&mut out), Stop::Exited(8));
+    let mut expect = vec![];
+    VM::execute_with_output(debugger.codes(), &mut expect).unwrap();
+    assert_eq!(out, expect);
+    assert!(debugger.frames().is_empty());
+  }
+
+  #[test]
+  fn test_step() {
+    let mut debugger = debugger(FIB);
+    let mut out = vec![];
+    let mut step = |debugger: &mut Debugger, stop: fn(&mut Debugger, &mut Vec<u8>) -> Stop| {
+      assert_eq!(stop(debugger, &mut out), Stop::Step);
+      (debugger.line_of(debugger.ip()).unwrap(), debugger.frames().len())
+    };
+
+    // 函数定义语句跳过函数体
+    assert_eq!(step(&mut debugger, |d, out| d.step_into(out)), (1, 1));
+    assert_eq!(step(&mut debugger, |d, out| d.step_into(out)), (8, 1));
+    assert_eq!(step(&mut debugger, |d, out| d.step_into(out)), (2, 2));
+    assert_eq!(step(&mut debugger, |d, out| d.step_over(out)), (5, 2));
+    assert_eq!(step(&mut debugger, |d, out| d.step_into(out)), (2, 3));
+    assert_eq!(step(&mut debugger, |d, out| d.step_out(out)), (5, 2));
+    assert_eq!(debugger.frames()[0].variables, [("n".to_string(), 5)]);
+    assert_eq!(step(&mut debugger, |d, out| d.step_over(out)), (6, 2));
+    assert_eq!(debugger.frames()[0].variables, [("n".to_string(), 5), ("a".to_string(), 5)]);
+    assert_eq!(step(&mut debugger, |d, out| d.step_over(out)), (9, 1));
+    assert_eq!(step(&mut debugger, |d, out| d.step_over(out)), (10, 1));
+    assert_eq!(debugger.step_out(&mut out), Stop::Exited(8));
+    assert_eq!(debugger.step_into(&mut out), Stop::Exited(8));
+  }
+
+  /// 逐条指令执行的结果与直接执行相同, 调用栈始终能够还原到全局作用域
+  #[test]
+  fn test_frames() {
+    let mut programs = [
+      FIB,
+      "fn adder(n) { fn add(x) { x + n } add } var add3 = adder(3); add3(4) + adder(10)(1)",
+      "fn counter() { var c = 0; fn inc() { c += 1 } inc } var a = counter(); a(); a()",
+      "var g; if 1 { var x = 5; fn h() { x } g = h }; g()",
+      "fn f(n) { while 1 { if n > 3 { return n } n += 1 } } f(0)",
+      "fn sum(n, acc) { if n == 0 { acc } else { sum(n - 1, acc + n) } } sum(100, 0)",
+      "var x = 1 / 0",
+    ]
+    .map(String::from)
+    .to_vec();
+    programs.extend((0..30).map(|seed| Generator::new(seed).program().unparse()));
+
+    for source in &programs {
+      let mut debugger = debugger(source);
+      let expect = VM::execute_with_output(debugger.codes(), &mut vec![]);
+
+      let stop = loop {
+        // 调用栈中的函数个数与调用的层数一致
+        let frames = debugger.frames();
+        let functions = frames.iter().filter(|frame| matches!(frame.kind, ScopeKind::Function(_))).count();
+        assert!(matches!(frames.last().map(|frame| &frame.kind), Some(ScopeKind::Program)), "{}", source);
+        assert_eq!(functions, debugger.depth, "{}", source);
+
+        match debugger.step_instruction(&mut vec![]) {
+          Stop::Step => {}
+          stop => break stop,
+        }
+      };
+      match expect {
+        Ok(result) => assert_eq!(stop, Stop::Exited(result)),
+        Err((err, ip)) => assert_eq!(stop, Stop::Error(err, ip)),
+      }
+    }
+  }
+}
@@ -0,0 +1,389 @@
+use std::{io::Write, result};
+
+use super::{Opcode, Result, RuntimeError, VM};
+
+/// 二元运算
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BinOp {
+  Add,
+  Sub,
+  Mul,
+  Div,
+  Eq,
+  Ne,
+  Lt,
+  Le,
+  Gt,
+  Ge,
+}
+
+impl BinOp {
+  fn from_opcode(code: Opcode) -> Option<Self> {
+    Some(match code {
+      Opcode::Add => BinOp::Add,
+      Opcode::Sub => BinOp::Sub,
+      Opcode::Mul => BinOp::Mul,
+      Opcode::Div => BinOp::Div,
+      Opcode::Eq => BinOp::Eq,
+      Opcode::Ne => BinOp::Ne,
+      Opcode::Lt => BinOp::Lt,
+      Opcode::Le => BinOp::Le,
+      Opcode::Gt => BinOp::Gt,
+      Opcode::Ge => BinOp::Ge,
+      _ => return None,
+    })
+  }
+
+  fn is_compare(self) -> bool {
+    !matches!(self, BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div)
+  }
+
+  #[inline(always)]
+  fn apply(self, op1: isize, op2: isize) -> result::Result<isize, RuntimeError> {
+    let result = match self {
+      BinOp::Add => op1.checked_add(op2),
+      BinOp::Sub => op1.checked_sub(op2),
+      BinOp::Mul => op1.checked_mul(op2),
+      BinOp::Div if op2 == 0 => return Err(RuntimeError::DivisionByZero),
+      BinOp::Div => op1.checked_div(op2),
+      BinOp::Eq => Some((op1 == op2) as isize),
+      BinOp::Ne => Some((op1 != op2) as isize),
+      BinOp::Lt => Some((op1 < op2) as isize),
+      BinOp::Le => Some((op1 <= op2) as isize),
+      BinOp::Gt => Some((op1 > op2) as isize),
+      BinOp::Ge => Some((op1 >= op2) as isize),
+    };
+    result.ok_or(RuntimeError::Overflow)
+  }
+}
+
+///
+/// 预先解码的指令, 与目标代码一一对应 (下标相同)
+///
+/// 二元运算各自是一条指令, 访问当前作用域的 Lod / Sto 单独解码, 另外将常见的指令序列合并为超级指令:
+/// 超级指令放在序列第一条指令的位置, 之后的位置仍然是原来的指令, 所以跳转到序列中间也是正确的
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Instr {
+  Lit(isize),
+  Lod(usize, isize),
+  LodLocal(isize), // Lod(0, addr)
+  Lod1(usize),
+  Sto(usize, isize),
+  StoLocal(isize), // Sto(0, addr)
+  Int(usize),
+  Jmp(usize),
+  Jpc(usize),
+  Cal(usize),
+  Closure(usize, usize),
+  CallIndirect(usize),
+  TailCall(usize, usize, usize),
+  TailCallIndirect(usize, usize),
+  HeapFrame(usize, usize),
+  Builtin(usize, usize),
+  Ret,
+  CallClean(usize),
+  EnterScope,
+  HeapScope(usize),
+  LeaveScope,
+  Pop,
+  Not,
+  Add,
+  Sub,
+  Mul,
+  Div,
+  Eq,
+  Ne,
+  Lt,
+  Le,
+  Gt,
+  Ge,
+  None,
+
+  // 超级指令
+  LodLitCmpJpc(usize, isize, isize, BinOp, usize), // Lod(level, addr); Lit(value); 比较; Jpc(target)
+  LodLitOp(usize, isize, isize, BinOp),            // Lod(level, addr); Lit(value); 二元运算
+  LodLodOp(usize, isize, usize, isize, BinOp),     // Lod; Lod; 二元运算
+  LitCal(usize, usize),                            // Lit(addr); Cal(level)
+  EnterScopeInt(usize),                            // EnterScope; Int(size)
+}
+
+/// 解码目标代码, fuse 为 false 时不生成超级指令 (需要精确计算执行的指令数时)
+pub(crate) fn decode(codes: &[Opcode], fuse: bool) -> Vec<Instr> {
+  (0..codes.len()).map(|ip| if fuse { fused(&codes[ip..]) } else { None }.unwrap_or(single(codes[ip]))).collect()
+}
+
+fn single(code: Opcode) -> Instr {
+  match code {
+    Opcode::None => Instr::None,
+    Opcode::Lit(value) => Instr::Lit(value),
+    Opcode::Lod(0, addr) => Instr::LodLocal(addr),
+    Opcode::Lod(level, addr) => Instr::Lod(level, addr),
+    Opcode::Lod1(offset) => Instr::Lod1(offset),
+    Opcode::Sto(0, addr) => Instr::StoLocal(addr),
+    Opcode::Sto(level, addr) => Instr::Sto(level, addr),
+    Opcode::Int(size) => Instr::Int(size),
+    Opcode::Jmp(target) => Instr::Jmp(target),
+    Opcode::Jpc(target) => Instr::Jpc(target),
+    Opcode::Cal(level) => Instr::Cal(level),
+    Opcode::Closure(level, argc) => Instr::Closure(level, argc),
+    Opcode::CallIndirect(argc) => Instr::CallIndirect(argc),
+    Opcode::TailCall(level, argc, depth) => Instr::TailCall(level, argc, depth),
+    Opcode::TailCallIndirect(argc, depth) => Instr::TailCallIndirect(argc, depth),
+    Opcode::HeapFrame(argc, size) => Instr::HeapFrame(argc, size),
+    Opcode::Builtin(id, argc) => Instr::Builtin(id, argc),
+    Opcode::Ret => Instr::Ret,
+    Opcode::CallClean(num) => Instr::CallClean(num),
+    Opcode::EnterScope => Instr::EnterScope,
+    Opcode::HeapScope(size) => Instr::HeapScope(size),
+    Opcode::LeaveScope => Instr::LeaveScope,
+    Opcode::Pop => Instr::Pop,
+    Opcode::Not => Instr::Not,
+    Opcode::Add => Instr::Add,
+    Opcode::Sub => Instr::Sub,
+    Opcode::Mul => Instr::Mul,
+    Opcode::Div => Instr::Div,
+    Opcode::Eq => Instr::Eq,
+    Opcode::Ne => Instr::Ne,
+    Opcode::Lt => Instr::Lt,
+    Opcode::Le => Instr::Le,
+    Opcode::Gt => Instr::Gt,
+    Opcode::Ge => Instr::Ge,
+  }
+}
+
+fn fused(codes: &[Opcode]) -> Option<Instr> {
+  let op = |code: &Opcode| BinOp::from_opcode(*code);
+  let instr = match codes {
+    [Opcode::Lod(level, addr), Opcode::Lit(value), code, Opcode::Jpc(target), ..]
+      if op(code).is_some_and(BinOp::is_compare) =>
+    {
+      Instr::LodLitCmpJpc(*level, *addr, *value, op(code)?, *target)
+    }
+    [Opcode::Lod(level, addr), Opcode::Lit(value), code, ..] => Instr::LodLitOp(*level, *addr, *value, op(code)?),
+    [Opcode::Lod(level1, addr1), Opcode::Lod(level2, addr2), code, ..] => {
+      Instr::LodLodOp(*level1, *addr1, *level2, *addr2, op(code)?)
+    }
+    [Opcode::Lit(addr), Opcode::Cal(level), ..] if *addr >= 0 => Instr::LitCal(*addr as usize, *level),
+    [Opcode::EnterScope, Opcode::Int(size), ..] => Instr::EnterScopeInt(*size),
+    _ => return None,
+  };
+  Some(instr)
+}
+
+impl VM {
+  /// 在栈上读取 level 层之上作用域中的变量
+  #[inline(always)]
+  fn lod(&self, level: usize, addr: isize) -> isize {
+    self.read((self.base(level) as isize + addr) as usize)
+  }
+
+  #[inline(always)]
+  fn binary(&mut self, op: BinOp, ip: usize) -> Result<()> {
+    let op2 = self.pop();
+    let op1 = self.pop();
+    let result = op.apply(op1, op2).map_err(|err| (err, ip))?;
+    self.push(result);
+    Ok(())
+  }
+
+  ///
+  /// 执行预先解码的指令, 直到程序结束
+  ///
+  /// LIMITED 为 true 时检查资源限制, 此时解码不能生成超级指令
+  pub(crate) fn run_decoded<const LIMITED: bool>(&mut self, instrs: &[Instr], out: &mut dyn Write) -> Result<isize> {
+    loop {
+      let ip = self.ip;
+      if LIMITED {
+        if self.limits.instructions.is_some_and(|limit| self.executed >= limit) {
+          return Err((RuntimeError::InstructionLimit, ip));
+        }
+        self.executed += 1;
+      }
+      self.ip += 1;
+
+      match instrs[ip] {
+        Instr::Lit(value) => self.push(value),
+        Instr::LodLocal(addr) => {
+          let value = self.read((self.bp as isize + addr) as usize);
+          self.push(value);
+        }
+        Instr::Lod(level, addr) => self.push(self.lod(level, addr)),
+        Instr::Lod1(offset) => self.push(self.stack[self.sp - offset]),
+        Instr::StoLocal(addr) => self.write((self.bp as isize + addr) as usize, self.stack[self.sp - 1]),
+        Instr::Sto(level, addr) => {
+          let address = (self.base(level) as isize + addr) as usize;
+          self.write(address, self.stack[self.sp - 1]);
+        }
+        Instr::Int(size) => {
+          self.reserve(size);
+          self.sp += size;
+        }
+        Instr::Jmp(target) => self.ip = target,
+        Instr::Jpc(target) => {
+          if self.pop() == 0 {
+            self.ip = target;
+          }
+        }
+        Instr::Cal(level) => {
+          let addr = self.pop() as usize;
+          self.call(addr, self.base(level)).map_err(|err| (err, ip))?;
+        }
+        Instr::Closure(level, argc) => {
+          let addr = self.pop() as usize;
+          self.closure(addr, self.base(level), argc).map_err(|err| (err, ip))?;
+        }
+        Instr::CallIndirect(argc) => {
+          let (addr, sl) = self.unpack_closure(argc).map_err(|err| (err, ip))?;
+          self.call(addr, sl).map_err(|err| (err, ip))?;
+        }
+        Instr::TailCallIndirect(argc, depth) => {
+          let (addr, sl) = self.unpack_closure(argc).map_err(|err| (err, ip))?;
+          self.tail_call(addr, sl, argc, depth);
+        }
+        Instr::TailCall(level, argc, depth) => {
+          let addr = self.pop() as usize;
+          self.tail_call(addr, self.base(level), argc, depth);
+        }
+        Instr::HeapFrame(argc, size) => self.heap_frame(argc, size).map_err(|err| (err, ip))?,
+        Instr::Builtin(id, argc) => {
+          let args = (0..argc).map(|_| self.pop()).collect();
+          let result = self.builtins.call(id, args, out);
+          self.push(result);
+        }
+        Instr::Ret => {
+          self.ret();
+          if self.ip == 0 {
+            if self.sp != 1 {
+              panic!("虚拟机栈未清理干净");
+            }
+            return Ok(self.stack[0]);
+          }
+        }
+        Instr::CallClean(num) => {
+          let result = self.pop();
+          self.sp -= num;
+          self.push(result);
+        }
+        Instr::EnterScope => self.enter_scope(),
+        Instr::HeapScope(size) => self.heap_scope(size).map_err(|err| (err, ip))?,
+        Instr::LeaveScope => self.leave_scope(),
+        Instr::Pop => self.sp -= 1,
+        Instr::Not => {
+          let value = self.pop();
+          self.push((value == 0) as isize);
+        }
+        Instr::Add => self.binary(BinOp::Add, ip)?,
+        Instr::Sub => self.binary(BinOp::Sub, ip)?,
+        Instr::Mul => self.binary(BinOp::Mul, ip)?,
+        Instr::Div => self.binary(BinOp::Div, ip)?,
+        Instr::Eq => self.binary(BinOp::Eq, ip)?,
+        Instr::Ne => self.binary(BinOp::Ne, ip)?,
+        Instr::Lt => self.binary(BinOp::Lt, ip)?,
+        Instr::Le => self.binary(BinOp::Le, ip)?,
+        Instr::Gt => self.binary(BinOp::Gt, ip)?,
+        Instr::Ge => self.binary(BinOp::Ge, ip)?,
+        Instr::None => {}
+
+        Instr::LodLitCmpJpc(level, addr, value, op, target) => {
+          // 比较运算不会出错
+          let condition = op.apply(self.lod(level, addr), value).unwrap_or_default();
+          self.ip = if condition == 0 { target } else { ip + 4 };
+        }
+        Instr::LodLitOp(level, addr, value, op) => {
+          let result = op.apply(self.lod(level, addr), value).map_err(|err| (err, ip + 2))?;
+          self.push(result);
+          self.ip = ip + 3;
+        }
+        Instr::LodLodOp(level1, addr1, level2, addr2, op) => {
+          let result = op.apply(self.lod(level1, addr1), self.lod(level2, addr2)).map_err(|err| (err, ip + 2))?;
+          self.push(result);
+          self.ip = ip + 3;
+        }
+        Instr::LitCal(addr, level) => {
+          self.ip = ip + 2;
+          self.call(addr, self.base(level)).map_err(|err| (err, ip + 1))?;
+        }
+        Instr::EnterScopeInt(size) => {
+          self.enter_scope();
+          self.reserve(size);
+          self.sp += size;
+          self.ip = ip + 2;
+        }
+      }
+
+      if LIMITED && self.limits.stack.is_some_and(|limit| self.sp > limit) {
+        return Err((RuntimeError::StackOverflow, ip));
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    compiler::Compiler,
+    generator::Generator,
+    parser::Paser,
+    vm::{Limits, Opcode, RuntimeError, Status, VM},
+  };
+
+  use super::{decode, BinOp, Instr};
+
+  /// 逐条执行 (调试器等使用的方式) 的结果
+  fn step(codes: &[Opcode], out: &mut Vec<u8>) -> Status {
+    let mut vm = VM::new();
+    vm.load(codes.to_vec());
+    vm.run_for(u64::MAX, out)
+  }
+
+  fn status(result: crate::vm::Result<isize>) -> Status {
+    match result {
+      Ok(result) => Status::Finished(result),
+      Err((err, ip)) => Status::Error(err, ip),
+    }
+  }
+
+  #[test]
+  fn test_decode() {
+    let source = "fn fib(n) { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } } fib(10)";
+    let codes = Compiler::compile(&Paser::paser(source).unwrap()).unwrap();
+    let instrs = decode(&codes, true);
+    assert_eq!(instrs.len(), codes.len());
+    assert!(instrs.contains(&Instr::LodLitCmpJpc(0, -1, 2, BinOp::Lt, 15)));
+    assert!(instrs.contains(&Instr::LodLitOp(1, -1, 1, BinOp::Sub)));
+    assert!(instrs.iter().any(|instr| matches!(instr, Instr::LitCal(3, 2))));
+    assert!(instrs.iter().any(|instr| matches!(instr, Instr::EnterScopeInt(1))));
+    assert!(decode(&codes, false).iter().all(|instr| !matches!(instr, Instr::LodLitOp(..) | Instr::LitCal(..))));
+
+    // 超级指令中的运行时错误, 指令地址为出错的运算指令
+    let source = "var a = 9223372036854775807; var b = 1; a + b";
+    let codes = Compiler::compile(&Paser::paser(source).unwrap()).unwrap();
+    let (err, ip) = VM::execute_with_output(&codes, &mut vec![]).unwrap_err();
+    assert_eq!((err, codes[ip]), (RuntimeError::Overflow, Opcode::Add));
+    assert_eq!(step(&codes, &mut vec![]), Status::Error(err, ip));
+  }
+
+  #[test]
+  fn test_differential() {
+    for seed in 0..100 {
+      let codes = Compiler::compile(&Generator::new(seed).program()).unwrap();
+      let mut expect_out = vec![];
+      let expect = step(&codes, &mut expect_out);
+
+      let mut out = vec![];
+      assert_eq!(status(VM::execute_with_output(&codes, &mut out)), expect, "seed {}", seed);
+      assert_eq!(out, expect_out, "seed {}", seed);
+
+      // 有资源限制时不合并指令序列
+      let mut out = vec![];
+      let limits = Limits { depth: Some(1 << 20), ..Limits::default() };
+      assert_eq!(status(VM::execute_with_limits(&codes, &mut out, limits)), expect, "seed {}", seed);
+      assert_eq!(out, expect_out, "seed {}", seed);
+
+      // 性能对比的基准
+      let mut out = vec![];
+      assert_eq!(status(VM::execute_step(&codes, &mut out)), expect, "seed {}", seed);
+      assert_eq!(out, expect_out, "seed {}", seed);
+    }
+  }
+}
@@ -1,26 +1,46 @@
 pub mod builtins;
-
-use std::fmt::Debug;
-
-use self::builtins::Builtins;
+pub mod debugger;
+mod dispatch;
+pub mod profile;
+pub mod snapshot;
+pub mod trace;
+
+use std::{
+  fmt::{Debug, Display},
+  io::{self, Write},
+  mem, result,
+};
+
+use self::{
+  builtins::Builtins,
+  snapshot::Snapshot,
+  trace::{Trace, Tracer},
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Opcode {
   None, // 空指令
 
-  Lit(isize),            // 将指定 字面量压入栈中
-  Lod(usize, isize),     // 将指定地址的压入栈顶
-  Lod1(usize),           // 将栈中 相对栈顶 offset 位置的数压入栈顶
-  Sto(usize, isize),     // 将栈顶元素放入指定地址
-  Int(usize),            // 分配内存
-  Jmp(usize),            // 无条件跳转
-  Jpc(usize),            // 栈顶为 0 时跳转
-  Cal(usize),            // 调用函数, 地址为栈顶的值
-  Builtin(usize, usize), // 调用内建函数
-  Ret,                   // 将栈顶元素返回
-  CallClean(usize),      // 清理调用 函数后 上级函数的垃圾数据
-  EnterScope,            // 进入作用域
-  LeaveScope,            // 离开作用域
+  Lit(isize),                     // 将指定 字面量压入栈中
+  Lod(usize, isize),              // 将指定地址的压入栈顶
+  Lod1(usize),                    // 将栈中 相对栈顶 offset 位置的数压入栈顶
+  Sto(usize, isize),              // 将栈顶元素放入指定地址
+  Int(usize),                     // 分配内存
+  Jmp(usize),                     // 无条件跳转
+  Jpc(usize),                     // 栈顶为 0 时跳转
+  Cal(usize),                     // 调用函数, 地址为栈顶的值
+  Closure(usize, usize),          // 将栈顶的函数地址与 level 层的基地址创建为闭包 (静态链层差, 参数个数)
+  CallIndirect(usize),            // 调用栈顶的闭包 (实参个数), 函数地址与静态链都取自闭包
+  TailCall(usize, usize, usize),  // 尾调用 (静态链层差, 实参个数, 所在语句块层数), 复用当前函数的栈帧
+  TailCallIndirect(usize, usize), // 尾调用栈顶的闭包 (实参个数, 所在语句块层数)
+  HeapFrame(usize, usize),        // 在堆上分配函数栈帧 (参数个数, 栈帧大小), 并复制参数与链接数据
+  Builtin(usize, usize),          // 调用内建函数
+  Ret,                            // 将栈顶元素返回
+  CallClean(usize),               // 清理调用 函数后 上级函数的垃圾数据
+  EnterScope,                     // 进入作用域
+  HeapScope(usize),               // 进入在堆上分配的作用域
+  LeaveScope,                     // 离开作用域
+  Pop,                            // 弹出栈顶元素
 
   // 一元操作
   Not, // ! 逻辑取反
@@ -38,25 +58,80 @@ pub enum Opcode {
   Ge,  // >=
 }
 
+/// 运行时错误, 以及发生错误的指令地址
+type Result<T> = result::Result<T, (RuntimeError, usize)>;
+
+/// 堆地址的起始位置, 栈与堆共用同一个地址空间
+///
+/// 被闭包捕获的栈帧会分配在堆上, 函数返回后仍然可以通过静态链访问
+const HEAP_BASE: usize = 1 << 30;
+
+/// 预先分配的栈的长度
+const STACK_SIZE: usize = 1 << 12;
+
+/// 虚拟机的资源限制, None 表示不限制
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+  pub instructions: Option<u64>, // 最多执行的指令数
+  pub stack: Option<usize>,      // 栈的最大长度
+  pub depth: Option<usize>,      // 最大调用深度
+  pub heap: Option<usize>,       // 堆的最大长度, 闭包表的每一项占 3 个单位
+}
+
+/// 分段执行的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+  Paused,                     // 执行完指定数量的指令, 程序尚未结束
+  Finished(isize),            // 程序结束, 以及程序的返回值
+  Error(RuntimeError, usize), // 运行时错误, 以及出错的指令地址
+}
+
 pub struct VM {
   builtins: Builtins,
-  stack: Vec<isize>, // 栈
-  ip: usize,         // 指令指针
-  bp: usize,         // 基地址指针
-  sp: usize,         // 栈顶
+  codes: Vec<Opcode>,     // 通过 load 加载的目标代码
+  status: Option<Status>, // 程序结束或出错后的状态
+  stack: Vec<isize>,      // 栈
+  heap: Vec<isize>,       // 堆, 只分配不回收
+  ip: usize,              // 指令指针
+  bp: usize,              // 基地址指针
+  sp: usize,              // 栈顶
+  limits: Limits,
+  executed: u64,                        // 已经执行的指令数
+  depth: usize,                         // 当前调用深度
+  globals: usize,                       // 全局作用域栈帧的大小, 开始执行程序之后才分配
+  closures: Vec<(usize, usize, usize)>, // 创建过的闭包 (函数地址, 静态链, 参数个数)
+}
+
+impl Default for VM {
+  fn default() -> Self {
+    Self::new()
+  }
 }
 
 impl VM {
   pub fn new() -> Self {
     VM {
       builtins: Builtins::new(),
-      stack: vec![],
+      codes: vec![],
+      status: None,
+      stack: vec![0; STACK_SIZE],
+      heap: vec![],
       ip: 0, // 下一条执行命令的位置
       bp: 0,
       sp: 0, // 指向可以使用的位置
+      limits: Limits::default(),
+      executed: 0,
+      depth: 0,
+      globals: 0,
+      closures: vec![],
     }
   }
 
+  /// 带有资源限制的虚拟机, 超出限制时返回相应的运行时错误
+  pub fn with_limits(limits: Limits) -> Self {
+    VM { limits, ..Self::new() }
+  }
+
   #[allow(unused)]
   pub fn print_codes(codes: &[Opcode]) {
     codes.iter().enumerate().for_each(|(index, code)| {
@@ -67,149 +142,350 @@ impl VM {
   /// 执行虚拟机指令
   ///
   /// codes: 虚拟机指令集
-  pub fn execute(codes: &[Opcode]) -> isize {
+  pub fn execute(codes: &[Opcode]) -> Result<isize> {
+    Self::execute_with_output(codes, &mut io::stdout())
+  }
+
+  /// 执行虚拟机指令, 内建函数的输出写入到 out 中
+  pub fn execute_with_output(codes: &[Opcode], out: &mut dyn Write) -> Result<isize> {
+    VM::new().run(codes, out)
+  }
+
+  /// 在资源限制下执行虚拟机指令, 用于运行不可信的程序
+  pub fn execute_with_limits(codes: &[Opcode], out: &mut dyn Write, limits: Limits) -> Result<isize> {
+    VM::with_limits(limits).run(codes, out)
+  }
+
+  ///
+  /// 从头开始执行虚拟机指令
+  ///
+  /// 先将目标代码解码为 dispatch::Instr (每条指令只需要一次分派, 并合并常见的指令序列), 再执行.
+  /// 有资源限制时不合并指令序列, 以便精确地计算执行的指令数
+  pub fn run(&mut self, codes: &[Opcode], out: &mut dyn Write) -> Result<isize> {
+    if codes.is_empty() {
+      return Ok(0);
+    }
+
+    // Self::print_codes(codes);
+
+    self.enter_program(codes);
+    if self.limits == Limits::default() {
+      self.run_decoded::<false>(&dispatch::decode(codes, true), out)
+    } else {
+      self.run_decoded::<true>(&dispatch::decode(codes, false), out)
+    }
+  }
+
+  /// 不经过解码, 逐条分派原来的指令执行 (引入 dispatch 之前的执行方式), 作为性能对比的基准
+  pub fn execute_step(codes: &[Opcode], out: &mut dyn Write) -> Result<isize> {
     if codes.is_empty() {
-      return 0;
+      return Ok(0);
     }
 
     let mut vm = VM::new();
+    vm.enter_program(codes);
+    loop {
+      if let Some(result) = vm.step(codes, out)? {
+        return Ok(result);
+      }
+    }
+  }
 
-    // Self::print_codes(codes);
+  /// 执行虚拟机指令, 每执行完一条指令调用一次 tracer
+  pub fn execute_with_tracer(codes: &[Opcode], out: &mut dyn Write, tracer: &mut dyn Tracer) -> Result<isize> {
+    VM::new().run_with_tracer(codes, out, tracer)
+  }
+
+  /// 从头开始执行虚拟机指令, 每执行完一条指令调用一次 tracer
+  pub fn run_with_tracer(&mut self, codes: &[Opcode], out: &mut dyn Write, tracer: &mut dyn Tracer) -> Result<isize> {
+    if codes.is_empty() {
+      return Ok(0);
+    }
 
+    let vm = self;
+    vm.enter_program(codes);
     loop {
-      let instruction = codes[vm.ip];
-      // println!("当前指令: {:?}, {:?}", vm.pc, &instruction,);
+      let ip = vm.ip;
+      let result = vm.step(codes, out)?;
+      let (sp, bp) = (vm.sp, vm.bp);
+      tracer.trace(&Trace { ip, instruction: codes[ip], sp, bp, stack: &vm.stack[..sp] });
+
+      if let Some(result) = result {
+        return Ok(result);
+      }
+    }
+  }
 
-      vm.ip += 1;
+  /// 加载目标代码, 并重置虚拟机的状态 (保留资源限制)
+  pub fn load(&mut self, codes: Vec<Opcode>) {
+    *self = VM { codes, limits: self.limits, ..VM::new() };
+  }
 
-      match instruction {
-        Opcode::CallClean(num) => {
-          let result = vm.pop();
-          vm.sp -= num;
-          vm.push(result);
-        }
-        Opcode::Sto(rlevel, address) => {
-          // 将栈顶与 指定位置
-          let address = (vm.base(rlevel) as isize + address) as usize;
-          vm.stack[address] = vm.stack[vm.sp - 1];
-        }
+  ///
+  /// 继续执行通过 load 加载的目标代码, 最多执行 n 条指令
+  ///
+  /// 程序结束或出错之后, 再次调用返回相同的结果
+  pub fn run_for(&mut self, n: u64, out: &mut dyn Write) -> Status {
+    if let Some(status) = self.status {
+      return status;
+    }
+    if self.codes.is_empty() {
+      self.status = Some(Status::Finished(0));
+      return Status::Finished(0);
+    }
 
-        Opcode::Cal(rlevel) => {
-          let ip = vm.pop() as usize;
+    let codes = mem::take(&mut self.codes);
+    if n > 0 {
+      self.enter_program(&codes);
+    }
+    let mut status = Status::Paused;
+    for _ in 0..n {
+      match self.step(&codes, out) {
+        Ok(None) => {}
+        Ok(Some(result)) => status = Status::Finished(result),
+        Err((err, ip)) => status = Status::Error(err, ip),
+      }
+      if status != Status::Paused {
+        self.status = Some(status);
+        break;
+      }
+    }
+    self.codes = codes;
+    status
+  }
 
-          vm.reserve(3);
-          vm.stack[vm.sp] = vm.base(rlevel) as isize;
-          vm.stack[vm.sp + 1] = vm.bp as isize;
-          vm.stack[vm.sp + 2] = vm.ip as isize;
-          vm.bp = vm.sp;
-          vm.ip = ip;
-        }
+  ///
+  /// 在通过 load 加载的程序结束之后, 调用入口地址为 entry 的全局函数
+  ///
+  /// 重新使用全局作用域的栈帧 (全局变量的值保留在栈中), 函数的返回地址为程序最后的 Ret,
+  /// 函数返回之后即停止执行. 资源限制中的指令数对每次调用分别计算
+  pub fn invoke(&mut self, entry: usize, args: &[isize], out: &mut dyn Write) -> Result<isize> {
+    let Some(&Opcode::Int(size)) = self.codes.first() else {
+      return Err((RuntimeError::IllegalFunctionAddress(entry), 0));
+    };
+    if !matches!(self.codes.get(entry), Some(Opcode::Int(_) | Opcode::HeapFrame(..))) || entry == 0 {
+      return Err((RuntimeError::IllegalFunctionAddress(entry), 0));
+    }
 
-        Opcode::Ret => {
-          let x = vm.pop();
-          vm.sp = vm.bp;
-          vm.bp = vm.stack[vm.sp + 1] as usize;
-          vm.ip = vm.stack[vm.sp + 2] as usize;
+    (self.bp, self.sp, self.depth, self.executed) = (0, size, 0, 0);
+    self.reserve(0);
+    for arg in args.iter().rev() {
+      self.push(*arg);
+    }
+    self.ip = self.codes.len() - 1;
+    self.call(entry, self.bp).map_err(|err| (err, self.ip))?;
 
-          vm.push(x);
-        }
+    let codes = mem::take(&mut self.codes);
+    let mut result = Ok(());
+    while self.depth > 0 && result.is_ok() {
+      result = self.step(&codes, out).map(|_| ());
+    }
+    self.codes = codes;
+    result?;
 
-        Opcode::EnterScope => {
-          vm.reserve(1);
-          vm.stack[vm.sp] = vm.bp as isize; // 记录上一层作用域的基地止
-          vm.bp = vm.sp;
-        }
+    let value = self.pop();
+    self.sp -= args.len();
+    Ok(value)
+  }
 
-        Opcode::LeaveScope => {
-          let x = vm.pop();
-          vm.sp = vm.bp;
-          vm.bp = vm.stack[vm.bp] as usize;
-          vm.push(x);
-        }
+  /// 全局作用域中 addr 处的变量的值, 全局作用域的栈帧还没有分配或者 addr 超出栈帧时为 None
+  pub fn global(&self, addr: usize) -> Option<isize> {
+    (addr < self.globals).then(|| self.stack[addr])
+  }
 
-        Opcode::Builtin(id, argc) => {
-          // 调用内建函数
-          let args = (0..argc).map(|_| vm.pop()).collect();
-          let result = vm.builtins.call(id, args);
-          vm.push(result);
-        }
+  /// 程序从头开始执行时, 第一条指令 Int(size) 分配全局作用域的栈帧
+  fn enter_program(&mut self, codes: &[Opcode]) {
+    if let (0, 0, Some(&Opcode::Int(size))) = (self.ip, self.depth, codes.first()) {
+      self.globals = size;
+    }
+  }
 
-        Opcode::Int(num) => {
-          vm.reserve(num);
-          vm.sp += num;
-        }
+  ///
+  /// 当前的执行状态
+  ///
+  /// 只保存栈中使用的部分: Cal 与 EnterScope 之后, Int 分配空间之前, 栈帧的头部在栈顶之上;
+  /// 程序结束之后全局作用域的栈帧也在栈顶之上, 需要保留以便之后调用全局函数
+  pub fn snapshot(&self) -> Snapshot {
+    let frame = if self.bp < HEAP_BASE { self.bp + 3 } else { 0 };
+    let len = self.sp.max(frame).max(self.globals).min(self.stack.len());
+    Snapshot {
+      stack: self.stack[..len].to_vec(),
+      heap: self.heap.clone(),
+      closures: self.closures.clone(),
+      ip: self.ip,
+      bp: self.bp,
+      sp: self.sp,
+      executed: self.executed,
+      depth: self.depth,
+    }
+  }
 
-        Opcode::Jmp(address) => vm.ip = address, //
-        //
-        Opcode::Jpc(address) => {
-          if vm.pop() == 0 {
-            vm.ip = address;
-          }
-        }
+  /// 恢复到快照时的执行状态, 快照需要与已加载的目标代码对应
+  pub fn restore(&mut self, snapshot: Snapshot) -> result::Result<(), String> {
+    let Snapshot { stack, heap, closures, ip, bp, sp, executed, depth } = snapshot;
+    if ip >= self.codes.len() || sp > stack.len() || (bp > stack.len() && bp < HEAP_BASE) {
+      return Err("snapshot does not match the loaded codes".to_string());
+    }
+    // 闭包的函数入口一定是分配栈帧的指令
+    let entry = |&(ip, _, _): &(usize, usize, usize)| {
+      ip != 0 && matches!(self.codes.get(ip), Some(Opcode::Int(_) | Opcode::HeapFrame(..)))
+    };
+    if !closures.iter().all(entry) {
+      return Err("snapshot does not match the loaded codes".to_string());
+    }
 
-        Opcode::Lit(value) => vm.push(value),
-        Opcode::Lod(rlevel, address) => {
-          let address = (address + vm.base(rlevel) as isize) as usize;
-          vm.push(vm.stack[address]);
-        }
+    // 快照中的栈包含全局作用域的栈帧时, 全局作用域已经分配
+    self.globals = match self.codes.first() {
+      Some(&Opcode::Int(size)) if stack.len() >= size => size,
+      _ => 0,
+    };
+    (self.stack, self.heap, self.closures, self.ip, self.bp, self.sp) = (stack, heap, closures, ip, bp, sp);
+    (self.executed, self.depth, self.status) = (executed, depth, None);
+    Ok(())
+  }
 
-        Opcode::Lod1(offset) => {
-          let address = (vm.sp - offset) as usize;
-          vm.push(vm.stack[address]);
-        }
+  /// 执行一条指令, 程序结束时返回程序的返回值
+  fn step(&mut self, codes: &[Opcode], out: &mut dyn Write) -> Result<Option<isize>> {
+    let vm = self;
+    let ip = vm.ip;
+    let instruction = codes[ip];
+    // println!("当前指令: {:?}, {:?}", vm.pc, &instruction,);
 
-        Opcode::Not => {
-          let operator = !(vm.pop() != 0) as isize;
-          vm.push(operator);
-        }
+    if vm.limits.instructions.is_some_and(|limit| vm.executed >= limit) {
+      return Err((RuntimeError::InstructionLimit, ip));
+    }
+    vm.executed += 1;
+    vm.ip += 1;
+
+    match instruction {
+      Opcode::CallClean(num) => {
+        let result = vm.pop();
+        vm.sp -= num;
+        vm.push(result);
+      }
+      Opcode::Sto(rlevel, address) => {
+        // 将栈顶与 指定位置
+        let address = (vm.base(rlevel) as isize + address) as usize;
+        vm.write(address, vm.stack[vm.sp - 1]);
+      }
+
+      Opcode::Cal(rlevel) => {
+        let addr = vm.pop() as usize;
+        vm.call(addr, vm.base(rlevel)).map_err(|err| (err, ip))?;
+      }
+
+      Opcode::Closure(rlevel, argc) => {
+        let addr = vm.pop() as usize;
+        vm.closure(addr, vm.base(rlevel), argc).map_err(|err| (err, ip))?;
+      }
+
+      Opcode::CallIndirect(argc) => {
+        let (addr, sl) = vm.unpack_closure(argc).map_err(|err| (err, ip))?;
+        vm.call(addr, sl).map_err(|err| (err, ip))?;
+      }
+
+      Opcode::TailCall(rlevel, argc, depth) => {
+        let ip = vm.pop() as usize;
+        vm.tail_call(ip, vm.base(rlevel), argc, depth);
+      }
+
+      Opcode::TailCallIndirect(argc, depth) => {
+        let (ip, sl) = vm.unpack_closure(argc).map_err(|err| (err, ip))?;
+        vm.tail_call(ip, sl, argc, depth);
+      }
+
+      Opcode::HeapFrame(argc, size) => vm.heap_frame(argc, size).map_err(|err| (err, ip))?,
+      Opcode::Ret => vm.ret(),
+      Opcode::EnterScope => vm.enter_scope(),
+      Opcode::HeapScope(size) => vm.heap_scope(size).map_err(|err| (err, ip))?,
+      Opcode::LeaveScope => vm.leave_scope(),
+
+      Opcode::Builtin(id, argc) => {
+        // 调用内建函数
+        let args = (0..argc).map(|_| vm.pop()).collect();
+        let result = vm.builtins.call(id, args, out);
+        vm.push(result);
+      }
+
+      Opcode::Int(num) => {
+        vm.reserve(num);
+        vm.sp += num;
+      }
+
+      Opcode::Pop => {
+        vm.pop();
+      }
 
-        _ => {
-          let op2 = vm.pop();
-          let op1 = vm.pop();
-
-          match instruction {
-            Opcode::Add => vm.push(op1 + op2),
-            Opcode::Sub => vm.push(op1 - op2),
-            Opcode::Div => vm.push(op1 / op2),
-            Opcode::Mul => vm.push(op1 * op2),
-            Opcode::Lt => vm.push((op1 < op2) as isize),
-            Opcode::Gt => vm.push((op1 > op2) as isize),
-            Opcode::Le => vm.push((op1 <= op2) as isize),
-            Opcode::Ge => vm.push((op1 >= op2) as isize),
-            Opcode::Eq => vm.push((op1 == op2) as isize),
-            Opcode::Ne => vm.push((op1 != op2) as isize),
-            _ => unreachable!(),
-          };
+      Opcode::Jmp(address) => vm.ip = address, //
+      //
+      Opcode::Jpc(address) => {
+        if vm.pop() == 0 {
+          vm.ip = address;
         }
       }
 
-      // println!(
-      //   "{:?}, sp: {:?}, ip: {:?}, bp: {:?}",
-      //   &vm.stack[0..vm.sp],
-      //   vm.sp,
-      //   vm.ip,
-      //   vm.bp
-      // );
+      Opcode::Lit(value) => vm.push(value),
+      Opcode::Lod(rlevel, address) => {
+        let address = (address + vm.base(rlevel) as isize) as usize;
+        vm.push(vm.read(address));
+      }
 
-      if vm.ip == 0 {
-        break;
+      Opcode::Lod1(offset) => {
+        let address = vm.sp - offset;
+        vm.push(vm.stack[address]);
+      }
+
+      Opcode::Not => {
+        let operator = !(vm.pop() != 0) as isize;
+        vm.push(operator);
+      }
+
+      _ => {
+        let op2 = vm.pop();
+        let op1 = vm.pop();
+
+        let result = match instruction {
+          Opcode::Add => op1.checked_add(op2),
+          Opcode::Sub => op1.checked_sub(op2),
+          Opcode::Div if op2 == 0 => return Err((RuntimeError::DivisionByZero, ip)),
+          Opcode::Div => op1.checked_div(op2),
+          Opcode::Mul => op1.checked_mul(op2),
+          Opcode::Lt => Some((op1 < op2) as isize),
+          Opcode::Gt => Some((op1 > op2) as isize),
+          Opcode::Le => Some((op1 <= op2) as isize),
+          Opcode::Ge => Some((op1 >= op2) as isize),
+          Opcode::Eq => Some((op1 == op2) as isize),
+          Opcode::Ne => Some((op1 != op2) as isize),
+          _ => unreachable!(),
+        };
+        vm.push(result.ok_or((RuntimeError::Overflow, ip))?);
       }
     }
 
+    if vm.limits.stack.is_some_and(|limit| vm.sp > limit) {
+      return Err((RuntimeError::StackOverflow, ip));
+    }
+    if vm.ip != 0 {
+      return Ok(None);
+    }
     if vm.sp != 1 {
       panic!("虚拟机栈未清理干净");
     }
-    vm.stack[0]
+    Ok(Some(vm.stack[0]))
   }
 
   /// 压栈
+  #[inline(always)]
   fn push(&mut self, value: isize) {
-    self.reserve(1);
+    if self.sp == self.stack.len() {
+      self.reserve(1);
+    }
     self.stack[self.sp] = value;
     self.sp += 1;
   }
 
   /// 弹栈
+  #[inline(always)]
   fn pop(&mut self) -> isize {
     self.sp -= 1;
     self.stack[self.sp]
@@ -217,26 +493,365 @@ impl VM {
 
   /// 通过过程基址求上 level 层过程的基地止
   fn base(&self, rlevel: usize) -> usize {
-    (0..rlevel).fold(self.bp, |pre, _| self.stack[pre] as usize)
+    (0..rlevel).fold(self.bp, |pre, _| self.read(pre) as usize)
+  }
+
+  /// 建立函数调用的栈帧: 静态链, 动态链, 返回地址
+  fn call(&mut self, ip: usize, sl: usize) -> result::Result<(), RuntimeError> {
+    if self.limits.depth.is_some_and(|limit| self.depth >= limit) {
+      return Err(RuntimeError::CallDepthLimit);
+    }
+    self.depth += 1;
+
+    self.reserve(3);
+    self.stack[self.sp] = sl as isize;
+    self.stack[self.sp + 1] = self.bp as isize;
+    self.stack[self.sp + 2] = self.ip as isize;
+    self.bp = self.sp;
+    self.ip = ip;
+    Ok(())
+  }
+
+  /// 将 Cal 建立的栈帧复制到堆上 (参数个数, 栈帧大小), Cal 在栈上建立的栈帧保留下来, 用于返回时恢复栈顶
+  fn heap_frame(&mut self, argc: usize, size: usize) -> result::Result<(), RuntimeError> {
+    let frame = self.bp;
+    let base = self.alloc(argc + size)? + argc;
+
+    for index in 1..=argc {
+      self.write(base - index, self.stack[frame - index]);
+    }
+    self.write(base, self.stack[frame]);
+    self.write(base + 1, frame as isize);
+    self.write(base + 2, self.stack[frame + 2]);
+
+    self.sp = frame + 3;
+    self.bp = base;
+    Ok(())
+  }
+
+  /// 将栈顶元素返回
+  fn ret(&mut self) {
+    let x = self.pop();
+    // 堆上的栈帧中记录了调用时在栈上建立的栈帧
+    let frame = if self.bp >= HEAP_BASE { self.read(self.bp + 1) as usize } else { self.bp };
+    self.sp = frame;
+    self.bp = self.stack[frame + 1] as usize;
+    self.ip = self.stack[frame + 2] as usize;
+    self.depth = self.depth.saturating_sub(1);
+
+    self.push(x);
+  }
+
+  fn enter_scope(&mut self) {
+    self.reserve(1);
+    self.stack[self.sp] = self.bp as isize; // 记录上一层作用域的基地止
+    self.bp = self.sp;
+  }
+
+  fn heap_scope(&mut self, size: usize) -> result::Result<(), RuntimeError> {
+    let base = self.alloc(size)?;
+    self.write(base, self.bp as isize); // 记录上一层作用域的基地止
+    self.write(base + 1, self.sp as isize); // 记录进入作用域时的栈顶
+    self.bp = base;
+    Ok(())
+  }
+
+  fn leave_scope(&mut self) {
+    let x = self.pop();
+    let frame = self.bp;
+    self.sp = if frame >= HEAP_BASE { self.read(frame + 1) as usize } else { frame };
+    self.bp = self.read(frame) as usize;
+    self.push(x);
+  }
+
+  /// 复用当前函数在栈上的栈帧调用函数
+  ///
+  /// 实参移动到当前函数实参的位置 (与栈帧对齐), 动态链与返回地址保持不变
+  fn tail_call(&mut self, ip: usize, sl: usize, argc: usize, depth: usize) {
+    let frame = (0..depth).fold(self.bp, |pre, _| self.read(pre) as usize);
+    let frame = if frame >= HEAP_BASE { self.read(frame + 1) as usize } else { frame };
+
+    for index in 1..=argc {
+      self.stack[frame - index] = self.stack[self.sp - index];
+    }
+    self.stack[frame] = sl as isize;
+
+    self.sp = frame;
+    self.bp = frame;
+    self.ip = ip;
+  }
+
+  /// 创建闭包: 高 32 位为闭包表中的序号 (从 1 开始), 低 32 位为函数地址
+  fn closure(&mut self, ip: usize, sl: usize, argc: usize) -> result::Result<(), RuntimeError> {
+    self.check_heap(3)?;
+    self.closures.push((ip, sl, argc));
+    self.push(((self.closures.len() as isize) << 32) | ip as isize);
+    Ok(())
   }
 
+  /// 弹出栈顶的闭包并解开, 返回函数地址与静态链
   ///
+  /// 闭包只能由 Closure 创建, 与闭包表不对应的值 (例如对闭包做算术运算的结果) 不能调用;
+  /// 实参个数必须与函数的参数个数一致, 否则被调用的函数会读取调用方栈帧之外的数据
+  fn unpack_closure(&mut self, argc: usize) -> result::Result<(usize, usize), RuntimeError> {
+    let closure = self.pop();
+    let (index, address) = ((closure >> 32) as usize, (closure & 0xffff_ffff) as usize);
+    let &(ip, sl, expect) = match index.checked_sub(1).and_then(|index| self.closures.get(index)) {
+      Some(entry) if entry.0 == address => entry,
+      _ => return Err(RuntimeError::IllegalFunctionAddress(address)),
+    };
+    if argc != expect {
+      return Err(RuntimeError::ArityMismatch(expect, argc));
+    }
+    Ok((ip, sl))
+  }
+
+  /// 读取栈或堆上的数据
+  fn read(&self, address: usize) -> isize {
+    if address >= HEAP_BASE {
+      self.heap[address - HEAP_BASE]
+    } else {
+      self.stack[address]
+    }
+  }
+
+  /// 写入栈或堆上的数据
+  fn write(&mut self, address: usize, value: isize) {
+    if address >= HEAP_BASE {
+      self.heap[address - HEAP_BASE] = value;
+    } else {
+      self.stack[address] = value;
+    }
+  }
+
+  /// 在堆上分配 size 大小的空间, 返回其起始地址
+  fn alloc(&mut self, size: usize) -> result::Result<usize, RuntimeError> {
+    self.check_heap(size)?;
+    let address = HEAP_BASE + self.heap.len();
+    self.heap.resize(self.heap.len() + size, 0);
+    Ok(address)
+  }
+
+  /// 堆上再分配 size 个单位之后是否超出限制, 堆只分配不回收, 闭包表同样只增不减
+  fn check_heap(&self, size: usize) -> result::Result<(), RuntimeError> {
+    match self.limits.heap {
+      Some(limit) if self.heap.len() + self.closures.len() * 3 + size > limit => Err(RuntimeError::HeapLimit),
+      _ => Ok(()),
+    }
+  }
+
+  /// 预留栈空间, 空间不足时至少扩大为原来的两倍
+  #[inline(always)]
   fn reserve(&mut self, additional: usize) {
     let expect_len = self.sp + additional;
 
     if self.stack.len() < expect_len {
-      (0..expect_len - self.stack.len()).for_each(|_| self.stack.push(0));
+      self.stack.resize(expect_len.max(self.stack.len() * 2), 0);
     }
   }
 }
 
 /// 运行时错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RuntimeError {
-  IllegalFunctionAddress(usize), // ()
+  IllegalFunctionAddress(usize), // 调用的函数地址不是函数入口
+  DivisionByZero,                // 除数为 0
+  Overflow,                      // 整数运算溢出
+  InstructionLimit,              // 执行的指令数超出限制
+  StackOverflow,                 // 栈的长度超出限制
+  CallDepthLimit,                // 调用深度超出限制
+  HeapLimit,                     // 堆的长度超出限制
+  ArityMismatch(usize, usize),   // 实参个数与函数的参数个数不一致 (参数个数, 实参个数)
+}
+
+impl Display for RuntimeError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      RuntimeError::IllegalFunctionAddress(address) => write!(f, "illegal function address: {}", address),
+      RuntimeError::DivisionByZero => write!(f, "division by zero"),
+      RuntimeError::Overflow => write!(f, "integer overflow"),
+      RuntimeError::InstructionLimit => write!(f, "instruction limit exceeded"),
+      RuntimeError::StackOverflow => write!(f, "stack overflow"),
+      RuntimeError::CallDepthLimit => write!(f, "call depth limit exceeded"),
+      RuntimeError::HeapLimit => write!(f, "heap limit exceeded"),
+      RuntimeError::ArityMismatch(expect, argc) => {
+        write!(f, "function expects {} arguments, but {} were given", expect, argc)
+      }
+    }
+  }
 }
 
 #[cfg(test)]
 mod tests {
+  use std::io;
+
+  use crate::{compiler::Compiler, generator::Generator, parser::Paser};
+
+  use super::{snapshot::Snapshot, Limits, Opcode, RuntimeError, Status, VM};
+
   #[test]
   fn test() {}
+
+  fn execute(source: &str, limits: Limits) -> super::Result<isize> {
+    let codes = Compiler::compile(&Paser::paser(source).unwrap()).unwrap();
+    VM::execute_with_limits(&codes, &mut vec![], limits)
+  }
+
+  fn opcode(source: &str, ip: usize) -> Opcode {
+    Compiler::compile(&Paser::paser(source).unwrap()).unwrap()[ip]
+  }
+
+  #[test]
+  fn test_limits() {
+    let limits = Limits { instructions: Some(10_000), stack: Some(1000), depth: Some(100), heap: Some(1000) };
+
+    // 死循环在执行的指令数达到限制时停止
+    let source = "var i = 0; while 1 { i += 1 }";
+    let (err, ip) = execute(source, limits).unwrap_err();
+    assert_eq!(err, RuntimeError::InstructionLimit);
+    assert!(ip < Compiler::compile(&Paser::paser(source).unwrap()).unwrap().len());
+
+    // 无限递归: 调用深度与栈的长度
+    let source = "fn f(n) { f(n + 1) + 1 } f(0)";
+    let (err, ip) = execute(source, limits).unwrap_err();
+    assert_eq!((err, opcode(source, ip)), (RuntimeError::CallDepthLimit, Opcode::Cal(1)));
+
+    let (err, _) = execute(source, Limits { depth: None, ..limits }).unwrap_err();
+    assert_eq!(err, RuntimeError::StackOverflow);
+
+    // 尾递归不会增加调用深度与栈的长度, 只受指令数的限制
+    let source = "fn f(n) { f(n + 1) } f(0)";
+    assert_eq!(execute(source, limits).unwrap_err().0, RuntimeError::InstructionLimit);
+
+    // 循环中创建捕获参数的闭包, 堆上的栈帧与闭包表不会回收
+    let source = "fn f(n) { fn g() { n } g } var i = 0; while 1 { f(i); i += 1 }";
+    let (err, ip) = execute(source, limits).unwrap_err();
+    assert_eq!(err, RuntimeError::HeapLimit);
+    assert!(matches!(opcode(source, ip), Opcode::HeapFrame(..) | Opcode::Closure(..)));
+    assert_eq!(execute(source, Limits { heap: None, ..limits }).unwrap_err().0, RuntimeError::InstructionLimit);
+
+    // 正常结束的程序不受影响
+    let source = "fn fib(n) { if n <= 1 { n } else { fib(n - 1) + fib(n - 2) } } fib(10)";
+    assert_eq!(execute(source, limits), Ok(55));
+    assert_eq!(
+      execute(source, Limits { instructions: Some(100), ..limits }).unwrap_err().0,
+      RuntimeError::InstructionLimit
+    );
+  }
+
+  #[test]
+  fn test_closure() {
+    let cases = [
+      // 实参个数与函数的参数个数不一致, 包括尾调用
+      ("fn f(a, b, c, d, e, f2, g, h, i, j) { j } var g = f; g()", RuntimeError::ArityMismatch(10, 0)),
+      ("fn f(a, b) { a } fn h(g) { g(1) } h(f)", RuntimeError::ArityMismatch(2, 1)),
+      // 对闭包做算术运算得到的值不是闭包
+      ("fn f() { 1 } var g = f + 4294967296 * 1000; g()", RuntimeError::IllegalFunctionAddress(3)),
+      ("var f = -1; f(1)", RuntimeError::IllegalFunctionAddress(0xffff_ffff)),
+      ("fn f() { 1 } var g = f; g = g + 1; g()", RuntimeError::IllegalFunctionAddress(4)),
+    ];
+
+    for (source, expect) in cases {
+      assert_eq!(execute(source, Limits::default()).unwrap_err().0, expect, "{}", source);
+
+      let mut vm = VM::new();
+      vm.load(Compiler::compile(&Paser::paser(source).unwrap()).unwrap());
+      assert!(matches!(vm.run_for(u64::MAX, &mut io::sink()), Status::Error(err, _) if err == expect), "{}", source);
+    }
+  }
+
+  #[test]
+  fn test_run_for() {
+    let fib = Compiler::compile(
+      &Paser::paser("fn fib(n) { if n <= 1 { n } else { fib(n - 1) + fib(n - 2) } } fib(15)").unwrap(),
+    )
+    .unwrap();
+    let div = Compiler::compile(&Paser::paser("var i = 3; while i >= 0 { println(6 / i); i -= 1 }").unwrap()).unwrap();
+
+    // 两个程序轮流执行, 每次执行 100 条指令
+    let (mut a, mut b) = (VM::new(), VM::new());
+    a.load(fib);
+    b.load(div);
+    let (mut out_a, mut out_b) = (vec![], vec![]);
+    let (mut status_a, mut status_b) = (Status::Paused, Status::Paused);
+    let mut slices = 0;
+    while status_a == Status::Paused || status_b == Status::Paused {
+      status_a = a.run_for(100, &mut out_a);
+      status_b = b.run_for(100, &mut out_b);
+      slices += 1;
+    }
+    assert_eq!(status_a, Status::Finished(610));
+    assert!(matches!(status_b, Status::Error(RuntimeError::DivisionByZero, _)));
+    assert_eq!(String::from_utf8(out_b).unwrap(), "\"2\"\n\"3\"\n\"6\"\n");
+    assert!(slices > 10);
+
+    // 结束之后再次调用返回相同的结果
+    assert_eq!(a.run_for(100, &mut out_a), Status::Finished(610));
+    assert_eq!(VM::new().run_for(1, &mut vec![]), Status::Finished(0));
+  }
+
+  #[test]
+  fn test_snapshot() {
+    for seed in 0..30 {
+      let codes = Compiler::compile(&Generator::new(seed).program()).unwrap();
+      let mut expect_out = vec![];
+      let expect = VM::execute_with_output(&codes, &mut expect_out);
+
+      // 每执行 7 条指令保存一次快照, 再在新的虚拟机中恢复并继续执行
+      let mut vm = VM::new();
+      vm.load(codes.clone());
+      let mut out = vec![];
+      let status = loop {
+        match vm.run_for(7, &mut out) {
+          Status::Paused => {
+            let mut file = vec![];
+            vm.snapshot().write(&mut file).unwrap();
+            vm = VM::new();
+            vm.load(codes.clone());
+            vm.restore(Snapshot::read(file.as_slice()).unwrap()).unwrap();
+          }
+          status => break status,
+        }
+      };
+
+      match expect {
+        Ok(result) => assert_eq!(status, Status::Finished(result), "seed {}", seed),
+        Err((err, ip)) => assert_eq!(status, Status::Error(err, ip), "seed {}", seed),
+      }
+      assert_eq!(out, expect_out, "seed {}", seed);
+    }
+
+    // 快照与加载的目标代码不对应
+    let mut vm = VM::new();
+    vm.load(vec![Opcode::Int(3), Opcode::Ret]);
+    let snapshot =
+      Snapshot { stack: vec![], heap: vec![], closures: vec![], ip: 10, bp: 0, sp: 0, executed: 0, depth: 0 };
+    assert!(vm.restore(snapshot).is_err());
+  }
+
+  #[test]
+  fn test_global() {
+    let codes = Compiler::compile(&Paser::paser("var a = 1, b = 2; fn f() { a += b } f()").unwrap()).unwrap();
+    let Opcode::Int(size) = codes[0] else { unreachable!() };
+
+    // 开始执行之前没有全局变量
+    let mut vm = VM::new();
+    vm.load(codes.clone());
+    assert_eq!(vm.global(3), None);
+    assert!(vm.snapshot().stack.len() < 16);
+
+    // 执行中与结束之后都只能访问全局作用域的栈帧, 快照只保存栈中使用的部分
+    vm.run_for(4, &mut io::sink());
+    assert!(vm.snapshot().stack.len() < 16);
+    assert_eq!(vm.run_for(u64::MAX, &mut io::sink()), Status::Finished(3));
+    assert_eq!((vm.global(3), vm.global(4), vm.global(size)), (Some(3), Some(2), None));
+    assert_eq!(vm.snapshot().stack.len(), size);
+
+    // 恢复结束时的快照之后, 全局变量依然可以访问
+    let snapshot = vm.snapshot();
+    let mut vm = VM::new();
+    vm.load(codes);
+    vm.restore(snapshot).unwrap();
+    assert_eq!(vm.global(3), Some(3));
+  }
 }
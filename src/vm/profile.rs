@@ -0,0 +1,243 @@
+use std::{
+  cmp::Reverse,
+  collections::BTreeMap,
+  io::{self, Write},
+};
+
+use crate::compiler::debuginfo::DebugFunction;
+
+use super::{
+  trace::{Trace, Tracer},
+  Opcode,
+};
+
+/// 一个函数的统计数据
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FunctionProfile {
+  pub name: String,
+  pub entry: usize,   // 入口地址, 全局作用域为 0
+  pub calls: u64,     // 调用次数 (包括尾调用)
+  pub inclusive: u64, // 执行的指令数, 包括调用的其他函数
+  pub exclusive: u64, // 执行的指令数, 只包括函数本身
+  active: usize,      // 当前在调用栈中的次数, 递归时只统计最外层的 inclusive
+}
+
+///
+/// 指令级别的性能分析
+///
+/// 统计每个地址的指令执行次数, 并按照函数的入口地址 (符号表中过程的值) 将指令归属到函数:
+/// 执行到函数入口时压入影子调用栈, 执行 Ret 之后弹出, 尾调用会替换栈顶的函数
+pub struct Profiler {
+  counts: Vec<u64>,                // 每个地址的指令执行次数
+  functions: Vec<FunctionProfile>, // 下标 0 为全局作用域
+  entries: BTreeMap<usize, usize>, // 入口地址 -> 函数
+  stack: Vec<(usize, u64)>,        // 影子调用栈: 函数, 进入时已经执行的指令数
+  total: u64,                      // 执行的指令总数
+  tail_call: bool,                 // 上一条指令为尾调用
+  pub max_sp: usize,               // 最大栈深度
+  pub max_depth: usize,            // 最大调用深度
+}
+
+impl Profiler {
+  /// functions: 调试信息中每个函数的名字与入口地址
+  pub fn new(codes: &[Opcode], functions: &[DebugFunction]) -> Self {
+    let main = FunctionProfile { name: "<main>".to_string(), ..Default::default() };
+    let functions = [main]
+      .into_iter()
+      .chain(functions.iter().map(|function| FunctionProfile {
+        name: function.name.clone(),
+        entry: function.entry,
+        ..Default::default()
+      }))
+      .collect::<Vec<_>>();
+    let entries = functions.iter().enumerate().skip(1).map(|(index, function)| (function.entry, index)).collect();
+
+    Profiler {
+      counts: vec![0; codes.len()],
+      functions,
+      entries,
+      stack: vec![],
+      total: 0,
+      tail_call: false,
+      max_sp: 0,
+      max_depth: 0,
+    }
+  }
+
+  /// 每个地址的指令执行次数
+  pub fn counts(&self) -> &[u64] {
+    &self.counts
+  }
+
+  /// 执行的指令总数
+  pub fn total(&self) -> u64 {
+    self.total
+  }
+
+  /// 每个函数的统计数据, 下标 0 为全局作用域
+  pub fn functions(&self) -> &[FunctionProfile] {
+    &self.functions
+  }
+
+  /// 按照指令种类 (不含操作数) 统计执行次数, 次数多的在前
+  pub fn opcodes(&self, codes: &[Opcode]) -> Vec<(String, u64)> {
+    let mut kinds = BTreeMap::new();
+    for (code, count) in codes.iter().zip(&self.counts).filter(|(_, count)| **count > 0) {
+      let name = format!("{:?}", code);
+      let name = name.split('(').next().unwrap_or_default().to_string();
+      *kinds.entry(name).or_insert(0) += count;
+    }
+
+    let mut kinds = kinds.into_iter().collect::<Vec<_>>();
+    kinds.sort_by_key(|(_, count)| Reverse(*count));
+    kinds
+  }
+
+  fn enter(&mut self, function: usize) {
+    self.functions[function].calls += 1;
+    self.functions[function].active += 1;
+    self.stack.push((function, self.total));
+    self.max_depth = self.max_depth.max(self.stack.len() - 1);
+  }
+
+  fn leave(&mut self) {
+    if let Some((function, start)) = self.stack.pop() {
+      let function = &mut self.functions[function];
+      function.active -= 1;
+      if function.active == 0 {
+        function.inclusive += self.total - start;
+      }
+    }
+  }
+
+  /// 输出性能分析报告, hot 为输出的热点指令个数
+  pub fn report(&self, codes: &[Opcode], hot: usize, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "指令总数: {}, 最大栈深度: {}, 最大调用深度: {}", self.total, self.max_sp, self.max_depth)?;
+
+    writeln!(out, "\n{}", header(&[("指令", -16), ("次数", 12), ("%", 7)]))?;
+    for (name, count) in self.opcodes(codes) {
+      writeln!(out, "{:<16} {:>12} {:>7.2}", name, count, self.percent(count))?;
+    }
+
+    let mut functions = self.functions.iter().filter(|function| function.calls > 0).collect::<Vec<_>>();
+    functions.sort_by_key(|function| Reverse(function.exclusive));
+    let columns = [("函数", -16), ("入口", 6), ("调用次数", 10), ("包含", 12), ("独占", 12), ("独占%", 7)];
+    writeln!(out, "\n{}", header(&columns))?;
+    for function in functions {
+      writeln!(
+        out,
+        "{:<16} {:>5X}H {:>10} {:>12} {:>12} {:>7.2}",
+        function.name,
+        function.entry,
+        function.calls,
+        function.inclusive,
+        function.exclusive,
+        self.percent(function.exclusive)
+      )?;
+    }
+
+    let mut addresses = (0..codes.len()).filter(|ip| self.counts[*ip] > 0).collect::<Vec<_>>();
+    addresses.sort_by(|a, b| self.counts[*b].cmp(&self.counts[*a]).then(a.cmp(b)));
+    writeln!(out, "\n{}", header(&[("地址", -5), ("指令", -24), ("次数", 12)]))?;
+    for ip in addresses.into_iter().take(hot) {
+      writeln!(out, "{:04X}H {:<24} {:>12}", ip, format!("{:?}", codes[ip]), self.counts[ip])?;
+    }
+    Ok(())
+  }
+
+  fn percent(&self, count: u64) -> f64 {
+    count as f64 * 100.0 / self.total.max(1) as f64
+  }
+}
+
+/// 表头, 宽度为负数时左对齐, 中文字符占两个字符的宽度
+fn header(columns: &[(&str, isize)]) -> String {
+  let columns = columns.iter().map(|(name, width)| {
+    let padding =
+      " ".repeat(width.unsigned_abs().saturating_sub(name.chars().map(|c| 1 + !c.is_ascii() as usize).sum()));
+    if *width < 0 {
+      format!("{}{}", name, padding)
+    } else {
+      format!("{}{}", padding, name)
+    }
+  });
+  columns.collect::<Vec<_>>().join(" ").trim_end().to_string()
+}
+
+impl Tracer for Profiler {
+  fn trace(&mut self, trace: &Trace) {
+    if self.stack.is_empty() {
+      self.enter(0);
+    }
+    if let Some(&function) = self.entries.get(&trace.ip) {
+      if self.tail_call {
+        self.leave();
+      }
+      self.enter(function);
+    }
+
+    self.total += 1;
+    self.counts[trace.ip] += 1;
+    self.functions[self.stack.last().unwrap().0].exclusive += 1;
+    self.max_sp = self.max_sp.max(trace.sp);
+    self.tail_call = matches!(trace.instruction, Opcode::TailCall(..) | Opcode::TailCallIndirect(..));
+
+    // 全局作用域的 Ret 为程序结束
+    if trace.instruction == Opcode::Ret {
+      self.leave();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{compiler::Compiler, parser::Paser, vm::VM};
+
+  use super::Profiler;
+
+  fn profile(source: &str) -> (Vec<crate::vm::Opcode>, Profiler) {
+    let (codes, debug) = Compiler::compile_with_debug(&Paser::paser(source).unwrap()).unwrap();
+    let mut profiler = Profiler::new(&codes, &debug.functions);
+    VM::execute_with_tracer(&codes, &mut vec![], &mut profiler).unwrap();
+    (codes, profiler)
+  }
+
+  #[test]
+  fn test_recursion() {
+    let (codes, profiler) = profile("fn fib(n) { if n <= 1 { n } else { fib(n - 1) + fib(n - 2) } } fib(10)");
+    let functions = profiler.functions();
+    let (main, fib) = (&functions[0], &functions[1]);
+
+    assert_eq!((main.calls, fib.name.as_str(), fib.calls), (1, "fib", 177));
+    assert_eq!(main.inclusive, profiler.total());
+    assert_eq!(main.exclusive + fib.exclusive, profiler.total());
+    // 递归调用只统计最外层的调用
+    assert_eq!(fib.inclusive, fib.exclusive);
+    assert_eq!(profiler.max_depth, 10);
+    assert_eq!(profiler.counts()[fib.entry], 177);
+
+    let opcodes = profiler.opcodes(&codes);
+    assert_eq!(opcodes.iter().map(|(_, count)| count).sum::<u64>(), profiler.total());
+    assert!(opcodes.contains(&("Ret".to_string(), 178)));
+  }
+
+  #[test]
+  fn test_nested() {
+    // h 尾调用 f, f 替换栈顶的 h, 不增加调用深度
+    let source = "fn g(x) { x * 2 } fn f(n) { var s = 0; while n > 0 { s += g(n); n -= 1 } s } fn h(n) { f(n) } h(3)";
+    let (codes, profiler) = profile(source);
+    let functions = profiler.functions();
+    let calls = functions.iter().map(|function| (function.name.as_str(), function.calls)).collect::<Vec<_>>();
+    assert_eq!(calls, [("<main>", 1), ("g", 3), ("f", 1), ("h", 1)]);
+
+    let (g, f, h) = (&functions[1], &functions[2], &functions[3]);
+    assert_eq!(f.inclusive, f.exclusive + g.inclusive);
+    assert_eq!(h.inclusive, h.exclusive);
+    assert_eq!(profiler.max_depth, 2);
+
+    let mut report = vec![];
+    profiler.report(&codes, 5, &mut report).unwrap();
+    let report = String::from_utf8(report).unwrap();
+    assert!(report.lines().any(|line| line.starts_with("g ") && line.contains(" 3 ")));
+  }
+}
@@ -0,0 +1,70 @@
+use std::io::{self, Read, Write};
+
+use serde_json::{json, Value};
+
+///
+/// 虚拟机执行状态的快照: 栈, 堆以及各个寄存器
+///
+/// 快照中不包含目标代码, 恢复时虚拟机需要已经加载了同一份目标代码
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+  pub stack: Vec<isize>,
+  pub heap: Vec<isize>,
+  pub closures: Vec<(usize, usize, usize)>, // 闭包 (函数地址, 静态链, 参数个数)
+  pub ip: usize,
+  pub bp: usize,
+  pub sp: usize,
+  pub executed: u64, // 已经执行的指令数
+  pub depth: usize,  // 当前调用深度
+}
+
+impl Snapshot {
+  pub fn to_json(&self) -> Value {
+    json!({
+      "stack": self.stack,
+      "heap": self.heap,
+      "closures": self.closures,
+      "ip": self.ip,
+      "bp": self.bp,
+      "sp": self.sp,
+      "executed": self.executed,
+      "depth": self.depth,
+    })
+  }
+
+  pub fn from_json(value: &Value) -> Option<Self> {
+    let numbers = |key: &str| -> Option<Vec<isize>> {
+      value[key].as_array()?.iter().map(|value| value.as_i64().map(|value| value as isize)).collect()
+    };
+    let number = |key: &str| value[key].as_u64();
+    let closure = |value: &Value| -> Option<(usize, usize, usize)> {
+      match value.as_array()?.as_slice() {
+        [ip, sl, argc] => Some((ip.as_u64()? as usize, sl.as_u64()? as usize, argc.as_u64()? as usize)),
+        _ => None,
+      }
+    };
+
+    Some(Snapshot {
+      stack: numbers("stack")?,
+      heap: numbers("heap")?,
+      closures: value["closures"].as_array()?.iter().map(closure).collect::<Option<_>>()?,
+      ip: number("ip")? as usize,
+      bp: number("bp")? as usize,
+      sp: number("sp")? as usize,
+      executed: number("executed")?,
+      depth: number("depth")? as usize,
+    })
+  }
+
+  /// 以 JSON 格式写入快照, 例如保存到文件中
+  pub fn write(&self, mut output: impl Write) -> io::Result<()> {
+    serde_json::to_writer(&mut output, &self.to_json())?;
+    output.flush()
+  }
+
+  /// 读取 write 写入的快照
+  pub fn read(input: impl Read) -> io::Result<Self> {
+    let value = serde_json::from_reader(input)?;
+    Self::from_json(&value).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid snapshot"))
+  }
+}
@@ -0,0 +1,151 @@
+use std::{
+  io::{self, Write},
+  ops::Range,
+};
+
+use serde_json::json;
+
+use super::Opcode;
+
+/// 执行一条指令之后虚拟机的状态
+#[derive(Debug)]
+pub struct Trace<'a> {
+  pub ip: usize,           // 指令地址
+  pub instruction: Opcode, // 执行的指令
+  pub sp: usize,           // 执行之后的栈顶
+  pub bp: usize,           // 执行之后的基地址
+  pub stack: &'a [isize],  // 执行之后栈中的数据 (栈底到栈顶)
+}
+
+/// 每执行一条指令调用一次
+pub trait Tracer {
+  fn trace(&mut self, trace: &Trace);
+}
+
+impl<F: FnMut(&Trace)> Tracer for F {
+  fn trace(&mut self, trace: &Trace) {
+    self(trace)
+  }
+}
+
+///
+/// 以 JSON lines 的格式输出每条指令的执行记录, 每行一个对象:
+///
+/// `{"bp":5,"ip":5,"op":"Lod(0, -1)","sp":10,"top":[0,36,0,5]}`
+///
+/// top 为栈顶的若干个数据, 最后一个为栈顶
+pub struct JsonTracer<W: Write> {
+  output: W,
+  range: Option<Range<usize>>, // 只记录该地址范围内的指令
+  slots: usize,                // 记录栈顶数据的个数
+  error: Option<io::Error>,
+}
+
+impl<W: Write> JsonTracer<W> {
+  pub fn new(output: W) -> Self {
+    JsonTracer { output, range: None, slots: 4, error: None }
+  }
+
+  /// 只记录地址在 range 中的指令, 例如一个函数的代码
+  pub fn with_range(mut self, range: Range<usize>) -> Self {
+    self.set_range(range);
+    self
+  }
+
+  pub fn set_range(&mut self, range: Range<usize>) {
+    self.range = Some(range);
+  }
+
+  /// 记录栈顶数据的个数
+  pub fn with_slots(mut self, slots: usize) -> Self {
+    self.slots = slots;
+    self
+  }
+
+  /// 写入过程中的第一个错误, 并刷新输出
+  pub fn finish(mut self) -> io::Result<W> {
+    if let Some(err) = self.error.take() {
+      return Err(err);
+    }
+    self.output.flush()?;
+    Ok(self.output)
+  }
+}
+
+impl<W: Write> Tracer for JsonTracer<W> {
+  fn trace(&mut self, trace: &Trace) {
+    if self.error.is_some() || self.range.as_ref().is_some_and(|range| !range.contains(&trace.ip)) {
+      return;
+    }
+
+    let top = &trace.stack[trace.stack.len().saturating_sub(self.slots)..];
+    let line = json!({
+      "ip": trace.ip,
+      "op": format!("{:?}", trace.instruction),
+      "sp": trace.sp,
+      "bp": trace.bp,
+      "top": top,
+    });
+    if let Err(err) = writeln!(self.output, "{}", line) {
+      self.error = Some(err);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::Value;
+
+  use crate::{compiler::Compiler, parser::Paser, vm::VM};
+
+  use super::{JsonTracer, Trace};
+
+  fn compile(source: &str) -> Vec<crate::vm::Opcode> {
+    Compiler::compile(&Paser::paser(source).unwrap()).unwrap()
+  }
+
+  #[test]
+  fn test_tracer() {
+    let codes = compile("var a = 1; if a { var b = a + 1; println(b) }; a");
+
+    let mut count = 0;
+    let mut scopes = vec![];
+    let result = VM::execute_with_tracer(&codes, &mut vec![], &mut |trace: &Trace| {
+      count += 1;
+      assert_eq!(trace.stack.len(), trace.sp);
+      if matches!(trace.instruction, crate::vm::Opcode::EnterScope | crate::vm::Opcode::LeaveScope) {
+        scopes.push(trace.bp);
+      }
+    });
+    assert_eq!(result, Ok(1));
+    assert_eq!(scopes.len(), 2);
+    // 进入语句块时 bp 指向新的语句块, 离开时恢复为全局作用域的基地址
+    assert_ne!(scopes[0], scopes[1]);
+    assert!(count >= codes.len() - 1);
+  }
+
+  #[test]
+  fn test_json() {
+    let source = "fn f(n) { n + 1 } var x = f(1); f(x)";
+    let (codes, debug) = Compiler::compile_with_debug(&Paser::paser(source).unwrap()).unwrap();
+
+    let mut tracer = JsonTracer::new(vec![]).with_slots(2);
+    VM::execute_with_tracer(&codes, &mut vec![], &mut tracer).unwrap();
+    let output = String::from_utf8(tracer.finish().unwrap()).unwrap();
+    let lines = output.lines().map(|line| serde_json::from_str::<Value>(line).unwrap()).collect::<Vec<_>>();
+    assert_eq!(lines[0]["ip"], 0);
+    assert!(lines.iter().all(|line| line["top"].as_array().unwrap().len() <= 2));
+    let last = lines.last().unwrap();
+    assert_eq!((last["sp"].as_u64(), last["top"].as_array().unwrap().last()), (Some(1), Some(&3.into())));
+
+    // 只记录函数 f 中的指令, 每次调用都从函数入口开始, 到 Ret 结束
+    let range = debug.function_range("f").unwrap();
+    let mut tracer = JsonTracer::new(vec![]).with_range(range.clone());
+    VM::execute_with_tracer(&codes, &mut vec![], &mut tracer).unwrap();
+    let output = String::from_utf8(tracer.finish().unwrap()).unwrap();
+    let ops = output.lines().map(|line| serde_json::from_str::<Value>(line).unwrap()["op"].clone()).collect::<Vec<_>>();
+    assert!(ops.len() > 2);
+    assert_eq!(ops.iter().filter(|op| *op == "Ret").count(), 2);
+    assert_eq!(ops.last().unwrap(), "Ret");
+  }
+}